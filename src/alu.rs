@@ -1,8 +1,11 @@
+#![allow(non_snake_case)]
+
 use std::{mem, ops::BitAnd, ops::BitOr, ops::BitXor, ops::Shl};
 
 use bitflags::bitflags;
 use num::{NumCast, Unsigned};
 
+#[allow(clippy::upper_case_acronyms)]
 enum OperationType {
     ADD,
     ADC,
@@ -16,6 +19,48 @@ enum OperationType {
     CMP,
     AAS,
     DAS,
+    AND,
+    OR,
+    XOR,
+    TEST,
+    AAM,
+}
+
+// the shift and rotate group, in the order of the D0-D3 reg field. /6 is the
+// undocumented SETMO, which sets the operand to all ones.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftOperation {
+    ROL,
+    ROR,
+    RCL,
+    RCR,
+    SHL,
+    SHR,
+    SETMO,
+    SAR,
+}
+
+impl ShiftOperation {
+    pub fn from_index(index: u8) -> ShiftOperation {
+        match index & 7 {
+            0 => ShiftOperation::ROL,
+            1 => ShiftOperation::ROR,
+            2 => ShiftOperation::RCL,
+            3 => ShiftOperation::RCR,
+            4 => ShiftOperation::SHL,
+            5 => ShiftOperation::SHR,
+            6 => ShiftOperation::SETMO,
+            _ => ShiftOperation::SAR,
+        }
+    }
+
+    fn is_rotate(self) -> bool {
+        matches!(
+            self,
+            ShiftOperation::ROL | ShiftOperation::ROR | ShiftOperation::RCL | ShiftOperation::RCR
+        )
+    }
 }
 
 bitflags! {
@@ -33,11 +78,263 @@ bitflags! {
     }
 }
 
-// pub fn mul16(op1: u16, op2: u16) -> (u32, Flags) {}
+// the maker of the CPU, whose microcode the ALU follows. The NEC V20 and
+// V30 differ from the 8088 in a few results that software uses to tell
+// them apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Vendor {
+    #[default]
+    Intel,
+    Nec,
+}
+
+impl Vendor {
+    // the NEC chips mask shift and rotate counts to 5 bits like the 80186
+    pub fn shift_count(self, count: u8) -> u8 {
+        match self {
+            Vendor::Intel => count,
+            Vendor::Nec => count & 0x1F,
+        }
+    }
+
+    // MUL and IMUL leave ZF as it was on the NEC chips, where the 8088
+    // clears it
+    pub fn mul_flags(self, before: Flags, flags: Flags) -> Flags {
+        match self {
+            Vendor::Intel => flags,
+            Vendor::Nec => (flags - Flags::ZERO_FLAG) | (before & Flags::ZERO_FLAG),
+        }
+    }
+
+    // AAM and AAD ignore their immediate on the NEC chips and always work
+    // in base 10
+    pub fn bcd_base(self, immediate: u8) -> u8 {
+        match self {
+            Vendor::Intel => immediate,
+            Vendor::Nec => 10,
+        }
+    }
+}
+
+// CF and OF are set when the upper half of the product is significant, the
+// other flags are undefined and left clear
+fn mul_flags(upper_significant: bool) -> Flags {
+    if upper_significant {
+        return Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG;
+    }
+    Flags::empty()
+}
+
+pub fn mul16(op1: u16, op2: u16) -> (u32, Flags) {
+    let result = (op1 as u32) * (op2 as u32);
+    (result, mul_flags(result > 0xFFFF))
+}
+
+pub fn mul8(op1: u8, op2: u8) -> (u16, Flags) {
+    let result = (op1 as u16) * (op2 as u16);
+    (result, mul_flags(result > 0xFF))
+}
+
+pub fn imul16(op1: u16, op2: u16) -> (u32, Flags) {
+    let result = (op1 as i16 as i32) * (op2 as i16 as i32);
+    (result as u32, mul_flags(result != result as i16 as i32))
+}
+
+pub fn imul8(op1: u8, op2: u8) -> (u16, Flags) {
+    let result = (op1 as i8 as i16) * (op2 as i8 as i16);
+    (result as u16, mul_flags(result != result as i8 as i16))
+}
+
+// the divisions return (quotient, remainder), or None when the 8088 raises a
+// divide error: division by zero or a quotient that does not fit
+pub fn div16(op1: u32, op2: u16) -> Option<(u16, u16)> {
+    if op2 == 0 || op1 / op2 as u32 > 0xFFFF {
+        return None;
+    }
+    Some(((op1 / op2 as u32) as u16, (op1 % op2 as u32) as u16))
+}
+
+pub fn div8(op1: u16, op2: u8) -> Option<(u8, u8)> {
+    if op2 == 0 || op1 / op2 as u16 > 0xFF {
+        return None;
+    }
+    Some(((op1 / op2 as u16) as u8, (op1 % op2 as u16) as u8))
+}
+
+// unlike later CPUs the 8088 also faults on the most negative quotient
+pub fn idiv16(op1: u32, op2: u16) -> Option<(u16, u16)> {
+    let dividend = op1 as i32 as i64;
+    let divisor = op2 as i16 as i64;
+    if divisor == 0 {
+        return None;
+    }
+    let quotient = dividend / divisor;
+    if !(-0x7FFF..=0x7FFF).contains(&quotient) {
+        return None;
+    }
+    Some((quotient as u16, (dividend % divisor) as u16))
+}
+
+pub fn idiv8(op1: u16, op2: u8) -> Option<(u8, u8)> {
+    let dividend = op1 as i16 as i32;
+    let divisor = op2 as i8 as i32;
+    if divisor == 0 {
+        return None;
+    }
+    let quotient = dividend / divisor;
+    if !(-0x7F..=0x7F).contains(&quotient) {
+        return None;
+    }
+    Some((quotient as u8, (dividend % divisor) as u8))
+}
+
+// AH = AL / base, AL = AL % base; a zero base is a divide error
+pub fn aam(op1: u8, base: u8) -> Option<(u16, Flags)> {
+    if base == 0 {
+        return None;
+    }
+    let (high, low) = (op1 / base, op1 % base);
+    let flags = compute_flags(low, low, low, None, OperationType::AAM);
+    Some((u16::from_le_bytes([low, high]), flags))
+}
 
-// pub fn mul8(op1: u8, op2: u8) -> (u16, Flags) {
-//     let result = (op1 as u16) * (op2 as u16);
-// }
+// AL = AH * base + AL, AH = 0. The flags come from that final addition.
+pub fn aad(op1: u16, base: u8) -> (u16, Flags) {
+    let [low, high] = op1.to_le_bytes();
+    let (result, flags) = add8(low, high * base);
+    (result as u16, flags)
+}
+
+// the 8088 does not mask the count and shifts one bit per step, so OF ends up
+// telling whether the last step changed the sign bit. Returns CF and OF only.
+fn shift_bits(op: ShiftOperation, op1: u16, msb: u16, count: u8, flags: Flags) -> (u16, Flags) {
+    let mask = msb | (msb - 1);
+    let mut value = op1;
+    let mut carry = flags.contains(Flags::CARRY_FLAG);
+    let mut overflow = false;
+    for _ in 0..count {
+        let before = value;
+        let carry_bit = if carry { 1 } else { 0 };
+        match op {
+            ShiftOperation::ROL => {
+                carry = value & msb != 0;
+                value = ((value << 1) | (value & msb != 0) as u16) & mask;
+            }
+            ShiftOperation::ROR => {
+                carry = value & 1 != 0;
+                value = (value >> 1) | if carry { msb } else { 0 };
+            }
+            ShiftOperation::RCL => {
+                carry = value & msb != 0;
+                value = ((value << 1) | carry_bit) & mask;
+            }
+            ShiftOperation::RCR => {
+                let low = value & 1 != 0;
+                value = (value >> 1) | (carry_bit * msb);
+                carry = low;
+            }
+            ShiftOperation::SHL => {
+                carry = value & msb != 0;
+                value = (value << 1) & mask;
+            }
+            ShiftOperation::SHR => {
+                carry = value & 1 != 0;
+                value >>= 1;
+            }
+            ShiftOperation::SETMO => {
+                carry = false;
+                value = mask;
+            }
+            ShiftOperation::SAR => {
+                carry = value & 1 != 0;
+                value = (value >> 1) | (value & msb);
+            }
+        }
+        overflow = op != ShiftOperation::SETMO && (before ^ value) & msb != 0;
+    }
+    let mut r_flags = Flags::empty();
+    r_flags.set(Flags::CARRY_FLAG, carry);
+    r_flags.set(Flags::OVERFLOW_FLAG, overflow);
+    (value, r_flags)
+}
+
+// rotates only touch CF and OF, shifts also set PF ZF SF from the result.
+// AF is undefined for shifts and left clear.
+fn shift_flags<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T> + Shl<Output = T> + Copy>(
+    op: ShiftOperation,
+    result: T,
+    carry_overflow: Flags,
+    flags: Flags,
+) -> Flags {
+    if op.is_rotate() {
+        return (flags - Flags::CARRY_FLAG - Flags::OVERFLOW_FLAG) | carry_overflow;
+    }
+    carry_overflow | compute_PF(result) | compute_ZF(result) | compute_SF(result)
+}
+
+// a zero count leaves the operand and every flag unchanged
+pub fn shift16(op: ShiftOperation, op1: u16, count: u8, flags: Flags) -> (u16, Flags) {
+    if count == 0 {
+        return (op1, flags);
+    }
+    let (result, carry_overflow) = shift_bits(op, op1, 0x8000, count, flags);
+    (result, shift_flags(op, result, carry_overflow, flags))
+}
+
+pub fn shift8(op: ShiftOperation, op1: u8, count: u8, flags: Flags) -> (u8, Flags) {
+    if count == 0 {
+        return (op1, flags);
+    }
+    let (result, carry_overflow) = shift_bits(op, op1 as u16, 0x80, count, flags);
+    let result = result as u8;
+    (result, shift_flags(op, result, carry_overflow, flags))
+}
+
+pub fn test16(op1: u16, op2: u16) -> Flags {
+    let result = op1 & op2;
+    compute_flags(op1, op2, result, None, OperationType::TEST)
+}
+
+pub fn test8(op1: u8, op2: u8) -> Flags {
+    let result = op1 & op2;
+    compute_flags(op1, op2, result, None, OperationType::TEST)
+}
+
+pub fn xor16(op1: u16, op2: u16) -> (u16, Flags) {
+    let result = op1 ^ op2;
+    let r_flags = compute_flags(op1, op2, result, None, OperationType::XOR);
+    (result, r_flags)
+}
+
+pub fn xor8(op1: u8, op2: u8) -> (u8, Flags) {
+    let result = op1 ^ op2;
+    let r_flags = compute_flags(op1, op2, result, None, OperationType::XOR);
+    (result, r_flags)
+}
+
+pub fn or16(op1: u16, op2: u16) -> (u16, Flags) {
+    let result = op1 | op2;
+    let r_flags = compute_flags(op1, op2, result, None, OperationType::OR);
+    (result, r_flags)
+}
+
+pub fn or8(op1: u8, op2: u8) -> (u8, Flags) {
+    let result = op1 | op2;
+    let r_flags = compute_flags(op1, op2, result, None, OperationType::OR);
+    (result, r_flags)
+}
+
+pub fn and16(op1: u16, op2: u16) -> (u16, Flags) {
+    let result = op1 & op2;
+    let r_flags = compute_flags(op1, op2, result, None, OperationType::AND);
+    (result, r_flags)
+}
+
+pub fn and8(op1: u8, op2: u8) -> (u8, Flags) {
+    let result = op1 & op2;
+    let r_flags = compute_flags(op1, op2, result, None, OperationType::AND);
+    (result, r_flags)
+}
 
 pub fn das(op1: u8, flags: Flags) -> (u8, Flags) {
     // base on https://www.cs.ubbcluj.ro/~mihai-suciu/asc/html/DAS.html
@@ -99,13 +396,13 @@ pub fn dec8(op1: u8, flags: Flags) -> (u8, Flags) {
 
 pub fn sbb16(op1: u16, op2: u16, carry: u16) -> (u16, Flags) {
     let result = op1 - op2 - carry;
-    let r_flags = compute_flags(op1, op2, result, None, OperationType::SBB);
+    let r_flags = compute_flags(op1, op2, result, Some(carry_in(carry)), OperationType::SBB);
     (result, r_flags)
 }
 
 pub fn sbb8(op1: u8, op2: u8, carry: u8) -> (u8, Flags) {
     let result = op1 - op2 - carry;
-    let r_flags = compute_flags(op1, op2, result, None, OperationType::SBB);
+    let r_flags = compute_flags(op1, op2, result, Some(carry_in(carry)), OperationType::SBB);
     (result, r_flags)
 }
 
@@ -160,13 +457,13 @@ pub fn inc8(op1: u8, flags: Flags) -> (u8, Flags) {
 
 pub fn adc16(op1: u16, op2: u16, carry: u16) -> (u16, Flags) {
     let result = op1 + op2 + carry;
-    let r_flags = compute_flags(op1, op2, result, None, OperationType::ADC);
+    let r_flags = compute_flags(op1, op2, result, Some(carry_in(carry)), OperationType::ADC);
     (result, r_flags)
 }
 
 pub fn adc8(op1: u8, op2: u8, carry: u8) -> (u8, Flags) {
     let result = op1 + op2 + carry;
-    let r_flags = compute_flags(op1, op2, result, None, OperationType::ADC);
+    let r_flags = compute_flags(op1, op2, result, Some(carry_in(carry)), OperationType::ADC);
     (result, r_flags)
 }
 
@@ -182,18 +479,41 @@ pub fn add8(op1: u8, op2: u8) -> (u8, Flags) {
     (result, r_flags)
 }
 
+fn carry_in<T: Unsigned>(carry: T) -> Flags {
+    if carry != T::zero() {
+        return Flags::CARRY_FLAG;
+    }
+    Flags::empty()
+}
+
 fn compute_CF_add<T: PartialOrd>(op1: T, result: T) -> Flags {
     if result < op1 {
         return Flags::CARRY_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_CF_sub<T: PartialOrd>(op1: T, op2: T) -> Flags {
     if op2 > op1 {
         return Flags::CARRY_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
+}
+
+// with a carry in, op1 + op2 + 1 == op1 also means the addition wrapped
+fn compute_CF_adc<T: PartialOrd>(op1: T, result: T, carry: Flags) -> Flags {
+    if result < op1 || (carry == Flags::CARRY_FLAG && result == op1) {
+        return Flags::CARRY_FLAG;
+    }
+    Flags::empty()
+}
+
+// likewise a borrow in makes op1 - op2 - 1 borrow when op1 == op2
+fn compute_CF_sbb<T: PartialOrd>(op1: T, op2: T, carry: Flags) -> Flags {
+    if op2 > op1 || (carry == Flags::CARRY_FLAG && op2 == op1) {
+        return Flags::CARRY_FLAG;
+    }
+    Flags::empty()
 }
 
 fn compute_PF<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T> + Shl<Output = T> + Copy>(
@@ -208,7 +528,7 @@ fn compute_PF<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T> + Shl<Outp
     if bits_set & 1 == 0 {
         return Flags::PARITY_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_AF_add<
@@ -233,7 +553,7 @@ fn compute_AF_add<
     {
         return Flags::AUXILIARY_CARRY_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_AF_sub<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T>>(
@@ -243,14 +563,27 @@ fn compute_AF_sub<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T>>(
     if op2 & T::from(0x0F).unwrap() > op1 & T::from(0x0F).unwrap() {
         return Flags::AUXILIARY_CARRY_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
+}
+
+fn compute_AF_sbb<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T> + Copy>(
+    op1: T,
+    op2: T,
+    carry: Flags,
+) -> Flags {
+    let nibble1 = op1 & T::from(0x0F).unwrap();
+    let nibble2 = op2 & T::from(0x0F).unwrap();
+    if nibble2 > nibble1 || (carry == Flags::CARRY_FLAG && nibble2 == nibble1) {
+        return Flags::AUXILIARY_CARRY_FLAG;
+    }
+    Flags::empty()
 }
 
 fn compute_ZF<T: Unsigned + PartialOrd>(result: T) -> Flags {
     if result == T::zero() {
         return Flags::ZERO_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_SF<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T> + Shl<Output = T>>(
@@ -261,7 +594,7 @@ fn compute_SF<T: Unsigned + PartialOrd + NumCast + BitAnd<Output = T> + Shl<Outp
     if msb_result == (T::one() << T::from(msb_bit).unwrap()) {
         return Flags::SIGN_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_OF_add<
@@ -288,7 +621,7 @@ fn compute_OF_add<
     {
         return Flags::OVERFLOW_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_OF_sub<
@@ -312,7 +645,7 @@ fn compute_OF_sub<
     if msb_op1 ^ msb_op2 == (T::one() << T::from(msb_bit).unwrap()) && msb_result == msb_op2 {
         return Flags::OVERFLOW_FLAG;
     }
-    return Flags::empty();
+    Flags::empty()
 }
 
 fn compute_flags<
@@ -334,7 +667,7 @@ fn compute_flags<
     let mut flags = Flags::empty();
 
     match op_type {
-        OperationType::ADD | OperationType::ADC => {
+        OperationType::ADD => {
             flags |= compute_CF_add(op1, result)
                 | compute_PF(result)
                 | compute_AF_add(op1, op2, result)
@@ -342,6 +675,14 @@ fn compute_flags<
                 | compute_SF(result)
                 | compute_OF_add(op1, op2, result);
         }
+        OperationType::ADC => {
+            flags |= compute_CF_adc(op1, result, input_flags.unwrap())
+                | compute_PF(result)
+                | compute_AF_add(op1, op2, result)
+                | compute_ZF(result)
+                | compute_SF(result)
+                | compute_OF_add(op1, op2, result);
+        }
         OperationType::INC => {
             flags |= input_flags.unwrap() & Flags::CARRY_FLAG
                 | compute_PF(result)
@@ -379,7 +720,15 @@ fn compute_flags<
 
             flags |= compute_PF(result) | compute_ZF(result) | compute_SF(result);
         }
-        OperationType::SUB | OperationType::SBB | OperationType::CMP => {
+        OperationType::SBB => {
+            flags |= compute_CF_sbb(op1, op2, input_flags.unwrap())
+                | compute_PF(result)
+                | compute_AF_sbb(op1, op2, input_flags.unwrap())
+                | compute_ZF(result)
+                | compute_SF(result)
+                | compute_OF_sub(op1, op2, result);
+        }
+        OperationType::SUB | OperationType::CMP => {
             flags |= compute_CF_sub(op1, op2)
                 | compute_PF(result)
                 | compute_AF_sub(op1, op2)
@@ -405,6 +754,14 @@ fn compute_flags<
                 | compute_SF(result)
                 | compute_OF_sub(op1, op2, result);
         }
+        OperationType::AND
+        | OperationType::OR
+        | OperationType::XOR
+        | OperationType::TEST
+        | OperationType::AAM => {
+            // CF and OF are always cleared, AF is undefined and left clear
+            flags |= compute_PF(result) | compute_ZF(result) | compute_SF(result);
+        }
         OperationType::AAS => {
             if op1 & T::from(0x000F).unwrap() > T::from(9).unwrap()
                 || input_flags.unwrap() & Flags::AUXILIARY_CARRY_FLAG == Flags::AUXILIARY_CARRY_FLAG
@@ -613,6 +970,16 @@ mod tests {
         assert_eq!((0, Flags::ZERO_FLAG | Flags::PARITY_FLAG), sbb16(0, 0, 0));
         assert_eq!((1, Flags::empty()), sbb16(1, 0, 0));
         assert_eq!((1, Flags::empty()), sbb16(3, 1, 1));
+        assert_eq!(
+            (
+                0xFFFF,
+                Flags::CARRY_FLAG
+                    | Flags::PARITY_FLAG
+                    | Flags::AUXILIARY_CARRY_FLAG
+                    | Flags::SIGN_FLAG
+            ),
+            sbb16(0x1234, 0x1234, 1)
+        );
     }
 
     #[test]
@@ -701,6 +1068,38 @@ mod tests {
         assert_eq!((0, Flags::ZERO_FLAG | Flags::PARITY_FLAG), adc8(0, 0, 0));
         assert_eq!((1, Flags::empty()), adc8(0, 0, 1));
         assert_eq!((2, Flags::empty()), adc8(1, 0, 1));
+        assert_eq!(
+            (
+                0x12,
+                Flags::CARRY_FLAG | Flags::PARITY_FLAG | Flags::AUXILIARY_CARRY_FLAG
+            ),
+            adc8(0x12, 0xFF, 1)
+        );
+    }
+
+    #[test]
+    fn test_and8() {
+        assert_eq!((0x0C, Flags::PARITY_FLAG), and8(0x3C, 0x0F));
+        assert_eq!((0, Flags::ZERO_FLAG | Flags::PARITY_FLAG), and8(0xF0, 0x0F));
+    }
+
+    #[test]
+    fn test_or16() {
+        assert_eq!((0x8001, Flags::SIGN_FLAG), or16(0x8000, 0x0001));
+    }
+
+    #[test]
+    fn test_xor8() {
+        assert_eq!((0, Flags::ZERO_FLAG | Flags::PARITY_FLAG), xor8(0x5A, 0x5A));
+        assert_eq!((0x80, Flags::SIGN_FLAG), xor8(0x7F, 0xFF));
+    }
+
+    #[test]
+    fn test_test16() {
+        assert_eq!(
+            Flags::SIGN_FLAG | Flags::PARITY_FLAG,
+            test16(0xFF00, 0x8003)
+        );
     }
 
     #[test]
@@ -766,4 +1165,179 @@ mod tests {
             add8(0x7F, 1)
         );
     }
+    #[test]
+    fn test_mul8() {
+        assert_eq!((0x00C8, Flags::empty()), mul8(10, 20));
+        assert_eq!(
+            (0xFE01, Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG),
+            mul8(0xFF, 0xFF)
+        );
+    }
+
+    #[test]
+    fn test_mul16() {
+        assert_eq!(
+            (0x0001_0000, Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG),
+            mul16(0x100, 0x100)
+        );
+        assert_eq!((0xFFFF, Flags::empty()), mul16(0xFFFF, 1));
+    }
+
+    #[test]
+    fn test_imul8() {
+        // -1 * -1 fits in AL
+        assert_eq!((1, Flags::empty()), imul8(0xFF, 0xFF));
+        assert_eq!((0xFF80, Flags::empty()), imul8(0x80, 1));
+        assert_eq!(
+            (0x4000, Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG),
+            imul8(0x80, 0x80)
+        );
+    }
+
+    #[test]
+    fn test_imul16() {
+        assert_eq!((0xFFFF_FFFE, Flags::empty()), imul16(0xFFFF, 2));
+        assert_eq!(
+            (0x4000_0000, Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG),
+            imul16(0x8000, 0x8000)
+        );
+    }
+
+    #[test]
+    fn test_div8() {
+        assert_eq!(Some((33, 1)), div8(100, 3));
+        assert_eq!(None, div8(100, 0));
+        assert_eq!(None, div8(0x100, 1));
+        assert_eq!(Some((0xFF, 0)), div8(0xFF, 1));
+    }
+
+    #[test]
+    fn test_div16() {
+        assert_eq!(Some((0x8000, 1)), div16(0x10001, 2));
+        assert_eq!(None, div16(0x10000, 1));
+        assert_eq!(None, div16(1, 0));
+    }
+
+    #[test]
+    fn test_idiv8() {
+        // -7 / 2 truncates towards zero with the remainder taking the sign of the dividend
+        assert_eq!(Some((0xFD, 0xFF)), idiv8(0xFFF9, 2));
+        assert_eq!(Some((0x7F, 0)), idiv8(0x7F, 1));
+        assert_eq!(Some((0x81, 0)), idiv8(0xFF81, 1));
+        assert_eq!(None, idiv8(0xFF80, 1));
+        assert_eq!(None, idiv8(0x80, 1));
+        assert_eq!(None, idiv8(5, 0));
+    }
+
+    #[test]
+    fn test_idiv16() {
+        assert_eq!(Some((0xFFFD, 0xFFFF)), idiv16(0xFFFF_FFF9, 2));
+        assert_eq!(None, idiv16(0xFFFF_8000, 1));
+        assert_eq!(None, idiv16(0x8000_0000, 0xFFFF));
+    }
+
+    #[test]
+    fn test_aam() {
+        assert_eq!(Some((0x0702, Flags::empty())), aam(72, 10));
+        assert_eq!(
+            Some((0x0100, Flags::ZERO_FLAG | Flags::PARITY_FLAG)),
+            aam(0x10, 16)
+        );
+        assert_eq!(None, aam(72, 0));
+    }
+
+    #[test]
+    fn test_aad() {
+        assert_eq!((72, Flags::PARITY_FLAG), aad(0x0702, 10));
+        assert_eq!(
+            (
+                0x80,
+                Flags::SIGN_FLAG | Flags::OVERFLOW_FLAG | Flags::AUXILIARY_CARRY_FLAG
+            ),
+            aad(0x0779, 1)
+        );
+    }
+
+    #[test]
+    fn test_shift8_shl() {
+        assert_eq!(
+            (0x02, Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG),
+            shift8(ShiftOperation::SHL, 0x81, 1, Flags::empty())
+        );
+        // the count is not masked, a count past the width keeps shifting
+        assert_eq!(
+            (0x00, Flags::ZERO_FLAG | Flags::PARITY_FLAG),
+            shift8(ShiftOperation::SHL, 0xFF, 9, Flags::empty())
+        );
+    }
+
+    #[test]
+    fn test_shift16_sar_shr() {
+        assert_eq!(
+            (0xE000, Flags::SIGN_FLAG | Flags::PARITY_FLAG),
+            shift16(ShiftOperation::SAR, 0x8001, 2, Flags::empty())
+        );
+        assert_eq!(
+            (0x4000, Flags::OVERFLOW_FLAG | Flags::PARITY_FLAG),
+            shift16(ShiftOperation::SHR, 0x8000, 1, Flags::empty())
+        );
+    }
+
+    #[test]
+    fn test_rotates_keep_other_flags() {
+        assert_eq!(
+            (
+                0x03,
+                Flags::ZERO_FLAG | Flags::SIGN_FLAG | Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG
+            ),
+            shift8(
+                ShiftOperation::ROL,
+                0x81,
+                1,
+                Flags::ZERO_FLAG | Flags::SIGN_FLAG
+            )
+        );
+        assert_eq!(
+            (0x80, Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG),
+            shift8(ShiftOperation::RCR, 0x01, 1, Flags::CARRY_FLAG)
+        );
+        assert_eq!(
+            (0x8001, Flags::OVERFLOW_FLAG),
+            shift16(ShiftOperation::RCL, 0x4000, 1, Flags::CARRY_FLAG)
+        );
+        assert_eq!(
+            (0x1234, Flags::empty()),
+            shift16(ShiftOperation::ROR, 0x1234, 16, Flags::CARRY_FLAG)
+        );
+    }
+
+    #[test]
+    fn test_setmo() {
+        assert_eq!(
+            (0xFF, Flags::PARITY_FLAG | Flags::SIGN_FLAG),
+            shift8(
+                ShiftOperation::SETMO,
+                0x12,
+                1,
+                Flags::CARRY_FLAG | Flags::ZERO_FLAG
+            )
+        );
+        assert_eq!(
+            (0x12, Flags::CARRY_FLAG),
+            shift8(ShiftOperation::SETMO, 0x12, 0, Flags::CARRY_FLAG)
+        );
+    }
+
+    #[test]
+    fn test_nec_vendor() {
+        assert_eq!(1, Vendor::Nec.shift_count(33));
+        assert_eq!(33, Vendor::Intel.shift_count(33));
+        let (_, flags) = mul8(0x40, 4);
+        assert_eq!(
+            Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG | Flags::ZERO_FLAG,
+            Vendor::Nec.mul_flags(Flags::ZERO_FLAG, flags)
+        );
+        assert_eq!(flags, Vendor::Intel.mul_flags(Flags::ZERO_FLAG, flags));
+        assert_eq!(10, Vendor::Nec.bcd_base(16));
+    }
 }
@@ -0,0 +1,405 @@
+// Runs the 8088 SingleStepTests corpus (github.com/SingleStepTests/8088)
+// against the CPU from a local copy of its files, one instruction per case:
+//
+//     cargo run --release --features single-step --bin single_step -- <dir> [opcode...]
+//
+// <dir> holds the per-opcode files (00.json.gz, D0.4.json, ...) and the
+// 8088.json metadata, either next to them or one level up. Opcodes such as
+// "D0" or "D0.4" limit the run to those files.
+
+use std::{
+    env, fs,
+    fs::File,
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+};
+
+use emu_intel_8088::{
+    cpu::{Cpu, Registers},
+    io::IoBus,
+    memory::{MemoryBus, ADDRESS_SPACE_SIZE},
+};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::Value;
+
+// mismatches printed for each opcode file
+const DETAILS_PER_FILE: usize = 3;
+
+#[derive(Debug, Default, Deserialize)]
+struct Regs {
+    ax: Option<u16>,
+    bx: Option<u16>,
+    cx: Option<u16>,
+    dx: Option<u16>,
+    cs: Option<u16>,
+    ss: Option<u16>,
+    ds: Option<u16>,
+    es: Option<u16>,
+    sp: Option<u16>,
+    bp: Option<u16>,
+    si: Option<u16>,
+    di: Option<u16>,
+    ip: Option<u16>,
+    flags: Option<u16>,
+}
+
+impl Regs {
+    fn apply(&self, regs: &mut Registers) {
+        let fields = [
+            (self.ax, &mut regs.ax),
+            (self.bx, &mut regs.bx),
+            (self.cx, &mut regs.cx),
+            (self.dx, &mut regs.dx),
+            (self.cs, &mut regs.cs),
+            (self.ss, &mut regs.ss),
+            (self.ds, &mut regs.ds),
+            (self.es, &mut regs.es),
+            (self.sp, &mut regs.sp),
+            (self.bp, &mut regs.bp),
+            (self.si, &mut regs.si),
+            (self.di, &mut regs.di),
+            (self.ip, &mut regs.ip),
+        ];
+        for (value, reg) in fields {
+            if let Some(value) = value {
+                *reg = value;
+            }
+        }
+        if let Some(flags) = self.flags {
+            regs.set_flags_word(flags);
+        }
+    }
+
+    // (name, value in the test, value in the CPU)
+    fn pairs(&self, regs: &Registers) -> [(&'static str, Option<u16>, u16); 14] {
+        [
+            ("ax", self.ax, regs.ax),
+            ("bx", self.bx, regs.bx),
+            ("cx", self.cx, regs.cx),
+            ("dx", self.dx, regs.dx),
+            ("cs", self.cs, regs.cs),
+            ("ss", self.ss, regs.ss),
+            ("ds", self.ds, regs.ds),
+            ("es", self.es, regs.es),
+            ("sp", self.sp, regs.sp),
+            ("bp", self.bp, regs.bp),
+            ("si", self.si, regs.si),
+            ("di", self.di, regs.di),
+            ("ip", self.ip, regs.ip),
+            ("flags", self.flags, regs.flags_word()),
+        ]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct State {
+    regs: Regs,
+    #[serde(default)]
+    ram: Vec<(u32, u8)>,
+    #[serde(default)]
+    queue: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Case {
+    name: String,
+    #[serde(default)]
+    idx: Option<u64>,
+    initial: State,
+    #[serde(rename = "final")]
+    final_state: State,
+    // one entry per clock of the instruction, with the bus activity of that
+    // clock. The CPU keeps no record of its bus cycles, so only the number
+    // of entries is checked.
+    #[serde(default)]
+    cycles: Vec<Value>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Pass { clock_count_matches: bool },
+    Fail(Vec<String>),
+    Panic(String),
+}
+
+// runs the instruction of a case, a repeated string instruction to the end
+fn run(case: &Case, flags_mask: u16) -> Outcome {
+    let mut mem = MemoryBus::new();
+    mem.map_ram(0, ADDRESS_SPACE_SIZE).unwrap();
+    for &(address, value) in &case.initial.ram {
+        mem.write_u8(address, value);
+    }
+    let mut cpu = Cpu::new(mem, IoBus::new());
+    case.initial.regs.apply(&mut cpu.regs);
+    let initial = cpu.regs;
+    cpu.set_queue(&case.initial.queue);
+
+    let mut cycles = cpu.step();
+    while cpu.is_repeating() {
+        cycles += cpu.step();
+    }
+
+    let mut mismatches = Vec::new();
+    let expected = case.final_state.regs.pairs(&cpu.regs);
+    // registers left out of the final state are unchanged
+    for ((name, value, actual), (_, initial, _)) in expected
+        .iter()
+        .zip(case.initial.regs.pairs(&initial).iter())
+    {
+        let value = match value.or(*initial) {
+            Some(value) => value,
+            None => continue,
+        };
+        let mask = if *name == "flags" { flags_mask } else { 0xFFFF };
+        if (value ^ actual) & mask != 0 {
+            mismatches.push(format!("{} {:04X} != {:04X}", name, actual, value));
+        }
+    }
+    for &(address, value) in &case.final_state.ram {
+        let actual = cpu.mem.read_u8(address);
+        if actual != value {
+            mismatches.push(format!("[{:05X}] {:02X} != {:02X}", address, actual, value));
+        }
+    }
+    if mismatches.is_empty() {
+        Outcome::Pass {
+            clock_count_matches: case.cycles.is_empty() || cycles as usize == case.cycles.len(),
+        }
+    } else {
+        Outcome::Fail(mismatches)
+    }
+}
+
+fn run_guarded(case: &Case, flags_mask: u16) -> Outcome {
+    match panic::catch_unwind(AssertUnwindSafe(|| run(case, flags_mask))) {
+        Ok(outcome) => outcome,
+        Err(payload) => Outcome::Panic(
+            payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default(),
+        ),
+    }
+}
+
+// the per-opcode entries of 8088.json: status and undefined flags
+struct Metadata(Value);
+
+impl Metadata {
+    fn entry(&self, opcode: &str, reg: Option<&str>) -> &Value {
+        let entry = &self.0["opcodes"][opcode];
+        match reg {
+            Some(reg) if entry.get("reg").is_some() => &entry["reg"][reg],
+            _ => entry,
+        }
+    }
+
+    fn flags_mask(&self, opcode: &str, reg: Option<&str>) -> u16 {
+        self.entry(opcode, reg)["flags-mask"]
+            .as_u64()
+            .map_or(0xFFFF, |mask| mask as u16)
+    }
+
+    fn status(&self, opcode: &str, reg: Option<&str>) -> &str {
+        self.entry(opcode, reg)["status"].as_str().unwrap_or("-")
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        GzDecoder::new(file).read_to_string(&mut text)?;
+    } else {
+        io::BufReader::new(file).read_to_string(&mut text)?;
+    }
+    Ok(text)
+}
+
+fn load_metadata(dir: &Path) -> Metadata {
+    let candidates = [dir.join("8088.json"), dir.join("..").join("8088.json")];
+    let value = candidates
+        .iter()
+        .find_map(|path| read_file(path).ok())
+        .and_then(|text| serde_json::from_str(&text).ok());
+    if value.is_none() {
+        eprintln!("no 8088.json metadata found, comparing every flag");
+    }
+    Metadata(value.unwrap_or(Value::Null))
+}
+
+// "D0.4.json.gz" -> "D0.4"
+fn stem(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name
+        .strip_suffix(".json.gz")
+        .or_else(|| name.strip_suffix(".json"))?;
+    // the opcode, and the reg field for the groups; not 8088.json
+    let opcode = stem.split('.').next()?;
+    if opcode.len() != 2 || !opcode.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(stem.to_ascii_uppercase())
+}
+
+fn test_files(dir: &Path, filters: &[String]) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(stem) = stem(&path) {
+            if filters.is_empty() || filters.iter().any(|f| stem.starts_with(f.as_str())) {
+                files.push((stem, path));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let dir = match args.next() {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            eprintln!("usage: single_step <dir> [opcode...]");
+            process::exit(2);
+        }
+    };
+    let filters: Vec<String> = args.map(|a| a.to_ascii_uppercase()).collect();
+    let metadata = load_metadata(&dir);
+    let files = test_files(&dir, &filters).unwrap_or_else(|err| {
+        eprintln!("{}: {}", dir.display(), err);
+        process::exit(2);
+    });
+    // unimplemented opcodes panic, those are counted instead of printed
+    panic::set_hook(Box::new(|_| {}));
+
+    let (mut total, mut failed, mut timing) = (0, 0, 0);
+    for (stem, path) in files {
+        let mut parts = stem.splitn(2, '.');
+        let opcode = parts.next().unwrap();
+        let reg = parts.next();
+        let cases: Vec<Case> = match read_file(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|err| err.to_string()))
+        {
+            Ok(cases) => cases,
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                continue;
+            }
+        };
+        let mask = metadata.flags_mask(opcode, reg);
+        let (mut passed, mut clocks_off, mut details) = (0, 0, Vec::new());
+        for case in &cases {
+            match run_guarded(case, mask) {
+                Outcome::Pass {
+                    clock_count_matches,
+                } => {
+                    passed += 1;
+                    if !clock_count_matches {
+                        clocks_off += 1;
+                    }
+                }
+                Outcome::Fail(mismatches) => details.push(format!(
+                    "#{} {}: {}",
+                    case.idx.unwrap_or_default(),
+                    case.name,
+                    mismatches.join(", ")
+                )),
+                Outcome::Panic(message) => details.push(format!(
+                    "#{} {}: panicked: {}",
+                    case.idx.unwrap_or_default(),
+                    case.name,
+                    message
+                )),
+            }
+        }
+        println!(
+            "{:<6} {:<12} {:>6}/{:<6} passed, {} with other clock counts",
+            stem,
+            metadata.status(opcode, reg),
+            passed,
+            cases.len(),
+            clocks_off
+        );
+        for detail in details.iter().take(DETAILS_PER_FILE) {
+            println!("    {}", detail);
+        }
+        total += cases.len();
+        failed += cases.len() - passed;
+        timing += clocks_off;
+    }
+    println!(
+        "{} cases, {} failed, {} passed with other clock counts",
+        total, failed, timing
+    );
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASE: &str = r#"{
+        "name": "inc ax",
+        "bytes": [64],
+        "initial": {
+            "regs": {"ax": 65535, "bx": 0, "cx": 0, "dx": 0, "cs": 4096, "ss": 0,
+                     "ds": 0, "es": 0, "sp": 256, "bp": 0, "si": 0, "di": 0,
+                     "ip": 0, "flags": 61443},
+            "ram": [[65536, 64], [65537, 144]],
+            "queue": []
+        },
+        "final": {
+            "regs": {"ax": 0, "ip": 1, "flags": 61527},
+            "ram": [[65536, 64], [65537, 144]],
+            "queue": []
+        },
+        "cycles": [[], [], [], [], [], []],
+        "idx": 0
+    }"#;
+
+    #[test]
+    fn test_passing_case() {
+        let case: Case = serde_json::from_str(CASE).unwrap();
+        // from an empty queue, 4 clocks to fetch and 2 for INC
+        assert_eq!(
+            Outcome::Pass {
+                clock_count_matches: true
+            },
+            run(&case, 0xFFFF)
+        );
+    }
+
+    #[test]
+    fn test_flags_mask() {
+        let mut case: Case = serde_json::from_str(CASE).unwrap();
+        // AF flipped in the expected flags
+        case.final_state.regs.flags = Some(61527 ^ 0x10);
+        assert_eq!(
+            Outcome::Fail(vec!["flags F057 != F047".to_string()]),
+            run(&case, 0xFFFF)
+        );
+        assert_eq!(
+            Outcome::Pass {
+                clock_count_matches: true
+            },
+            run(&case, !0x10)
+        );
+    }
+
+    #[test]
+    fn test_stem() {
+        assert_eq!(Some("D0.4".to_string()), stem(Path::new("v1/d0.4.json.gz")));
+        assert_eq!(Some("00".to_string()), stem(Path::new("00.json")));
+        assert_eq!(None, stem(Path::new("8088.json")));
+        assert_eq!(None, stem(Path::new("README.md")));
+    }
+}
@@ -0,0 +1,763 @@
+use super::{
+    modrm::{ModRm, Operand},
+    Cpu,
+};
+use crate::alu::{self, Flags, ShiftOperation};
+
+// ALU operations in the order of the opcode/reg field encoding
+const CMP: u8 = 7;
+
+// extra clocks MUL and DIV take for a memory operand, on top of the EA
+fn memory_operand_clocks(operand: Operand) -> u32 {
+    match operand {
+        Operand::Register(_) => 0,
+        Operand::Memory(..) => 6,
+    }
+}
+
+// clocks for reg,reg / mem,reg / CMP mem,reg
+fn rm_clocks(op: u8, operand: Operand, mem: u32, cmp_mem: u32) -> u32 {
+    match operand {
+        Operand::Register(_) => 3,
+        Operand::Memory(..) if op == CMP => cmp_mem,
+        Operand::Memory(..) => mem,
+    }
+}
+
+impl Cpu {
+    fn carry(&self) -> bool {
+        self.regs.flag(Flags::CARRY_FLAG)
+    }
+
+    pub(super) fn alu8(&mut self, op: u8, op1: u8, op2: u8) -> u8 {
+        let carry = self.carry() as u8;
+        let (result, flags) = match op & 7 {
+            0 => alu::add8(op1, op2),
+            1 => alu::or8(op1, op2),
+            2 => alu::adc8(op1, op2, carry),
+            3 => alu::sbb8(op1, op2, carry),
+            4 => alu::and8(op1, op2),
+            5 => alu::sub8(op1, op2),
+            6 => alu::xor8(op1, op2),
+            _ => (op1, alu::cmp8(op1, op2)),
+        };
+        self.set_arithmetic_flags(flags);
+        result
+    }
+
+    pub(super) fn alu16(&mut self, op: u8, op1: u16, op2: u16) -> u16 {
+        let carry = self.carry() as u16;
+        let (result, flags) = match op & 7 {
+            0 => alu::add16(op1, op2),
+            1 => alu::or16(op1, op2),
+            2 => alu::adc16(op1, op2, carry),
+            3 => alu::sbb16(op1, op2, carry),
+            4 => alu::and16(op1, op2),
+            5 => alu::sub16(op1, op2),
+            6 => alu::xor16(op1, op2),
+            _ => (op1, alu::cmp16(op1, op2)),
+        };
+        self.set_arithmetic_flags(flags);
+        result
+    }
+
+    // 00-3D: ADD OR ADC SBB AND SUB XOR CMP in their six encodings
+    pub(super) fn alu_op(&mut self, opcode: u8) {
+        let op = (opcode >> 3) & 7;
+        match opcode & 7 {
+            0 => {
+                let modrm = self.decode_modrm();
+                let op1 = self.read_operand8(modrm.operand);
+                let result = self.alu8(op, op1, self.regs.reg8(modrm.reg));
+                if op != CMP {
+                    self.write_operand8(modrm.operand, result);
+                }
+                self.clock(rm_clocks(op, modrm.operand, 16, 9));
+            }
+            1 => {
+                let modrm = self.decode_modrm();
+                let op1 = self.read_operand16(modrm.operand);
+                let result = self.alu16(op, op1, self.regs.reg16(modrm.reg));
+                if op != CMP {
+                    self.write_operand16(modrm.operand, result);
+                }
+                self.clock(rm_clocks(op, modrm.operand, 16, 9));
+            }
+            2 => {
+                let modrm = self.decode_modrm();
+                let op2 = self.read_operand8(modrm.operand);
+                let result = self.alu8(op, self.regs.reg8(modrm.reg), op2);
+                if op != CMP {
+                    self.regs.set_reg8(modrm.reg, result);
+                }
+                self.clock(rm_clocks(op, modrm.operand, 9, 9));
+            }
+            3 => {
+                let modrm = self.decode_modrm();
+                let op2 = self.read_operand16(modrm.operand);
+                let result = self.alu16(op, self.regs.reg16(modrm.reg), op2);
+                if op != CMP {
+                    self.regs.set_reg16(modrm.reg, result);
+                }
+                self.clock(rm_clocks(op, modrm.operand, 9, 9));
+            }
+            4 => {
+                let op2 = self.fetch_u8();
+                let result = self.alu8(op, self.regs.al(), op2);
+                if op != CMP {
+                    self.regs.set_al(result);
+                }
+                self.clock(4);
+            }
+            _ => {
+                let op2 = self.fetch_u16();
+                let result = self.alu16(op, self.regs.ax, op2);
+                if op != CMP {
+                    self.regs.ax = result;
+                }
+                self.clock(4);
+            }
+        }
+    }
+
+    // 80-83: ALU operation with an immediate, 82 is an alias of 80 and 83
+    // sign-extends its byte immediate
+    pub(super) fn group1(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let op = modrm.reg;
+        if opcode & 1 == 0 {
+            let op1 = self.read_operand8(modrm.operand);
+            let op2 = self.fetch_u8();
+            let result = self.alu8(op, op1, op2);
+            if op != CMP {
+                self.write_operand8(modrm.operand, result);
+            }
+        } else {
+            let op1 = self.read_operand16(modrm.operand);
+            let op2 = if opcode == 0x83 {
+                self.fetch_u8() as i8 as u16
+            } else {
+                self.fetch_u16()
+            };
+            let result = self.alu16(op, op1, op2);
+            if op != CMP {
+                self.write_operand16(modrm.operand, result);
+            }
+        }
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 4,
+            Operand::Memory(..) if op == CMP => 10,
+            Operand::Memory(..) => 17,
+        });
+    }
+
+    pub(super) fn test_rm_reg(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let flags = if opcode & 1 == 0 {
+            let op1 = self.read_operand8(modrm.operand);
+            alu::test8(op1, self.regs.reg8(modrm.reg))
+        } else {
+            let op1 = self.read_operand16(modrm.operand);
+            alu::test16(op1, self.regs.reg16(modrm.reg))
+        };
+        self.set_arithmetic_flags(flags);
+        self.clock(rm_clocks(CMP, modrm.operand, 9, 9));
+    }
+
+    pub(super) fn test_acc_imm(&mut self, opcode: u8) {
+        let flags = if opcode & 1 == 0 {
+            let op2 = self.fetch_u8();
+            alu::test8(self.regs.al(), op2)
+        } else {
+            let op2 = self.fetch_u16();
+            alu::test16(self.regs.ax, op2)
+        };
+        self.set_arithmetic_flags(flags);
+        self.clock(4);
+    }
+
+    pub(super) fn inc_reg16(&mut self, index: u8) {
+        let (result, flags) = alu::inc16(self.regs.reg16(index), self.regs.flags);
+        self.regs.set_reg16(index, result);
+        self.set_arithmetic_flags(flags);
+        self.clock(2);
+    }
+
+    pub(super) fn dec_reg16(&mut self, index: u8) {
+        let (result, flags) = alu::dec16(self.regs.reg16(index), self.regs.flags);
+        self.regs.set_reg16(index, result);
+        self.set_arithmetic_flags(flags);
+        self.clock(2);
+    }
+
+    // F6/F7: TEST NOT NEG on r/m
+    pub(super) fn group3(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let word = opcode & 1 == 1;
+        let register = matches!(modrm.operand, Operand::Register(_));
+        match modrm.reg {
+            // /1 is an undocumented alias of TEST
+            0 | 1 => {
+                if modrm.reg == 1 && self.trap_undocumented() {
+                    return;
+                }
+                let flags = if word {
+                    let op1 = self.read_operand16(modrm.operand);
+                    alu::test16(op1, self.fetch_u16())
+                } else {
+                    let op1 = self.read_operand8(modrm.operand);
+                    alu::test8(op1, self.fetch_u8())
+                };
+                self.set_arithmetic_flags(flags);
+                self.clock(if register { 5 } else { 11 });
+            }
+            2 => {
+                if word {
+                    let value = self.read_operand16(modrm.operand);
+                    self.write_operand16(modrm.operand, !value);
+                } else {
+                    let value = self.read_operand8(modrm.operand);
+                    self.write_operand8(modrm.operand, !value);
+                }
+                self.clock(if register { 3 } else { 16 });
+            }
+            3 => {
+                let flags = if word {
+                    let (result, flags) = alu::neg16(self.read_operand16(modrm.operand));
+                    self.write_operand16(modrm.operand, result);
+                    flags
+                } else {
+                    let (result, flags) = alu::neg8(self.read_operand8(modrm.operand));
+                    self.write_operand8(modrm.operand, result);
+                    flags
+                };
+                self.set_arithmetic_flags(flags);
+                self.clock(if register { 3 } else { 16 });
+            }
+            4 | 5 => self.multiply(modrm.reg == 5, word, modrm.operand),
+            _ => self.divide(modrm.reg == 7, word, modrm.operand),
+        }
+    }
+
+    // a REP prefix makes the microcode negate the result of IMUL and IDIV
+    fn negate_result(&self, signed: bool) -> bool {
+        signed && self.prefixes.repeat.is_some()
+    }
+
+    // F6/F7 /4 /5: MUL and IMUL into AX or DX:AX
+    fn multiply(&mut self, signed: bool, word: bool, operand: Operand) {
+        let negate = self.negate_result(signed);
+        let flags = if word {
+            let op2 = self.read_operand16(operand);
+            let (mut result, flags) = if signed {
+                alu::imul16(self.regs.ax, op2)
+            } else {
+                alu::mul16(self.regs.ax, op2)
+            };
+            if negate {
+                result = result.wrapping_neg();
+            }
+            self.regs.ax = result as u16;
+            self.regs.dx = (result >> 16) as u16;
+            flags
+        } else {
+            let op2 = self.read_operand8(operand);
+            let (mut result, flags) = if signed {
+                alu::imul8(self.regs.al(), op2)
+            } else {
+                alu::mul8(self.regs.al(), op2)
+            };
+            if negate {
+                result = result.wrapping_neg();
+            }
+            self.regs.ax = result;
+            flags
+        };
+        let flags = self.vendor().mul_flags(self.regs.flags, flags);
+        self.set_arithmetic_flags(flags);
+        let clocks = match (signed, word) {
+            (false, false) => 70,
+            (false, true) => 118,
+            (true, false) => 80,
+            (true, true) => 128,
+        };
+        self.clock(clocks + memory_operand_clocks(operand));
+    }
+
+    // 69: IMUL r16, r/m16, imm16 and 6B: IMUL r16, r/m16, imm8 on the V20,
+    // keeping the low half of the product
+    pub(super) fn imul_imm(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let op1 = self.read_operand16(modrm.operand);
+        let op2 = if opcode == 0x69 {
+            self.fetch_u16()
+        } else {
+            self.fetch_u8() as i8 as u16
+        };
+        let (result, flags) = alu::imul16(op1, op2);
+        self.regs.set_reg16(modrm.reg, result as u16);
+        let flags = self.vendor().mul_flags(self.regs.flags, flags);
+        self.set_arithmetic_flags(flags);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 22,
+            Operand::Memory(..) => 29,
+        });
+    }
+
+    // F6/F7 /6 /7: DIV and IDIV of AX or DX:AX. An oversized quotient or a
+    // zero divisor raises a divide error and leaves the registers alone.
+    fn divide(&mut self, signed: bool, word: bool, operand: Operand) {
+        let negate = self.negate_result(signed);
+        let clocks = match (signed, word) {
+            (false, false) => 80,
+            (false, true) => 144,
+            (true, false) => 101,
+            (true, true) => 165,
+        };
+        self.clock(clocks + memory_operand_clocks(operand));
+        let ok = if word {
+            let op1 = (self.regs.dx as u32) << 16 | self.regs.ax as u32;
+            let op2 = self.read_operand16(operand);
+            let result = if signed {
+                alu::idiv16(op1, op2)
+            } else {
+                alu::div16(op1, op2)
+            };
+            result.map(|(quotient, remainder)| {
+                self.regs.ax = if negate {
+                    quotient.wrapping_neg()
+                } else {
+                    quotient
+                };
+                self.regs.dx = remainder;
+            })
+        } else {
+            let op2 = self.read_operand8(operand);
+            let result = if signed {
+                alu::idiv8(self.regs.ax, op2)
+            } else {
+                alu::div8(self.regs.ax, op2)
+            };
+            result.map(|(quotient, remainder)| {
+                self.regs.set_al(if negate {
+                    quotient.wrapping_neg()
+                } else {
+                    quotient
+                });
+                self.regs.set_ah(remainder);
+            })
+        };
+        if ok.is_none() {
+            self.divide_error();
+        }
+    }
+
+    // FE: INC/DEC r/m8. The undocumented /2-/7 do what FF does, on a byte.
+    pub(super) fn group4(&mut self) {
+        let modrm = self.decode_modrm();
+        if modrm.reg >= 2 {
+            if !self.trap_undocumented() {
+                self.indirect_or_push(modrm, false);
+            }
+            return;
+        }
+        let register = matches!(modrm.operand, Operand::Register(_));
+        let value = self.read_operand8(modrm.operand);
+        let (result, flags) = if modrm.reg == 0 {
+            alu::inc8(value, self.regs.flags)
+        } else {
+            alu::dec8(value, self.regs.flags)
+        };
+        self.write_operand8(modrm.operand, result);
+        self.set_arithmetic_flags(flags);
+        self.clock(if register { 3 } else { 15 });
+    }
+
+    // FF: INC/DEC r/m16, indirect CALL and JMP, PUSH r/m16
+    pub(super) fn group5(&mut self) {
+        let modrm = self.decode_modrm();
+        let register = matches!(modrm.operand, Operand::Register(_));
+        match modrm.reg {
+            0 | 1 => {
+                let value = self.read_operand16(modrm.operand);
+                let (result, flags) = if modrm.reg == 0 {
+                    alu::inc16(value, self.regs.flags)
+                } else {
+                    alu::dec16(value, self.regs.flags)
+                };
+                self.write_operand16(modrm.operand, result);
+                self.set_arithmetic_flags(flags);
+                self.clock(if register { 3 } else { 15 });
+            }
+            7 if self.trap_undocumented() => {}
+            _ => self.indirect_or_push(modrm, true),
+        }
+    }
+
+    // FE/FF /2-/7
+    fn indirect_or_push(&mut self, modrm: ModRm, word: bool) {
+        match modrm.reg {
+            2 => self.call_near_indirect(modrm, word),
+            3 => self.call_far_indirect(modrm, word),
+            4 => self.jmp_near_indirect(modrm, word),
+            5 => self.jmp_far_indirect(modrm, word),
+            _ => self.push_rm(modrm.operand, word),
+        }
+    }
+
+    // D0-D3: shifts and rotates by 1 or by CL, 4 clocks per bit for CL. C0/C1
+    // on the V20 shift by an immediate.
+    pub(super) fn group2(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let op = ShiftOperation::from_index(modrm.reg);
+        if op == ShiftOperation::SETMO && self.trap_undocumented() {
+            return;
+        }
+        let immediate = opcode < 0xD0;
+        let by_cl = opcode & 2 != 0;
+        let count = if immediate {
+            self.fetch_u8()
+        } else if by_cl {
+            self.regs.reg8(1)
+        } else {
+            1
+        };
+        let count = self.vendor().shift_count(count);
+        let flags = if opcode & 1 == 1 {
+            let value = self.read_operand16(modrm.operand);
+            let (result, flags) = alu::shift16(op, value, count, self.regs.flags);
+            self.write_operand16(modrm.operand, result);
+            flags
+        } else {
+            let value = self.read_operand8(modrm.operand);
+            let (result, flags) = alu::shift8(op, value, count, self.regs.flags);
+            self.write_operand8(modrm.operand, result);
+            flags
+        };
+        self.set_arithmetic_flags(flags);
+        let register = matches!(modrm.operand, Operand::Register(_));
+        self.clock(match (immediate, by_cl, register) {
+            (true, _, true) => 5 + count as u32,
+            (true, _, false) => 17 + count as u32,
+            (false, false, true) => 2,
+            (false, false, false) => 15,
+            (false, true, true) => 8 + 4 * count as u32,
+            (false, true, false) => 20 + 4 * count as u32,
+        });
+    }
+
+    // D6: SALC, AL = CF ? FFh : 00h
+    pub(super) fn salc(&mut self) {
+        let value = if self.regs.flag(Flags::CARRY_FLAG) {
+            0xFF
+        } else {
+            0x00
+        };
+        self.regs.set_al(value);
+        self.clock(4);
+    }
+
+    pub(super) fn daa(&mut self) {
+        let (result, flags) = alu::daa(self.regs.al(), self.regs.flags);
+        self.regs.set_al(result);
+        self.set_arithmetic_flags(flags);
+        self.clock(4);
+    }
+
+    pub(super) fn das(&mut self) {
+        let (result, flags) = alu::das(self.regs.al(), self.regs.flags);
+        self.regs.set_al(result);
+        self.set_arithmetic_flags(flags);
+        self.clock(4);
+    }
+
+    pub(super) fn aaa(&mut self) {
+        let (result, flags) = alu::aaa(self.regs.ax, self.regs.flags);
+        self.regs.ax = result;
+        self.set_arithmetic_flags(flags);
+        self.clock(4);
+    }
+
+    pub(super) fn aas(&mut self) {
+        let (result, flags) = alu::aas(self.regs.ax, self.regs.flags);
+        self.regs.ax = result;
+        self.set_arithmetic_flags(flags);
+        self.clock(4);
+    }
+
+    // D4: AAM imm8, the base is the immediate and is 10 in the documented form
+    pub(super) fn aam(&mut self) {
+        let base = self.vendor().bcd_base(self.fetch_u8());
+        self.clock(83);
+        match alu::aam(self.regs.al(), base) {
+            Some((result, flags)) => {
+                self.regs.ax = result;
+                self.set_arithmetic_flags(flags);
+            }
+            None => self.divide_error(),
+        }
+    }
+
+    // D5: AAD imm8
+    pub(super) fn aad(&mut self) {
+        let base = self.vendor().bcd_base(self.fetch_u8());
+        let (result, flags) = alu::aad(self.regs.ax, base);
+        self.regs.ax = result;
+        self.set_arithmetic_flags(flags);
+        self.clock(60);
+    }
+
+    pub(super) fn cbw(&mut self) {
+        self.regs.ax = self.regs.al() as i8 as u16;
+        self.clock(2);
+    }
+
+    pub(super) fn cwd(&mut self) {
+        self.regs.dx = if self.regs.ax & 0x8000 != 0 {
+            0xFFFF
+        } else {
+            0
+        };
+        self.clock(5);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, DATA_SEGMENT, STACK_SEGMENT};
+    use super::*;
+
+    #[test]
+    fn test_add_reg_reg() {
+        // add al, bl
+        let mut cpu = cpu_with_code(&[0x00, 0xD8]);
+        cpu.regs.ax = 0x00FF;
+        cpu.regs.bx = 0x0001;
+        cpu.regs.flags = Flags::DIRECTION_FLAG;
+        assert_eq!(3, cpu.step());
+        assert_eq!(0, cpu.regs.ax);
+        assert_eq!(
+            Flags::DIRECTION_FLAG
+                | Flags::CARRY_FLAG
+                | Flags::ZERO_FLAG
+                | Flags::PARITY_FLAG
+                | Flags::AUXILIARY_CARRY_FLAG,
+            cpu.regs.flags
+        );
+    }
+
+    #[test]
+    fn test_sub_mem_reg_word() {
+        // sub [bx], cx
+        let mut cpu = cpu_with_code(&[0x29, 0x0F]);
+        cpu.regs.bx = 0x10;
+        cpu.regs.cx = 0x0101;
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x1234);
+        // 16 + 5 EA + 4 + 4 for the two extra byte cycles
+        assert_eq!(29, cpu.step());
+        assert_eq!(
+            0x1133,
+            cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0x10))
+        );
+    }
+
+    #[test]
+    fn test_cmp_does_not_write() {
+        // cmp ax, 5 ; cmp al, 10h
+        let mut cpu = cpu_with_code(&[0x3D, 0x05, 0x00, 0x3C, 0x10]);
+        cpu.regs.ax = 0x0003;
+        cpu.step();
+        assert_eq!(0x0003, cpu.regs.ax);
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+        cpu.step();
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+        assert!(cpu.regs.flag(Flags::SIGN_FLAG));
+    }
+
+    #[test]
+    fn test_adc_sbb_use_carry() {
+        // adc ax, bx ; sbb cx, dx
+        let mut cpu = cpu_with_code(&[0x11, 0xD8, 0x19, 0xD1]);
+        cpu.regs.ax = 1;
+        cpu.regs.bx = 1;
+        cpu.regs.cx = 5;
+        cpu.regs.dx = 1;
+        cpu.regs.flags = Flags::CARRY_FLAG;
+        cpu.step();
+        assert_eq!(3, cpu.regs.ax);
+        cpu.regs.flags = Flags::CARRY_FLAG;
+        cpu.step();
+        assert_eq!(3, cpu.regs.cx);
+    }
+
+    #[test]
+    fn test_group1_sign_extends() {
+        // add word [bx], -2 ; and bl, 0Fh
+        let mut cpu = cpu_with_code(&[0x83, 0x07, 0xFE, 0x80, 0xE3, 0x0F]);
+        cpu.regs.bx = 0x00F3;
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0xF3), 0x0100);
+        cpu.step();
+        assert_eq!(
+            0x00FE,
+            cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0xF3))
+        );
+        assert_eq!(4, cpu.step());
+        assert_eq!(0x0003, cpu.regs.bx);
+    }
+
+    #[test]
+    fn test_inc_dec_keep_carry() {
+        // inc ax ; dec byte [bx]
+        let mut cpu = cpu_with_code(&[0x40, 0xFE, 0x0F]);
+        cpu.regs.ax = 0xFFFF;
+        cpu.regs.flags = Flags::CARRY_FLAG;
+        cpu.step();
+        assert_eq!(0, cpu.regs.ax);
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+        assert!(cpu.regs.flag(Flags::ZERO_FLAG));
+        cpu.step();
+        assert_eq!(0xFF, cpu.mem.read_u8(physical_address(DATA_SEGMENT, 0)));
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+        assert!(cpu.regs.flag(Flags::SIGN_FLAG));
+    }
+
+    #[test]
+    fn test_not_neg() {
+        // not ax ; neg bl
+        let mut cpu = cpu_with_code(&[0xF7, 0xD0, 0xF6, 0xDB]);
+        cpu.regs.ax = 0x00FF;
+        cpu.regs.bx = 0x0001;
+        cpu.step();
+        assert_eq!(0xFF00, cpu.regs.ax);
+        cpu.step();
+        assert_eq!(0x00FF, cpu.regs.bx);
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+    }
+
+    #[test]
+    fn test_cbw_cwd() {
+        let mut cpu = cpu_with_code(&[0x98, 0x99]);
+        cpu.regs.ax = 0x1280;
+        cpu.step();
+        assert_eq!(0xFF80, cpu.regs.ax);
+        cpu.step();
+        assert_eq!(0xFFFF, cpu.regs.dx);
+    }
+
+    #[test]
+    fn test_mul_imul() {
+        // mul bx ; imul byte [0]
+        let mut cpu = cpu_with_code(&[0xF7, 0xE3, 0xF6, 0x2E, 0x00, 0x00]);
+        cpu.regs.ax = 0x1234;
+        cpu.regs.bx = 0x0100;
+        assert_eq!(118, cpu.step());
+        assert_eq!((0x0012, 0x3400), (cpu.regs.dx, cpu.regs.ax));
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG));
+        cpu.mem.write_u8(physical_address(DATA_SEGMENT, 0), 0xFE);
+        cpu.regs.ax = 0x0003;
+        // 80 + 6 + 6 for the disp16 EA
+        assert_eq!(92, cpu.step());
+        assert_eq!(0xFFFA, cpu.regs.ax);
+        assert!(!cpu.regs.flag(Flags::CARRY_FLAG));
+    }
+
+    #[test]
+    fn test_div_idiv() {
+        // div cl ; idiv bx
+        let mut cpu = cpu_with_code(&[0xF6, 0xF1, 0xF7, 0xFB]);
+        cpu.regs.ax = 100;
+        cpu.regs.cx = 7;
+        assert_eq!(80, cpu.step());
+        assert_eq!(0x020E, cpu.regs.ax);
+        cpu.regs.dx = 0xFFFF;
+        cpu.regs.ax = 0xFFF9;
+        cpu.regs.bx = 2;
+        assert_eq!(165, cpu.step());
+        assert_eq!((0xFFFD, 0xFFFF), (cpu.regs.ax, cpu.regs.dx));
+    }
+
+    #[test]
+    fn test_rep_negates_imul_idiv() {
+        // rep imul cl ; rep idiv cl
+        let mut cpu = cpu_with_code(&[0xF3, 0xF6, 0xE9, 0xF3, 0xF6, 0xF9]);
+        cpu.regs.ax = 6;
+        cpu.regs.cx = 7;
+        cpu.step();
+        assert_eq!(0xFFD6, cpu.regs.ax);
+        cpu.regs.ax = 20;
+        cpu.step();
+        assert_eq!(0x06FE, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_aam_aad() {
+        // aam ; aad ; aam 16
+        let mut cpu = cpu_with_code(&[0xD4, 0x0A, 0xD5, 0x0A, 0xD4, 0x10]);
+        cpu.regs.ax = 0x004F;
+        assert_eq!(83, cpu.step());
+        assert_eq!(0x0709, cpu.regs.ax);
+        assert_eq!(60, cpu.step());
+        assert_eq!(0x004F, cpu.regs.ax);
+        cpu.step();
+        assert_eq!(0x040F, cpu.regs.ax);
+        assert!(!cpu.regs.flag(Flags::ZERO_FLAG));
+    }
+
+    #[test]
+    fn test_shift_group() {
+        // shl ax, 1 ; ror bl, cl ; sar word [0], cl
+        let mut cpu = cpu_with_code(&[0xD1, 0xE0, 0xD2, 0xCB, 0xD3, 0x3E, 0x00, 0x00]);
+        cpu.regs.ax = 0x4001;
+        cpu.regs.bx = 0x0003;
+        cpu.regs.cx = 2;
+        assert_eq!(2, cpu.step());
+        assert_eq!(0x8002, cpu.regs.ax);
+        assert!(cpu.regs.flag(Flags::OVERFLOW_FLAG | Flags::SIGN_FLAG));
+        assert_eq!(16, cpu.step());
+        assert_eq!(0xC0, cpu.regs.reg8(3));
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+        cpu.mem.write_u16(physical_address(DATA_SEGMENT, 0), 0x8000);
+        // 20 + 6 EA + 2 * 4 per bit + 8 for the word accesses
+        assert_eq!(42, cpu.step());
+        assert_eq!(0xE000, cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0)));
+    }
+
+    #[test]
+    fn test_undocumented_setmo_salc() {
+        // setmo al, 1 (D0 /6) ; salc ; stc ; salc
+        let mut cpu = cpu_with_code(&[0xD0, 0xF0, 0xD6, 0xF9, 0xD6]);
+        cpu.regs.ax = 0x1234;
+        cpu.regs.flags = Flags::CARRY_FLAG;
+        cpu.step();
+        assert_eq!(0x12FF, cpu.regs.ax);
+        assert_eq!(Flags::PARITY_FLAG | Flags::SIGN_FLAG, cpu.regs.flags);
+        cpu.step();
+        assert_eq!(0x1200, cpu.regs.ax);
+        cpu.step();
+        cpu.step();
+        assert_eq!(0x12FF, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_undocumented_group_aliases() {
+        // test bl, 0Fh as F6 /1 ; push word ax as FF /7 ; call bl as FE /2
+        let mut cpu = cpu_with_code(&[0xF6, 0xCB, 0x0F, 0xFF, 0xF8, 0xFE, 0xD3]);
+        cpu.regs.ax = 0xBEEF;
+        cpu.regs.bx = 0x0030;
+        cpu.step();
+        assert!(cpu.regs.flag(Flags::ZERO_FLAG));
+        cpu.step();
+        assert_eq!(0xFE, cpu.regs.sp);
+        assert_eq!(
+            0xBEEF,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        // a byte operand gets FFh as its upper half
+        cpu.step();
+        assert_eq!(0xFF30, cpu.regs.ip);
+        assert_eq!(7, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFC)));
+    }
+}
@@ -0,0 +1,306 @@
+use super::{physical_address, Cpu};
+
+// the 8088 prefetches up to 4 instruction bytes, the 8086 6
+const MAX_QUEUE_SIZE: usize = 6;
+// T-states of a bus cycle without wait states
+pub const BUS_CYCLE: u32 = 4;
+
+// DRAM refresh through DMA channel 0, triggered by PIT channel 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaRefresh {
+    // clocks from one refresh to the next
+    pub period: u32,
+    // clocks each refresh keeps the CPU off the bus
+    pub duration: u32,
+}
+
+impl DmaRefresh {
+    // the BIOS programs channel 1 to 18 ticks of the 1.19MHz PIT clock, and
+    // each refresh is one 4 clock DMA cycle
+    pub const IBM_PC: DmaRefresh = DmaRefresh {
+        period: 72,
+        duration: 4,
+    };
+}
+
+// The bus interface unit fetches instruction bytes into the queue whenever
+// the bus is not busy with the execution unit's memory and I/O cycles.
+// Instruction timings are the documented ones, which assume the bytes are
+// already queued; fetching from an empty queue stalls the EU on top of them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Biu {
+    queue: [u8; MAX_QUEUE_SIZE],
+    len: usize,
+    // offset in CS of the next byte to prefetch
+    fetch_ip: u16,
+    // T-states the BIU has had toward its next prefetch, negative while the
+    // bus is still taken by the EU's own bus cycles
+    progress: i64,
+    refresh: Option<DmaRefresh>,
+    next_refresh: u64,
+}
+
+impl Cpu {
+    // the bytes fetched ahead of IP
+    pub fn queue(&self) -> &[u8] {
+        &self.biu.queue[..self.biu.len]
+    }
+
+    // replaces the queue with bytes prefetched from CS:IP onwards, for
+    // restoring a saved state
+    pub fn set_queue(&mut self, bytes: &[u8]) {
+        self.flush_queue();
+        let len = bytes.len().min(self.variant.queue_size());
+        self.biu.queue[..len].copy_from_slice(&bytes[..len]);
+        self.biu.len = len;
+        self.biu.fetch_ip = self.regs.ip.wrapping_add(len as u16);
+    }
+
+    // EU time, during which the BIU prefetches
+    pub(crate) fn clock(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        self.biu.progress += cycles as i64;
+        self.prefetch();
+    }
+
+    fn prefetch(&mut self) {
+        if self.biu.len == 0 {
+            self.biu.fetch_ip = self.regs.ip;
+        }
+        loop {
+            let width = self.fetch_width();
+            // with the queue full the BIU sits idle
+            if width == 0 {
+                self.biu.progress = 0;
+                break;
+            }
+            let address = physical_address(self.regs.cs, self.biu.fetch_ip);
+            let cost = (BUS_CYCLE + self.mem.wait_states(address)) as i64;
+            if self.biu.progress < cost {
+                break;
+            }
+            self.fill_queue(width);
+            self.biu.progress -= cost;
+        }
+    }
+
+    // the bytes the next prefetch brings in, 0 while there is no room. The
+    // 8086 fetches aligned words and waits for room for a whole one.
+    fn fetch_width(&self) -> usize {
+        let free = self.variant.queue_size() - self.biu.len;
+        if !self.variant.wide_bus() {
+            free.min(1)
+        } else if free < 2 {
+            0
+        } else {
+            2 - (self.biu.fetch_ip & 1) as usize
+        }
+    }
+
+    fn fill_queue(&mut self, width: usize) {
+        let biu = &mut self.biu;
+        for _ in 0..width {
+            biu.queue[biu.len] = self
+                .mem
+                .read_u8(physical_address(self.regs.cs, biu.fetch_ip));
+            biu.len += 1;
+            biu.fetch_ip = biu.fetch_ip.wrapping_add(1);
+        }
+    }
+
+    // an EU memory or I/O byte cycle, which holds off prefetching. Wait
+    // states stretch the instruction as well as the bus cycle.
+    pub(crate) fn bus_cycle(&mut self, wait_states: u32) {
+        self.biu.progress -= BUS_CYCLE as i64;
+        self.cycles += wait_states as u64;
+    }
+
+    // another bus master, such as the DMA controller, takes the bus. The CPU
+    // is held for the whole time and the BIU makes no progress.
+    pub fn hold_bus(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+    }
+
+    pub fn set_dma_refresh(&mut self, refresh: Option<DmaRefresh>) {
+        self.biu.refresh = refresh;
+        if let Some(refresh) = refresh {
+            self.biu.next_refresh = self.cycles + refresh.period as u64;
+        }
+    }
+
+    // steals the bus for every refresh that came due
+    pub(crate) fn dma_refresh(&mut self) {
+        let refresh = match self.biu.refresh {
+            Some(refresh) => refresh,
+            None => return,
+        };
+        while self.cycles >= self.biu.next_refresh {
+            self.hold_bus(refresh.duration);
+            self.biu.next_refresh += refresh.period as u64;
+        }
+    }
+
+    // a jump discards whatever was prefetched and restarts at the new CS:IP.
+    // Loading CS alone with MOV or POP does not, the queued bytes from the
+    // old segment still run.
+    pub(crate) fn flush_queue(&mut self) {
+        self.biu.len = 0;
+        self.biu.fetch_ip = self.regs.ip;
+        self.biu.progress = 0;
+    }
+
+    pub(crate) fn fetch_u8(&mut self) -> u8 {
+        // IP moved by something other than a jump, e.g. a debugger
+        if self.biu.len > 0 && self.biu.fetch_ip.wrapping_sub(self.biu.len as u16) != self.regs.ip {
+            self.flush_queue();
+        }
+        if self.biu.len == 0 {
+            // the EU waits for the bus cycle in progress and then the fetch
+            let address = physical_address(self.regs.cs, self.regs.ip);
+            let cost = (BUS_CYCLE + self.mem.wait_states(address)) as i64;
+            let stall = (cost - self.biu.progress).max(0);
+            self.cycles += stall as u64;
+            self.biu.progress = 0;
+            self.biu.fetch_ip = self.regs.ip;
+            self.fill_queue(self.fetch_width());
+        }
+        let biu = &mut self.biu;
+        let byte = biu.queue[0];
+        biu.queue.copy_within(1.., 0);
+        biu.len -= 1;
+        self.regs.ip = self.regs.ip.wrapping_add(1);
+        byte
+    }
+
+    pub(crate) fn fetch_u16(&mut self) -> u16 {
+        let low = self.fetch_u8();
+        let high = self.fetch_u8();
+        u16::from_le_bytes([low, high])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, cpu_with_variant, CODE_SEGMENT};
+    use super::super::Variant;
+    use super::DmaRefresh;
+
+    #[test]
+    fn test_empty_queue_stalls() {
+        // nop ; nop after a jump
+        let mut cpu = cpu_with_code(&[0x90, 0x90]);
+        cpu.flush_queue();
+        // 4 to fetch the opcode, then 3 clocks during which nothing completes
+        assert_eq!(4 + 3, cpu.step());
+        assert!(cpu.queue().is_empty());
+        // the BIU is 3 clocks into fetching the second nop
+        assert_eq!(1 + 3, cpu.step());
+    }
+
+    #[test]
+    fn test_queue_fills_during_execution() {
+        // mul bl is long enough to fill the queue behind it
+        let mut cpu = cpu_with_code(&[0xF6, 0xE3, 0x90, 0x91, 0x92, 0x93, 0x94]);
+        cpu.step();
+        assert_eq!(&[0x90, 0x91, 0x92, 0x93], cpu.queue());
+        assert_eq!(3, cpu.step());
+        assert_eq!(&[0x91, 0x92, 0x93], cpu.queue());
+    }
+
+    #[test]
+    fn test_jump_flushes_queue() {
+        // jmp short +2 ; nop ; nop ; inc ax
+        let mut cpu = cpu_with_code(&[0xEB, 0x02, 0x90, 0x90, 0x40]);
+        cpu.step();
+        assert_eq!(4, cpu.regs.ip);
+        assert_eq!(&[0x40], &cpu.queue()[..1]);
+        cpu.step();
+        assert_eq!(1, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_self_modifying_code_sees_stale_bytes() {
+        // stosb over the nop right behind it, which is already queued
+        let mut cpu = cpu_with_code(&[0xAA, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90]);
+        cpu.regs.es = CODE_SEGMENT;
+        cpu.regs.di = 1;
+        cpu.regs.ax = 0x40;
+        cpu.step();
+        assert_eq!(0x40, cpu.mem.read_u8(physical_address(CODE_SEGMENT, 1)));
+        cpu.step();
+        assert_eq!(0x40, cpu.regs.ax);
+
+        // past the queue the new byte is what runs
+        let mut cpu = cpu_with_code(&[0xAA, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90]);
+        cpu.regs.es = CODE_SEGMENT;
+        cpu.regs.di = 6;
+        cpu.regs.ax = 0x40;
+        for _ in 0..7 {
+            cpu.step();
+        }
+        assert_eq!(0x41, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_pop_cs_keeps_queue() {
+        // push ax ; pop cs, the queued bytes after it still come from 1000h
+        let mut cpu = cpu_with_code(&[0x50, 0x0F, 0x40, 0x40]);
+        cpu.regs.ax = 0x2000;
+        cpu.step();
+        cpu.step();
+        assert_eq!(0x2000, cpu.regs.cs);
+        cpu.step();
+        assert_eq!(0x2001, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_wait_states() {
+        // mov al, [0010h] ; nop ; nop ; nop
+        let mut cpu = cpu_with_code(&[0xA0, 0x10, 0x00, 0x90, 0x90, 0x90]);
+        cpu.mem.set_wait_states(0, 1);
+        // the queued bytes are free, the data byte costs one more clock
+        assert_eq!(10 + 1, cpu.step());
+        // 10 clocks fetched two bytes at 5 clocks each
+        assert_eq!(&[0x90, 0x90], cpu.queue());
+    }
+
+    #[test]
+    fn test_dma_refresh_steals_cycles() {
+        // mul bl ; nop
+        let mut cpu = cpu_with_code(&[0xF6, 0xE3, 0x90]);
+        cpu.set_dma_refresh(Some(DmaRefresh::IBM_PC));
+        assert_eq!(70, cpu.step());
+        assert_eq!(3 + 4, cpu.step());
+        // the next refresh is due 72 clocks after the first
+        assert_eq!(144, cpu.biu.next_refresh);
+    }
+
+    #[test]
+    fn test_8086_queue_holds_six_bytes() {
+        // mul bl ; nops
+        let code = [0xF6, 0xE3, 0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96];
+        let mut cpu = cpu_with_variant(&code, Variant::I8086);
+        cpu.step();
+        assert_eq!(&[0x90, 0x91, 0x92, 0x93, 0x94, 0x95], cpu.queue());
+    }
+
+    #[test]
+    fn test_8086_fetches_words() {
+        let mut cpu = cpu_with_variant(&[0x90; 8], Variant::I8086);
+        cpu.flush_queue();
+        // one fetch brings in both nops
+        assert_eq!(4 + 3, cpu.step());
+        assert_eq!(&[0x90], cpu.queue());
+        assert_eq!(3, cpu.step());
+
+        // from an odd address the first fetch is the odd byte alone
+        cpu.regs.ip = 1;
+        cpu.flush_queue();
+        assert_eq!(4 + 3, cpu.step());
+        assert!(cpu.queue().is_empty());
+        // the BIU is 3 clocks into the word at 2
+        assert_eq!(1 + 3, cpu.step());
+        assert_eq!(&[0x90], cpu.queue());
+    }
+}
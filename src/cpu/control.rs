@@ -0,0 +1,420 @@
+use super::{
+    modrm::{ModRm, Operand},
+    Cpu, SegReg,
+};
+use crate::alu::Flags;
+
+impl Cpu {
+    // the condition codes of Jcc, in opcode order JO JNO JB JAE JE JNE JBE JA
+    // JS JNS JP JNP JL JGE JLE JG
+    pub(super) fn condition(&self, code: u8) -> bool {
+        let flag = |f| self.regs.flag(f);
+        let less = flag(Flags::SIGN_FLAG) != flag(Flags::OVERFLOW_FLAG);
+        let result = match (code >> 1) & 7 {
+            0 => flag(Flags::OVERFLOW_FLAG),
+            1 => flag(Flags::CARRY_FLAG),
+            2 => flag(Flags::ZERO_FLAG),
+            3 => flag(Flags::CARRY_FLAG) || flag(Flags::ZERO_FLAG),
+            4 => flag(Flags::SIGN_FLAG),
+            5 => flag(Flags::PARITY_FLAG),
+            6 => less,
+            _ => less || flag(Flags::ZERO_FLAG),
+        };
+        // odd codes are the negated forms
+        result != (code & 1 == 1)
+    }
+
+    pub(crate) fn jump_near(&mut self, ip: u16) {
+        self.regs.ip = ip;
+        self.flush_queue();
+    }
+
+    pub(crate) fn jump_far(&mut self, cs: u16, ip: u16) {
+        self.regs.cs = cs;
+        self.jump_near(ip);
+    }
+
+    fn jump_relative(&mut self, displacement: u16) {
+        self.jump_near(self.regs.ip.wrapping_add(displacement));
+    }
+
+    // 70-7F: Jcc rel8
+    pub(super) fn jcc(&mut self, opcode: u8) {
+        let displacement = self.fetch_u8() as i8 as u16;
+        if self.condition(opcode & 0x0F) {
+            self.jump_relative(displacement);
+            self.clock(16);
+        } else {
+            self.clock(4);
+        }
+    }
+
+    // E0-E3: LOOPNE LOOPE LOOP JCXZ
+    pub(super) fn loop_op(&mut self, opcode: u8) {
+        let displacement = self.fetch_u8() as i8 as u16;
+        if opcode != 0xE3 {
+            self.regs.cx = self.regs.cx.wrapping_sub(1);
+        }
+        let zero = self.regs.flag(Flags::ZERO_FLAG);
+        let cx = self.regs.cx;
+        let (taken, clocks_taken, clocks_not_taken) = match opcode {
+            0xE0 => (cx != 0 && !zero, 19, 5),
+            0xE1 => (cx != 0 && zero, 18, 6),
+            0xE2 => (cx != 0, 17, 5),
+            _ => (cx == 0, 18, 6),
+        };
+        if taken {
+            self.jump_relative(displacement);
+            self.clock(clocks_taken);
+        } else {
+            self.clock(clocks_not_taken);
+        }
+    }
+
+    // EB: JMP rel8
+    pub(super) fn jmp_short(&mut self) {
+        let displacement = self.fetch_u8() as i8 as u16;
+        self.jump_relative(displacement);
+        self.clock(15);
+    }
+
+    // E9: JMP rel16
+    pub(super) fn jmp_near(&mut self) {
+        let displacement = self.fetch_u16();
+        self.jump_relative(displacement);
+        self.clock(15);
+    }
+
+    // EA: JMP ptr16:16
+    pub(super) fn jmp_far(&mut self) {
+        let ip = self.fetch_u16();
+        let cs = self.fetch_u16();
+        self.jump_far(cs, ip);
+        self.clock(15);
+    }
+
+    // E8: CALL rel16
+    pub(super) fn call_near(&mut self) {
+        let displacement = self.fetch_u16();
+        self.push16(self.regs.ip);
+        self.jump_relative(displacement);
+        self.clock(19);
+    }
+
+    // 9A: CALL ptr16:16
+    pub(super) fn call_far(&mut self) {
+        let ip = self.fetch_u16();
+        let cs = self.fetch_u16();
+        self.push16(self.regs.cs);
+        self.push16(self.regs.ip);
+        self.jump_far(cs, ip);
+        self.clock(28);
+    }
+
+    // C2/C3: RET [imm16], C0/C1 are aliases
+    pub(super) fn ret_near(&mut self, opcode: u8) {
+        let release = if opcode & 1 == 0 { self.fetch_u16() } else { 0 };
+        let ip = self.pop16();
+        self.regs.sp = self.regs.sp.wrapping_add(release);
+        self.jump_near(ip);
+        self.clock(if opcode & 1 == 0 { 12 } else { 8 });
+    }
+
+    // CA/CB: RETF [imm16], C8/C9 are aliases
+    pub(super) fn ret_far(&mut self, opcode: u8) {
+        let release = if opcode & 1 == 0 { self.fetch_u16() } else { 0 };
+        let ip = self.pop16();
+        let cs = self.pop16();
+        self.regs.sp = self.regs.sp.wrapping_add(release);
+        self.jump_far(cs, ip);
+        self.clock(if opcode & 1 == 0 { 17 } else { 18 });
+    }
+
+    // a far pointer operand of FF /3 and /5. A register operand makes the
+    // 8088 read from the last effective address instead.
+    fn far_pointer(&mut self, operand: Operand, word: bool) -> (u16, u16) {
+        let (seg, offset) = match operand {
+            Operand::Memory(seg, offset) => (seg, offset),
+            Operand::Register(_) => (self.segment_or(SegReg::Ds), self.last_ea),
+        };
+        let ip = self.read_operand(Operand::Memory(seg, offset), word);
+        let cs = self.read_operand(Operand::Memory(seg, offset.wrapping_add(2)), word);
+        (cs, ip)
+    }
+
+    // FF /2: CALL r/m16
+    pub(super) fn call_near_indirect(&mut self, modrm: ModRm, word: bool) {
+        let ip = self.read_operand(modrm.operand, word);
+        self.push16(self.regs.ip);
+        self.jump_near(ip);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 16,
+            Operand::Memory(..) => 21,
+        });
+    }
+
+    // FF /3: CALL m16:16
+    pub(super) fn call_far_indirect(&mut self, modrm: ModRm, word: bool) {
+        let (cs, ip) = self.far_pointer(modrm.operand, word);
+        self.push16(self.regs.cs);
+        self.push16(self.regs.ip);
+        self.jump_far(cs, ip);
+        self.clock(37);
+    }
+
+    // FF /4: JMP r/m16
+    pub(super) fn jmp_near_indirect(&mut self, modrm: ModRm, word: bool) {
+        let ip = self.read_operand(modrm.operand, word);
+        self.jump_near(ip);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 11,
+            Operand::Memory(..) => 18,
+        });
+    }
+
+    // FF /5: JMP m16:16
+    pub(super) fn jmp_far_indirect(&mut self, modrm: ModRm, word: bool) {
+        let (cs, ip) = self.far_pointer(modrm.operand, word);
+        self.jump_far(cs, ip);
+        self.clock(24);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, CODE_SEGMENT, DATA_SEGMENT, STACK_SEGMENT};
+    use super::*;
+
+    struct Case {
+        name: &'static str,
+        code: Vec<u8>,
+        flags: Flags,
+        cx: u16,
+        ip: u16,
+        clocks: u32,
+    }
+
+    // every Jcc once with its condition met and once without
+    fn jcc_cases() -> Vec<Case> {
+        let conditions = [
+            (Flags::OVERFLOW_FLAG, Flags::empty()),
+            (Flags::CARRY_FLAG, Flags::empty()),
+            (Flags::ZERO_FLAG, Flags::empty()),
+            (Flags::ZERO_FLAG, Flags::empty()),
+            (Flags::SIGN_FLAG, Flags::empty()),
+            (Flags::PARITY_FLAG, Flags::empty()),
+            (Flags::SIGN_FLAG, Flags::SIGN_FLAG | Flags::OVERFLOW_FLAG),
+            (Flags::ZERO_FLAG, Flags::OVERFLOW_FLAG | Flags::SIGN_FLAG),
+        ];
+        let mut cases = Vec::new();
+        for opcode in 0x70..=0x7Fu8 {
+            let (met, unmet) = conditions[(opcode as usize >> 1) & 7];
+            let (met, unmet) = if opcode & 1 == 0 {
+                (met, unmet)
+            } else {
+                (unmet, met)
+            };
+            cases.push(Case {
+                name: "taken",
+                code: vec![opcode, 0x10],
+                flags: met,
+                cx: 0,
+                ip: 0x12,
+                clocks: 16,
+            });
+            cases.push(Case {
+                name: "not taken",
+                code: vec![opcode, 0x10],
+                flags: unmet,
+                cx: 0,
+                ip: 2,
+                clocks: 4,
+            });
+        }
+        cases
+    }
+
+    fn loop_cases() -> Vec<Case> {
+        let case = |name, code: &[u8], flags, cx, ip, clocks| Case {
+            name,
+            code: code.to_vec(),
+            flags,
+            cx,
+            ip,
+            clocks,
+        };
+        vec![
+            case("loop taken", &[0xE2, 0xFE], Flags::empty(), 2, 0, 17),
+            case("loop not taken", &[0xE2, 0xFE], Flags::empty(), 1, 2, 5),
+            case("loop wraps cx", &[0xE2, 0xFE], Flags::empty(), 0, 0, 17),
+            case("loope taken", &[0xE1, 0x04], Flags::ZERO_FLAG, 2, 6, 18),
+            case("loope not taken zf", &[0xE1, 0x04], Flags::empty(), 2, 2, 6),
+            case(
+                "loope not taken cx",
+                &[0xE1, 0x04],
+                Flags::ZERO_FLAG,
+                1,
+                2,
+                6,
+            ),
+            case("loopne taken", &[0xE0, 0x04], Flags::empty(), 2, 6, 19),
+            case("loopne not taken", &[0xE0, 0x04], Flags::ZERO_FLAG, 2, 2, 5),
+            case("jcxz taken", &[0xE3, 0x80], Flags::empty(), 0, 0xFF82, 18),
+            case("jcxz not taken", &[0xE3, 0x80], Flags::empty(), 1, 2, 6),
+        ]
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        for case in jcc_cases().iter().chain(loop_cases().iter()) {
+            let mut cpu = cpu_with_code(&case.code);
+            cpu.regs.flags = case.flags;
+            cpu.regs.cx = case.cx;
+            let clocks = cpu.step();
+            let opcode = case.code[0];
+            assert_eq!(case.ip, cpu.regs.ip, "{:02X}h {}", opcode, case.name);
+            assert_eq!(CODE_SEGMENT, cpu.regs.cs, "{:02X}h {}", opcode, case.name);
+            assert_eq!(case.clocks, clocks, "{:02X}h {}", opcode, case.name);
+        }
+    }
+
+    #[test]
+    fn test_loop_leaves_flags() {
+        let mut cpu = cpu_with_code(&[0xE2, 0x00]);
+        cpu.regs.cx = 1;
+        cpu.step();
+        assert_eq!(0, cpu.regs.cx);
+        assert_eq!(Flags::empty(), cpu.regs.flags);
+    }
+
+    #[test]
+    fn test_jmp() {
+        // jmp short +3 ; jmp near +100h ; jmp far 2000:0010
+        let mut cpu = cpu_with_code(&[0xEB, 0x03, 0, 0, 0, 0xE9, 0x00, 0x01]);
+        assert_eq!(15, cpu.step());
+        assert_eq!(5, cpu.regs.ip);
+        assert_eq!(15, cpu.step());
+        assert_eq!(0x108, cpu.regs.ip);
+
+        let mut cpu = cpu_with_code(&[0xEA, 0x10, 0x00, 0x00, 0x20]);
+        assert_eq!(15 + 4, cpu.step());
+        assert_eq!((0x2000, 0x0010), (cpu.regs.cs, cpu.regs.ip));
+    }
+
+    #[test]
+    fn test_call_ret_near() {
+        // call +3 ; ... ; ret
+        let mut cpu = cpu_with_code(&[0xE8, 0x03, 0x00, 0, 0, 0, 0xC3]);
+        assert_eq!(23, cpu.step());
+        assert_eq!(6, cpu.regs.ip);
+        assert_eq!(0xFE, cpu.regs.sp);
+        assert_eq!(3, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE)));
+        assert_eq!(12, cpu.step());
+        assert_eq!(3, cpu.regs.ip);
+        assert_eq!(0x100, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_ret_imm_releases_parameters() {
+        // ret 4 ; retf 2
+        let mut cpu = cpu_with_code(&[0xC2, 0x04, 0x00]);
+        cpu.regs.sp = 0xF0;
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0xF0), 0x1234);
+        assert_eq!(16, cpu.step());
+        assert_eq!(0x1234, cpu.regs.ip);
+        assert_eq!(0xF6, cpu.regs.sp);
+
+        let mut cpu = cpu_with_code(&[0xCA, 0x02, 0x00]);
+        cpu.regs.sp = 0xF0;
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0xF0), 0x0010);
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0xF2), 0x5000);
+        assert_eq!(25, cpu.step());
+        assert_eq!((0x5000, 0x0010), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0xF6, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_call_far_retf() {
+        // call far 2000:0004 ; retf
+        let mut cpu = cpu_with_code(&[0x9A, 0x04, 0x00, 0x00, 0x20]);
+        cpu.mem.write_u8(physical_address(0x2000, 4), 0xCB);
+        // the fifth byte is not in the queue yet
+        assert_eq!(36 + 4, cpu.step());
+        assert_eq!((0x2000, 4), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0xFC, cpu.regs.sp);
+        assert_eq!(
+            CODE_SEGMENT,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        assert_eq!(5, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFC)));
+        assert_eq!(26, cpu.step());
+        assert_eq!((CODE_SEGMENT, 5), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0x100, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_indirect_near() {
+        // call bx ; jmp [si]
+        let mut cpu = cpu_with_code(&[0xFF, 0xD3]);
+        cpu.regs.bx = 0x40;
+        assert_eq!(20, cpu.step());
+        assert_eq!(0x40, cpu.regs.ip);
+        assert_eq!(2, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE)));
+
+        let mut cpu = cpu_with_code(&[0xFF, 0x24]);
+        cpu.regs.si = 0x10;
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x0777);
+        // 18 + 5 EA + 4
+        assert_eq!(27, cpu.step());
+        assert_eq!(0x0777, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_indirect_far() {
+        // call far [bx] ; jmp far es:[bx]
+        let mut cpu = cpu_with_code(&[0xFF, 0x1F]);
+        cpu.regs.bx = 0x20;
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x20), 0x0100);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x22), 0x6000);
+        // 37 + 5 EA + 2 * 4 reading + 2 * 4 pushing
+        assert_eq!(58, cpu.step());
+        assert_eq!((0x6000, 0x0100), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(2, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFC)));
+
+        let mut cpu = cpu_with_code(&[0x26, 0xFF, 0x2F]);
+        cpu.regs.es = 0x7000;
+        cpu.mem.write_u16(physical_address(0x7000, 0), 0x0200);
+        cpu.mem.write_u16(physical_address(0x7000, 2), 0x8000);
+        cpu.step();
+        assert_eq!((0x8000, 0x0200), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0x100, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_undocumented_aliases() {
+        // 64h is JZ ; C0h is RET imm16 ; C9h is RETF
+        let mut cpu = cpu_with_code(&[0x64, 0x02, 0, 0, 0xC0, 0x02, 0x00]);
+        cpu.regs.flags = Flags::ZERO_FLAG;
+        cpu.regs.sp = 0xF0;
+        cpu.mem.write_u8(physical_address(CODE_SEGMENT, 0x10), 0xC9);
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0xF0), 0x0010);
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0xF4), 0x0020);
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0xF6), 0x5000);
+        assert_eq!(16, cpu.step());
+        assert_eq!(4, cpu.regs.ip);
+        assert_eq!(16, cpu.step());
+        assert_eq!((0x10, 0xF4), (cpu.regs.ip, cpu.regs.sp));
+        cpu.step();
+        assert_eq!((0x5000, 0x20), (cpu.regs.cs, cpu.regs.ip));
+    }
+}
@@ -0,0 +1,194 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{modrm::Operand, physical_address, Cpu};
+use crate::memory::MemoryBus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeOperand {
+    // ST(i) for the 8087
+    Register(u8),
+    // physical address of the operand the CPU read
+    Memory(u32),
+}
+
+// an ESC instruction as the coprocessor decodes it from the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Escape {
+    // D8-DF
+    pub opcode: u8,
+    pub modrm: u8,
+    pub operand: EscapeOperand,
+    // physical address of the instruction, prefixes included
+    pub instruction: u32,
+}
+
+/// A coprocessor such as the 8087 on the local bus. It follows the CPU's
+/// instruction stream and runs the ESC instructions, using the memory bus
+/// itself for operands past the first word, and holds the CPU's TEST pin
+/// while busy.
+pub trait Coprocessor {
+    fn escape(&mut self, escape: Escape, mem: &mut MemoryBus);
+    // the BUSY output, wired to TEST
+    fn busy(&self) -> bool;
+    // clock cycles passed on the CPU since the last call
+    fn run(&mut self, cycles: u32);
+    // the INT output where the board takes it to NMI, as the XT does
+    fn nmi_request(&self) -> bool;
+}
+
+// lets the board keep a handle on the coprocessor it gave the CPU
+impl<T: Coprocessor + ?Sized> Coprocessor for Rc<RefCell<T>> {
+    fn escape(&mut self, escape: Escape, mem: &mut MemoryBus) {
+        self.borrow_mut().escape(escape, mem);
+    }
+
+    fn busy(&self) -> bool {
+        self.borrow().busy()
+    }
+
+    fn run(&mut self, cycles: u32) {
+        self.borrow_mut().run(cycles);
+    }
+
+    fn nmi_request(&self) -> bool {
+        self.borrow().nmi_request()
+    }
+}
+
+impl Cpu {
+    // without a coprocessor ESC instructions only do their bus cycle, and
+    // software probing with FNINIT/FNSTSW finds its memory untouched
+    pub fn set_coprocessor(&mut self, coprocessor: Option<Box<dyn Coprocessor>>) {
+        self.coprocessor = coprocessor;
+    }
+
+    // TEST is active low, so it reads as ready with nothing connected
+    fn test_pin(&self) -> bool {
+        match &self.coprocessor {
+            Some(coprocessor) => !coprocessor.busy(),
+            None => true,
+        }
+    }
+
+    // 9B. Each poll of TEST that finds the coprocessor busy is a step of its
+    // own, repeated like a string instruction, so interrupts are serviced
+    // while waiting and return to the WAIT.
+    pub(super) fn wait(&mut self) {
+        if self.test_pin() {
+            self.clock(3);
+        } else {
+            self.repeat_pending = Some(0x9B);
+            self.clock(5);
+        }
+    }
+
+    // D8-DF. A memory operand is read once for the coprocessor to latch its
+    // address and first word from the bus.
+    pub(super) fn escape(&mut self, opcode: u8) {
+        let byte = self.fetch_u8();
+        let modrm = self.modrm_operand(byte);
+        let operand = match modrm.operand {
+            Operand::Register(index) => {
+                self.clock(2);
+                EscapeOperand::Register(index)
+            }
+            Operand::Memory(seg, offset) => {
+                self.read_u16(seg, offset);
+                self.clock(8);
+                EscapeOperand::Memory(physical_address(self.regs.seg(seg), offset))
+            }
+        };
+        if let Some(coprocessor) = &mut self.coprocessor {
+            let escape = Escape {
+                opcode,
+                modrm: byte,
+                operand,
+                instruction: physical_address(self.regs.cs, self.prefixes.start_ip),
+            };
+            coprocessor.escape(escape, &mut self.mem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cpu_with_code, DATA_SEGMENT};
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        escapes: Vec<Escape>,
+        busy_for: u32,
+    }
+
+    impl Coprocessor for Recorder {
+        fn escape(&mut self, escape: Escape, _mem: &mut MemoryBus) {
+            self.escapes.push(escape);
+            self.busy_for = 20;
+        }
+
+        fn busy(&self) -> bool {
+            self.busy_for > 0
+        }
+
+        fn run(&mut self, cycles: u32) {
+            self.busy_for = self.busy_for.saturating_sub(cycles);
+        }
+
+        fn nmi_request(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_escape_without_coprocessor() {
+        // fninit ; fnstsw [10h]
+        let mut cpu = cpu_with_code(&[0xDB, 0xE3, 0xDD, 0x3E, 0x10, 0x00]);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x5A5A);
+        assert_eq!(2, cpu.step());
+        // 8 + 6 EA + 4 for the word read
+        // the displacement is fetched after the queue runs dry
+        assert_eq!(18 + 6, cpu.step());
+        assert_eq!(
+            0x5A5A,
+            cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0x10))
+        );
+    }
+
+    #[test]
+    fn test_escape_and_wait() {
+        // fld dword [bx] ; fadd st, st(1) ; wait ; nop
+        let mut cpu = cpu_with_code(&[0xD9, 0x07, 0xD8, 0xC1, 0x9B, 0x90]);
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        cpu.set_coprocessor(Some(Box::new(recorder.clone())));
+        cpu.regs.bx = 0x20;
+        cpu.step();
+        cpu.step();
+        assert_eq!(
+            vec![
+                Escape {
+                    opcode: 0xD9,
+                    modrm: 0x07,
+                    operand: EscapeOperand::Memory(physical_address(DATA_SEGMENT, 0x20)),
+                    instruction: physical_address(cpu.regs.cs, 0),
+                },
+                Escape {
+                    opcode: 0xD8,
+                    modrm: 0xC1,
+                    operand: EscapeOperand::Register(1),
+                    instruction: physical_address(cpu.regs.cs, 2),
+                },
+            ],
+            recorder.borrow().escapes
+        );
+        // busy for 20 clocks after the second escape
+        let mut polls = 0;
+        while cpu.step() == 5 {
+            assert!(cpu.is_repeating());
+            polls += 1;
+        }
+        assert_eq!(4, polls);
+        assert_eq!(5, cpu.regs.ip);
+    }
+}
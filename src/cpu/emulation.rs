@@ -0,0 +1,459 @@
+// The 8080 emulation mode of the V20. The 8080 registers live in the low
+// halves of the native ones: A is AL, BC DE HL are CX DX BX and SP is BP,
+// and its flags byte is the low byte of FLAGS. Code is fetched from CS:IP,
+// data and the stack are in the segment DS points to.
+
+use super::{transfer::AH_FLAGS, Cpu, Prefixes, SegReg};
+use crate::alu::{self, Flags, ShiftOperation};
+
+// B C D E H L (M) A as native 8 bit register indexes
+const REGISTERS: [u8; 8] = [5, 1, 6, 2, 7, 3, 0xFF, 0];
+const M: u8 = 6;
+
+// ADD ADC SUB SBB ANA XRA ORA CMP as native ALU operations
+const ALU_OPERATIONS: [u8; 8] = [0, 2, 5, 3, 4, 6, 1, 7];
+
+impl Cpu {
+    fn read_8080(&mut self, index: u8) -> u8 {
+        if index == M {
+            self.read_u8(SegReg::Ds, self.regs.bx)
+        } else {
+            self.regs.reg8(REGISTERS[index as usize])
+        }
+    }
+
+    fn write_8080(&mut self, index: u8, value: u8) {
+        if index == M {
+            self.write_u8(SegReg::Ds, self.regs.bx, value);
+        } else {
+            self.regs.set_reg8(REGISTERS[index as usize], value);
+        }
+    }
+
+    // BC DE HL SP
+    fn pair(&self, index: u8) -> u16 {
+        match index & 3 {
+            0 => self.regs.cx,
+            1 => self.regs.dx,
+            2 => self.regs.bx,
+            _ => self.regs.bp,
+        }
+    }
+
+    fn set_pair(&mut self, index: u8, value: u16) {
+        match index & 3 {
+            0 => self.regs.cx = value,
+            1 => self.regs.dx = value,
+            2 => self.regs.bx = value,
+            _ => self.regs.bp = value,
+        }
+    }
+
+    fn push_8080(&mut self, value: u16) {
+        self.regs.bp = self.regs.bp.wrapping_sub(2);
+        self.write_u16(SegReg::Ds, self.regs.bp, value);
+    }
+
+    fn pop_8080(&mut self) -> u16 {
+        let value = self.read_u16(SegReg::Ds, self.regs.bp);
+        self.regs.bp = self.regs.bp.wrapping_add(2);
+        value
+    }
+
+    // NZ Z NC C PO PE P M
+    fn condition_8080(&self, code: u8) -> bool {
+        let flag = match (code >> 1) & 3 {
+            0 => Flags::ZERO_FLAG,
+            1 => Flags::CARRY_FLAG,
+            2 => Flags::PARITY_FLAG,
+            _ => Flags::SIGN_FLAG,
+        };
+        self.regs.flag(flag) == (code & 1 == 1)
+    }
+
+    fn call_8080(&mut self, ip: u16) {
+        self.push_8080(self.regs.ip);
+        self.jump_near(ip);
+    }
+
+    // one 8080 instruction, taking its 8080 state count in clocks
+    pub(super) fn emulated_instruction(&mut self) {
+        self.prefixes = Prefixes {
+            restart_ip: self.regs.ip,
+            start_ip: self.regs.ip,
+            ..Prefixes::default()
+        };
+        let opcode = self.fetch_u8();
+        let r = (opcode >> 3) & 7;
+        let clocks = match opcode {
+            // HLT where MOV M,M would be
+            0x76 => {
+                self.halted = true;
+                7
+            }
+            0x40..=0x7F => {
+                let value = self.read_8080(opcode & 7);
+                self.write_8080(r, value);
+                if r == M || opcode & 7 == M {
+                    7
+                } else {
+                    5
+                }
+            }
+            0x80..=0xBF => {
+                let value = self.read_8080(opcode & 7);
+                let result = self.alu8(ALU_OPERATIONS[r as usize], self.regs.al(), value);
+                self.regs.set_al(result);
+                if opcode & 7 == M {
+                    7
+                } else {
+                    4
+                }
+            }
+            _ if opcode & 0xC7 == 0x00 => 4,
+            _ if opcode & 0xCF == 0x01 => {
+                let value = self.fetch_u16();
+                self.set_pair(opcode >> 4, value);
+                10
+            }
+            // STAX B, STAX D
+            0x02 | 0x12 => {
+                self.write_u8(SegReg::Ds, self.pair(opcode >> 4), self.regs.al());
+                7
+            }
+            // LDAX B, LDAX D
+            0x0A | 0x1A => {
+                let value = self.read_u8(SegReg::Ds, self.pair(opcode >> 4));
+                self.regs.set_al(value);
+                7
+            }
+            // SHLD, LHLD
+            0x22 => {
+                let address = self.fetch_u16();
+                self.write_u16(SegReg::Ds, address, self.regs.bx);
+                16
+            }
+            0x2A => {
+                let address = self.fetch_u16();
+                self.regs.bx = self.read_u16(SegReg::Ds, address);
+                16
+            }
+            // STA, LDA
+            0x32 => {
+                let address = self.fetch_u16();
+                self.write_u8(SegReg::Ds, address, self.regs.al());
+                13
+            }
+            0x3A => {
+                let address = self.fetch_u16();
+                let value = self.read_u8(SegReg::Ds, address);
+                self.regs.set_al(value);
+                13
+            }
+            // INX, DCX
+            _ if opcode & 0xC7 == 0x03 => {
+                let delta = if opcode & 8 == 0 { 1 } else { 0xFFFF };
+                let index = opcode >> 4;
+                self.set_pair(index, self.pair(index).wrapping_add(delta));
+                5
+            }
+            // DAD, which only sets CY
+            _ if opcode & 0xCF == 0x09 => {
+                let (result, carry) = self.regs.bx.overflowing_add(self.pair(opcode >> 4));
+                self.regs.bx = result;
+                self.regs.set_flag(Flags::CARRY_FLAG, carry);
+                10
+            }
+            // INR, DCR
+            _ if opcode & 0xC6 == 0x04 => {
+                let value = self.read_8080(r);
+                let (result, flags) = if opcode & 1 == 0 {
+                    alu::inc8(value, self.regs.flags)
+                } else {
+                    alu::dec8(value, self.regs.flags)
+                };
+                self.write_8080(r, result);
+                self.set_arithmetic_flags(flags);
+                if r == M {
+                    10
+                } else {
+                    5
+                }
+            }
+            // MVI
+            _ if opcode & 0xC7 == 0x06 => {
+                let value = self.fetch_u8();
+                self.write_8080(r, value);
+                if r == M {
+                    10
+                } else {
+                    7
+                }
+            }
+            // RLC RRC RAL RAR, which only set CY
+            0x07 | 0x0F | 0x17 | 0x1F => {
+                let op = ShiftOperation::from_index(r);
+                let (result, flags) = alu::shift8(op, self.regs.al(), 1, self.regs.flags);
+                self.regs.set_al(result);
+                self.regs
+                    .set_flag(Flags::CARRY_FLAG, flags.contains(Flags::CARRY_FLAG));
+                4
+            }
+            0x27 => {
+                let (result, flags) = alu::daa(self.regs.al(), self.regs.flags);
+                self.regs.set_al(result);
+                self.set_arithmetic_flags(flags);
+                4
+            }
+            // CMA
+            0x2F => {
+                self.regs.set_al(!self.regs.al());
+                4
+            }
+            // STC, CMC
+            0x37 => {
+                self.regs.set_flag(Flags::CARRY_FLAG, true);
+                4
+            }
+            0x3F => {
+                self.regs.flags.toggle(Flags::CARRY_FLAG);
+                4
+            }
+            // Rcc
+            _ if opcode & 0xC7 == 0xC0 => {
+                if self.condition_8080(r) {
+                    let ip = self.pop_8080();
+                    self.jump_near(ip);
+                    11
+                } else {
+                    5
+                }
+            }
+            // POP, with PSW for SP
+            _ if opcode & 0xCF == 0xC1 => {
+                let value = self.pop_8080();
+                if opcode == 0xF1 {
+                    let [low, high] = value.to_le_bytes();
+                    self.regs.set_al(high);
+                    let flags = (self.regs.flags.bits() & !AH_FLAGS) | (low as u16 & AH_FLAGS);
+                    self.regs.set_flags_word(flags);
+                } else {
+                    self.set_pair(opcode >> 4, value);
+                }
+                10
+            }
+            // Jcc
+            _ if opcode & 0xC7 == 0xC2 => {
+                let ip = self.fetch_u16();
+                if self.condition_8080(r) {
+                    self.jump_near(ip);
+                }
+                10
+            }
+            // JMP
+            0xC3 | 0xCB => {
+                let ip = self.fetch_u16();
+                self.jump_near(ip);
+                10
+            }
+            // OUT, IN
+            0xD3 => {
+                let port = self.fetch_u8() as u16;
+                self.write_io_u8(port, self.regs.al());
+                10
+            }
+            0xDB => {
+                let port = self.fetch_u8() as u16;
+                let value = self.read_io_u8(port);
+                self.regs.set_al(value);
+                10
+            }
+            // XTHL
+            0xE3 => {
+                let value = self.read_u16(SegReg::Ds, self.regs.bp);
+                self.write_u16(SegReg::Ds, self.regs.bp, self.regs.bx);
+                self.regs.bx = value;
+                18
+            }
+            // XCHG
+            0xEB => {
+                std::mem::swap(&mut self.regs.bx, &mut self.regs.dx);
+                4
+            }
+            // DI, EI
+            0xF3 => {
+                self.regs.set_flag(Flags::INTERRUPT_FLAG, false);
+                4
+            }
+            0xFB => {
+                self.regs.set_flag(Flags::INTERRUPT_FLAG, true);
+                self.sti_shadow = true;
+                4
+            }
+            // Ccc
+            _ if opcode & 0xC7 == 0xC4 => {
+                let ip = self.fetch_u16();
+                if self.condition_8080(r) {
+                    self.call_8080(ip);
+                    17
+                } else {
+                    11
+                }
+            }
+            // PUSH, with PSW for SP
+            _ if opcode & 0xCF == 0xC5 => {
+                let value = if opcode == 0xF5 {
+                    u16::from_le_bytes([self.regs.flags_word() as u8, self.regs.al()])
+                } else {
+                    self.pair(opcode >> 4)
+                };
+                self.push_8080(value);
+                11
+            }
+            // the ED prefix of RETEM and CALLN
+            0xED => self.leave_emulation(),
+            // CALL
+            0xCD | 0xDD | 0xFD => {
+                let ip = self.fetch_u16();
+                self.call_8080(ip);
+                17
+            }
+            // ALU immediates
+            _ if opcode & 0xC7 == 0xC6 => {
+                let value = self.fetch_u8();
+                let result = self.alu8(ALU_OPERATIONS[r as usize], self.regs.al(), value);
+                self.regs.set_al(result);
+                7
+            }
+            // RST
+            _ if opcode & 0xC7 == 0xC7 => {
+                self.call_8080(r as u16 * 8);
+                11
+            }
+            // RET
+            0xC9 | 0xD9 => {
+                let ip = self.pop_8080();
+                self.jump_near(ip);
+                10
+            }
+            // PCHL
+            0xE9 => {
+                self.jump_near(self.regs.bx);
+                5
+            }
+            // SPHL
+            _ => {
+                self.regs.bp = self.regs.bx;
+                5
+            }
+        };
+        self.clock(clocks);
+    }
+
+    // ED FD: RETEM returns from BRKEM like IRET, restoring MD and with it
+    // native mode. ED ED imm8: CALLN runs a native interrupt handler, whose
+    // IRET comes back to 8080 mode. Anything else after ED does nothing.
+    fn leave_emulation(&mut self) -> u32 {
+        match self.fetch_u8() {
+            0xFD => {
+                self.iret();
+                self.brkem_active = false;
+                0
+            }
+            0xED => {
+                let vector = self.fetch_u8();
+                self.interrupt(vector);
+                38
+            }
+            _ => 4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cpu_with_code, CODE_SEGMENT, DATA_SEGMENT, STACK_SEGMENT};
+    use super::super::{physical_address, Variant};
+    use super::*;
+
+    // BRKEM 80h into 8080 code at 1000:0100
+    fn v20_in_emulation(code: &[u8]) -> Cpu {
+        let mut cpu = cpu_with_code(&[0x0F, 0xFF, 0x80]);
+        cpu.set_variant(Variant::V20);
+        cpu.mem.write_u16(0x200, 0x0100);
+        cpu.mem.write_u16(0x202, CODE_SEGMENT);
+        cpu.mem.load(physical_address(CODE_SEGMENT, 0x100), code);
+        cpu.step();
+        cpu
+    }
+
+    #[test]
+    fn test_brkem_retem() {
+        // mvi a, 12h ; retem
+        let mut cpu = v20_in_emulation(&[0x3E, 0x12, 0xED, 0xFD]);
+        assert!(cpu.in_emulation_mode());
+        assert_eq!(0x0100, cpu.regs.ip);
+        // the native flags are saved with MD set
+        assert_eq!(
+            0xF002,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        cpu.step();
+        assert_eq!(0x12, cpu.regs.al());
+        cpu.step();
+        assert!(!cpu.in_emulation_mode());
+        assert_eq!((3, 0x100), (cpu.regs.ip, cpu.regs.sp));
+    }
+
+    #[test]
+    fn test_8080_registers_and_memory() {
+        // lxi h, 10h ; mvi m, 7 ; lxi sp, 20h ; push h ; mov b, m ; inr b ;
+        // dad h ; xchg
+        let code = [
+            0x21, 0x10, 0x00, 0x36, 0x07, 0x31, 0x20, 0x00, 0xE5, 0x46, 0x04, 0x29, 0xEB,
+        ];
+        let mut cpu = v20_in_emulation(&code);
+        for _ in 0..8 {
+            cpu.step();
+        }
+        assert_eq!(7, cpu.mem.read_u8(physical_address(DATA_SEGMENT, 0x10)));
+        // SP is BP and the stack is in the data segment
+        assert_eq!(0x1E, cpu.regs.bp);
+        assert_eq!(0x10, cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0x1E)));
+        assert_eq!(0x0800, cpu.regs.cx);
+        assert_eq!((0x20, 0), (cpu.regs.dx, cpu.regs.bx));
+    }
+
+    #[test]
+    fn test_8080_arithmetic_and_jumps() {
+        // mvi a, 0FFh ; adi 1 ; jnc 0 ; cpi 0 ; jz 110h
+        let code = [
+            0x3E, 0xFF, 0xC6, 0x01, 0xD2, 0x00, 0x00, 0xFE, 0x00, 0xCA, 0x10, 0x01,
+        ];
+        let mut cpu = v20_in_emulation(&code);
+        cpu.step();
+        cpu.step();
+        assert_eq!(0, cpu.regs.al());
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG | Flags::ZERO_FLAG));
+        cpu.step();
+        assert_eq!(0x107, cpu.regs.ip);
+        cpu.step();
+        cpu.step();
+        assert_eq!(0x110, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_calln_returns_to_emulation() {
+        // calln 81h ; nop, with a native IRET at 1000:0000 for INT 81h
+        let mut cpu = v20_in_emulation(&[0xED, 0xED, 0x81, 0x00]);
+        cpu.mem.write_u16(0x204, 0x0040);
+        cpu.mem.write_u16(0x206, CODE_SEGMENT);
+        cpu.mem.write_u8(physical_address(CODE_SEGMENT, 0x40), 0xCF);
+        cpu.step();
+        assert!(!cpu.in_emulation_mode());
+        assert_eq!(0x40, cpu.regs.ip);
+        cpu.step();
+        assert!(cpu.in_emulation_mode());
+        assert_eq!(0x103, cpu.regs.ip);
+    }
+}
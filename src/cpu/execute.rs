@@ -0,0 +1,75 @@
+use super::Cpu;
+use crate::alu::Vendor;
+
+impl Cpu {
+    pub(super) fn execute(&mut self, opcode: u8) {
+        match opcode {
+            // the V20 decodes these as its own instructions
+            0x0F | 0x60..=0x6F | 0xC0 | 0xC1 | 0xC8 | 0xC9 if self.vendor() == Vendor::Nec => {
+                self.execute_nec(opcode)
+            }
+            0x27 => self.daa(),
+            0x2F => self.das(),
+            0x37 => self.aaa(),
+            0x3F => self.aas(),
+            0x00..=0x3F if opcode & 7 < 6 => self.alu_op(opcode),
+            0x06 | 0x0E | 0x16 | 0x1E => self.push_seg(opcode),
+            0x07 | 0x0F | 0x17 | 0x1F => self.pop_seg(opcode),
+            0x40..=0x47 => self.inc_reg16(opcode & 7),
+            0x48..=0x4F => self.dec_reg16(opcode & 7),
+            0x50..=0x57 => self.push_reg16(opcode & 7),
+            0x58..=0x5F => self.pop_reg16(opcode & 7),
+            // undocumented aliases; F1 only gets here in strict mode
+            0x60..=0x6F | 0xC0 | 0xC1 | 0xC8 | 0xC9 | 0xD6 | 0xF1 if self.trap_undocumented() => {}
+            0x60..=0x6F => self.jcc(opcode),
+            0x70..=0x7F => self.jcc(opcode),
+            0x80..=0x83 => self.group1(opcode),
+            0x84 | 0x85 => self.test_rm_reg(opcode),
+            0x86 | 0x87 => self.xchg_rm_reg(opcode),
+            0x88..=0x8B => self.mov_rm_reg(opcode),
+            0x8C => self.mov_rm_seg(),
+            0x8D => self.lea(),
+            0x8E => self.mov_seg_rm(),
+            0x8F => self.pop_rm(),
+            0x90..=0x97 => self.xchg_ax(opcode & 7),
+            0x98 => self.cbw(),
+            0x99 => self.cwd(),
+            0x9A => self.call_far(),
+            0x9B => self.wait(),
+            0x9C => self.pushf(),
+            0x9D => self.popf(),
+            0x9E => self.sahf(),
+            0x9F => self.lahf(),
+            0xA0..=0xA3 => self.mov_acc_moffs(opcode),
+            0xA4..=0xA7 | 0xAA..=0xAF => self.string_op(opcode),
+            0xA8 | 0xA9 => self.test_acc_imm(opcode),
+            0xB0..=0xBF => self.mov_reg_imm(opcode),
+            0xC0..=0xC3 => self.ret_near(opcode),
+            0xC4 => self.load_far_pointer(super::SegReg::Es),
+            0xC5 => self.load_far_pointer(super::SegReg::Ds),
+            0xC6 | 0xC7 => self.mov_rm_imm(opcode),
+            0xC8..=0xCB => self.ret_far(opcode),
+            0xCC..=0xCE => self.int_op(opcode),
+            0xCF => self.iret(),
+            0xD0..=0xD3 => self.group2(opcode),
+            0xD4 => self.aam(),
+            0xD5 => self.aad(),
+            0xD6 => self.salc(),
+            0xD7 => self.xlat(),
+            0xD8..=0xDF => self.escape(opcode),
+            0xE0..=0xE3 => self.loop_op(opcode),
+            0xE4..=0xE7 | 0xEC..=0xEF => self.in_out(opcode),
+            0xE8 => self.call_near(),
+            0xE9 => self.jmp_near(),
+            0xEA => self.jmp_far(),
+            0xEB => self.jmp_short(),
+            0xF4 => self.hlt(),
+            0xF5 | 0xF8..=0xFD => self.flag_op(opcode),
+            0xF6 | 0xF7 => self.group3(opcode),
+            0xFE => self.group4(),
+            0xFF => self.group5(),
+            // the prefixes, which fetch_opcode has already consumed
+            _ => unreachable!("prefix {:02X}h reached execute", opcode),
+        }
+    }
+}
@@ -0,0 +1,544 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{modrm::Operand, registers::MODE_FLAG, Cpu, SegReg};
+use crate::alu::{Flags, Vendor};
+
+pub const DIVIDE_ERROR: u8 = 0;
+pub const SINGLE_STEP: u8 = 1;
+pub const NMI: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const OVERFLOW: u8 = 4;
+pub const BOUND_RANGE: u8 = 5;
+pub const INVALID_OPCODE: u8 = 6;
+
+/// What the CPU sees of an interrupt controller: the INTR line, and the INTA
+/// cycles that fetch the vector once the CPU accepts the interrupt.
+pub trait InterruptController {
+    fn intr(&self) -> bool;
+    fn acknowledge(&mut self) -> u8;
+}
+
+impl<T: InterruptController + ?Sized> InterruptController for Rc<RefCell<T>> {
+    fn intr(&self) -> bool {
+        self.borrow().intr()
+    }
+
+    fn acknowledge(&mut self) -> u8 {
+        self.borrow_mut().acknowledge()
+    }
+}
+
+impl Cpu {
+    pub fn set_interrupt_controller(&mut self, controller: Box<dyn InterruptController>) {
+        self.interrupt_controller = Some(controller);
+    }
+
+    // F4. The CPU stays halted until it takes an interrupt, which returns to
+    // the instruction after the HLT. With IF clear only NMI gets it going.
+    pub(super) fn hlt(&mut self) {
+        self.halted = true;
+        self.clock(2);
+    }
+
+    // NMI is edge triggered, the request stays latched until serviced
+    pub fn raise_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // pushes FLAGS, CS and IP and continues at the vector from the table at
+    // 0000:0000, with IF and TF cleared. A V20 in 8080 mode saves MD clear and
+    // runs the handler natively.
+    pub(crate) fn interrupt(&mut self, vector: u8) {
+        self.halted = false;
+        let mut flags = self.regs.flags_word();
+        if self.emulation {
+            flags &= !MODE_FLAG;
+            self.emulation = false;
+        }
+        self.push16(flags);
+        self.regs.set_flag(Flags::INTERRUPT_FLAG, false);
+        self.regs.set_flag(Flags::TRAP_FLAG, false);
+        self.push16(self.regs.cs);
+        self.push16(self.regs.ip);
+        let ip = self.read_mem_u16(0, vector as u16 * 4);
+        let cs = self.read_mem_u16(0, vector as u16 * 4 + 2);
+        self.jump_far(cs, ip);
+    }
+
+    // loads a segment register from MOV or POP. The 8088 does not recognise
+    // interrupts until the next instruction has run, so SS:SP can be switched
+    // with a pair of instructions; this holds for every segment register.
+    pub(crate) fn load_segment(&mut self, seg: SegReg, value: u16) {
+        self.regs.set_seg(seg, value);
+        self.segment_shadow = true;
+    }
+
+    // a repeated string instruction interrupted between iterations resumes at
+    // its last prefix, so any prefix before that one is lost
+    fn suspend_repeat(&mut self) {
+        if self.repeat_pending.take().is_some() {
+            self.regs.ip = self.prefixes.restart_ip;
+        }
+    }
+
+    // checked at every instruction boundary, returns true when an external
+    // interrupt was taken instead of executing an instruction
+    pub(super) fn service_interrupts(&mut self) -> bool {
+        let sti_shadow = std::mem::take(&mut self.sti_shadow);
+        if std::mem::take(&mut self.segment_shadow) {
+            return false;
+        }
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.suspend_repeat();
+            self.interrupt(NMI);
+            self.clock(50);
+            return true;
+        }
+        if !self.regs.flag(Flags::INTERRUPT_FLAG) || sti_shadow {
+            return false;
+        }
+        let vector = match &mut self.interrupt_controller {
+            Some(controller) if controller.intr() => controller.acknowledge(),
+            _ => return false,
+        };
+        self.suspend_repeat();
+        self.interrupt(vector);
+        self.clock(61);
+        true
+    }
+
+    // INT 1 after an instruction that started with TF set. A software
+    // interrupt clears TF but still traps, so the saved address is the first
+    // instruction of its handler. A REP string instruction traps after every
+    // iteration and, like any interrupt there, resumes at its last prefix.
+    pub(super) fn single_step(&mut self) {
+        if self.segment_shadow {
+            return;
+        }
+        self.suspend_repeat();
+        self.interrupt(SINGLE_STEP);
+        self.clock(50);
+    }
+
+    // called on an undocumented encoding, returns true when strict mode
+    // trapped it. The saved address is the start of the instruction.
+    pub(crate) fn trap_undocumented(&mut self) -> bool {
+        if !self.strict {
+            return false;
+        }
+        self.regs.ip = self.prefixes.start_ip;
+        self.interrupt(INVALID_OPCODE);
+        self.clock(51);
+        true
+    }
+
+    // the return address is the instruction after the one that faulted
+    pub(crate) fn divide_error(&mut self) {
+        self.interrupt(DIVIDE_ERROR);
+        self.clock(51);
+    }
+
+    // CC: INT3, CD: INT imm8, CE: INTO
+    pub(super) fn int_op(&mut self, opcode: u8) {
+        match opcode {
+            0xCC => {
+                self.interrupt(BREAKPOINT);
+                self.clock(52);
+            }
+            0xCD => {
+                let vector = self.fetch_u8();
+                self.interrupt(vector);
+                self.clock(51);
+            }
+            _ => {
+                if self.regs.flag(Flags::OVERFLOW_FLAG) {
+                    self.interrupt(OVERFLOW);
+                    self.clock(53);
+                } else {
+                    self.clock(4);
+                }
+            }
+        }
+    }
+
+    // CF. On the V20 this is also how 8080 mode is entered again, by
+    // popping MD clear, but only inside a BRKEM; elsewhere MD stays set.
+    pub(super) fn iret(&mut self) {
+        let ip = self.pop16();
+        let cs = self.pop16();
+        let flags = self.pop16();
+        self.jump_far(cs, ip);
+        self.regs.set_flags_word(flags);
+        self.emulation =
+            self.vendor() == Vendor::Nec && self.brkem_active && flags & MODE_FLAG == 0;
+        self.clock(24);
+    }
+
+    // 62: BOUND r16, m16&16 on the V20. An index outside the signed bounds
+    // raises INT 5 with the BOUND itself as the return address.
+    pub(super) fn bound(&mut self) {
+        let modrm = self.decode_modrm();
+        let (seg, offset) = match modrm.operand {
+            Operand::Memory(seg, offset) => (seg, offset),
+            // no bounds to read, the register form is undefined
+            Operand::Register(_) => {
+                self.clock(2);
+                return;
+            }
+        };
+        let lower = self.read_u16(seg, offset) as i16;
+        let upper = self.read_u16(seg, offset.wrapping_add(2)) as i16;
+        let index = self.regs.reg16(modrm.reg) as i16;
+        if index < lower || index > upper {
+            self.regs.ip = self.prefixes.start_ip;
+            self.interrupt(BOUND_RANGE);
+            self.clock(51);
+        } else {
+            self.clock(33);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, CODE_SEGMENT};
+    use super::*;
+
+    struct Controller {
+        pending: Option<u8>,
+    }
+
+    impl InterruptController for Controller {
+        fn intr(&self) -> bool {
+            self.pending.is_some()
+        }
+
+        fn acknowledge(&mut self) -> u8 {
+            self.pending.take().unwrap()
+        }
+    }
+
+    fn with_vector(mut cpu: Cpu, vector: u8, cs: u16, ip: u16) -> Cpu {
+        cpu.mem.write_u16(vector as u32 * 4, ip);
+        cpu.mem.write_u16(vector as u32 * 4 + 2, cs);
+        cpu
+    }
+
+    fn controller(cpu: &mut Cpu) -> Rc<RefCell<Controller>> {
+        let controller = Rc::new(RefCell::new(Controller { pending: None }));
+        cpu.set_interrupt_controller(Box::new(controller.clone()));
+        controller
+    }
+
+    fn stack(cpu: &mut Cpu, index: u16) -> u16 {
+        cpu.mem
+            .read_u16(physical_address(cpu.regs.ss, cpu.regs.sp + 2 * index))
+    }
+
+    #[test]
+    fn test_int_n() {
+        let mut cpu = with_vector(cpu_with_code(&[0xCD, 0x21]), 0x21, 0x5000, 0x0123);
+        cpu.regs.flags = Flags::INTERRUPT_FLAG | Flags::CARRY_FLAG;
+        // 51 + 5 extra byte cycles for three pushes and the vector
+        assert_eq!(71, cpu.step());
+        assert_eq!((0x5000, 0x0123), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(Flags::CARRY_FLAG, cpu.regs.flags);
+        assert_eq!(0xFA, cpu.regs.sp);
+        assert_eq!(2, stack(&mut cpu, 0));
+        assert_eq!(CODE_SEGMENT, stack(&mut cpu, 1));
+        assert_eq!(0xF203, stack(&mut cpu, 2));
+    }
+
+    #[test]
+    fn test_int3_forms() {
+        let mut cpu = with_vector(cpu_with_code(&[0xCC]), 3, 0x5000, 0);
+        assert_eq!(72, cpu.step());
+        assert_eq!(1, stack(&mut cpu, 0));
+
+        let mut cpu = with_vector(cpu_with_code(&[0xCD, 0x03]), 3, 0x5000, 0);
+        assert_eq!(71, cpu.step());
+        assert_eq!(2, stack(&mut cpu, 0));
+        assert_eq!(0x5000, cpu.regs.cs);
+    }
+
+    #[test]
+    fn test_into() {
+        let mut cpu = with_vector(cpu_with_code(&[0xCE, 0xCE]), 4, 0x5000, 0);
+        assert_eq!(4, cpu.step());
+        assert_eq!((CODE_SEGMENT, 1), (cpu.regs.cs, cpu.regs.ip));
+        cpu.regs.flags = Flags::OVERFLOW_FLAG;
+        assert_eq!(73, cpu.step());
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+    }
+
+    #[test]
+    fn test_iret() {
+        // int 10h ; nop, with the handler being a lone iret
+        let mut cpu = with_vector(cpu_with_code(&[0xCD, 0x10, 0x90]), 0x10, 0x5000, 0);
+        cpu.mem.write_u8(physical_address(0x5000, 0), 0xCF);
+        cpu.regs.flags = Flags::INTERRUPT_FLAG | Flags::ZERO_FLAG;
+        cpu.step();
+        assert_eq!(36, cpu.step());
+        assert_eq!((CODE_SEGMENT, 2), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(Flags::INTERRUPT_FLAG | Flags::ZERO_FLAG, cpu.regs.flags);
+        assert_eq!(0x100, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_divide_error() {
+        // div bl ; nop
+        let mut cpu = with_vector(cpu_with_code(&[0xF6, 0xF3, 0x90]), 0, 0x5000, 0);
+        cpu.regs.ax = 0x1234;
+        cpu.regs.bx = 0;
+        cpu.step();
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        // on the 8088 the saved address is past the faulting instruction
+        assert_eq!(2, stack(&mut cpu, 0));
+        assert_eq!(0x1234, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_nmi_ignores_if() {
+        let mut cpu = with_vector(cpu_with_code(&[0x90, 0x90]), NMI, 0x5000, 0x10);
+        cpu.mem.write_u8(physical_address(0x5000, 0x10), 0x90);
+        cpu.step();
+        cpu.raise_nmi();
+        assert_eq!(70, cpu.step());
+        assert_eq!((0x5000, 0x10), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(1, stack(&mut cpu, 0));
+        // taken only once
+        assert_eq!(3, cpu.step());
+    }
+
+    #[test]
+    fn test_intr_gated_by_if() {
+        let mut cpu = with_vector(cpu_with_code(&[0x90, 0xFB, 0x90, 0x90]), 8, 0x5000, 0);
+        let controller = controller(&mut cpu);
+        controller.borrow_mut().pending = Some(8);
+        cpu.step();
+        assert_eq!(1, cpu.regs.ip);
+        // sti ; nop, and only then the interrupt
+        cpu.step();
+        cpu.step();
+        assert_eq!(3, cpu.regs.ip);
+        assert_eq!(81, cpu.step());
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(3, stack(&mut cpu, 0));
+        assert!(controller.borrow().pending.is_none());
+    }
+
+    #[test]
+    fn test_sti_shadow_leaves_nmi() {
+        // sti ; nop, with INTR and NMI both waiting
+        let cpu = with_vector(cpu_with_code(&[0xFB, 0x90]), 8, 0x6000, 0);
+        let mut cpu = with_vector(cpu, NMI, 0x5000, 0);
+        let controller = controller(&mut cpu);
+        controller.borrow_mut().pending = Some(8);
+        cpu.step();
+        cpu.raise_nmi();
+        cpu.step();
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(1, stack(&mut cpu, 0));
+        assert!(controller.borrow().pending.is_some());
+    }
+
+    #[test]
+    fn test_hlt_until_interrupt() {
+        let mut cpu = with_vector(cpu_with_code(&[0xF4, 0x90]), 8, 0x5000, 0);
+        let controller = controller(&mut cpu);
+        cpu.regs.flags = Flags::INTERRUPT_FLAG;
+        assert_eq!(2, cpu.step());
+        assert!(cpu.is_halted());
+        assert_eq!(4, cpu.step());
+        assert_eq!(1, cpu.regs.ip);
+        controller.borrow_mut().pending = Some(8);
+        cpu.step();
+        assert!(!cpu.is_halted());
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(1, stack(&mut cpu, 0));
+    }
+
+    #[test]
+    fn test_hlt_with_if_clear_waits_for_nmi() {
+        let mut cpu = with_vector(cpu_with_code(&[0xF4]), NMI, 0x5000, 0);
+        controller(&mut cpu).borrow_mut().pending = Some(8);
+        cpu.step();
+        cpu.step();
+        assert!(cpu.is_halted());
+        cpu.raise_nmi();
+        cpu.step();
+        assert!(!cpu.is_halted());
+        assert_eq!(0x5000, cpu.regs.cs);
+    }
+
+    #[test]
+    fn test_mov_ss_shadow() {
+        // mov ss, ax ; mov sp, 80h ; nop
+        let mut cpu = with_vector(
+            cpu_with_code(&[0x8E, 0xD0, 0xBC, 0x80, 0x00, 0x90]),
+            8,
+            0x5000,
+            0,
+        );
+        let controller = controller(&mut cpu);
+        cpu.regs.flags = Flags::INTERRUPT_FLAG;
+        cpu.regs.ax = 0x6000;
+        cpu.step();
+        controller.borrow_mut().pending = Some(8);
+        cpu.raise_nmi();
+        cpu.step();
+        assert_eq!(5, cpu.regs.ip);
+        cpu.step();
+        assert_eq!((0x6000, 0x7A), (cpu.regs.ss, cpu.regs.sp));
+        assert_eq!(5, stack(&mut cpu, 0));
+    }
+
+    #[test]
+    fn test_trap_after_instruction() {
+        // nop ; nop, with a handler that is a lone iret
+        let mut cpu = with_vector(cpu_with_code(&[0x90, 0x90]), SINGLE_STEP, 0x5000, 0);
+        cpu.mem.write_u8(physical_address(0x5000, 0), 0xCF);
+        cpu.regs.flags = Flags::TRAP_FLAG;
+        assert_eq!(3 + 70, cpu.step());
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(1, stack(&mut cpu, 0));
+        assert_eq!(0xF102, stack(&mut cpu, 2));
+        assert!(!cpu.regs.flag(Flags::TRAP_FLAG));
+
+        // iret sets TF again but is not traced itself
+        assert_eq!(36, cpu.step());
+        assert_eq!((CODE_SEGMENT, 1), (cpu.regs.cs, cpu.regs.ip));
+        assert!(cpu.regs.flag(Flags::TRAP_FLAG));
+        cpu.step();
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(2, stack(&mut cpu, 0));
+    }
+
+    #[test]
+    fn test_trap_after_int_enters_handler() {
+        let cpu = with_vector(cpu_with_code(&[0xCD, 0x21]), 0x21, 0x6000, 0x40);
+        let mut cpu = with_vector(cpu, SINGLE_STEP, 0x5000, 0);
+        cpu.regs.flags = Flags::TRAP_FLAG;
+        cpu.step();
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0x40, stack(&mut cpu, 0));
+        assert_eq!(0x6000, stack(&mut cpu, 1));
+        // the flags INT 21h saw had TF clear already
+        assert_eq!(0xF002, stack(&mut cpu, 2));
+        assert_eq!(2, stack(&mut cpu, 3));
+    }
+
+    #[test]
+    fn test_trap_held_off_by_segment_load() {
+        // mov ds, ax ; nop
+        let mut cpu = with_vector(cpu_with_code(&[0x8E, 0xD8, 0x90]), SINGLE_STEP, 0x5000, 0);
+        cpu.regs.flags = Flags::TRAP_FLAG;
+        cpu.step();
+        assert_eq!((CODE_SEGMENT, 2), (cpu.regs.cs, cpu.regs.ip));
+        cpu.step();
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(3, stack(&mut cpu, 0));
+    }
+
+    #[test]
+    fn test_trap_between_rep_iterations() {
+        // rep es: movsb traced, each iteration traps and the REP is lost
+        let mut cpu = with_vector(cpu_with_code(&[0xF3, 0x26, 0xA4]), SINGLE_STEP, 0x5000, 0);
+        cpu.regs.flags = Flags::TRAP_FLAG;
+        cpu.regs.cx = 3;
+        cpu.step();
+        assert_eq!(2, cpu.regs.cx);
+        assert!(!cpu.is_repeating());
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(1, stack(&mut cpu, 0));
+    }
+
+    #[test]
+    fn test_rep_interrupt_loses_earlier_prefixes() {
+        // rep es: movsb, interrupted after one iteration; the handler irets
+        let mut cpu = with_vector(cpu_with_code(&[0xF3, 0x26, 0xA4]), 8, 0x5000, 0);
+        cpu.mem.write_u8(physical_address(0x5000, 0), 0xCF);
+        let controller = controller(&mut cpu);
+        cpu.regs.flags = Flags::INTERRUPT_FLAG;
+        cpu.regs.cx = 3;
+        cpu.step();
+        assert!(cpu.is_repeating());
+        assert_eq!(2, cpu.regs.cx);
+
+        controller.borrow_mut().pending = Some(8);
+        cpu.step();
+        assert!(!cpu.is_repeating());
+        assert_eq!(1, stack(&mut cpu, 0));
+        cpu.step();
+        assert_eq!(1, cpu.regs.ip);
+
+        // es: movsb runs once more without the REP, leaving CX alone
+        cpu.step();
+        assert_eq!(3, cpu.regs.ip);
+        assert_eq!(2, cpu.regs.cx);
+        assert_eq!(2, cpu.regs.si);
+        assert!(!cpu.is_repeating());
+    }
+
+    #[test]
+    fn test_rep_interrupt_with_single_prefix_resumes() {
+        // rep movsb keeps its REP when there is nothing in front of it
+        let mut cpu = with_vector(cpu_with_code(&[0xF3, 0xA4]), 8, 0x5000, 0);
+        cpu.mem.write_u8(physical_address(0x5000, 0), 0xCF);
+        let controller = controller(&mut cpu);
+        cpu.regs.flags = Flags::INTERRUPT_FLAG;
+        cpu.regs.cx = 3;
+        cpu.step();
+        controller.borrow_mut().pending = Some(8);
+        cpu.step();
+        cpu.step();
+        assert_eq!(0, cpu.regs.ip);
+        cpu.step();
+        while cpu.is_repeating() {
+            cpu.step();
+        }
+        assert_eq!(0, cpu.regs.cx);
+        assert_eq!(3, cpu.regs.di);
+    }
+
+    #[test]
+    fn test_strict_mode_traps_undocumented() {
+        let cases: [&[u8]; 6] = [
+            &[0x60, 0x00],
+            &[0xC1],
+            &[0xD6],
+            &[0xD0, 0xF0],
+            &[0xFF, 0xF8],
+            &[0xFE, 0xD0],
+        ];
+        for code in cases.iter() {
+            let mut cpu = with_vector(cpu_with_code(code), INVALID_OPCODE, 0x5000, 0);
+            cpu.set_strict(true);
+            cpu.step();
+            assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip), "{:02X?}", code);
+            assert_eq!(0, stack(&mut cpu, 0), "{:02X?}", code);
+        }
+    }
+
+    #[test]
+    fn test_strict_mode_lock_alias() {
+        // F1 is LOCK unless strict, then the saved address covers the prefixes
+        let mut cpu = cpu_with_code(&[0xF1, 0x90]);
+        assert_eq!(5, cpu.step());
+        assert_eq!(2, cpu.regs.ip);
+
+        let mut cpu = with_vector(
+            cpu_with_code(&[0x2E, 0xF1, 0x90]),
+            INVALID_OPCODE,
+            0x5000,
+            0,
+        );
+        cpu.set_strict(true);
+        cpu.step();
+        assert_eq!((0x5000, 0), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0, stack(&mut cpu, 0));
+        assert_eq!(0x100 - 6, cpu.regs.sp);
+    }
+}
@@ -0,0 +1,587 @@
+mod arith;
+mod biu;
+mod control;
+pub mod coprocessor;
+mod emulation;
+mod execute;
+pub mod interrupt;
+mod modrm;
+mod nec;
+pub mod registers;
+mod stack;
+mod string;
+mod transfer;
+
+use crate::{
+    alu::{Flags, Vendor},
+    io::IoBus,
+    memory::MemoryBus,
+};
+
+use biu::Biu;
+
+pub use biu::{DmaRefresh, BUS_CYCLE};
+pub use coprocessor::Coprocessor;
+pub use interrupt::InterruptController;
+pub use registers::{Registers, SegReg};
+
+// CF PF AF ZF SF OF, the flags the ALU functions compute
+pub const ARITHMETIC_FLAGS: Flags = Flags::CARRY_FLAG
+    .union(Flags::PARITY_FLAG)
+    .union(Flags::AUXILIARY_CARRY_FLAG)
+    .union(Flags::ZERO_FLAG)
+    .union(Flags::SIGN_FLAG)
+    .union(Flags::OVERFLOW_FLAG);
+
+// the chips that fit the 8088 and 8086 sockets. Each pair shares its
+// instruction set and differs only in the bus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    I8088,
+    // 16-bit data bus and a 6 byte queue
+    I8086,
+    // adds the 80186 instructions, its own bit and BCD string instructions
+    // and an 8080 emulation mode
+    V20,
+    // the V20 on the 8086 bus
+    V30,
+}
+
+impl Variant {
+    pub fn vendor(self) -> Vendor {
+        match self {
+            Variant::I8088 | Variant::I8086 => Vendor::Intel,
+            Variant::V20 | Variant::V30 => Vendor::Nec,
+        }
+    }
+
+    // a 16-bit data bus, which moves an even addressed word in one bus cycle
+    pub fn wide_bus(self) -> bool {
+        matches!(self, Variant::I8086 | Variant::V30)
+    }
+
+    pub fn queue_size(self) -> usize {
+        if self.wide_bus() {
+            6
+        } else {
+            4
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    // F3, REP/REPE/REPZ
+    WhileEqual,
+    // F2, REPNE/REPNZ
+    WhileNotEqual,
+    // 65 and 64 on the V20, REPC and REPNC
+    WhileCarry,
+    WhileNoCarry,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Prefixes {
+    // the last override wins when there are several
+    pub segment: Option<SegReg>,
+    pub lock: bool,
+    // the last of F2/F3, or REPC/REPNC on the V20, wins
+    pub repeat: Option<Repeat>,
+    // where a repeated instruction resumes after an interrupt. The 8088 only
+    // backs up to the last prefix byte, so earlier prefixes are lost.
+    pub restart_ip: u16,
+    // the first byte of the instruction, prefixes included
+    pub start_ip: u16,
+}
+
+pub struct Cpu {
+    pub regs: Registers,
+    pub mem: MemoryBus,
+    pub io: IoBus,
+    // clock cycles executed since reset
+    pub cycles: u64,
+    prefixes: Prefixes,
+    biu: Biu,
+    // opcode of a repeated string instruction with iterations left to run,
+    // or of a WAIT still polling
+    repeat_pending: Option<u8>,
+    // offset computed by the last memory ModR/M, what LEA reg,reg reads back
+    last_ea: u16,
+    interrupt_controller: Option<Box<dyn InterruptController>>,
+    // an NMI has been raised and not yet serviced
+    nmi_pending: bool,
+    // set by segment register loads, holds off every interrupt and the
+    // single step trap for one more instruction
+    segment_shadow: bool,
+    // set by STI, which holds off only INTR
+    sti_shadow: bool,
+    // trap on undocumented encodings instead of running them
+    strict: bool,
+    // stopped by HLT until an interrupt is taken
+    halted: bool,
+    variant: Variant,
+    // the V20 is running 8080 code, the MD flag clear
+    emulation: bool,
+    // between a BRKEM and the RETEM returning from it, the only time an
+    // IRET may clear MD
+    brkem_active: bool,
+    coprocessor: Option<Box<dyn Coprocessor>>,
+    // the coprocessor's NMI request as of the last step, for its edges
+    coprocessor_nmi: bool,
+}
+
+impl Cpu {
+    pub fn new(mem: MemoryBus, io: IoBus) -> Cpu {
+        let mut cpu = Cpu {
+            regs: Registers::default(),
+            mem,
+            io,
+            cycles: 0,
+            prefixes: Prefixes::default(),
+            biu: Biu::default(),
+            repeat_pending: None,
+            last_ea: 0,
+            interrupt_controller: None,
+            nmi_pending: false,
+            segment_shadow: false,
+            sti_shadow: false,
+            strict: false,
+            halted: false,
+            variant: Variant::default(),
+            emulation: false,
+            brkem_active: false,
+            coprocessor: None,
+            coprocessor_nmi: false,
+        };
+        cpu.reset();
+        cpu
+    }
+
+    pub fn reset(&mut self) {
+        self.regs = Registers {
+            cs: 0xFFFF,
+            ..Registers::default()
+        };
+        self.prefixes = Prefixes::default();
+        self.flush_queue();
+        self.repeat_pending = None;
+        self.nmi_pending = false;
+        self.segment_shadow = false;
+        self.sti_shadow = false;
+        self.halted = false;
+        self.emulation = false;
+        self.brkem_active = false;
+    }
+
+    // in strict mode the undocumented aliases and SETMO raise INT 6, the
+    // invalid opcode exception of later CPUs, instead of running
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    fn vendor(&self) -> Vendor {
+        self.variant.vendor()
+    }
+
+    // true while a V20 runs 8080 code after BRKEM
+    pub fn in_emulation_mode(&self) -> bool {
+        self.emulation
+    }
+
+    // LOCK# is asserted for the duration of an instruction with a LOCK prefix
+    pub fn bus_locked(&self) -> bool {
+        self.prefixes.lock
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // true while a REP string instruction is part way through, or a WAIT is
+    // polling a busy coprocessor; IP already points past it and the next
+    // step runs its next iteration
+    pub fn is_repeating(&self) -> bool {
+        self.repeat_pending.is_some()
+    }
+
+    // executes one instruction, or one iteration of a repeated string
+    // instruction, and returns the clock cycles it took. Entering a pending
+    // interrupt takes a step of its own.
+    pub fn step(&mut self) -> u32 {
+        let start = self.cycles;
+        if !self.service_interrupts() {
+            if self.halted {
+                // idle for a bus cycle's time before looking again
+                self.cycles += BUS_CYCLE as u64;
+            } else {
+                self.instruction();
+            }
+        }
+        self.dma_refresh();
+        let cycles = (self.cycles - start) as u32;
+        if let Some(coprocessor) = &mut self.coprocessor {
+            coprocessor.run(cycles);
+            let nmi = coprocessor.nmi_request();
+            // NMI latches on the rising edge
+            if nmi && !self.coprocessor_nmi {
+                self.nmi_pending = true;
+            }
+            self.coprocessor_nmi = nmi;
+        }
+        cycles
+    }
+
+    fn instruction(&mut self) {
+        // TF as it was before the instruction decides whether it traps, so
+        // the instruction that sets TF runs untraced and the one that clears
+        // it still traps
+        let trap = self.regs.flag(Flags::TRAP_FLAG);
+        match self.repeat_pending.take() {
+            Some(0x9B) => self.wait(),
+            Some(opcode) => self.string_iteration(opcode),
+            None if self.emulation => self.emulated_instruction(),
+            None => {
+                let opcode = self.fetch_opcode();
+                self.execute(opcode);
+            }
+        }
+        if trap {
+            self.single_step();
+        }
+    }
+
+    // consumes the prefix chain in front of an opcode. Interrupts are never
+    // recognised between a prefix and its instruction, since the whole chain
+    // is decoded in one step.
+    fn fetch_opcode(&mut self) -> u8 {
+        self.prefixes = Prefixes {
+            restart_ip: self.regs.ip,
+            start_ip: self.regs.ip,
+            ..Prefixes::default()
+        };
+        loop {
+            let ip = self.regs.ip;
+            let byte = self.fetch_u8();
+            match byte {
+                0x26 | 0x2E | 0x36 | 0x3E => {
+                    self.prefixes.segment = Some(SegReg::from_index(byte >> 3))
+                }
+                // F1 is an undocumented alias of LOCK
+                0xF0 => self.prefixes.lock = true,
+                0xF1 if !self.strict => self.prefixes.lock = true,
+                0xF2 => self.prefixes.repeat = Some(Repeat::WhileNotEqual),
+                0xF3 => self.prefixes.repeat = Some(Repeat::WhileEqual),
+                0x65 if self.vendor() == Vendor::Nec => {
+                    self.prefixes.repeat = Some(Repeat::WhileCarry)
+                }
+                0x64 if self.vendor() == Vendor::Nec => {
+                    self.prefixes.repeat = Some(Repeat::WhileNoCarry)
+                }
+                _ => return byte,
+            }
+            self.prefixes.restart_ip = ip;
+            self.clock(2);
+        }
+    }
+
+    fn segment_or(&self, default: SegReg) -> SegReg {
+        self.prefixes.segment.unwrap_or(default)
+    }
+
+    pub(crate) fn read_mem_u8(&mut self, segment: u16, offset: u16) -> u8 {
+        let address = physical_address(segment, offset);
+        self.bus_cycle(self.mem.wait_states(address));
+        self.mem.read_u8(address)
+    }
+
+    pub(crate) fn write_mem_u8(&mut self, segment: u16, offset: u16, value: u8) {
+        let address = physical_address(segment, offset);
+        self.bus_cycle(self.mem.wait_states(address));
+        self.mem.write_u8(address, value)
+    }
+
+    // a word is two bus cycles on the 8088, the second one costing 4 clocks on
+    // top of the 8086 timings. The 8086 moves an even addressed word in one
+    // cycle and pays the same as the 8088 for an odd one. The high byte wraps
+    // within the segment.
+    pub(crate) fn read_mem_u16(&mut self, segment: u16, offset: u16) -> u16 {
+        if self.aligned_word(offset) {
+            let address = physical_address(segment, offset);
+            self.bus_cycle(self.mem.wait_states(address));
+            return self.mem.read_u16(address);
+        }
+        let low = self.read_mem_u8(segment, offset);
+        let high = self.read_mem_u8(segment, offset.wrapping_add(1));
+        self.clock(4);
+        u16::from_le_bytes([low, high])
+    }
+
+    pub(crate) fn write_mem_u16(&mut self, segment: u16, offset: u16, value: u16) {
+        if self.aligned_word(offset) {
+            let address = physical_address(segment, offset);
+            self.bus_cycle(self.mem.wait_states(address));
+            self.mem.write_u16(address, value);
+            return;
+        }
+        let [low, high] = value.to_le_bytes();
+        self.write_mem_u8(segment, offset, low);
+        self.write_mem_u8(segment, offset.wrapping_add(1), high);
+        self.clock(4);
+    }
+
+    // a word the 16-bit bus moves in a single cycle. Segments start on even
+    // addresses, so the offset alone decides.
+    fn aligned_word(&self, offset: u16) -> bool {
+        self.variant.wide_bus() && offset & 1 == 0
+    }
+
+    pub(crate) fn read_u8(&mut self, seg: SegReg, offset: u16) -> u8 {
+        self.read_mem_u8(self.regs.seg(seg), offset)
+    }
+
+    pub(crate) fn write_u8(&mut self, seg: SegReg, offset: u16, value: u8) {
+        self.write_mem_u8(self.regs.seg(seg), offset, value)
+    }
+
+    pub(crate) fn read_u16(&mut self, seg: SegReg, offset: u16) -> u16 {
+        self.read_mem_u16(self.regs.seg(seg), offset)
+    }
+
+    pub(crate) fn write_u16(&mut self, seg: SegReg, offset: u16, value: u16) {
+        self.write_mem_u16(self.regs.seg(seg), offset, value)
+    }
+
+    pub(crate) fn push16(&mut self, value: u16) {
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        self.write_u16(SegReg::Ss, self.regs.sp, value);
+    }
+
+    pub(crate) fn pop16(&mut self) -> u16 {
+        let value = self.read_u16(SegReg::Ss, self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(2);
+        value
+    }
+
+    pub(crate) fn read_io_u8(&mut self, port: u16) -> u8 {
+        self.bus_cycle(0);
+        self.io.read_u8(port)
+    }
+
+    pub(crate) fn write_io_u8(&mut self, port: u16, value: u8) {
+        self.bus_cycle(0);
+        self.io.write_u8(port, value)
+    }
+
+    pub(crate) fn read_io_u16(&mut self, port: u16) -> u16 {
+        if self.aligned_word(port) {
+            self.bus_cycle(0);
+            return self.io.read_u16(port);
+        }
+        self.bus_cycle(0);
+        self.bus_cycle(0);
+        let value = self.io.read_u16(port);
+        self.clock(4);
+        value
+    }
+
+    pub(crate) fn write_io_u16(&mut self, port: u16, value: u16) {
+        if self.aligned_word(port) {
+            self.bus_cycle(0);
+            self.io.write_u16(port, value);
+            return;
+        }
+        self.bus_cycle(0);
+        self.bus_cycle(0);
+        self.io.write_u16(port, value);
+        self.clock(4);
+    }
+
+    pub(crate) fn set_arithmetic_flags(&mut self, flags: Flags) {
+        self.regs.flags = (self.regs.flags - ARITHMETIC_FLAGS) | (flags & ARITHMETIC_FLAGS);
+    }
+}
+
+pub fn physical_address(segment: u16, offset: u16) -> u32 {
+    (((segment as u32) << 4) + offset as u32) & crate::memory::ADDRESS_MASK
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const CODE_SEGMENT: u16 = 0x1000;
+    pub const DATA_SEGMENT: u16 = 0x2000;
+    pub const STACK_SEGMENT: u16 = 0x3000;
+    pub const EXTRA_SEGMENT: u16 = 0x4000;
+
+    // a CPU with 1MB of RAM and `code` at 1000:0000
+    pub fn cpu_with_code(code: &[u8]) -> Cpu {
+        cpu_with_variant(code, Variant::default())
+    }
+
+    pub fn cpu_with_variant(code: &[u8], variant: Variant) -> Cpu {
+        let mut mem = MemoryBus::new();
+        mem.map_ram(0, crate::memory::ADDRESS_SPACE_SIZE).unwrap();
+        mem.load(physical_address(CODE_SEGMENT, 0), code);
+        let mut cpu = Cpu::new(mem, IoBus::new());
+        cpu.regs.cs = CODE_SEGMENT;
+        cpu.regs.ip = 0;
+        cpu.regs.ds = DATA_SEGMENT;
+        cpu.regs.ss = STACK_SEGMENT;
+        cpu.regs.es = EXTRA_SEGMENT;
+        cpu.regs.sp = 0x100;
+        cpu.set_variant(variant);
+        // start with a full queue, as after any long instruction
+        cpu.flush_queue();
+        cpu.clock(BUS_CYCLE * variant.queue_size() as u32);
+        cpu.cycles = 0;
+        cpu
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut cpu = cpu_with_code(&[]);
+        cpu.regs.ax = 0x1234;
+        cpu.reset();
+        assert_eq!(0xFFFF, cpu.regs.cs);
+        assert_eq!(0, cpu.regs.ip);
+        assert_eq!(0, cpu.regs.ax);
+        assert_eq!(0xFFFF0, physical_address(cpu.regs.cs, cpu.regs.ip));
+    }
+
+    #[test]
+    fn test_physical_address_wraps() {
+        assert_eq!(0x0FFEF, physical_address(0xFFFF, 0xFFFF));
+        assert_eq!(0x12345, physical_address(0x1234, 0x0005));
+    }
+
+    #[test]
+    fn test_segment_override() {
+        // mov ax, es:[bx]
+        let mut cpu = cpu_with_code(&[0x26, 0x8B, 0x07]);
+        cpu.regs.bx = 0x10;
+        cpu.mem
+            .write_u16(physical_address(EXTRA_SEGMENT, 0x10), 0xBEEF);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x1111);
+        // 2 prefix + 8 mov + 5 EA + 4 second byte
+        assert_eq!(19, cpu.step());
+        assert_eq!(0xBEEF, cpu.regs.ax);
+        assert_eq!(3, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_bp_defaults_to_stack_segment() {
+        // mov al, [bp+2] ; mov al, ds:[bp+2]
+        let mut cpu = cpu_with_code(&[0x8A, 0x46, 0x02, 0x3E, 0x8A, 0x46, 0x02]);
+        cpu.regs.bp = 0x20;
+        cpu.mem
+            .write_u8(physical_address(STACK_SEGMENT, 0x22), 0x55);
+        cpu.mem.write_u8(physical_address(DATA_SEGMENT, 0x22), 0x66);
+        cpu.step();
+        assert_eq!(0x55, cpu.regs.al());
+        cpu.step();
+        assert_eq!(0x66, cpu.regs.al());
+    }
+
+    #[test]
+    fn test_conflicting_segment_overrides() {
+        // es: cs: ss: mov al, [si]; the last override wins
+        let mut cpu = cpu_with_code(&[0x26, 0x2E, 0x36, 0x8A, 0x04]);
+        cpu.regs.si = 0x40;
+        cpu.mem
+            .write_u8(physical_address(STACK_SEGMENT, 0x40), 0x77);
+        // every prefix costs 2 clocks
+        assert_eq!(3 * 2 + 8 + 5, cpu.step());
+        assert_eq!(0x77, cpu.regs.al());
+        assert_eq!(Some(SegReg::Ss), cpu.prefixes.segment);
+    }
+
+    #[test]
+    fn test_redundant_prefixes() {
+        // ds: ds: lock lock mov al, [0010h]
+        let mut cpu = cpu_with_code(&[0x3E, 0x3E, 0xF0, 0xF0, 0xA0, 0x10, 0x00]);
+        cpu.mem.write_u8(physical_address(DATA_SEGMENT, 0x10), 0x99);
+        // the last byte has to be fetched after the queue runs dry
+        assert_eq!(4 * 2 + 10 + 4, cpu.step());
+        assert_eq!(0x99, cpu.regs.al());
+        assert!(cpu.bus_locked());
+        assert_eq!(7, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_lock_is_per_instruction() {
+        // lock xchg [bx], al ; nop
+        let mut cpu = cpu_with_code(&[0xF0, 0x86, 0x07, 0x90]);
+        cpu.step();
+        assert!(cpu.bus_locked());
+        cpu.step();
+        assert!(!cpu.bus_locked());
+    }
+
+    #[test]
+    fn test_repeat_prefix_last_one_wins() {
+        // repne rep nop
+        let mut cpu = cpu_with_code(&[0xF2, 0xF3, 0x90, 0xF3, 0xF2, 0x90]);
+        cpu.step();
+        assert_eq!(Some(Repeat::WhileEqual), cpu.prefixes.repeat);
+        cpu.step();
+        assert_eq!(Some(Repeat::WhileNotEqual), cpu.prefixes.repeat);
+    }
+
+    #[test]
+    fn test_restart_address_is_last_prefix() {
+        // rep es: nop, es: rep nop, nop
+        let mut cpu = cpu_with_code(&[0xF3, 0x26, 0x90, 0x26, 0xF3, 0x90, 0x90]);
+        cpu.step();
+        assert_eq!(1, cpu.prefixes.restart_ip);
+        cpu.step();
+        assert_eq!(4, cpu.prefixes.restart_ip);
+        cpu.step();
+        assert_eq!(6, cpu.prefixes.restart_ip);
+    }
+
+    #[test]
+    fn test_word_access_wraps_within_segment() {
+        // mov ax, [0FFFFh]
+        let mut cpu = cpu_with_code(&[0xA1, 0xFF, 0xFF]);
+        cpu.mem
+            .write_u8(physical_address(DATA_SEGMENT, 0xFFFF), 0x34);
+        cpu.mem.write_u8(physical_address(DATA_SEGMENT, 0), 0x12);
+        cpu.mem
+            .write_u8(physical_address(DATA_SEGMENT + 0x1000, 0), 0x56);
+        cpu.step();
+        assert_eq!(0x1234, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_8086_word_access_timing() {
+        // mov ax, [0010h] ; mov ax, [0011h] ; in ax, dx
+        let code = [0xA1, 0x10, 0x00, 0xA1, 0x11, 0x00, 0xED];
+        let mut cpu = cpu_with_code(&code);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x1234);
+        assert_eq!(10 + 4, cpu.step());
+        assert_eq!(0x1234, cpu.regs.ax);
+
+        // an even address is one bus cycle, an odd one still two
+        let mut cpu = cpu_with_variant(&code, Variant::I8086);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x1234);
+        assert_eq!(10, cpu.step());
+        assert_eq!(0x1234, cpu.regs.ax);
+        assert_eq!(10 + 4, cpu.step());
+        assert_eq!(0x0012, cpu.regs.ax);
+        cpu.regs.dx = 0x40;
+        assert_eq!(8, cpu.step());
+    }
+}
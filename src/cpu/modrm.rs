@@ -0,0 +1,162 @@
+use super::{registers::SegReg, Cpu};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    // register index, 8 or 16 bit depending on the instruction
+    Register(u8),
+    Memory(SegReg, u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModRm {
+    pub reg: u8,
+    pub operand: Operand,
+}
+
+impl Cpu {
+    // fetches the ModR/M byte and its displacement and resolves the r/m
+    // operand, charging the effective address calculation time
+    pub(super) fn decode_modrm(&mut self) -> ModRm {
+        let byte = self.fetch_u8();
+        self.modrm_operand(byte)
+    }
+
+    // the rest of decode_modrm, for when the ModR/M byte itself is needed
+    pub(super) fn modrm_operand(&mut self, byte: u8) -> ModRm {
+        let mode = byte >> 6;
+        let reg = (byte >> 3) & 7;
+        let rm = byte & 7;
+        if mode == 3 {
+            return ModRm {
+                reg,
+                operand: Operand::Register(rm),
+            };
+        }
+
+        let displacement = match mode {
+            0 if rm == 6 => self.fetch_u16(),
+            0 => 0,
+            1 => self.fetch_u8() as i8 as u16,
+            _ => self.fetch_u16(),
+        };
+        let r = &self.regs;
+        // based on the 8086 user's manual, table 2-20
+        let (base, default_seg, clocks) = match rm {
+            0 => (r.bx.wrapping_add(r.si), SegReg::Ds, 7),
+            1 => (r.bx.wrapping_add(r.di), SegReg::Ds, 8),
+            2 => (r.bp.wrapping_add(r.si), SegReg::Ss, 8),
+            3 => (r.bp.wrapping_add(r.di), SegReg::Ss, 7),
+            4 => (r.si, SegReg::Ds, 5),
+            5 => (r.di, SegReg::Ds, 5),
+            6 if mode == 0 => (0, SegReg::Ds, 6),
+            6 => (r.bp, SegReg::Ss, 5),
+            _ => (r.bx, SegReg::Ds, 5),
+        };
+        let clocks = if mode == 0 { clocks } else { clocks + 4 };
+        self.clock(clocks);
+
+        let offset = base.wrapping_add(displacement);
+        self.last_ea = offset;
+        ModRm {
+            reg,
+            operand: Operand::Memory(self.segment_or(default_seg), offset),
+        }
+    }
+
+    pub(super) fn read_operand8(&mut self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Register(index) => self.regs.reg8(index),
+            Operand::Memory(seg, offset) => self.read_u8(seg, offset),
+        }
+    }
+
+    pub(super) fn write_operand8(&mut self, operand: Operand, value: u8) {
+        match operand {
+            Operand::Register(index) => self.regs.set_reg8(index, value),
+            Operand::Memory(seg, offset) => self.write_u8(seg, offset, value),
+        }
+    }
+
+    pub(super) fn read_operand16(&mut self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Register(index) => self.regs.reg16(index),
+            Operand::Memory(seg, offset) => self.read_u16(seg, offset),
+        }
+    }
+
+    // FE /2-/7 run the word operations of FF on a byte operand, the 8088
+    // fills the upper half with ones then
+    pub(super) fn read_operand(&mut self, operand: Operand, word: bool) -> u16 {
+        if word {
+            self.read_operand16(operand)
+        } else {
+            0xFF00 | self.read_operand8(operand) as u16
+        }
+    }
+
+    pub(super) fn write_operand16(&mut self, operand: Operand, value: u16) {
+        match operand {
+            Operand::Register(index) => self.regs.set_reg16(index, value),
+            Operand::Memory(seg, offset) => self.write_u16(seg, offset, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::cpu_with_code;
+    use super::*;
+
+    #[test]
+    fn test_register_operand() {
+        let mut cpu = cpu_with_code(&[0xD8]);
+        assert_eq!(
+            ModRm {
+                reg: 3,
+                operand: Operand::Register(0)
+            },
+            cpu.decode_modrm()
+        );
+        assert_eq!(0, cpu.cycles);
+    }
+
+    #[test]
+    fn test_memory_operands() {
+        // [bx+si], [bp+di+12h], [1234h], [bp-2]
+        let mut cpu = cpu_with_code(&[0x00, 0x43, 0x12, 0x06, 0x34, 0x12, 0x56, 0xFE]);
+        cpu.regs.bx = 0x100;
+        cpu.regs.si = 0x20;
+        cpu.regs.bp = 0x300;
+        cpu.regs.di = 0x4;
+
+        assert_eq!(
+            Operand::Memory(SegReg::Ds, 0x120),
+            cpu.decode_modrm().operand
+        );
+        assert_eq!(7, cpu.cycles);
+        assert_eq!(
+            Operand::Memory(SegReg::Ss, 0x316),
+            cpu.decode_modrm().operand
+        );
+        assert_eq!(7 + 11, cpu.cycles);
+        assert_eq!(
+            ModRm {
+                reg: 0,
+                operand: Operand::Memory(SegReg::Ds, 0x1234)
+            },
+            cpu.decode_modrm()
+        );
+        assert_eq!(7 + 11 + 6, cpu.cycles);
+        let modrm = cpu.decode_modrm();
+        assert_eq!(2, modrm.reg);
+        assert_eq!(Operand::Memory(SegReg::Ss, 0x2FE), modrm.operand);
+        assert_eq!(7 + 11 + 6 + 9, cpu.cycles);
+    }
+
+    #[test]
+    fn test_segment_override_replaces_default() {
+        let mut cpu = cpu_with_code(&[0x46, 0x00]);
+        cpu.prefixes.segment = Some(SegReg::Es);
+        assert_eq!(Operand::Memory(SegReg::Es, 0), cpu.decode_modrm().operand);
+    }
+}
@@ -0,0 +1,383 @@
+use super::{modrm::Operand, Cpu, SegReg};
+use crate::alu::Flags;
+
+// a packed BCD byte as a number, and back
+fn from_bcd(byte: u8) -> i32 {
+    (byte >> 4) as i32 * 10 + (byte & 0x0F) as i32
+}
+
+fn to_bcd(value: i32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+impl Cpu {
+    // the V20 opcodes that the 8088 runs as POP CS or as undocumented aliases
+    pub(super) fn execute_nec(&mut self, opcode: u8) {
+        match opcode {
+            0x0F => self.extended(),
+            0x60 => self.pusha(),
+            0x61 => self.popa(),
+            0x62 => self.bound(),
+            0x68 | 0x6A => self.push_imm(opcode),
+            0x69 | 0x6B => self.imul_imm(opcode),
+            0x6C..=0x6F => self.string_op(opcode),
+            0xC0 | 0xC1 => self.group2(opcode),
+            0xC8 => self.enter(),
+            0xC9 => self.leave(),
+            0x66 | 0x67 => self.fpo2(),
+            // 63; REPC and REPNC never get here, fetch_opcode takes them as
+            // prefixes
+            _ => self.undefined(),
+        }
+    }
+
+    // an opcode the V20 does not define runs as a no-op, or raises INT 6 in
+    // strict mode
+    fn undefined(&mut self) {
+        if !self.trap_undocumented() {
+            self.clock(2);
+        }
+    }
+
+    // 66/67: FPO2, the escape for a second coprocessor. Nothing answers
+    // it, but like ESC it carries a ModR/M byte and displacement.
+    fn fpo2(&mut self) {
+        let byte = self.fetch_u8();
+        self.modrm_operand(byte);
+        self.undefined();
+    }
+
+    // 0F xx
+    fn extended(&mut self) {
+        let opcode = self.fetch_u8();
+        match opcode {
+            0x10..=0x1F => self.bit_op(opcode),
+            0x20 | 0x22 | 0x26 => self.bcd_string(opcode),
+            0x28 | 0x2A => self.rotate_nibble(opcode),
+            0xFF => self.brkem(),
+            _ => self.undefined(),
+        }
+    }
+
+    // TEST1 CLR1 SET1 NOT1 on r/m8 or r/m16, the bit number in CL or, for
+    // 18-1F, an immediate. Only TEST1 touches the flags: ZF is set for a
+    // clear bit, CF and OF are cleared.
+    fn bit_op(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let word = opcode & 1 == 1;
+        let bit = if opcode & 8 != 0 {
+            self.fetch_u8()
+        } else {
+            self.regs.reg8(1)
+        };
+        let mask = 1u16 << (bit & if word { 15 } else { 7 });
+        let value = self.read_operand(modrm.operand, word);
+        let register = matches!(modrm.operand, Operand::Register(_));
+        let result = match (opcode >> 1) & 3 {
+            0 => {
+                let mut flags = self.regs.flags - Flags::CARRY_FLAG - Flags::OVERFLOW_FLAG;
+                flags.set(Flags::ZERO_FLAG, value & mask == 0);
+                self.set_arithmetic_flags(flags);
+                self.clock(if register { 3 } else { 12 });
+                return;
+            }
+            1 => value & !mask,
+            2 => value | mask,
+            _ => value ^ mask,
+        };
+        if word {
+            self.write_operand16(modrm.operand, result);
+        } else {
+            self.write_operand8(modrm.operand, result as u8);
+        }
+        self.clock(if register { 5 } else { 14 });
+    }
+
+    // ADD4S, SUB4S and CMP4S on packed BCD strings of CL digits, least
+    // significant byte first: ES:DI += DS:SI, ES:DI -= DS:SI, or the compare
+    // alone. SI and DI are left as they were. CF is the final carry or
+    // borrow and ZF is set for a zero result.
+    fn bcd_string(&mut self, opcode: u8) {
+        let src = self.segment_or(SegReg::Ds);
+        let bytes = (self.regs.reg8(1) as u16).div_ceil(2);
+        let mut carry = 0;
+        let mut zero = true;
+        for i in 0..bytes {
+            let si = self.regs.si.wrapping_add(i);
+            let di = self.regs.di.wrapping_add(i);
+            let op2 = from_bcd(self.read_u8(src, si));
+            let op1 = from_bcd(self.read_u8(SegReg::Es, di));
+            let mut value = if opcode == 0x20 {
+                op1 + op2 + carry
+            } else {
+                op1 - op2 - carry
+            };
+            carry = 0;
+            if value > 99 {
+                value -= 100;
+                carry = 1;
+            } else if value < 0 {
+                value += 100;
+                carry = 1;
+            }
+            zero &= value == 0;
+            if opcode != 0x26 {
+                self.write_u8(SegReg::Es, di, to_bcd(value));
+            }
+        }
+        let mut flags = self.regs.flags;
+        flags.set(Flags::CARRY_FLAG, carry != 0);
+        flags.set(Flags::ZERO_FLAG, zero);
+        self.set_arithmetic_flags(flags);
+        let per_byte = if opcode == 0x26 { 14 } else { 19 };
+        self.clock(7 + per_byte * bytes as u32);
+    }
+
+    // ROL4 and ROR4 rotate the nibbles of r/m8 through the low nibble of AL
+    fn rotate_nibble(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let value = self.read_operand8(modrm.operand);
+        let al = self.regs.al();
+        let (result, low) = if opcode == 0x28 {
+            (value << 4 | (al & 0x0F), value >> 4)
+        } else {
+            ((al & 0x0F) << 4 | value >> 4, value & 0x0F)
+        };
+        self.write_operand8(modrm.operand, result);
+        self.regs.set_al((al & 0xF0) | low);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 25,
+            Operand::Memory(..) => 28,
+        });
+    }
+
+    // BRKEM imm8 calls the 8080 program at the vector like an interrupt and
+    // switches to 8080 mode, until RETEM returns past the BRKEM
+    fn brkem(&mut self) {
+        let vector = self.fetch_u8();
+        self.interrupt(vector);
+        self.emulation = true;
+        self.brkem_active = true;
+        self.clock(50);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{
+        cpu_with_code, CODE_SEGMENT, DATA_SEGMENT, EXTRA_SEGMENT, STACK_SEGMENT,
+    };
+    use super::super::{physical_address, Variant};
+    use super::*;
+
+    fn v20_with_code(code: &[u8]) -> Cpu {
+        let mut cpu = cpu_with_code(code);
+        cpu.set_variant(Variant::V20);
+        cpu
+    }
+
+    #[test]
+    fn test_native_iret_keeps_md() {
+        // xor ax, ax ; push ax ; push cs ; mov ax, 9 ; push ax ; iret ; nop
+        let mut cpu = v20_with_code(&[0x31, 0xC0, 0x50, 0x0E, 0xB8, 0x09, 0x00, 0x50, 0xCF, 0x90]);
+        for _ in 0..6 {
+            cpu.step();
+        }
+        assert_eq!((CODE_SEGMENT, 9), (cpu.regs.cs, cpu.regs.ip));
+        assert!(!cpu.in_emulation_mode());
+        // the NOP runs as a native one byte instruction
+        cpu.step();
+        assert_eq!(10, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_pusha_popa() {
+        // pusha ; popa
+        let mut cpu = v20_with_code(&[0x60, 0x61]);
+        cpu.regs.ax = 1;
+        cpu.regs.bx = 2;
+        cpu.regs.di = 7;
+        cpu.step();
+        assert_eq!(0xF0, cpu.regs.sp);
+        let stack =
+            |cpu: &mut Cpu, offset| cpu.mem.read_u16(physical_address(STACK_SEGMENT, offset));
+        assert_eq!(0x100, stack(&mut cpu, 0xF6));
+        assert_eq!(7, stack(&mut cpu, 0xF0));
+        cpu.regs.ax = 0;
+        cpu.regs.di = 0;
+        cpu.step();
+        assert_eq!(
+            (1, 2, 7, 0x100),
+            (cpu.regs.ax, cpu.regs.bx, cpu.regs.di, cpu.regs.sp)
+        );
+    }
+
+    #[test]
+    fn test_enter_leave() {
+        // enter 6, 2 ; leave
+        let mut cpu = v20_with_code(&[0xC8, 0x06, 0x00, 0x02, 0xC9]);
+        cpu.regs.bp = 0x80;
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0x7E), 0x1234);
+        cpu.step();
+        // the old BP, the copied frame pointer and the new one
+        assert_eq!(0xFE, cpu.regs.bp);
+        assert_eq!(0xFA - 6, cpu.regs.sp);
+        assert_eq!(
+            0x80,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        assert_eq!(
+            0x1234,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFC))
+        );
+        assert_eq!(
+            0xFE,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFA))
+        );
+        cpu.step();
+        assert_eq!((0x80, 0x100), (cpu.regs.bp, cpu.regs.sp));
+    }
+
+    #[test]
+    fn test_push_and_imul_immediates() {
+        // push -2 ; imul ax, bx, 300 ; shl cx, 33
+        let mut cpu = v20_with_code(&[0x6A, 0xFE, 0x69, 0xC3, 0x2C, 0x01, 0xC1, 0xE1, 0x21]);
+        cpu.regs.bx = 3;
+        cpu.regs.cx = 1;
+        cpu.step();
+        assert_eq!(
+            0xFFFE,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        cpu.regs.flags = Flags::ZERO_FLAG;
+        cpu.step();
+        assert_eq!(900, cpu.regs.ax);
+        // the V20 leaves ZF alone on a multiply
+        assert_eq!(Flags::ZERO_FLAG, cpu.regs.flags);
+        // the count is masked to 1
+        cpu.step();
+        assert_eq!(2, cpu.regs.cx);
+    }
+
+    #[test]
+    fn test_bound() {
+        // bound ax, [0]
+        let mut cpu = v20_with_code(&[0x62, 0x06, 0x00, 0x00, 0x62, 0x06, 0x00, 0x00]);
+        cpu.mem.write_u16(0x14, 0x0200);
+        cpu.mem.write_u16(0x16, 0x3000);
+        cpu.mem.write_u16(physical_address(DATA_SEGMENT, 0), 0xFFFF);
+        cpu.mem.write_u16(physical_address(DATA_SEGMENT, 2), 10);
+        cpu.regs.ax = 10;
+        cpu.step();
+        assert_eq!(4, cpu.regs.ip);
+        cpu.regs.ax = 11;
+        cpu.step();
+        assert_eq!((0x3000, 0x0200), (cpu.regs.cs, cpu.regs.ip));
+        // the return address is the BOUND
+        assert_eq!(4, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFA)));
+    }
+
+    #[test]
+    fn test_bit_ops() {
+        // set1 bl, 7 ; test1 bx, cl ; not1 word [0], cl ; clr1 bl, cl
+        let code = [
+            0x0F, 0x1C, 0xC3, 0x07, 0x0F, 0x11, 0xC3, 0x0F, 0x17, 0x06, 0x00, 0x00, 0x0F, 0x12,
+            0xC3,
+        ];
+        let mut cpu = v20_with_code(&code);
+        cpu.regs.cx = 9;
+        cpu.regs.flags = Flags::CARRY_FLAG;
+        cpu.step();
+        assert_eq!(0x80, cpu.regs.bx);
+        cpu.step();
+        assert_eq!(Flags::ZERO_FLAG, cpu.regs.flags);
+        cpu.step();
+        assert_eq!(0x0200, cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0)));
+        cpu.regs.cx = 7;
+        cpu.step();
+        assert_eq!(0, cpu.regs.bx);
+    }
+
+    #[test]
+    fn test_bcd_strings() {
+        // add4s ; cmp4s
+        let mut cpu = v20_with_code(&[0x0F, 0x20, 0x0F, 0x26]);
+        cpu.mem
+            .load(physical_address(DATA_SEGMENT, 0), &[0x99, 0x19]);
+        cpu.mem
+            .load(physical_address(EXTRA_SEGMENT, 0), &[0x01, 0x80]);
+        cpu.regs.cx = 4;
+        cpu.step();
+        // 8001 + 1999 = 10000
+        assert_eq!(0, cpu.mem.read_u16(physical_address(EXTRA_SEGMENT, 0)));
+        assert_eq!(Flags::CARRY_FLAG | Flags::ZERO_FLAG, cpu.regs.flags);
+        cpu.step();
+        assert_eq!(Flags::CARRY_FLAG, cpu.regs.flags);
+        assert_eq!((0, 0), (cpu.regs.si, cpu.regs.di));
+    }
+
+    #[test]
+    fn test_rotate_nibbles() {
+        // rol4 bl ; ror4 bl
+        let mut cpu = v20_with_code(&[0x0F, 0x28, 0xC3, 0x0F, 0x2A, 0xC3]);
+        cpu.regs.ax = 0x00A5;
+        cpu.regs.bx = 0x0012;
+        cpu.step();
+        assert_eq!((0x00A1, 0x0025), (cpu.regs.ax, cpu.regs.bx));
+        cpu.step();
+        assert_eq!((0x00A5, 0x0012), (cpu.regs.ax, cpu.regs.bx));
+    }
+
+    #[test]
+    fn test_repc_repnc() {
+        // repc cmpsb ; repnc cmpsb
+        let mut cpu = v20_with_code(&[0x65, 0xA6, 0x64, 0xA6]);
+        cpu.mem
+            .load(physical_address(DATA_SEGMENT, 0), &[1, 5, 1, 1, 9, 9, 1]);
+        cpu.mem
+            .load(physical_address(EXTRA_SEGMENT, 0), &[2, 2, 2, 2, 2, 2, 2]);
+        cpu.regs.cx = 4;
+        cpu.step();
+        while cpu.is_repeating() {
+            cpu.step();
+        }
+        // stops after 5 - 2 clears CF
+        assert_eq!((2, 2), (cpu.regs.cx, cpu.regs.si));
+        cpu.regs.si = 4;
+        cpu.regs.di = 4;
+        cpu.regs.cx = 3;
+        cpu.step();
+        while cpu.is_repeating() {
+            cpu.step();
+        }
+        assert_eq!((0, 7), (cpu.regs.cx, cpu.regs.si));
+    }
+
+    #[test]
+    fn test_fpo2_skips_its_operand() {
+        // fpo2 [bp+1234h] ; nop
+        let code = [0x66, 0x86, 0x34, 0x12, 0x90];
+        let mut cpu = v20_with_code(&code);
+        cpu.step();
+        assert_eq!(4, cpu.regs.ip);
+        cpu.step();
+        assert_eq!(5, cpu.regs.ip);
+
+        // INT 6 returns to the FPO2 itself
+        let mut cpu = v20_with_code(&code);
+        cpu.set_strict(true);
+        cpu.step();
+        assert_eq!(0, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFA)));
+    }
+
+    #[test]
+    fn test_8088_still_pops_cs() {
+        // pop cs
+        let mut cpu = cpu_with_code(&[0x0F]);
+        cpu.mem
+            .write_u16(physical_address(STACK_SEGMENT, 0x100), 0x5000);
+        cpu.step();
+        assert_eq!(0x5000, cpu.regs.cs);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::alu::Flags;
+
+// bits 1 and 12-15 of FLAGS always read back as 1 on the 8088
+pub const FLAGS_RESERVED_ONES: u16 = 0xF002;
+// bit 15 is the MD flag of the V20, clear while it runs 8080 code
+pub const MODE_FLAG: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegReg {
+    Es,
+    Cs,
+    Ss,
+    Ds,
+}
+
+impl SegReg {
+    // segment registers as encoded in the ModR/M reg field and PUSH/POP opcodes
+    pub fn from_index(index: u8) -> SegReg {
+        match index & 3 {
+            0 => SegReg::Es,
+            1 => SegReg::Cs,
+            2 => SegReg::Ss,
+            _ => SegReg::Ds,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    pub dx: u16,
+    pub sp: u16,
+    pub bp: u16,
+    pub si: u16,
+    pub di: u16,
+    pub es: u16,
+    pub cs: u16,
+    pub ss: u16,
+    pub ds: u16,
+    pub ip: u16,
+    pub flags: Flags,
+}
+
+impl Registers {
+    // 16 bit registers in encoding order: AX CX DX BX SP BP SI DI
+    pub fn reg16(&self, index: u8) -> u16 {
+        match index & 7 {
+            0 => self.ax,
+            1 => self.cx,
+            2 => self.dx,
+            3 => self.bx,
+            4 => self.sp,
+            5 => self.bp,
+            6 => self.si,
+            _ => self.di,
+        }
+    }
+
+    pub fn set_reg16(&mut self, index: u8, value: u16) {
+        match index & 7 {
+            0 => self.ax = value,
+            1 => self.cx = value,
+            2 => self.dx = value,
+            3 => self.bx = value,
+            4 => self.sp = value,
+            5 => self.bp = value,
+            6 => self.si = value,
+            _ => self.di = value,
+        }
+    }
+
+    // 8 bit registers in encoding order: AL CL DL BL AH CH DH BH
+    pub fn reg8(&self, index: u8) -> u8 {
+        let word = self.reg16(index & 3);
+        if index & 4 == 0 {
+            word as u8
+        } else {
+            (word >> 8) as u8
+        }
+    }
+
+    pub fn set_reg8(&mut self, index: u8, value: u8) {
+        let word = self.reg16(index & 3);
+        let word = if index & 4 == 0 {
+            (word & 0xFF00) | value as u16
+        } else {
+            (word & 0x00FF) | (value as u16) << 8
+        };
+        self.set_reg16(index & 3, word);
+    }
+
+    pub fn seg(&self, seg: SegReg) -> u16 {
+        match seg {
+            SegReg::Es => self.es,
+            SegReg::Cs => self.cs,
+            SegReg::Ss => self.ss,
+            SegReg::Ds => self.ds,
+        }
+    }
+
+    pub fn set_seg(&mut self, seg: SegReg, value: u16) {
+        match seg {
+            SegReg::Es => self.es = value,
+            SegReg::Cs => self.cs = value,
+            SegReg::Ss => self.ss = value,
+            SegReg::Ds => self.ds = value,
+        }
+    }
+
+    pub fn al(&self) -> u8 {
+        self.ax as u8
+    }
+
+    pub fn set_al(&mut self, value: u8) {
+        self.set_reg8(0, value);
+    }
+
+    pub fn ah(&self) -> u8 {
+        (self.ax >> 8) as u8
+    }
+
+    pub fn set_ah(&mut self, value: u8) {
+        self.set_reg8(4, value);
+    }
+
+    // FLAGS as the CPU stores it on the stack
+    pub fn flags_word(&self) -> u16 {
+        self.flags.bits() | FLAGS_RESERVED_ONES
+    }
+
+    pub fn set_flags_word(&mut self, value: u16) {
+        self.flags = Flags::from_bits_truncate(value);
+    }
+
+    pub fn flag(&self, flag: Flags) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn set_flag(&mut self, flag: Flags, value: bool) {
+        self.flags.set(flag, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reg8_aliases_reg16() {
+        let mut regs = Registers {
+            bx: 0x1234,
+            ..Registers::default()
+        };
+        assert_eq!(0x34, regs.reg8(3));
+        assert_eq!(0x12, regs.reg8(7));
+        regs.set_reg8(7, 0xAB);
+        regs.set_reg8(1, 0xCD);
+        assert_eq!(0xAB34, regs.bx);
+        assert_eq!(0x00CD, regs.cx);
+    }
+
+    #[test]
+    fn test_flags_word() {
+        let mut regs = Registers::default();
+        assert_eq!(0xF002, regs.flags_word());
+        regs.set_flags_word(0x0FFF);
+        assert_eq!(
+            Flags::CARRY_FLAG
+                | Flags::PARITY_FLAG
+                | Flags::AUXILIARY_CARRY_FLAG
+                | Flags::ZERO_FLAG
+                | Flags::SIGN_FLAG
+                | Flags::TRAP_FLAG
+                | Flags::INTERRUPT_FLAG
+                | Flags::DIRECTION_FLAG
+                | Flags::OVERFLOW_FLAG,
+            regs.flags
+        );
+        assert_eq!(0xFFD7, regs.flags_word());
+    }
+}
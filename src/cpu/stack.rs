@@ -0,0 +1,261 @@
+use super::{modrm::Operand, Cpu, SegReg};
+
+impl Cpu {
+    // 50-57. PUSH SP stores SP after it has been decremented, the 80286 and
+    // later store the value from before the instruction.
+    pub(super) fn push_reg16(&mut self, index: u8) {
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        let value = self.regs.reg16(index);
+        self.write_u16(SegReg::Ss, self.regs.sp, value);
+        self.clock(11);
+    }
+
+    // 58-5F
+    pub(super) fn pop_reg16(&mut self, index: u8) {
+        let value = self.pop16();
+        self.regs.set_reg16(index, value);
+        self.clock(8);
+    }
+
+    // 06 0E 16 1E
+    pub(super) fn push_seg(&mut self, opcode: u8) {
+        let value = self.regs.seg(SegReg::from_index(opcode >> 3));
+        self.push16(value);
+        self.clock(10);
+    }
+
+    // 07 0F 17 1F. 0F is POP CS, which only the 8086/8088 execute.
+    pub(super) fn pop_seg(&mut self, opcode: u8) {
+        let value = self.pop16();
+        self.load_segment(SegReg::from_index(opcode >> 3), value);
+        self.clock(8);
+    }
+
+    // FF /6, and its alias FF /7. As with PUSH SP the register form stores
+    // the decremented SP.
+    pub(super) fn push_rm(&mut self, operand: Operand, word: bool) {
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        let value = self.read_operand(operand, word);
+        self.write_u16(SegReg::Ss, self.regs.sp, value);
+        self.clock(match operand {
+            Operand::Register(_) => 11,
+            Operand::Memory(..) => 16,
+        });
+    }
+
+    // 8F /0, the 8088 ignores the reg field
+    pub(super) fn pop_rm(&mut self) {
+        let modrm = self.decode_modrm();
+        if modrm.reg != 0 && self.trap_undocumented() {
+            return;
+        }
+        let value = self.pop16();
+        self.write_operand16(modrm.operand, value);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 8,
+            Operand::Memory(..) => 17,
+        });
+    }
+
+    // 60: PUSHA on the V20, SP as it was before the instruction
+    pub(super) fn pusha(&mut self) {
+        let sp = self.regs.sp;
+        for index in 0..8 {
+            let value = if index == 4 {
+                sp
+            } else {
+                self.regs.reg16(index)
+            };
+            self.push16(value);
+        }
+        self.clock(36);
+    }
+
+    // 61: POPA, the saved SP is skipped
+    pub(super) fn popa(&mut self) {
+        for index in (0..8).rev() {
+            let value = self.pop16();
+            if index != 4 {
+                self.regs.set_reg16(index, value);
+            }
+        }
+        self.clock(51);
+    }
+
+    // 68: PUSH imm16, 6A: PUSH imm8 sign extended
+    pub(super) fn push_imm(&mut self, opcode: u8) {
+        let value = if opcode == 0x68 {
+            self.fetch_u16()
+        } else {
+            self.fetch_u8() as i8 as u16
+        };
+        self.push16(value);
+        self.clock(10);
+    }
+
+    // C8: ENTER imm16, imm8. The nesting level is taken modulo 32 and copies
+    // that many frame pointers from the enclosing frame.
+    pub(super) fn enter(&mut self) {
+        let size = self.fetch_u16();
+        let level = self.fetch_u8() & 0x1F;
+        self.push16(self.regs.bp);
+        let frame = self.regs.sp;
+        if level > 0 {
+            for _ in 1..level {
+                self.regs.bp = self.regs.bp.wrapping_sub(2);
+                let value = self.read_u16(SegReg::Ss, self.regs.bp);
+                self.push16(value);
+            }
+            self.push16(frame);
+        }
+        self.regs.bp = frame;
+        self.regs.sp = self.regs.sp.wrapping_sub(size);
+        self.clock(match level {
+            0 => 15,
+            1 => 25,
+            _ => 22 + 16 * (level as u32 - 1),
+        });
+    }
+
+    // C9: LEAVE
+    pub(super) fn leave(&mut self) {
+        self.regs.sp = self.regs.bp;
+        self.regs.bp = self.pop16();
+        self.clock(8);
+    }
+
+    pub(super) fn pushf(&mut self) {
+        self.push16(self.regs.flags_word());
+        self.clock(10);
+    }
+
+    pub(super) fn popf(&mut self) {
+        let value = self.pop16();
+        self.regs.set_flags_word(value);
+        self.clock(8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, DATA_SEGMENT, STACK_SEGMENT};
+    use crate::alu::Flags;
+
+    #[test]
+    fn test_push_pop_reg() {
+        // push bx ; pop cx
+        let mut cpu = cpu_with_code(&[0x53, 0x59]);
+        cpu.regs.bx = 0x1234;
+        assert_eq!(15, cpu.step());
+        assert_eq!(0xFE, cpu.regs.sp);
+        assert_eq!(
+            0x1234,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        assert_eq!(12, cpu.step());
+        assert_eq!(0x1234, cpu.regs.cx);
+        assert_eq!(0x100, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_push_sp_stores_decremented_value() {
+        // push sp ; push word sp (FF F4)
+        let mut cpu = cpu_with_code(&[0x54, 0xFF, 0xF4]);
+        cpu.step();
+        assert_eq!(
+            0xFE,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFE))
+        );
+        cpu.step();
+        assert_eq!(
+            0xFC,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFC))
+        );
+    }
+
+    #[test]
+    fn test_pop_cs() {
+        // push ax ; pop cs
+        let mut cpu = cpu_with_code(&[0x50, 0x0F]);
+        cpu.regs.ax = 0x5000;
+        cpu.step();
+        assert_eq!(12, cpu.step());
+        assert_eq!(0x5000, cpu.regs.cs);
+        assert_eq!(2, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_push_pop_seg() {
+        // push es ; pop ds
+        let mut cpu = cpu_with_code(&[0x06, 0x1F]);
+        assert_eq!(14, cpu.step());
+        cpu.step();
+        assert_eq!(cpu.regs.es, cpu.regs.ds);
+    }
+
+    #[test]
+    fn test_sp_wraps_within_segment() {
+        // push ax with SP at 0 and at 1 ; pop bx
+        let mut cpu = cpu_with_code(&[0x50, 0x50, 0x5B]);
+        cpu.regs.ax = 0xABCD;
+        cpu.regs.sp = 0;
+        cpu.step();
+        assert_eq!(0xFFFE, cpu.regs.sp);
+        assert_eq!(
+            0xABCD,
+            cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFFFE))
+        );
+        cpu.regs.sp = 1;
+        cpu.step();
+        assert_eq!(0xFFFF, cpu.regs.sp);
+        assert_eq!(
+            0xCD,
+            cpu.mem.read_u8(physical_address(STACK_SEGMENT, 0xFFFF))
+        );
+        assert_eq!(0xAB, cpu.mem.read_u8(physical_address(STACK_SEGMENT, 0)));
+        cpu.step();
+        assert_eq!(0xABCD, cpu.regs.bx);
+        assert_eq!(1, cpu.regs.sp);
+    }
+
+    #[test]
+    fn test_push_pop_memory() {
+        // push word [10h] ; pop word [12h]
+        let mut cpu = cpu_with_code(&[0xFF, 0x36, 0x10, 0x00, 0x8F, 0x06, 0x12, 0x00]);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x10), 0x4321);
+        // 16 + 6 EA + 8 for two word accesses
+        assert_eq!(30, cpu.step());
+        // the BIU is still 2 clocks short of the last byte of the pop
+        assert_eq!(2 + 31, cpu.step());
+        assert_eq!(
+            0x4321,
+            cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0x12))
+        );
+    }
+
+    #[test]
+    fn test_pushf_popf() {
+        // pushf ; pop ax ; push bx ; popf
+        let mut cpu = cpu_with_code(&[0x9C, 0x58, 0x53, 0x9D]);
+        cpu.regs.flags = Flags::CARRY_FLAG | Flags::DIRECTION_FLAG;
+        cpu.regs.bx = 0x0ED5;
+        assert_eq!(14, cpu.step());
+        cpu.step();
+        assert_eq!(0xF403, cpu.regs.ax);
+        cpu.step();
+        cpu.step();
+        assert_eq!(
+            Flags::CARRY_FLAG
+                | Flags::PARITY_FLAG
+                | Flags::AUXILIARY_CARRY_FLAG
+                | Flags::ZERO_FLAG
+                | Flags::SIGN_FLAG
+                | Flags::INTERRUPT_FLAG
+                | Flags::DIRECTION_FLAG
+                | Flags::OVERFLOW_FLAG,
+            cpu.regs.flags
+        );
+    }
+}
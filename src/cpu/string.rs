@@ -0,0 +1,288 @@
+use super::{Cpu, Repeat, SegReg};
+use crate::alu::{self, Flags};
+
+impl Cpu {
+    // A4-A7, AA-AF, and INS/OUTS 6C-6F on the V20. With a REP prefix only
+    // one iteration runs per step, so interrupts get a chance between
+    // iterations; the rest are picked up by the following steps through
+    // `repeat_pending`.
+    pub(super) fn string_op(&mut self, opcode: u8) {
+        if self.prefixes.repeat.is_some() {
+            self.clock(9);
+            if self.regs.cx == 0 {
+                return;
+            }
+        }
+        self.string_iteration(opcode);
+    }
+
+    pub(super) fn string_iteration(&mut self, opcode: u8) {
+        let word = opcode & 1 == 1;
+        let delta: u16 = match (word, self.regs.flag(Flags::DIRECTION_FLAG)) {
+            (false, false) => 1,
+            (false, true) => 0xFFFF,
+            (true, false) => 2,
+            (true, true) => 0xFFFE,
+        };
+        let src = self.segment_or(SegReg::Ds);
+        let repeat = self.prefixes.repeat;
+        let (si, di) = (self.regs.si, self.regs.di);
+
+        // clocks for a single instruction and per repetition
+        let (single, repeated) = match opcode {
+            // MOVS
+            0xA4 | 0xA5 => {
+                if word {
+                    let value = self.read_u16(src, si);
+                    self.write_u16(SegReg::Es, di, value);
+                } else {
+                    let value = self.read_u8(src, si);
+                    self.write_u8(SegReg::Es, di, value);
+                }
+                self.regs.si = si.wrapping_add(delta);
+                self.regs.di = di.wrapping_add(delta);
+                (18, 17)
+            }
+            // CMPS
+            0xA6 | 0xA7 => {
+                let flags = if word {
+                    let op1 = self.read_u16(src, si);
+                    alu::cmp16(op1, self.read_u16(SegReg::Es, di))
+                } else {
+                    let op1 = self.read_u8(src, si);
+                    alu::cmp8(op1, self.read_u8(SegReg::Es, di))
+                };
+                self.set_arithmetic_flags(flags);
+                self.regs.si = si.wrapping_add(delta);
+                self.regs.di = di.wrapping_add(delta);
+                (22, 22)
+            }
+            // STOS
+            0xAA | 0xAB => {
+                if word {
+                    self.write_u16(SegReg::Es, di, self.regs.ax);
+                } else {
+                    self.write_u8(SegReg::Es, di, self.regs.al());
+                }
+                self.regs.di = di.wrapping_add(delta);
+                (11, 10)
+            }
+            // LODS
+            0xAC | 0xAD => {
+                if word {
+                    self.regs.ax = self.read_u16(src, si);
+                } else {
+                    let value = self.read_u8(src, si);
+                    self.regs.set_al(value);
+                }
+                self.regs.si = si.wrapping_add(delta);
+                (12, 13)
+            }
+            // INS
+            0x6C | 0x6D => {
+                let port = self.regs.dx;
+                if word {
+                    let value = self.read_io_u16(port);
+                    self.write_u16(SegReg::Es, di, value);
+                } else {
+                    let value = self.read_io_u8(port);
+                    self.write_u8(SegReg::Es, di, value);
+                }
+                self.regs.di = di.wrapping_add(delta);
+                (14, 8)
+            }
+            // OUTS
+            0x6E | 0x6F => {
+                let port = self.regs.dx;
+                if word {
+                    let value = self.read_u16(src, si);
+                    self.write_io_u16(port, value);
+                } else {
+                    let value = self.read_u8(src, si);
+                    self.write_io_u8(port, value);
+                }
+                self.regs.si = si.wrapping_add(delta);
+                (14, 8)
+            }
+            // SCAS
+            _ => {
+                let flags = if word {
+                    alu::cmp16(self.regs.ax, self.read_u16(SegReg::Es, di))
+                } else {
+                    alu::cmp8(self.regs.al(), self.read_u8(SegReg::Es, di))
+                };
+                self.set_arithmetic_flags(flags);
+                self.regs.di = di.wrapping_add(delta);
+                (15, 15)
+            }
+        };
+
+        let repeat = match repeat {
+            Some(repeat) => repeat,
+            None => {
+                self.clock(single);
+                return;
+            }
+        };
+        self.clock(repeated);
+        self.regs.cx = self.regs.cx.wrapping_sub(1);
+        // only CMPS and SCAS look at ZF, or CF for REPC/REPNC; the others
+        // treat every repeat prefix like F3
+        let compares = matches!(opcode, 0xA6 | 0xA7 | 0xAE | 0xAF);
+        let zero = self.regs.flag(Flags::ZERO_FLAG);
+        let carry = self.regs.flag(Flags::CARRY_FLAG);
+        let finished = self.regs.cx == 0
+            || compares
+                && match repeat {
+                    Repeat::WhileEqual => !zero,
+                    Repeat::WhileNotEqual => zero,
+                    Repeat::WhileCarry => !carry,
+                    Repeat::WhileNoCarry => carry,
+                };
+        if !finished {
+            self.repeat_pending = Some(opcode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, DATA_SEGMENT, EXTRA_SEGMENT, STACK_SEGMENT};
+    use super::*;
+
+    fn run_to_completion(cpu: &mut Cpu) -> u32 {
+        let mut cycles = cpu.step();
+        while cpu.is_repeating() {
+            cycles += cpu.step();
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_movsb() {
+        let mut cpu = cpu_with_code(&[0xA4]);
+        cpu.regs.si = 0x10;
+        cpu.regs.di = 0x20;
+        cpu.mem.write_u8(physical_address(DATA_SEGMENT, 0x10), 0x42);
+        assert_eq!(18, cpu.step());
+        assert_eq!(0x42, cpu.mem.read_u8(physical_address(EXTRA_SEGMENT, 0x20)));
+        assert_eq!((0x11, 0x21), (cpu.regs.si, cpu.regs.di));
+        assert!(!cpu.is_repeating());
+    }
+
+    #[test]
+    fn test_rep_movsw_backwards() {
+        // std ; rep movsw
+        let mut cpu = cpu_with_code(&[0xFD, 0xF3, 0xA5]);
+        cpu.regs.si = 0x14;
+        cpu.regs.di = 0x24;
+        cpu.regs.cx = 3;
+        for (i, word) in [0x1111, 0x2222, 0x3333].iter().enumerate() {
+            cpu.mem
+                .write_u16(physical_address(DATA_SEGMENT, 0x10 + 2 * i as u16), *word);
+        }
+        cpu.step();
+        // 2 prefix + 9 + 3 * (17 + 8)
+        assert_eq!(2 + 9 + 3 * 25, run_to_completion(&mut cpu));
+        assert_eq!(0, cpu.regs.cx);
+        assert_eq!((0x0E, 0x1E), (cpu.regs.si, cpu.regs.di));
+        assert_eq!(
+            0x1111,
+            cpu.mem.read_u16(physical_address(EXTRA_SEGMENT, 0x20))
+        );
+        assert_eq!(
+            0x3333,
+            cpu.mem.read_u16(physical_address(EXTRA_SEGMENT, 0x24))
+        );
+        assert_eq!(3, cpu.regs.ip);
+    }
+
+    #[test]
+    fn test_rep_with_zero_count() {
+        let mut cpu = cpu_with_code(&[0xF3, 0xAA]);
+        assert_eq!(11, cpu.step());
+        assert_eq!(0, cpu.regs.di);
+        assert!(!cpu.is_repeating());
+    }
+
+    #[test]
+    fn test_rep_runs_one_iteration_per_step() {
+        // rep stosb
+        let mut cpu = cpu_with_code(&[0xF3, 0xAA]);
+        cpu.regs.cx = 3;
+        cpu.regs.ax = 0x55;
+        assert_eq!(2 + 9 + 10, cpu.step());
+        assert_eq!(2, cpu.regs.cx);
+        assert!(cpu.is_repeating());
+        assert_eq!(10, cpu.step());
+        assert_eq!(10, cpu.step());
+        assert!(!cpu.is_repeating());
+        assert_eq!(0, cpu.regs.cx);
+        assert_eq!(3, cpu.regs.di);
+        assert_eq!(0x55, cpu.mem.read_u8(physical_address(EXTRA_SEGMENT, 2)));
+    }
+
+    #[test]
+    fn test_source_override_destination_fixed() {
+        // ss: movsb ; es stays the destination whatever the override
+        let mut cpu = cpu_with_code(&[0x36, 0xA4]);
+        cpu.mem.write_u8(physical_address(STACK_SEGMENT, 0), 0x99);
+        cpu.step();
+        assert_eq!(0x99, cpu.mem.read_u8(physical_address(EXTRA_SEGMENT, 0)));
+        assert_eq!(0, cpu.mem.read_u8(physical_address(DATA_SEGMENT, 0)));
+    }
+
+    #[test]
+    fn test_repe_cmpsb_stops_on_difference() {
+        let mut cpu = cpu_with_code(&[0xF3, 0xA6]);
+        cpu.mem.load(physical_address(DATA_SEGMENT, 0), b"abcd");
+        cpu.mem.load(physical_address(EXTRA_SEGMENT, 0), b"abXd");
+        cpu.regs.cx = 4;
+        run_to_completion(&mut cpu);
+        assert_eq!(1, cpu.regs.cx);
+        assert_eq!((3, 3), (cpu.regs.si, cpu.regs.di));
+        assert!(!cpu.regs.flag(Flags::ZERO_FLAG));
+        assert_eq!(
+            alu::cmp8(b'c', b'X'),
+            cpu.regs.flags & super::super::ARITHMETIC_FLAGS
+        );
+    }
+
+    #[test]
+    fn test_repne_scasb_finds_byte() {
+        let mut cpu = cpu_with_code(&[0xF2, 0xAE]);
+        cpu.mem.load(physical_address(EXTRA_SEGMENT, 0), b"hello\0");
+        cpu.regs.cx = 0xFFFF;
+        cpu.regs.ax = 0;
+        run_to_completion(&mut cpu);
+        assert_eq!(6, cpu.regs.di);
+        assert_eq!(0xFFFF - 6, cpu.regs.cx);
+        assert!(cpu.regs.flag(Flags::ZERO_FLAG));
+    }
+
+    #[test]
+    fn test_repne_movsb_repeats_like_rep() {
+        // F2 on a non-comparing string op ignores ZF
+        let mut cpu = cpu_with_code(&[0xF2, 0xA4]);
+        cpu.regs.cx = 2;
+        cpu.regs.flags = Flags::ZERO_FLAG;
+        run_to_completion(&mut cpu);
+        assert_eq!(0, cpu.regs.cx);
+        assert_eq!(2, cpu.regs.si);
+    }
+
+    #[test]
+    fn test_lodsw_scasw() {
+        // lodsw ; scasw
+        let mut cpu = cpu_with_code(&[0xAD, 0xAF]);
+        cpu.mem.write_u16(physical_address(DATA_SEGMENT, 0), 0x1234);
+        cpu.mem
+            .write_u16(physical_address(EXTRA_SEGMENT, 0), 0x1235);
+        assert_eq!(16, cpu.step());
+        assert_eq!(0x1234, cpu.regs.ax);
+        assert_eq!(19, cpu.step());
+        assert_eq!(alu::cmp16(0x1234, 0x1235), cpu.regs.flags);
+        assert_eq!(2, cpu.regs.di);
+    }
+}
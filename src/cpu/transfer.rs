@@ -0,0 +1,369 @@
+use super::{modrm::Operand, Cpu, SegReg};
+use crate::alu::Flags;
+
+// SF ZF AF PF CF, the flags LAHF/SAHF move through AH
+pub(super) const AH_FLAGS: u16 = 0x00D5;
+
+impl Cpu {
+    // 88-8B: MOV between r/m and a register
+    pub(super) fn mov_rm_reg(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        let to_reg = opcode & 2 != 0;
+        match (opcode & 1 == 1, to_reg) {
+            (false, false) => self.write_operand8(modrm.operand, self.regs.reg8(modrm.reg)),
+            (true, false) => self.write_operand16(modrm.operand, self.regs.reg16(modrm.reg)),
+            (false, true) => {
+                let value = self.read_operand8(modrm.operand);
+                self.regs.set_reg8(modrm.reg, value);
+            }
+            (true, true) => {
+                let value = self.read_operand16(modrm.operand);
+                self.regs.set_reg16(modrm.reg, value);
+            }
+        }
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 2,
+            Operand::Memory(..) if to_reg => 8,
+            Operand::Memory(..) => 9,
+        });
+    }
+
+    // 8C: MOV r/m16, sreg. Only the low two bits of reg select the segment.
+    pub(super) fn mov_rm_seg(&mut self) {
+        let modrm = self.decode_modrm();
+        let value = self.regs.seg(SegReg::from_index(modrm.reg));
+        self.write_operand16(modrm.operand, value);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 2,
+            Operand::Memory(..) => 9,
+        });
+    }
+
+    // 8E: MOV sreg, r/m16. The 8088 happily loads CS this way.
+    pub(super) fn mov_seg_rm(&mut self) {
+        let modrm = self.decode_modrm();
+        let value = self.read_operand16(modrm.operand);
+        self.load_segment(SegReg::from_index(modrm.reg), value);
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 2,
+            Operand::Memory(..) => 8,
+        });
+    }
+
+    // with a register operand LEA yields the last offset the EA logic computed
+    pub(super) fn lea(&mut self) {
+        let modrm = self.decode_modrm();
+        let offset = match modrm.operand {
+            Operand::Memory(_, offset) => offset,
+            Operand::Register(_) => self.last_ea,
+        };
+        self.regs.set_reg16(modrm.reg, offset);
+        self.clock(2);
+    }
+
+    // C4/C5: LES/LDS
+    pub(super) fn load_far_pointer(&mut self, seg: SegReg) {
+        let modrm = self.decode_modrm();
+        let (ptr_seg, offset) = match modrm.operand {
+            Operand::Memory(ptr_seg, offset) => (ptr_seg, offset),
+            Operand::Register(_) => (self.segment_or(SegReg::Ds), self.last_ea),
+        };
+        let value = self.read_u16(ptr_seg, offset);
+        let segment = self.read_u16(ptr_seg, offset.wrapping_add(2));
+        self.regs.set_reg16(modrm.reg, value);
+        self.regs.set_seg(seg, segment);
+        self.clock(16);
+    }
+
+    // 86/87: XCHG r/m, reg
+    pub(super) fn xchg_rm_reg(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        if opcode & 1 == 0 {
+            let value = self.read_operand8(modrm.operand);
+            self.write_operand8(modrm.operand, self.regs.reg8(modrm.reg));
+            self.regs.set_reg8(modrm.reg, value);
+        } else {
+            let value = self.read_operand16(modrm.operand);
+            self.write_operand16(modrm.operand, self.regs.reg16(modrm.reg));
+            self.regs.set_reg16(modrm.reg, value);
+        }
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 4,
+            Operand::Memory(..) => 17,
+        });
+    }
+
+    // 90-97: XCHG AX, r16; 90 is NOP
+    pub(super) fn xchg_ax(&mut self, index: u8) {
+        let value = self.regs.reg16(index);
+        self.regs.set_reg16(index, self.regs.ax);
+        self.regs.ax = value;
+        self.clock(3);
+    }
+
+    // A0-A3: MOV between the accumulator and a direct address
+    pub(super) fn mov_acc_moffs(&mut self, opcode: u8) {
+        let offset = self.fetch_u16();
+        let seg = self.segment_or(SegReg::Ds);
+        match opcode & 3 {
+            0 => {
+                let value = self.read_u8(seg, offset);
+                self.regs.set_al(value);
+            }
+            1 => self.regs.ax = self.read_u16(seg, offset),
+            2 => self.write_u8(seg, offset, self.regs.al()),
+            _ => self.write_u16(seg, offset, self.regs.ax),
+        }
+        self.clock(10);
+    }
+
+    // B0-BF: MOV reg, imm
+    pub(super) fn mov_reg_imm(&mut self, opcode: u8) {
+        if opcode & 8 == 0 {
+            let value = self.fetch_u8();
+            self.regs.set_reg8(opcode & 7, value);
+        } else {
+            let value = self.fetch_u16();
+            self.regs.set_reg16(opcode & 7, value);
+        }
+        self.clock(4);
+    }
+
+    // C6/C7: MOV r/m, imm; the 8088 ignores the reg field, strict mode traps
+    // anything but 0
+    pub(super) fn mov_rm_imm(&mut self, opcode: u8) {
+        let modrm = self.decode_modrm();
+        if modrm.reg != 0 && self.trap_undocumented() {
+            return;
+        }
+        if opcode & 1 == 0 {
+            let value = self.fetch_u8();
+            self.write_operand8(modrm.operand, value);
+        } else {
+            let value = self.fetch_u16();
+            self.write_operand16(modrm.operand, value);
+        }
+        self.clock(match modrm.operand {
+            Operand::Register(_) => 4,
+            Operand::Memory(..) => 10,
+        });
+    }
+
+    pub(super) fn xlat(&mut self) {
+        let offset = self.regs.bx.wrapping_add(self.regs.al() as u16);
+        let value = self.read_u8(self.segment_or(SegReg::Ds), offset);
+        self.regs.set_al(value);
+        self.clock(11);
+    }
+
+    pub(super) fn lahf(&mut self) {
+        self.regs.set_ah(self.regs.flags_word() as u8);
+        self.clock(4);
+    }
+
+    pub(super) fn sahf(&mut self) {
+        let flags = (self.regs.flags.bits() & !AH_FLAGS) | (self.regs.ah() as u16 & AH_FLAGS);
+        self.regs.set_flags_word(flags);
+        self.clock(4);
+    }
+
+    // E4-E7 with an immediate port, EC-EF with the port in DX
+    pub(super) fn in_out(&mut self, opcode: u8) {
+        let (port, clocks) = if opcode & 8 == 0 {
+            (self.fetch_u8() as u16, 10)
+        } else {
+            (self.regs.dx, 8)
+        };
+        match opcode & 3 {
+            0 => {
+                let value = self.read_io_u8(port);
+                self.regs.set_al(value);
+            }
+            1 => self.regs.ax = self.read_io_u16(port),
+            2 => self.write_io_u8(port, self.regs.al()),
+            _ => self.write_io_u16(port, self.regs.ax),
+        }
+        self.clock(clocks);
+    }
+
+    // CMC CLC STC CLI STI CLD STD
+    pub(super) fn flag_op(&mut self, opcode: u8) {
+        match opcode {
+            0xF5 => self.regs.flags.toggle(Flags::CARRY_FLAG),
+            0xF8 => self.regs.set_flag(Flags::CARRY_FLAG, false),
+            0xF9 => self.regs.set_flag(Flags::CARRY_FLAG, true),
+            0xFA => self.regs.set_flag(Flags::INTERRUPT_FLAG, false),
+            0xFB => {
+                // INTR is recognised only after the next instruction
+                self.regs.set_flag(Flags::INTERRUPT_FLAG, true);
+                self.sti_shadow = true;
+            }
+            0xFC => self.regs.set_flag(Flags::DIRECTION_FLAG, false),
+            _ => self.regs.set_flag(Flags::DIRECTION_FLAG, true),
+        }
+        self.clock(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::super::physical_address;
+    use super::super::tests::{cpu_with_code, DATA_SEGMENT, EXTRA_SEGMENT};
+    use super::*;
+    use crate::io::IoDevice;
+
+    #[test]
+    fn test_mov_rm_reg() {
+        // mov [bx+si], ax ; mov cl, [bx+si+1]
+        let mut cpu = cpu_with_code(&[0x89, 0x00, 0x8A, 0x48, 0x01]);
+        cpu.regs.ax = 0xABCD;
+        cpu.regs.bx = 0x100;
+        // 9 + 7 EA + 4
+        assert_eq!(20, cpu.step());
+        assert_eq!(
+            0xABCD,
+            cpu.mem.read_u16(physical_address(DATA_SEGMENT, 0x100))
+        );
+        assert_eq!(8 + 11, cpu.step());
+        assert_eq!(0xAB, cpu.regs.cx);
+    }
+
+    #[test]
+    fn test_mov_segment_registers() {
+        // mov es, ax ; mov [0004h], es ; mov cs, bx
+        let mut cpu = cpu_with_code(&[0x8E, 0xC0, 0x8C, 0x06, 0x04, 0x00, 0x8E, 0xCB]);
+        cpu.regs.ax = 0x5000;
+        cpu.regs.bx = 0x0000;
+        cpu.step();
+        assert_eq!(0x5000, cpu.regs.es);
+        cpu.step();
+        assert_eq!(0x5000, cpu.mem.read_u16(physical_address(DATA_SEGMENT, 4)));
+        cpu.step();
+        assert_eq!(0x0000, cpu.regs.cs);
+    }
+
+    #[test]
+    fn test_lea_and_les() {
+        // lea si, [bp+di+10h] ; les di, [bx]
+        let mut cpu = cpu_with_code(&[0x8D, 0x73, 0x10, 0xC4, 0x3F]);
+        cpu.regs.bp = 0x200;
+        cpu.regs.di = 0x2;
+        cpu.regs.bx = 0x40;
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x40), 0x1234);
+        cpu.mem
+            .write_u16(physical_address(DATA_SEGMENT, 0x42), 0xB800);
+        cpu.step();
+        assert_eq!(0x212, cpu.regs.si);
+        // 16 + 5 EA + 4 + 4
+        assert_eq!(29, cpu.step());
+        assert_eq!(0x1234, cpu.regs.di);
+        assert_eq!(0xB800, cpu.regs.es);
+    }
+
+    #[test]
+    fn test_xchg() {
+        // xchg cx, ax ; xchg [bx], dl
+        let mut cpu = cpu_with_code(&[0x91, 0x86, 0x17]);
+        cpu.regs.ax = 1;
+        cpu.regs.cx = 2;
+        cpu.regs.dx = 0x33;
+        cpu.mem.write_u8(physical_address(DATA_SEGMENT, 0), 0x44);
+        assert_eq!(3, cpu.step());
+        assert_eq!((2, 1), (cpu.regs.ax, cpu.regs.cx));
+        cpu.step();
+        assert_eq!(0x44, cpu.regs.dx);
+        assert_eq!(0x33, cpu.mem.read_u8(physical_address(DATA_SEGMENT, 0)));
+    }
+
+    #[test]
+    fn test_mov_immediates() {
+        // mov bh, 12h ; mov bp, 3456h ; mov word es:[di], 789Ah
+        let mut cpu = cpu_with_code(&[0xB7, 0x12, 0xBD, 0x56, 0x34, 0x26, 0xC7, 0x05, 0x9A, 0x78]);
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert_eq!(0x1200, cpu.regs.bx);
+        assert_eq!(0x3456, cpu.regs.bp);
+        assert_eq!(0x789A, cpu.mem.read_u16(physical_address(EXTRA_SEGMENT, 0)));
+    }
+
+    #[test]
+    fn test_xlat_with_override() {
+        // es: xlat
+        let mut cpu = cpu_with_code(&[0x26, 0xD7]);
+        cpu.regs.bx = 0x100;
+        cpu.regs.ax = 0x05;
+        cpu.mem
+            .write_u8(physical_address(EXTRA_SEGMENT, 0x105), 0x42);
+        cpu.step();
+        assert_eq!(0x42, cpu.regs.al());
+    }
+
+    #[test]
+    fn test_lahf_sahf() {
+        // lahf ; sahf
+        let mut cpu = cpu_with_code(&[0x9F, 0x9E]);
+        cpu.regs.flags = Flags::CARRY_FLAG | Flags::OVERFLOW_FLAG | Flags::SIGN_FLAG;
+        cpu.step();
+        assert_eq!(0x83, cpu.regs.ah());
+        cpu.regs.set_ah(0x44);
+        cpu.step();
+        assert_eq!(
+            Flags::ZERO_FLAG | Flags::PARITY_FLAG | Flags::OVERFLOW_FLAG,
+            cpu.regs.flags
+        );
+    }
+
+    struct Port {
+        written: Vec<(u16, u8)>,
+    }
+
+    impl IoDevice for Port {
+        fn read_u8(&mut self, port: u16) -> u8 {
+            port as u8
+        }
+
+        fn write_u8(&mut self, port: u16, value: u8) {
+            self.written.push((port, value));
+        }
+    }
+
+    #[test]
+    fn test_in_out() {
+        // in al, 60h ; out dx, ax ; in ax, dx
+        let mut cpu = cpu_with_code(&[0xE4, 0x60, 0xEF, 0xED]);
+        let port = Rc::new(RefCell::new(Port {
+            written: Vec::new(),
+        }));
+        cpu.io.register(0, 0x3FF, Box::new(port.clone())).unwrap();
+        cpu.regs.dx = 0x3D4;
+        assert_eq!(10, cpu.step());
+        assert_eq!(0x60, cpu.regs.al());
+        cpu.regs.ax = 0x0A0E;
+        assert_eq!(12, cpu.step());
+        assert_eq!(vec![(0x3D4, 0x0E), (0x3D5, 0x0A)], port.borrow().written);
+        cpu.step();
+        assert_eq!(0xD5D4, cpu.regs.ax);
+    }
+
+    #[test]
+    fn test_flag_ops() {
+        // stc ; cmc ; std ; sti ; cli
+        let mut cpu = cpu_with_code(&[0xF9, 0xF5, 0xFD, 0xFB, 0xFA]);
+        cpu.step();
+        assert!(cpu.regs.flag(Flags::CARRY_FLAG));
+        cpu.step();
+        assert!(!cpu.regs.flag(Flags::CARRY_FLAG));
+        cpu.step();
+        cpu.step();
+        assert_eq!(
+            Flags::DIRECTION_FLAG | Flags::INTERRUPT_FLAG,
+            cpu.regs.flags
+        );
+        cpu.step();
+        assert_eq!(Flags::DIRECTION_FLAG, cpu.regs.flags);
+    }
+}
@@ -0,0 +1,617 @@
+// The 8237A DMA controller at 00h-0Fh, with the PC's page registers at
+// 80h-87h supplying address bits 16-19. Transfers go over the CPU's own
+// memory bus, so ROM ignores DMA writes and wait states slow DMA down as
+// they do the CPU.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{cpu::BUS_CYCLE, io::IoDevice, memory::MemoryBus};
+
+// command register
+const MEMORY_TO_MEMORY: u8 = 0x01;
+const ADDRESS_HOLD: u8 = 0x02;
+const DISABLE: u8 = 0x04;
+const COMPRESSED_TIMING: u8 = 0x08;
+const ROTATING_PRIORITY: u8 = 0x10;
+const DREQ_ACTIVE_LOW: u8 = 0x40;
+// mode register
+const TRANSFER_WRITE: u8 = 0x04;
+const TRANSFER_READ: u8 = 0x08;
+const AUTO_INIT: u8 = 0x10;
+const DECREMENT: u8 = 0x20;
+const DEMAND: u8 = 0x00;
+const SINGLE: u8 = 0x40;
+const BLOCK: u8 = 0x80;
+// the page register port of each channel, 80h-87h
+const PAGE_REGISTERS: [usize; 4] = [7, 3, 1, 2];
+
+/// A peripheral on a DMA channel. It asks for the bus with DREQ; each DACK
+/// then moves one byte, from the device to memory for a write transfer and
+/// from memory to the device for a read transfer.
+pub trait DmaDevice {
+    fn dreq(&self) -> bool;
+    // a write transfer takes the byte from the device
+    fn read(&mut self) -> u8;
+    // a read transfer hands it the byte from memory
+    fn write(&mut self, value: u8);
+    // the count ran out with this transfer, the TC/EOP pulse
+    fn terminal_count(&mut self) {}
+}
+
+impl<T: DmaDevice + ?Sized> DmaDevice for Rc<RefCell<T>> {
+    fn dreq(&self) -> bool {
+        self.borrow().dreq()
+    }
+
+    fn read(&mut self) -> u8 {
+        self.borrow_mut().read()
+    }
+
+    fn write(&mut self, value: u8) {
+        self.borrow_mut().write(value)
+    }
+
+    fn terminal_count(&mut self) {
+        self.borrow_mut().terminal_count()
+    }
+}
+
+#[derive(Default)]
+struct Channel {
+    base_address: u16,
+    base_count: u16,
+    address: u16,
+    // one less than the bytes left
+    count: u16,
+    mode: u8,
+    masked: bool,
+    device: Option<Box<dyn DmaDevice>>,
+}
+
+impl Channel {
+    fn step_address(&mut self, hold: bool) {
+        if hold {
+            return;
+        }
+        self.address = if self.mode & DECREMENT != 0 {
+            self.address.wrapping_sub(1)
+        } else {
+            self.address.wrapping_add(1)
+        };
+    }
+
+    // true when this was the last byte
+    fn step_count(&mut self) -> bool {
+        let done = self.count == 0;
+        self.count = self.count.wrapping_sub(1);
+        done
+    }
+}
+
+pub struct Dma {
+    channels: [Channel; 4],
+    command: u8,
+    // channels that reached terminal count since the status was last read
+    terminal_counts: u8,
+    // software DREQs from the request register
+    requests: u8,
+    temporary: u8,
+    // the next byte of an address or count is the high one
+    high_byte: bool,
+    pages: [u8; 8],
+    // the channel with the highest priority, 0 unless rotating
+    highest: usize,
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Dma::new()
+    }
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        let mut dma = Dma {
+            channels: Default::default(),
+            command: 0,
+            terminal_counts: 0,
+            requests: 0,
+            temporary: 0,
+            high_byte: false,
+            pages: [0; 8],
+            highest: 0,
+        };
+        dma.master_clear();
+        dma
+    }
+
+    pub fn set_device(&mut self, channel: usize, device: Option<Box<dyn DmaDevice>>) {
+        self.channels[channel].device = device;
+    }
+
+    // the 20-bit address the next transfer on `channel` goes to
+    pub fn address(&self, channel: usize) -> u32 {
+        let page = self.pages[PAGE_REGISTERS[channel]] as u32 & 0x0F;
+        (page << 16) | self.channels[channel].address as u32
+    }
+
+    pub fn count(&self, channel: usize) -> u16 {
+        self.channels[channel].count
+    }
+
+    pub fn is_masked(&self, channel: usize) -> bool {
+        self.channels[channel].masked
+    }
+
+    fn master_clear(&mut self) {
+        self.command = 0;
+        self.terminal_counts = 0;
+        self.requests = 0;
+        self.temporary = 0;
+        self.high_byte = false;
+        self.highest = 0;
+        for channel in &mut self.channels {
+            channel.masked = true;
+        }
+    }
+
+    fn dreq(&self, channel: usize) -> bool {
+        if self.requests & (1 << channel) != 0 {
+            return true;
+        }
+        let channel = &self.channels[channel];
+        match &channel.device {
+            Some(device) if !channel.masked => {
+                device.dreq() != (self.command & DREQ_ACTIVE_LOW != 0)
+            }
+            _ => false,
+        }
+    }
+
+    fn cycle_clocks(&self) -> u32 {
+        if self.command & COMPRESSED_TIMING != 0 {
+            BUS_CYCLE - 1
+        } else {
+            BUS_CYCLE
+        }
+    }
+
+    // services every channel with a request, in priority order, and returns
+    // the clocks the transfers kept the CPU off the bus. A single mode
+    // channel gets one byte per call, block mode runs to the terminal count
+    // and demand mode for as long as DREQ stays up.
+    pub fn run(&mut self, mem: &mut MemoryBus) -> u32 {
+        if self.command & DISABLE != 0 {
+            return 0;
+        }
+        let mut clocks = 0;
+        let highest = self.highest;
+        for i in 0..4 {
+            let channel = (highest + i) & 3;
+            if !self.dreq(channel) {
+                continue;
+            }
+            if channel == 0 && self.command & MEMORY_TO_MEMORY != 0 {
+                clocks += self.memory_to_memory(mem);
+            } else {
+                match self.channels[channel].mode & 0xC0 {
+                    SINGLE => clocks += self.transfer(channel, mem).0,
+                    BLOCK => loop {
+                        let (cycle, done) = self.transfer(channel, mem);
+                        clocks += cycle;
+                        if done {
+                            break;
+                        }
+                    },
+                    DEMAND => loop {
+                        let (cycle, done) = self.transfer(channel, mem);
+                        clocks += cycle;
+                        if done || !self.dreq(channel) {
+                            break;
+                        }
+                    },
+                    // a cascaded controller drives the bus itself
+                    _ => continue,
+                }
+            }
+            if self.command & ROTATING_PRIORITY != 0 {
+                self.highest = (channel + 1) & 3;
+            }
+        }
+        clocks
+    }
+
+    fn transfer(&mut self, index: usize, mem: &mut MemoryBus) -> (u32, bool) {
+        let address = self.address(index);
+        let channel = &mut self.channels[index];
+        match (
+            channel.mode & (TRANSFER_WRITE | TRANSFER_READ),
+            &mut channel.device,
+        ) {
+            (TRANSFER_WRITE, Some(device)) => mem.write_u8(address, device.read()),
+            (TRANSFER_READ, Some(device)) => device.write(mem.read_u8(address)),
+            // a verify transfer only counts
+            _ => {}
+        }
+        channel.step_address(false);
+        let done = channel.step_count();
+        if done {
+            self.terminal_count(index);
+        }
+        (self.cycle_clocks() + mem.wait_states(address), done)
+    }
+
+    // channel 0 reads into the temporary register and channel 1 writes it
+    // out, until channel 1 runs out. With address hold channel 0 keeps
+    // reading the same byte, which fills the block with it.
+    fn memory_to_memory(&mut self, mem: &mut MemoryBus) -> u32 {
+        let hold = self.command & ADDRESS_HOLD != 0;
+        let mut clocks = 0;
+        loop {
+            let source = self.address(0);
+            let destination = self.address(1);
+            self.temporary = mem.read_u8(source);
+            mem.write_u8(destination, self.temporary);
+            self.channels[0].step_address(hold);
+            self.channels[0].step_count();
+            self.channels[1].step_address(false);
+            let done = self.channels[1].step_count();
+            clocks +=
+                2 * self.cycle_clocks() + mem.wait_states(source) + mem.wait_states(destination);
+            if done {
+                self.terminal_count(0);
+                self.terminal_count(1);
+                return clocks;
+            }
+        }
+    }
+
+    // auto-init reloads the channel, otherwise it masks itself
+    fn terminal_count(&mut self, index: usize) {
+        self.terminal_counts |= 1 << index;
+        self.requests &= !(1 << index);
+        let channel = &mut self.channels[index];
+        if let Some(device) = &mut channel.device {
+            device.terminal_count();
+        }
+        if channel.mode & AUTO_INIT != 0 {
+            channel.address = channel.base_address;
+            channel.count = channel.base_count;
+        } else {
+            channel.masked = true;
+        }
+    }
+
+    fn flip_flop(&mut self) -> bool {
+        let high = self.high_byte;
+        self.high_byte = !high;
+        high
+    }
+}
+
+impl IoDevice for Dma {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        // the PC's page registers are write only
+        if port & 0x80 != 0 {
+            return 0xFF;
+        }
+        match port & 0x0F {
+            port @ 0..=7 => {
+                let channel = &self.channels[port as usize >> 1];
+                let value = if port & 1 == 0 {
+                    channel.address
+                } else {
+                    channel.count
+                };
+                value.to_le_bytes()[self.flip_flop() as usize]
+            }
+            // reading the status clears the terminal count bits
+            8 => {
+                let requests = (0..4)
+                    .filter(|&i| self.dreq(i))
+                    .fold(0, |r, i| r | (1 << i));
+                let status = self.terminal_counts | (requests << 4);
+                self.terminal_counts = 0;
+                status
+            }
+            0x0D => self.temporary,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        if port & 0x80 != 0 {
+            self.pages[port as usize & 7] = value;
+            return;
+        }
+        let channel = value as usize & 3;
+        let set = value & 0x04 != 0;
+        match port & 0x0F {
+            port @ 0..=7 => {
+                let high = self.flip_flop();
+                let channel = &mut self.channels[port as usize >> 1];
+                let register = if port & 1 == 0 {
+                    &mut channel.base_address
+                } else {
+                    &mut channel.base_count
+                };
+                let mut bytes = register.to_le_bytes();
+                bytes[high as usize] = value;
+                *register = u16::from_le_bytes(bytes);
+                // writes go to the base and current registers alike
+                channel.address = channel.base_address;
+                channel.count = channel.base_count;
+            }
+            8 => self.command = value,
+            9 => {
+                if set {
+                    self.requests |= 1 << channel;
+                } else {
+                    self.requests &= !(1 << channel);
+                }
+            }
+            0x0A => self.channels[channel].masked = set,
+            0x0B => self.channels[channel].mode = value,
+            0x0C => self.high_byte = false,
+            0x0D => self.master_clear(),
+            0x0E => {
+                for channel in &mut self.channels {
+                    channel.masked = false;
+                }
+            }
+            _ => {
+                for (i, channel) in self.channels.iter_mut().enumerate() {
+                    channel.masked = value & (1 << i) != 0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Fifo {
+        // what the device has for write transfers
+        data: Vec<u8>,
+        received: Vec<u8>,
+        // holds DREQ up as long as there is data, or up until it is cleared
+        dreq: bool,
+        terminal_count: bool,
+    }
+
+    impl DmaDevice for Fifo {
+        fn dreq(&self) -> bool {
+            self.dreq
+        }
+
+        fn read(&mut self) -> u8 {
+            let value = self.data.remove(0);
+            self.dreq = !self.data.is_empty();
+            value
+        }
+
+        fn write(&mut self, value: u8) {
+            self.received.push(value);
+        }
+
+        fn terminal_count(&mut self) {
+            self.terminal_count = true;
+        }
+    }
+
+    fn fifo(data: &[u8]) -> Rc<RefCell<Fifo>> {
+        Rc::new(RefCell::new(Fifo {
+            data: data.to_vec(),
+            dreq: true,
+            ..Fifo::default()
+        }))
+    }
+
+    fn ram() -> MemoryBus {
+        let mut mem = MemoryBus::new();
+        mem.map_ram(0, 0x40000).unwrap();
+        mem
+    }
+
+    fn bytes(mem: &mut MemoryBus, address: u32, len: u32) -> Vec<u8> {
+        (address..address + len).map(|a| mem.read_u8(a)).collect()
+    }
+
+    // programs `channel` the way the BIOS does, page first
+    fn program(dma: &mut Dma, channel: usize, mode: u8, address: u32, count: u16) {
+        dma.write_u8(0x0A, 0x04 | channel as u8);
+        dma.write_u8(0x0B, mode | channel as u8);
+        dma.write_u8(0x0C, 0);
+        dma.write_u8(0x80 + PAGE_REGISTERS[channel] as u16, (address >> 16) as u8);
+        let port = channel as u16 * 2;
+        dma.write_u8(port, address as u8);
+        dma.write_u8(port, (address >> 8) as u8);
+        dma.write_u8(port + 1, count as u8);
+        dma.write_u8(port + 1, (count >> 8) as u8);
+        dma.write_u8(0x0A, channel as u8);
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut dma = Dma::new();
+        assert!((0..4).all(|i| dma.is_masked(i)));
+        program(&mut dma, 2, SINGLE | TRANSFER_WRITE, 0x1_2345, 0x01FF);
+        assert_eq!(0x1_2345, dma.address(2));
+        assert_eq!(0x01FF, dma.count(2));
+        assert!(!dma.is_masked(2));
+        dma.write_u8(0x0C, 0);
+        assert_eq!(0x45, dma.read_u8(0x04));
+        assert_eq!(0x23, dma.read_u8(0x04));
+        assert_eq!(0xFF, dma.read_u8(0x05));
+        assert_eq!(0x01, dma.read_u8(0x05));
+        assert_eq!(0xFF, dma.read_u8(0x81));
+        // all masks at once
+        dma.write_u8(0x0F, 0x0B);
+        assert!(dma.is_masked(0) && dma.is_masked(1) && !dma.is_masked(2));
+        dma.write_u8(0x0E, 0);
+        assert!((0..4).all(|i| !dma.is_masked(i)));
+        dma.write_u8(0x0D, 0);
+        assert!((0..4).all(|i| dma.is_masked(i)));
+    }
+
+    #[test]
+    fn test_single_mode_one_byte_per_request() {
+        let mut mem = ram();
+        let mut dma = Dma::new();
+        let device = fifo(&[1, 2, 3]);
+        dma.set_device(2, Some(Box::new(device.clone())));
+        program(&mut dma, 2, SINGLE | TRANSFER_WRITE, 0x1_0000, 2);
+        assert_eq!(4, dma.run(&mut mem));
+        assert_eq!(1, mem.read_u8(0x1_0000));
+        assert_eq!(0, mem.read_u8(0x1_0001));
+        dma.run(&mut mem);
+        dma.run(&mut mem);
+        assert_eq!(vec![1, 2, 3], bytes(&mut mem, 0x1_0000, 3));
+        assert!(device.borrow().terminal_count);
+        // terminal count masks the channel and shows in the status once
+        assert!(dma.is_masked(2));
+        assert_eq!(0x04, dma.read_u8(0x08));
+        assert_eq!(0x00, dma.read_u8(0x08));
+        assert_eq!(0, dma.run(&mut mem));
+    }
+
+    #[test]
+    fn test_block_mode_read() {
+        let mut mem = ram();
+        mem.load(0x2000, b"block");
+        let mut dma = Dma::new();
+        let device = fifo(&[]);
+        dma.set_device(3, Some(Box::new(device.clone())));
+        program(&mut dma, 3, BLOCK | TRANSFER_READ, 0x2000, 4);
+        mem.set_wait_states(0, 1);
+        assert_eq!(5 * 5, dma.run(&mut mem));
+        assert_eq!(b"block".to_vec(), device.borrow().received);
+    }
+
+    #[test]
+    fn test_demand_mode_follows_dreq() {
+        let mut mem = ram();
+        let mut dma = Dma::new();
+        let device = fifo(&[1, 2]);
+        dma.set_device(1, Some(Box::new(device.clone())));
+        program(&mut dma, 1, DEMAND | TRANSFER_WRITE, 0x3000, 9);
+        // the device drops DREQ once it has nothing left
+        assert_eq!(2 * 4, dma.run(&mut mem));
+        assert_eq!(7, dma.count(1));
+        device.borrow_mut().data.push(3);
+        device.borrow_mut().dreq = true;
+        dma.run(&mut mem);
+        assert_eq!(vec![1, 2, 3], bytes(&mut mem, 0x3000, 3));
+    }
+
+    #[test]
+    fn test_auto_init_and_decrement() {
+        let mut mem = ram();
+        let mut dma = Dma::new();
+        let device = fifo(&[1, 2, 3, 4]);
+        dma.set_device(2, Some(Box::new(device.clone())));
+        program(
+            &mut dma,
+            2,
+            BLOCK | TRANSFER_WRITE | AUTO_INIT | DECREMENT,
+            0x4001,
+            1,
+        );
+        dma.run(&mut mem);
+        assert_eq!(vec![2, 1], bytes(&mut mem, 0x4000, 2));
+        // reloaded and still unmasked
+        assert_eq!((0x4001, 1), (dma.address(2), dma.count(2)));
+        assert!(!dma.is_masked(2));
+        dma.run(&mut mem);
+        assert_eq!(vec![4, 3], bytes(&mut mem, 0x4000, 2));
+    }
+
+    #[test]
+    fn test_address_wraps_within_page() {
+        let mut mem = ram();
+        let mut dma = Dma::new();
+        dma.set_device(2, Some(Box::new(fifo(&[1, 2]))));
+        program(&mut dma, 2, BLOCK | TRANSFER_WRITE, 0x1_FFFF, 1);
+        dma.run(&mut mem);
+        assert_eq!(1, mem.read_u8(0x1_FFFF));
+        assert_eq!(2, mem.read_u8(0x1_0000));
+        assert_eq!(0, mem.read_u8(0x2_0000));
+    }
+
+    #[test]
+    fn test_memory_to_memory() {
+        let mut mem = ram();
+        mem.load(0x5000, b"copy");
+        let mut dma = Dma::new();
+        dma.write_u8(0x08, MEMORY_TO_MEMORY);
+        program(&mut dma, 0, BLOCK, 0x5000, 3);
+        program(&mut dma, 1, BLOCK, 0x6000, 3);
+        // started by a software request on channel 0
+        dma.write_u8(0x09, 0x04);
+        assert_eq!(4 * 8, dma.run(&mut mem));
+        assert_eq!(b"copy".to_vec(), bytes(&mut mem, 0x6000, 4));
+        assert_eq!(b'y', dma.read_u8(0x0D));
+        assert_eq!(0x03, dma.read_u8(0x08));
+
+        // address hold fills with the first byte
+        dma.write_u8(0x08, MEMORY_TO_MEMORY | ADDRESS_HOLD);
+        program(&mut dma, 0, BLOCK, 0x5000, 3);
+        program(&mut dma, 1, BLOCK, 0x6000, 3);
+        dma.write_u8(0x09, 0x04);
+        dma.run(&mut mem);
+        assert_eq!(b"cccc".to_vec(), bytes(&mut mem, 0x6000, 4));
+    }
+
+    #[test]
+    fn test_dma_into_rom_is_ignored() {
+        let mut mem = ram();
+        mem.map_rom(0xF0000, vec![0xAA; 16]).unwrap();
+        let mut dma = Dma::new();
+        dma.set_device(2, Some(Box::new(fifo(&[1]))));
+        program(&mut dma, 2, SINGLE | TRANSFER_WRITE, 0xF0000, 0);
+        dma.run(&mut mem);
+        assert_eq!(0xAA, mem.read_u8(0xF0000));
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut mem = ram();
+        let mut dma = Dma::new();
+        let order = |dma: &mut Dma, mem: &mut MemoryBus| {
+            let first = fifo(&[1]);
+            let second = fifo(&[2]);
+            dma.set_device(1, Some(Box::new(first)));
+            dma.set_device(3, Some(Box::new(second)));
+            program(dma, 1, SINGLE | TRANSFER_WRITE, 0x7000, 0);
+            program(dma, 3, SINGLE | TRANSFER_WRITE, 0x7000, 0);
+            dma.run(mem);
+            mem.read_u8(0x7000)
+        };
+        // fixed priority serves channel 1 first, so channel 3 writes last
+        assert_eq!(2, order(&mut dma, &mut mem));
+        // after serving channel 3 rotation puts channel 0 on top, then 1
+        dma.write_u8(0x08, ROTATING_PRIORITY);
+        order(&mut dma, &mut mem);
+        assert_eq!(0, dma.highest);
+        dma.highest = 2;
+        assert_eq!(1, order(&mut dma, &mut mem));
+    }
+
+    #[test]
+    fn test_disable_and_software_request() {
+        let mut mem = ram();
+        let mut dma = Dma::new();
+        program(&mut dma, 2, BLOCK, 0x8000, 7);
+        dma.write_u8(0x08, DISABLE);
+        dma.write_u8(0x09, 0x06);
+        assert_eq!(0x40, dma.read_u8(0x08));
+        assert_eq!(0, dma.run(&mut mem));
+        // a verify transfer counts without touching memory
+        dma.write_u8(0x08, 0);
+        assert_eq!(8 * 4, dma.run(&mut mem));
+        assert_eq!(0x04, dma.read_u8(0x08));
+    }
+}
@@ -0,0 +1,812 @@
+use std::cmp::Ordering;
+
+use bitflags::bitflags;
+
+pub const BIAS: i32 = 16383;
+const MAX_EXPONENT: u16 = 0x7FFF;
+const INTEGER_BIT: u64 = 1 << 63;
+const QUIET_BIT: u64 = 1 << 62;
+
+bitflags! {
+    // the exception flags of the status word, and the masks of the control
+    // word in the same positions
+    pub struct Exceptions: u16 {
+        const INVALID = 0x01;
+        const DENORMAL = 0x02;
+        const ZERO_DIVIDE = 0x04;
+        const OVERFLOW = 0x08;
+        const UNDERFLOW = 0x10;
+        const PRECISION = 0x20;
+    }
+}
+
+// RC, bits 10-11 of the control word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Nearest,
+    Down,
+    Up,
+    Zero,
+}
+
+impl Rounding {
+    pub fn from_control(control: u16) -> Rounding {
+        match (control >> 10) & 3 {
+            0 => Rounding::Nearest,
+            1 => Rounding::Down,
+            2 => Rounding::Up,
+            _ => Rounding::Zero,
+        }
+    }
+
+    // whether a result cut short at `lsb` is incremented, given the first
+    // bit dropped and whether any bit after it was set
+    fn round_up(self, sign: bool, lsb: bool, half: bool, rest: bool) -> bool {
+        match self {
+            Rounding::Nearest => half && (rest || lsb),
+            Rounding::Down => sign && (half || rest),
+            Rounding::Up => !sign && (half || rest),
+            Rounding::Zero => false,
+        }
+    }
+}
+
+// the significand bits and exponent range a result is rounded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub bits: u32,
+    pub emin: i32,
+    pub emax: i32,
+}
+
+impl Format {
+    pub const SINGLE: Format = Format {
+        bits: 24,
+        emin: -126,
+        emax: 127,
+    };
+    pub const DOUBLE: Format = Format {
+        bits: 53,
+        emin: -1022,
+        emax: 1023,
+    };
+    pub const EXTENDED: Format = Format {
+        bits: 64,
+        emin: 1 - BIAS,
+        emax: BIAS,
+    };
+
+    // PC, bits 8-9 of the control word. It shortens the significand of
+    // arithmetic results but not their exponent range.
+    pub fn from_control(control: u16) -> Format {
+        let bits = match (control >> 8) & 3 {
+            0 => 24,
+            2 => 53,
+            _ => 64,
+        };
+        Format {
+            bits,
+            ..Format::EXTENDED
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Zero,
+    Denormal,
+    Normal,
+    // a non-zero exponent without the integer bit, which the 8087 still
+    // accepts as an operand
+    Unnormal,
+    Infinity,
+    NaN,
+}
+
+// the 80 bit temporary real format of the registers, with an explicit
+// integer bit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct F80 {
+    pub sign: bool,
+    pub exponent: u16,
+    pub significand: u64,
+}
+
+impl F80 {
+    pub const ZERO: F80 = F80 {
+        sign: false,
+        exponent: 0,
+        significand: 0,
+    };
+    pub const ONE: F80 = F80 {
+        sign: false,
+        exponent: BIAS as u16,
+        significand: INTEGER_BIT,
+    };
+    // the NaN a masked invalid operation returns
+    pub const INDEFINITE: F80 = F80 {
+        sign: true,
+        exponent: MAX_EXPONENT,
+        significand: INTEGER_BIT | QUIET_BIT,
+    };
+
+    pub fn zero(sign: bool) -> F80 {
+        F80 { sign, ..F80::ZERO }
+    }
+
+    pub fn infinity(sign: bool) -> F80 {
+        F80 {
+            sign,
+            exponent: MAX_EXPONENT,
+            significand: INTEGER_BIT,
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 10]) -> F80 {
+        let mut significand = [0; 8];
+        significand.copy_from_slice(&bytes[..8]);
+        let high = u16::from_le_bytes([bytes[8], bytes[9]]);
+        F80 {
+            sign: high & 0x8000 != 0,
+            exponent: high & MAX_EXPONENT,
+            significand: u64::from_le_bytes(significand),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 10] {
+        let mut bytes = [0; 10];
+        bytes[..8].copy_from_slice(&self.significand.to_le_bytes());
+        let high = (self.sign as u16) << 15 | self.exponent;
+        bytes[8..].copy_from_slice(&high.to_le_bytes());
+        bytes
+    }
+
+    pub fn class(self) -> Class {
+        match (self.exponent, self.significand) {
+            (0, 0) => Class::Zero,
+            (0, _) => Class::Denormal,
+            (MAX_EXPONENT, s) if s << 1 == 0 => Class::Infinity,
+            (MAX_EXPONENT, _) => Class::NaN,
+            (_, s) if s & INTEGER_BIT == 0 => Class::Unnormal,
+            _ => Class::Normal,
+        }
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.class() == Class::NaN
+    }
+
+    pub fn is_infinity(self) -> bool {
+        self.class() == Class::Infinity
+    }
+
+    pub fn negate(self) -> F80 {
+        F80 {
+            sign: !self.sign,
+            ..self
+        }
+    }
+
+    pub fn abs(self) -> F80 {
+        F80 {
+            sign: false,
+            ..self
+        }
+    }
+
+    pub fn quiet(self) -> F80 {
+        F80 {
+            significand: self.significand | INTEGER_BIT | QUIET_BIT,
+            ..self
+        }
+    }
+
+    // the value of anything but an infinity or NaN
+    pub fn unpack(self) -> Ext {
+        let exp = if self.exponent == 0 {
+            1 - BIAS
+        } else {
+            self.exponent as i32 - BIAS
+        };
+        Ext::new(self.sign, (self.significand as u128) << 64, exp)
+    }
+
+    // the result is denormal when the operand is one in its own format
+    pub fn from_f32_bits(bits: u32) -> (F80, bool) {
+        F80::from_binary(bits as u64, 23, 8)
+    }
+
+    pub fn from_f64_bits(bits: u64) -> (F80, bool) {
+        F80::from_binary(bits, 52, 11)
+    }
+
+    fn from_binary(bits: u64, fraction_bits: u32, exponent_bits: u32) -> (F80, bool) {
+        let sign = bits >> (fraction_bits + exponent_bits) & 1 != 0;
+        let max = (1 << exponent_bits) - 1;
+        let bias = (max >> 1) as i32;
+        let exponent = (bits >> fraction_bits) & max;
+        let fraction = bits & ((1 << fraction_bits) - 1);
+        let value = match (exponent, fraction) {
+            (0, 0) => F80::zero(sign),
+            (0, _) => Ext::from_int(sign, fraction as u128, 1 - bias - fraction_bits as i32).pack(),
+            (e, 0) if e == max => F80::infinity(sign),
+            (e, _) if e == max => F80 {
+                sign,
+                exponent: MAX_EXPONENT,
+                significand: INTEGER_BIT | fraction << (63 - fraction_bits),
+            },
+            (e, _) => F80 {
+                sign,
+                exponent: (e as i32 - bias + BIAS) as u16,
+                significand: INTEGER_BIT | fraction << (63 - fraction_bits),
+            },
+        };
+        (value, exponent == 0 && fraction != 0)
+    }
+
+    // encodes a value already rounded to Format::SINGLE
+    pub fn to_f32_bits(self) -> u32 {
+        self.to_binary(23, 8) as u32
+    }
+
+    // encodes a value already rounded to Format::DOUBLE
+    pub fn to_f64_bits(self) -> u64 {
+        self.to_binary(52, 11)
+    }
+
+    fn to_binary(self, fraction_bits: u32, exponent_bits: u32) -> u64 {
+        let sign = (self.sign as u64) << (fraction_bits + exponent_bits);
+        let max = (1 << exponent_bits) - 1;
+        let bias = (max >> 1) as i32;
+        let fraction_mask = (1 << fraction_bits) - 1;
+        match self.class() {
+            Class::Zero => sign,
+            Class::Infinity => sign | max << fraction_bits,
+            Class::NaN => {
+                // the top of the payload, kept a NaN when that is all zeros
+                let fraction = (self.significand << 1) >> (64 - fraction_bits);
+                sign | max << fraction_bits | fraction.max(1 << (fraction_bits - 1))
+            }
+            _ => {
+                let value = self.unpack();
+                if value.is_zero() {
+                    return sign;
+                }
+                let significand = (value.sig >> 64) as u64;
+                if value.exp >= 1 - bias {
+                    let exponent = (value.exp + bias) as u64;
+                    sign | exponent << fraction_bits
+                        | (significand >> (63 - fraction_bits)) & fraction_mask
+                } else {
+                    let shift = (63 - fraction_bits) as i32 + (1 - bias - value.exp);
+                    sign | significand.checked_shr(shift as u32).unwrap_or(0)
+                }
+            }
+        }
+    }
+}
+
+// an unpacked finite value, sig / 2^127 * 2^exp with the top bit of sig set
+// unless it is zero. Bits shifted out at the bottom are ORed into the last
+// one, so that rounding still sees them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ext {
+    pub sign: bool,
+    pub exp: i32,
+    pub sig: u128,
+}
+
+fn shift_right_jam(value: u128, shift: u32) -> u128 {
+    match shift {
+        0 => value,
+        1..=127 => value >> shift | (value << (128 - shift) != 0) as u128,
+        _ => (value != 0) as u128,
+    }
+}
+
+// the bits of `value` above `shift`, the first bit below it and whether any
+// further one is set
+fn split(value: u128, shift: u64) -> (u128, bool, bool) {
+    match shift {
+        0 => (value, false, false),
+        1..=127 => (
+            value >> shift,
+            value >> (shift - 1) & 1 != 0,
+            value & ((1 << (shift - 1)) - 1) != 0,
+        ),
+        128 => (0, value >> 127 != 0, value << 1 != 0),
+        _ => (0, false, value != 0),
+    }
+}
+
+// the full 256 bit product as (high, low)
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a1, a0) = (a >> 64, a & MASK);
+    let (b1, b0) = (b >> 64, b & MASK);
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let p11 = a1 * b1;
+    let mid = (p00 >> 64) + (p01 & MASK) + (p10 & MASK);
+    let low = (p00 & MASK) | mid << 64;
+    let high = p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64);
+    (high, low)
+}
+
+impl Ext {
+    pub const fn constant(sig: u128, exp: i32) -> Ext {
+        Ext {
+            sign: false,
+            exp,
+            sig,
+        }
+    }
+
+    pub fn new(sign: bool, sig: u128, exp: i32) -> Ext {
+        if sig == 0 {
+            return Ext { sign, exp: 0, sig };
+        }
+        let shift = sig.leading_zeros();
+        Ext {
+            sign,
+            exp: exp - shift as i32,
+            sig: sig << shift,
+        }
+    }
+
+    // value * 2^scale
+    pub fn from_int(sign: bool, value: u128, scale: i32) -> Ext {
+        Ext::new(sign, value, scale + 127)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.sig == 0
+    }
+
+    pub fn negate(self) -> Ext {
+        Ext {
+            sign: !self.sign,
+            ..self
+        }
+    }
+
+    pub fn abs(self) -> Ext {
+        Ext {
+            sign: false,
+            ..self
+        }
+    }
+
+    // times 2^n
+    pub fn scale(self, n: i32) -> Ext {
+        if self.is_zero() {
+            return self;
+        }
+        Ext {
+            exp: self.exp + n,
+            ..self
+        }
+    }
+
+    fn magnitude(self) -> Option<(i32, u128)> {
+        (!self.is_zero()).then_some((self.exp, self.sig))
+    }
+
+    pub fn compare(self, other: Ext) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => {
+                return if other.sign {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                }
+            }
+            (false, true) => {
+                return if self.sign {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            _ => {}
+        }
+        match (self.sign, other.sign) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => self.magnitude().cmp(&other.magnitude()),
+            (true, true) => other.magnitude().cmp(&self.magnitude()),
+        }
+    }
+
+    // an exact zero takes the sign of the operands when they agree and is
+    // positive otherwise, negative when rounding down
+    pub fn add(self, other: Ext, rounding: Rounding) -> Ext {
+        if other.is_zero() {
+            if self.is_zero() {
+                let sign = if self.sign == other.sign {
+                    self.sign
+                } else {
+                    rounding == Rounding::Down
+                };
+                return Ext::new(sign, 0, 0);
+            }
+            return self;
+        }
+        if self.is_zero() {
+            return other;
+        }
+        let (a, b) = if self.magnitude() >= other.magnitude() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        // one bit of headroom for the carry
+        let a_sig = shift_right_jam(a.sig, 1);
+        let distance = (a.exp - b.exp).min(200) as u32;
+        let b_sig = shift_right_jam(b.sig, 1 + distance);
+        if a.sign == b.sign {
+            Ext::new(a.sign, a_sig + b_sig, a.exp + 1)
+        } else if a_sig == b_sig {
+            Ext::new(rounding == Rounding::Down, 0, 0)
+        } else {
+            Ext::new(a.sign, a_sig - b_sig, a.exp + 1)
+        }
+    }
+
+    pub fn sub(self, other: Ext, rounding: Rounding) -> Ext {
+        self.add(other.negate(), rounding)
+    }
+
+    pub fn multiply(self, other: Ext) -> Ext {
+        let sign = self.sign != other.sign;
+        if self.is_zero() || other.is_zero() {
+            return Ext::new(sign, 0, 0);
+        }
+        let (high, low) = mul_wide(self.sig, other.sig);
+        Ext::new(sign, high | (low != 0) as u128, self.exp + other.exp + 1)
+    }
+
+    // `other` must not be zero
+    pub fn divide(self, other: Ext) -> Ext {
+        let sign = self.sign != other.sign;
+        if self.is_zero() {
+            return Ext::new(sign, 0, 0);
+        }
+        let divisor = other.sig;
+        let mut exp = self.exp - other.exp;
+        let mut remainder = self.sig;
+        // the bit shifted out of the top of the remainder
+        let mut carry = false;
+        if remainder < divisor {
+            carry = remainder >> 127 != 0;
+            remainder <<= 1;
+            exp -= 1;
+        }
+        let mut quotient = 0u128;
+        for _ in 0..128 {
+            quotient <<= 1;
+            if carry || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                quotient |= 1;
+            }
+            carry = remainder >> 127 != 0;
+            remainder <<= 1;
+        }
+        let sticky = carry || remainder != 0;
+        Ext::new(sign, quotient | sticky as u128, exp)
+    }
+
+    // exact for a value with a 64 bit significand, which is all the
+    // registers hold. A negative operand gives the root of its magnitude.
+    pub fn sqrt(self) -> Ext {
+        if self.is_zero() {
+            return self;
+        }
+        let mut exp = self.exp - 63;
+        let mut radicand = self.sig >> 64;
+        if exp & 1 != 0 {
+            radicand <<= 1;
+            exp -= 1;
+        }
+        // bit by bit over the radicand * 2^128, two bits at a time
+        let mut root = 0u128;
+        let mut remainder = 0u128;
+        for i in (0..97).rev() {
+            let pair = if i >= 64 {
+                (radicand >> (2 * (i - 64))) & 3
+            } else {
+                0
+            };
+            remainder = remainder << 2 | pair;
+            let trial = root << 2 | 1;
+            root <<= 1;
+            if remainder >= trial {
+                remainder -= trial;
+                root |= 1;
+            }
+        }
+        Ext::from_int(false, root | (remainder != 0) as u128, exp / 2 - 64)
+    }
+
+    // the nearest integer in the direction `rounding`, and whether that
+    // changed the value
+    pub fn round_to_int(self, rounding: Rounding) -> (Ext, bool) {
+        if self.is_zero() || self.exp >= 127 {
+            return (self, false);
+        }
+        let shift = (127 - self.exp as i64).min(200) as u64;
+        let (mut integer, half, rest) = split(self.sig, shift);
+        if rounding.round_up(self.sign, integer & 1 != 0, half, rest) {
+            integer += 1;
+        }
+        (Ext::from_int(self.sign, integer, 0), half || rest)
+    }
+
+    // an integral value as i64, None when it does not fit
+    pub fn to_i64(self) -> Option<i64> {
+        if self.is_zero() {
+            return Some(0);
+        }
+        if self.exp >= 64 {
+            return None;
+        }
+        let magnitude = self.sig >> (127 - self.exp);
+        match (self.sign, magnitude) {
+            (false, m) if m <= i64::MAX as u128 => Some(m as i64),
+            (true, m) if m <= 1 << 63 => Some((m as i64).wrapping_neg()),
+            _ => None,
+        }
+    }
+
+    // rounds to `format`, returning the masked response and the exceptions
+    // it raised. UNDERFLOW is raised for any tiny result and it is up to the
+    // caller to drop it for an exact one when underflow is masked.
+    pub fn round(self, format: Format, rounding: Rounding) -> (F80, Exceptions) {
+        if self.is_zero() {
+            return (F80::zero(self.sign), Exceptions::empty());
+        }
+        let mut flags = Exceptions::empty();
+        let tiny = self.exp < format.emin;
+        let denormal_shift = (format.emin as i64 - self.exp as i64).clamp(0, 200) as u64;
+        let shift = (128 - format.bits) as u64 + denormal_shift;
+        let (mut significand, half, rest) = split(self.sig, shift);
+        let mut exp = self.exp.max(format.emin);
+        if rounding.round_up(self.sign, significand & 1 != 0, half, rest) {
+            significand += 1;
+            if significand >> format.bits != 0 {
+                significand >>= 1;
+                exp += 1;
+            }
+        }
+        if half || rest {
+            flags |= Exceptions::PRECISION;
+        }
+        if tiny {
+            flags |= Exceptions::UNDERFLOW;
+        }
+        if exp > format.emax {
+            flags |= Exceptions::OVERFLOW | Exceptions::PRECISION;
+            let to_infinity = match rounding {
+                Rounding::Nearest => true,
+                Rounding::Up => !self.sign,
+                Rounding::Down => self.sign,
+                Rounding::Zero => false,
+            };
+            let value = if to_infinity {
+                F80::infinity(self.sign)
+            } else {
+                let largest = (1 << format.bits) - 1;
+                Ext::from_int(self.sign, largest, format.emax - (format.bits as i32 - 1)).pack()
+            };
+            return (value, flags);
+        }
+        let value = Ext::from_int(self.sign, significand, exp - (format.bits as i32 - 1));
+        (value.pack(), flags)
+    }
+
+    // a value that fits the extended format as it is
+    pub fn pack(self) -> F80 {
+        if self.is_zero() {
+            return F80::zero(self.sign);
+        }
+        if self.exp >= 1 - BIAS {
+            F80 {
+                sign: self.sign,
+                exponent: (self.exp + BIAS) as u16,
+                significand: (self.sig >> 64) as u64,
+            }
+        } else {
+            let shift = (1 - BIAS - self.exp) as u32;
+            F80 {
+                sign: self.sign,
+                exponent: 0,
+                significand: (shift_right_jam(self.sig, 64 + shift)) as u64,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f80(value: f64) -> F80 {
+        F80::from_f64_bits(value.to_bits()).0
+    }
+
+    fn f64_of(value: F80) -> f64 {
+        let (rounded, _) = value.unpack().round(Format::DOUBLE, Rounding::Nearest);
+        f64::from_bits(rounded.to_f64_bits())
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let bytes = [0x35, 0xC2, 0x68, 0x21, 0xA2, 0xDA, 0x0F, 0xC9, 0x00, 0x40];
+        let pi = F80::from_bytes(bytes);
+        assert_eq!(0x4000, pi.exponent);
+        assert_eq!(0xC90F_DAA2_2168_C235, pi.significand);
+        assert_eq!(bytes, pi.to_bytes());
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(Class::Zero, F80::ZERO.class());
+        assert_eq!(Class::Normal, F80::ONE.class());
+        assert_eq!(Class::Infinity, F80::infinity(true).class());
+        assert_eq!(Class::NaN, F80::INDEFINITE.class());
+        let denormal = F80 {
+            exponent: 0,
+            significand: 1,
+            sign: false,
+        };
+        assert_eq!(Class::Denormal, denormal.class());
+        let unnormal = F80 {
+            exponent: 0x3FFF,
+            significand: 1,
+            sign: false,
+        };
+        assert_eq!(Class::Unnormal, unnormal.class());
+    }
+
+    #[test]
+    fn test_binary_conversions() {
+        for value in [
+            0.0,
+            -1.5,
+            3.0e300,
+            -2.5e-310,
+            f64::MIN_POSITIVE,
+            f64::INFINITY,
+        ] {
+            assert_eq!(value.to_bits(), f64_of(f80(value)).to_bits());
+        }
+        let (value, denormal) = F80::from_f32_bits(1);
+        assert!(denormal);
+        let (single, _) = value.unpack().round(Format::SINGLE, Rounding::Nearest);
+        assert_eq!(1, single.to_f32_bits());
+        let (nan, _) = F80::from_f32_bits(0x7FC0_0001);
+        assert!(nan.is_nan());
+        assert_eq!(0x7FC0_0001, nan.to_f32_bits());
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let n = Rounding::Nearest;
+        let sum = f80(1.0).unpack().add(f80(2.0).unpack(), n);
+        assert_eq!(3.0, f64_of(sum.pack()));
+        let difference = f80(1.0).unpack().sub(f80(1.0).unpack(), n);
+        assert_eq!(F80::zero(false), difference.pack());
+        let difference = f80(1.0).unpack().sub(f80(1.0).unpack(), Rounding::Down);
+        assert_eq!(F80::zero(true), difference.pack());
+        // 1 + 2^-64 is a tie, broken to even
+        let tiny = Ext::from_int(false, 1, -64);
+        let (value, flags) = f80(1.0).unpack().add(tiny, n).round(Format::EXTENDED, n);
+        assert_eq!(F80::ONE, value);
+        assert_eq!(Exceptions::PRECISION, flags);
+        let (value, _) = f80(1.0)
+            .unpack()
+            .add(tiny, Rounding::Up)
+            .round(Format::EXTENDED, Rounding::Up);
+        assert_eq!(INTEGER_BIT | 1, value.significand);
+    }
+
+    #[test]
+    fn test_mul_div() {
+        let product = f80(1.5).unpack().multiply(f80(-4.0).unpack());
+        assert_eq!(-6.0, f64_of(product.pack()));
+        let quotient = f80(1.0).unpack().divide(f80(3.0).unpack());
+        let (third, flags) = quotient.round(Format::EXTENDED, Rounding::Nearest);
+        assert_eq!(0xAAAA_AAAA_AAAA_AAAB, third.significand);
+        assert_eq!(0x3FFD, third.exponent);
+        assert_eq!(Exceptions::PRECISION, flags);
+        let (third, _) = quotient.round(Format::EXTENDED, Rounding::Zero);
+        assert_eq!(0xAAAA_AAAA_AAAA_AAAA, third.significand);
+        let (exact, flags) = f80(10.0)
+            .unpack()
+            .divide(f80(4.0).unpack())
+            .round(Format::EXTENDED, Rounding::Nearest);
+        assert_eq!(2.5, f64_of(exact));
+        assert!(flags.is_empty());
+    }
+
+    #[test]
+    fn test_precision_control() {
+        let third = f80(1.0).unpack().divide(f80(3.0).unpack());
+        let (value, _) = third.round(Format::from_control(0x0000), Rounding::Nearest);
+        assert_eq!(0xAAAA_AB00_0000_0000, value.significand);
+        let (value, _) = third.round(Format::from_control(0x0200), Rounding::Nearest);
+        assert_eq!((1.0f64 / 3.0).to_bits(), value.to_f64_bits());
+    }
+
+    #[test]
+    fn test_sqrt() {
+        let (root, flags) = f80(2.25)
+            .unpack()
+            .sqrt()
+            .round(Format::EXTENDED, Rounding::Nearest);
+        assert_eq!(1.5, f64_of(root));
+        assert!(flags.is_empty());
+        let (root, flags) = f80(2.0)
+            .unpack()
+            .sqrt()
+            .round(Format::EXTENDED, Rounding::Nearest);
+        assert_eq!(0xB504_F333_F9DE_6484, root.significand);
+        assert_eq!(Exceptions::PRECISION, flags);
+        let (root, _) = f80(8.0)
+            .unpack()
+            .sqrt()
+            .round(Format::EXTENDED, Rounding::Nearest);
+        assert_eq!(0xB504_F333_F9DE_6484, root.significand);
+        assert_eq!(0x4000, root.exponent);
+    }
+
+    #[test]
+    fn test_overflow_underflow() {
+        let huge = f80(1e300).unpack();
+        let (value, flags) = huge.round(Format::SINGLE, Rounding::Nearest);
+        assert_eq!(F80::infinity(false), value);
+        assert_eq!(Exceptions::OVERFLOW | Exceptions::PRECISION, flags);
+        let (value, _) = huge.round(Format::SINGLE, Rounding::Zero);
+        assert_eq!(f32::MAX.to_bits(), value.to_f32_bits());
+        // 2^-16400 is a denormal of the extended format
+        let tiny = Ext::from_int(false, 1, -16400);
+        let (value, flags) = tiny.round(Format::EXTENDED, Rounding::Nearest);
+        assert_eq!((0, 1 << 45), (value.exponent, value.significand));
+        assert_eq!(Exceptions::UNDERFLOW, flags);
+    }
+
+    #[test]
+    fn test_round_to_int() {
+        let cases = [
+            (2.5, Rounding::Nearest, 2),
+            (3.5, Rounding::Nearest, 4),
+            (-2.5, Rounding::Down, -3),
+            (-2.5, Rounding::Up, -2),
+            (2.7, Rounding::Zero, 2),
+            (0.25, Rounding::Up, 1),
+        ];
+        for (value, rounding, expected) in cases {
+            let (integer, inexact) = f80(value).unpack().round_to_int(rounding);
+            assert_eq!(Some(expected), integer.to_i64(), "{} {:?}", value, rounding);
+            assert!(inexact);
+        }
+        assert_eq!(Some(i64::MIN), f80(-(2f64.powi(63))).unpack().to_i64());
+        assert_eq!(None, f80(2f64.powi(63)).unpack().to_i64());
+    }
+
+    #[test]
+    fn test_compare() {
+        let (a, b) = (f80(-1.0).unpack(), f80(2.0).unpack());
+        assert_eq!(Ordering::Less, a.compare(b));
+        assert_eq!(
+            Ordering::Greater,
+            f80(-1.0).unpack().compare(f80(-2.0).unpack())
+        );
+        assert_eq!(
+            Ordering::Equal,
+            f80(0.0).unpack().compare(f80(-0.0).unpack())
+        );
+    }
+}
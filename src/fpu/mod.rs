@@ -0,0 +1,1521 @@
+// The 8087 numeric data processor. It sits next to the CPU, decodes the same
+// instruction stream and takes over every ESC; see cpu::coprocessor for how
+// the two are tied together.
+
+pub mod float80;
+mod transcendental;
+
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use crate::{
+    cpu::coprocessor::{Coprocessor, Escape, EscapeOperand},
+    memory::{MemoryBus, ADDRESS_MASK},
+    pic::Pic,
+};
+use float80::{Class, Exceptions, Ext, Format, Rounding, F80};
+
+// control word
+const INTERRUPT_ENABLE_MASK: u16 = 0x0080;
+// infinity control, affine rather than projective closure
+const AFFINE: u16 = 0x1000;
+// status word
+const INTERRUPT_REQUEST: u16 = 0x0080;
+const C0: u16 = 0x0100;
+const C1: u16 = 0x0200;
+const C2: u16 = 0x0400;
+const C3: u16 = 0x4000;
+const CONDITION: u16 = C0 | C1 | C2 | C3;
+const TOP_SHIFT: u16 = 11;
+const TOP_MASK: u16 = 7 << TOP_SHIFT;
+const BUSY: u16 = 0x8000;
+
+// what the exponent of a result is wrapped by when overflow or underflow is
+// unmasked, so that a handler can still scale it back
+const EXPONENT_WRAP: i32 = 24576;
+
+const TAG_VALID: u16 = 0;
+const TAG_ZERO: u16 = 1;
+const TAG_SPECIAL: u16 = 2;
+const TAG_EMPTY: u16 = 3;
+
+const ENVIRONMENT_SIZE: u32 = 14;
+
+// an unmasked invalid operation, denormal operand or zero divide stopped the
+// instruction before it stored anything
+struct Abort;
+
+type Step<T> = Result<T, Abort>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MemoryFormat {
+    Real32,
+    Real64,
+    Real80,
+    Int16,
+    Int32,
+    Int64,
+    Bcd,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fpu {
+    // by physical register, ST(i) is regs[(top + i) % 8]
+    regs: [F80; 8],
+    control: u16,
+    status: u16,
+    tags: u16,
+    // the last instruction that was not a control instruction, and its
+    // memory operand, for exception handlers
+    instruction_pointer: u32,
+    opcode: u16,
+    operand_pointer: u32,
+    // clocks until the current instruction finishes
+    busy: u32,
+    // the IRQ line INT is jumpered to instead of NMI, and the level last
+    // driven on it
+    pic: Option<Rc<RefCell<Pic>>>,
+    irq: u8,
+    int: bool,
+}
+
+impl Default for Fpu {
+    fn default() -> Self {
+        Fpu::new()
+    }
+}
+
+impl Fpu {
+    pub fn new() -> Fpu {
+        let mut fpu = Fpu {
+            regs: [F80::ZERO; 8],
+            control: 0,
+            status: 0,
+            tags: 0,
+            instruction_pointer: 0,
+            opcode: 0,
+            operand_pointer: 0,
+            busy: 0,
+            pic: None,
+            irq: 0,
+            int: false,
+        };
+        fpu.reset();
+        fpu
+    }
+
+    // FINIT: all exceptions masked, IEM set, 64 bit precision, round to
+    // nearest, projective infinity and an empty stack
+    pub fn reset(&mut self) {
+        self.control = 0x03FF;
+        self.status = 0;
+        self.tags = 0xFFFF;
+        self.instruction_pointer = 0;
+        self.opcode = 0;
+        self.operand_pointer = 0;
+        self.update_irq();
+    }
+
+    // the XT takes INT to NMI, but some boards have a jumper to put it on a
+    // PIC input instead
+    pub fn set_pic(&mut self, pic: Option<Rc<RefCell<Pic>>>, irq: u8) {
+        if let Some(pic) = &pic {
+            pic.borrow_mut().set_irq(irq, self.int);
+        }
+        self.pic = pic;
+        self.irq = irq;
+    }
+
+    pub fn control_word(&self) -> u16 {
+        self.control
+    }
+
+    pub fn status_word(&self) -> u16 {
+        let busy = if self.busy > 0 { BUSY } else { 0 };
+        self.status | busy
+    }
+
+    pub fn tag_word(&self) -> u16 {
+        self.tags
+    }
+
+    // ST(i) as it is, empty or not
+    pub fn st(&self, i: u8) -> F80 {
+        self.regs[self.physical(i)]
+    }
+
+    // the INT output, which the XT takes to NMI. It stays up until the
+    // handler clears the exception.
+    pub fn interrupt_request(&self) -> bool {
+        self.status & INTERRUPT_REQUEST != 0 && self.control & INTERRUPT_ENABLE_MASK == 0
+    }
+
+    // follows the status and control words after anything that may have
+    // changed them
+    fn update_irq(&mut self) {
+        let int = self.interrupt_request();
+        if int != self.int {
+            self.int = int;
+            if let Some(pic) = &self.pic {
+                pic.borrow_mut().set_irq(self.irq, int);
+            }
+        }
+    }
+
+    fn top(&self) -> u8 {
+        ((self.status & TOP_MASK) >> TOP_SHIFT) as u8
+    }
+
+    fn set_top(&mut self, top: u8) {
+        self.status = (self.status & !TOP_MASK) | ((top as u16 & 7) << TOP_SHIFT);
+    }
+
+    fn physical(&self, i: u8) -> usize {
+        ((self.top() + i) & 7) as usize
+    }
+
+    fn tag(&self, physical: usize) -> u16 {
+        (self.tags >> (2 * physical)) & 3
+    }
+
+    fn set_tag(&mut self, physical: usize, tag: u16) {
+        self.tags = (self.tags & !(3 << (2 * physical))) | (tag << (2 * physical));
+    }
+
+    fn is_empty(&self, i: u8) -> bool {
+        self.tag(self.physical(i)) == TAG_EMPTY
+    }
+
+    fn rounding(&self) -> Rounding {
+        Rounding::from_control(self.control)
+    }
+
+    fn masks(&self) -> Exceptions {
+        Exceptions::from_bits_truncate(self.control)
+    }
+
+    fn affine(&self) -> bool {
+        self.control & AFFINE != 0
+    }
+
+    // records exceptions, requesting an interrupt for the unmasked ones.
+    // Unmasked invalid operation, denormal and zero divide abort.
+    fn signal(&mut self, flags: Exceptions) -> Step<()> {
+        self.status |= flags.bits();
+        let unmasked = flags - self.masks();
+        if !unmasked.is_empty() {
+            self.status |= INTERRUPT_REQUEST;
+        }
+        if unmasked.intersects(Exceptions::INVALID | Exceptions::DENORMAL | Exceptions::ZERO_DIVIDE)
+        {
+            Err(Abort)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn invalid(&mut self) -> Step<F80> {
+        self.signal(Exceptions::INVALID)?;
+        Ok(F80::INDEFINITE)
+    }
+
+    // an empty register reads as the indefinite NaN when invalid operation
+    // is masked
+    fn read(&mut self, i: u8) -> Step<F80> {
+        if self.is_empty(i) {
+            return self.invalid();
+        }
+        Ok(self.st(i))
+    }
+
+    fn write(&mut self, i: u8, value: F80) {
+        let physical = self.physical(i);
+        self.regs[physical] = value;
+        let tag = match value.class() {
+            Class::Zero => TAG_ZERO,
+            Class::Normal => TAG_VALID,
+            _ => TAG_SPECIAL,
+        };
+        self.set_tag(physical, tag);
+    }
+
+    // a push onto a full register is a stack overflow, which masked
+    // leaves the indefinite NaN there instead
+    fn push(&mut self, value: F80) -> Step<()> {
+        let value = if self.is_empty(7) {
+            value
+        } else {
+            self.invalid()?
+        };
+        self.set_top(self.top().wrapping_sub(1));
+        self.write(0, value);
+        Ok(())
+    }
+
+    fn pop(&mut self) {
+        let physical = self.physical(0);
+        self.set_tag(physical, TAG_EMPTY);
+        self.set_top(self.top() + 1);
+    }
+
+    // the 8087 predates quiet NaNs and signals invalid operation for any NaN
+    // operand; masked, the result is the NaN with the larger significand.
+    // Denormal operands are flagged and then used as they are.
+    fn check_operands(&mut self, operands: &[F80]) -> Step<Option<F80>> {
+        let nan = operands
+            .iter()
+            .filter(|operand| operand.is_nan())
+            .max_by_key(|operand| operand.significand);
+        if let Some(nan) = nan {
+            self.signal(Exceptions::INVALID)?;
+            return Ok(Some(nan.quiet()));
+        }
+        if operands
+            .iter()
+            .any(|operand| operand.class() == Class::Denormal)
+        {
+            self.signal(Exceptions::DENORMAL)?;
+        }
+        Ok(None)
+    }
+
+    // rounds an arithmetic result. With overflow or underflow unmasked the
+    // register gets the result with its exponent wrapped around.
+    fn deliver(&mut self, exact: Ext, format: Format) -> Step<F80> {
+        let rounding = self.rounding();
+        let (mut value, mut flags) = exact.round(format, rounding);
+        let masks = self.masks();
+        if flags.contains(Exceptions::OVERFLOW) && !masks.contains(Exceptions::OVERFLOW) {
+            value = exact.scale(-EXPONENT_WRAP).round(format, rounding).0;
+        }
+        if flags.contains(Exceptions::UNDERFLOW) {
+            if !masks.contains(Exceptions::UNDERFLOW) {
+                value = exact.scale(EXPONENT_WRAP).round(format, rounding).0;
+            } else if !flags.contains(Exceptions::PRECISION) {
+                flags.remove(Exceptions::UNDERFLOW);
+            }
+        }
+        self.signal(flags)?;
+        Ok(value)
+    }
+
+    fn arithmetic_format(&self) -> Format {
+        Format::from_control(self.control)
+    }
+
+    fn add(&mut self, a: F80, b: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[a, b])? {
+            return Ok(nan);
+        }
+        match (a.class(), b.class()) {
+            // in projective closure there is a single unsigned infinity
+            (Class::Infinity, Class::Infinity) if !self.affine() || a.sign != b.sign => {
+                self.invalid()
+            }
+            (Class::Infinity, _) => Ok(a),
+            (_, Class::Infinity) => Ok(b),
+            _ => {
+                let sum = a.unpack().add(b.unpack(), self.rounding());
+                self.deliver(sum, self.arithmetic_format())
+            }
+        }
+    }
+
+    fn mul(&mut self, a: F80, b: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[a, b])? {
+            return Ok(nan);
+        }
+        let sign = a.sign != b.sign;
+        match (a.class(), b.class()) {
+            (Class::Infinity, _) | (_, Class::Infinity) => {
+                let other = if a.is_infinity() { b } else { a };
+                if !other.is_infinity() && other.unpack().is_zero() {
+                    self.invalid()
+                } else {
+                    Ok(F80::infinity(sign))
+                }
+            }
+            _ => {
+                let product = a.unpack().multiply(b.unpack());
+                self.deliver(product, self.arithmetic_format())
+            }
+        }
+    }
+
+    // a / b
+    fn div(&mut self, a: F80, b: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[a, b])? {
+            return Ok(nan);
+        }
+        let sign = a.sign != b.sign;
+        match (a.class(), b.class()) {
+            (Class::Infinity, Class::Infinity) => self.invalid(),
+            (Class::Infinity, _) => Ok(F80::infinity(sign)),
+            (_, Class::Infinity) => Ok(F80::zero(sign)),
+            _ => {
+                let (dividend, divisor) = (a.unpack(), b.unpack());
+                if divisor.is_zero() {
+                    if dividend.is_zero() {
+                        return self.invalid();
+                    }
+                    self.signal(Exceptions::ZERO_DIVIDE)?;
+                    return Ok(F80::infinity(sign));
+                }
+                self.deliver(dividend.divide(divisor), self.arithmetic_format())
+            }
+        }
+    }
+
+    // the eight operations of the D8-DE groups, in reg field order with
+    // FCOM and FCOMP left out. The reversed forms swap the operands.
+    fn arithmetic(&mut self, reg: u8, st0: F80, other: F80) -> Step<F80> {
+        match reg {
+            0 => self.add(st0, other),
+            1 => self.mul(st0, other),
+            4 => self.add(st0, other.negate()),
+            5 => self.add(other, st0.negate()),
+            6 => self.div(st0, other),
+            _ => self.div(other, st0),
+        }
+    }
+
+    // None when the operands are unordered
+    fn compare(&mut self, a: F80, b: F80) -> Step<Option<Ordering>> {
+        let infinite = a.is_infinity() || b.is_infinity();
+        if a.is_nan() || b.is_nan() || (infinite && !self.affine()) {
+            self.signal(Exceptions::INVALID)?;
+            return Ok(None);
+        }
+        self.check_operands(&[a, b])?;
+        let order = match (a.is_infinity(), b.is_infinity()) {
+            (true, true) => b.sign.cmp(&a.sign),
+            (true, false) => {
+                if a.sign {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            (false, true) => {
+                if b.sign {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                }
+            }
+            _ => a.unpack().compare(b.unpack()),
+        };
+        Ok(Some(order))
+    }
+
+    fn set_condition(&mut self, bits: u16) {
+        self.status = (self.status & !CONDITION) | bits;
+    }
+
+    fn set_compare_condition(&mut self, order: Option<Ordering>) {
+        self.set_condition(match order {
+            Some(Ordering::Greater) => 0,
+            Some(Ordering::Less) => C0,
+            Some(Ordering::Equal) => C3,
+            None => C3 | C2 | C0,
+        });
+    }
+
+    // FCOM, popping once for FCOMP and twice for FCOMPP. Nothing is popped
+    // when an unmasked exception stops the compare.
+    fn fcom(&mut self, other: F80, pops: u32) -> Step<()> {
+        let st0 = self.read(0)?;
+        let order = self.compare(st0, other)?;
+        self.set_compare_condition(order);
+        for _ in 0..pops {
+            self.pop();
+        }
+        Ok(())
+    }
+
+    fn read_memory<const SIZE: usize>(mem: &mut MemoryBus, address: u32) -> [u8; SIZE] {
+        let mut bytes = [0; SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = mem.read_u8((address + i as u32) & ADDRESS_MASK);
+        }
+        bytes
+    }
+
+    fn write_memory(mem: &mut MemoryBus, address: u32, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            mem.write_u8((address + i as u32) & ADDRESS_MASK, byte);
+        }
+    }
+
+    fn read_word(mem: &mut MemoryBus, address: u32) -> u16 {
+        u16::from_le_bytes(Fpu::read_memory(mem, address))
+    }
+
+    // a memory operand converted to temporary real, which is always exact
+    fn load(&mut self, mem: &mut MemoryBus, address: u32, format: MemoryFormat) -> Step<F80> {
+        let (value, denormal) = match format {
+            MemoryFormat::Real32 => {
+                F80::from_f32_bits(u32::from_le_bytes(Fpu::read_memory(mem, address)))
+            }
+            MemoryFormat::Real64 => {
+                F80::from_f64_bits(u64::from_le_bytes(Fpu::read_memory(mem, address)))
+            }
+            MemoryFormat::Real80 => return Ok(F80::from_bytes(Fpu::read_memory(mem, address))),
+            MemoryFormat::Int16 => (
+                integer(i16::from_le_bytes(Fpu::read_memory(mem, address)) as i64),
+                false,
+            ),
+            MemoryFormat::Int32 => (
+                integer(i32::from_le_bytes(Fpu::read_memory(mem, address)) as i64),
+                false,
+            ),
+            MemoryFormat::Int64 => (
+                integer(i64::from_le_bytes(Fpu::read_memory(mem, address))),
+                false,
+            ),
+            MemoryFormat::Bcd => return Ok(from_bcd(Fpu::read_memory(mem, address))),
+        };
+        if value.is_nan() {
+            self.signal(Exceptions::INVALID)?;
+            return Ok(value.quiet());
+        }
+        if denormal {
+            self.signal(Exceptions::DENORMAL)?;
+        }
+        Ok(value)
+    }
+
+    // rounds ST(0) for a memory store. Unlike a register result nothing is
+    // stored when overflow or underflow is unmasked.
+    fn round_for_store(&mut self, value: F80, format: Format) -> Step<F80> {
+        if value.class() == Class::Denormal {
+            self.signal(Exceptions::DENORMAL)?;
+        }
+        if matches!(value.class(), Class::NaN | Class::Infinity) {
+            return Ok(value);
+        }
+        let (value, mut flags) = value.unpack().round(format, self.rounding());
+        let masks = self.masks();
+        if flags.contains(Exceptions::UNDERFLOW)
+            && masks.contains(Exceptions::UNDERFLOW)
+            && !flags.contains(Exceptions::PRECISION)
+        {
+            flags.remove(Exceptions::UNDERFLOW);
+        }
+        self.signal(flags)?;
+        if (flags - masks).intersects(Exceptions::OVERFLOW | Exceptions::UNDERFLOW) {
+            return Err(Abort);
+        }
+        Ok(value)
+    }
+
+    // ST(0) rounded to an integer, None for the integer indefinite when it is
+    // out of range or not a number
+    fn round_for_integer(&mut self, value: F80, limit: u64) -> Step<Option<i64>> {
+        if matches!(value.class(), Class::NaN | Class::Infinity) {
+            self.signal(Exceptions::INVALID)?;
+            return Ok(None);
+        }
+        if value.class() == Class::Denormal {
+            self.signal(Exceptions::DENORMAL)?;
+        }
+        let (integer, inexact) = value.unpack().round_to_int(self.rounding());
+        match integer.to_i64() {
+            Some(n) if n.unsigned_abs() <= limit || (n < 0 && n.unsigned_abs() == limit + 1) => {
+                if inexact {
+                    self.signal(Exceptions::PRECISION)?;
+                }
+                Ok(Some(n))
+            }
+            _ => {
+                self.signal(Exceptions::INVALID)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn store(&mut self, mem: &mut MemoryBus, address: u32, format: MemoryFormat) -> Step<()> {
+        let value = self.read(0)?;
+        match format {
+            MemoryFormat::Real32 => {
+                let value = self.round_for_store(value, Format::SINGLE)?;
+                Fpu::write_memory(mem, address, &value.to_f32_bits().to_le_bytes());
+            }
+            MemoryFormat::Real64 => {
+                let value = self.round_for_store(value, Format::DOUBLE)?;
+                Fpu::write_memory(mem, address, &value.to_f64_bits().to_le_bytes());
+            }
+            MemoryFormat::Real80 => Fpu::write_memory(mem, address, &value.to_bytes()),
+            MemoryFormat::Int16 => {
+                let n = self.round_for_integer(value, i16::MAX as u64)?;
+                let n = n.map_or(i16::MIN, |n| n as i16);
+                Fpu::write_memory(mem, address, &n.to_le_bytes());
+            }
+            MemoryFormat::Int32 => {
+                let n = self.round_for_integer(value, i32::MAX as u64)?;
+                let n = n.map_or(i32::MIN, |n| n as i32);
+                Fpu::write_memory(mem, address, &n.to_le_bytes());
+            }
+            MemoryFormat::Int64 => {
+                let n = self.round_for_integer(value, i64::MAX as u64)?;
+                Fpu::write_memory(mem, address, &n.unwrap_or(i64::MIN).to_le_bytes());
+            }
+            MemoryFormat::Bcd => {
+                // 18 digits, with nothing for -10^18
+                let n = self.round_for_integer(value, 999_999_999_999_999_999)?;
+                let bytes = match n {
+                    Some(n) if n != -1_000_000_000_000_000_000 => to_bcd(n),
+                    Some(_) => {
+                        self.signal(Exceptions::INVALID)?;
+                        BCD_INDEFINITE
+                    }
+                    None => BCD_INDEFINITE,
+                };
+                Fpu::write_memory(mem, address, &bytes);
+            }
+        }
+        Ok(())
+    }
+
+    // FSTENV layout: control, status and tag words, then the instruction
+    // and operand pointers with their top 4 address bits in bits 12-15
+    fn store_environment(&self, mem: &mut MemoryBus, address: u32) {
+        let words = [
+            self.control,
+            self.status_word(),
+            self.tags,
+            self.instruction_pointer as u16,
+            ((self.instruction_pointer >> 4) & 0xF000) as u16 | (self.opcode & 0x07FF),
+            self.operand_pointer as u16,
+            ((self.operand_pointer >> 4) & 0xF000) as u16,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            Fpu::write_memory(mem, address + 2 * i as u32, &word.to_le_bytes());
+        }
+    }
+
+    fn load_environment(&mut self, mem: &mut MemoryBus, address: u32) {
+        let word = |mem: &mut MemoryBus, i: u32| Fpu::read_word(mem, address + 2 * i);
+        self.control = word(mem, 0);
+        self.status = word(mem, 1) & !BUSY;
+        self.tags = word(mem, 2);
+        let high = word(mem, 4);
+        self.instruction_pointer = word(mem, 3) as u32 | ((high as u32 & 0xF000) << 4);
+        self.opcode = high & 0x07FF;
+        self.operand_pointer = word(mem, 5) as u32 | ((word(mem, 6) as u32 & 0xF000) << 4);
+    }
+
+    // the 80 bit registers follow the environment in stack order
+    fn save(&mut self, mem: &mut MemoryBus, address: u32) {
+        self.store_environment(mem, address);
+        for i in 0..8 {
+            let bytes = self.st(i).to_bytes();
+            Fpu::write_memory(mem, address + ENVIRONMENT_SIZE + 10 * i as u32, &bytes);
+        }
+        self.reset();
+    }
+
+    fn restore(&mut self, mem: &mut MemoryBus, address: u32) {
+        self.load_environment(mem, address);
+        for i in 0..8 {
+            let value = F80::from_bytes(Fpu::read_memory(
+                mem,
+                address + ENVIRONMENT_SIZE + 10 * i as u32,
+            ));
+            let physical = self.physical(i);
+            self.regs[physical] = value;
+        }
+    }
+
+    // the instructions with a memory operand, returning their clocks
+    fn memory_instruction(
+        &mut self,
+        opcode: u8,
+        reg: u8,
+        address: u32,
+        mem: &mut MemoryBus,
+    ) -> Step<u32> {
+        use MemoryFormat::*;
+        let clocks = match (opcode, reg) {
+            (0xD8 | 0xDA | 0xDC | 0xDE, _) => {
+                let (format, clocks) = match opcode {
+                    0xD8 => (Real32, 105),
+                    0xDA => (Int32, 125),
+                    0xDC => (Real64, 110),
+                    _ => (Int16, 120),
+                };
+                let other = self.load(mem, address, format)?;
+                match reg {
+                    2 | 3 => self.fcom(other, (reg - 2) as u32)?,
+                    _ => {
+                        let st0 = self.read(0)?;
+                        let result = self.arithmetic(reg, st0, other)?;
+                        self.write(0, result);
+                    }
+                }
+                // multiply and divide take longer than the rest
+                clocks + [0, 25, 0, 0, 0, 0, 100, 100][reg as usize]
+            }
+            (0xD9, 0) | (0xDD, 0) | (0xDB, 0) | (0xDF, 0) | (0xDB, 5) | (0xDF, 4) | (0xDF, 5) => {
+                let (format, clocks) = match (opcode, reg) {
+                    (0xD9, _) => (Real32, 43),
+                    (0xDD, _) => (Real64, 46),
+                    (0xDB, 0) => (Int32, 56),
+                    (0xDF, 0) => (Int16, 50),
+                    (0xDB, _) => (Real80, 57),
+                    (0xDF, 4) => (Bcd, 300),
+                    _ => (Int64, 64),
+                };
+                let value = self.load(mem, address, format)?;
+                self.push(value)?;
+                clocks
+            }
+            (0xD9 | 0xDB | 0xDD | 0xDF, 2 | 3) | (0xDB | 0xDF, 7) | (0xDF, 6) => {
+                let (format, clocks) = match (opcode, reg) {
+                    (0xD9, _) => (Real32, 87),
+                    (0xDD, _) => (Real64, 100),
+                    (0xDB, 7) => (Real80, 55),
+                    (0xDB, _) => (Int32, 88),
+                    (0xDF, 6) => (Bcd, 530),
+                    (0xDF, 7) => (Int64, 100),
+                    _ => (Int16, 85),
+                };
+                self.store(mem, address, format)?;
+                if reg != 2 {
+                    self.pop();
+                }
+                clocks
+            }
+            (0xD9, 4) => {
+                self.load_environment(mem, address);
+                40
+            }
+            (0xD9, 5) => {
+                self.control = Fpu::read_word(mem, address);
+                10
+            }
+            (0xD9, 6) => {
+                self.store_environment(mem, address);
+                45
+            }
+            (0xD9, 7) => {
+                Fpu::write_memory(mem, address, &self.control.to_le_bytes());
+                15
+            }
+            (0xDD, 4) => {
+                self.restore(mem, address);
+                210
+            }
+            (0xDD, 6) => {
+                self.save(mem, address);
+                210
+            }
+            (0xDD, 7) => {
+                Fpu::write_memory(mem, address, &self.status_word().to_le_bytes());
+                15
+            }
+            // reserved encodings do nothing
+            _ => 0,
+        };
+        Ok(clocks)
+    }
+
+    fn register_instruction(&mut self, opcode: u8, reg: u8, i: u8) -> Step<u32> {
+        let clocks = match (opcode, reg) {
+            (0xD8, 2 | 3) | (0xDC, 2 | 3) | (0xDE, 2) => {
+                let other = self.read(i)?;
+                self.fcom(other, (reg & 1) as u32)?;
+                45
+            }
+            (0xDE, 3) => {
+                // FCOMPP, only with ST(1)
+                let other = self.read(1)?;
+                self.fcom(other, 2)?;
+                50
+            }
+            (0xD8, _) => {
+                let (st0, other) = (self.read(0)?, self.read(i)?);
+                let result = self.arithmetic(reg, st0, other)?;
+                self.write(0, result);
+                [85, 138, 0, 0, 85, 85, 198, 198][reg as usize]
+            }
+            (0xDC | 0xDE, _) => {
+                let (st0, other) = (self.read(0)?, self.read(i)?);
+                let result = self.arithmetic(reg, st0, other)?;
+                self.write(i, result);
+                if opcode == 0xDE {
+                    self.pop();
+                }
+                [85, 138, 0, 0, 85, 85, 198, 198][reg as usize]
+            }
+            (0xD9, 0) => {
+                let value = self.read(i)?;
+                self.push(value)?;
+                20
+            }
+            (0xD9 | 0xDD | 0xDF, 1) => {
+                let (st0, other) = (self.read(0)?, self.read(i)?);
+                self.write(0, other);
+                self.write(i, st0);
+                12
+            }
+            (0xD9, 2) => 13,
+            (0xD9 | 0xDF, 3) | (0xDD, 2 | 3) | (0xDF, 2) => {
+                let value = self.read(0)?;
+                self.write(i, value);
+                if reg == 3 || opcode != 0xDD {
+                    self.pop();
+                }
+                18
+            }
+            (0xD9, 4) => self.sign_instruction(i)?,
+            (0xD9, 5) => {
+                let value = match i {
+                    0 => F80::ONE,
+                    1 => round_constant(transcendental::LOG2_10),
+                    2 => round_constant(transcendental::LOG2_E),
+                    3 => round_constant(transcendental::PI),
+                    4 => round_constant(transcendental::LOG10_2),
+                    5 => round_constant(transcendental::LN2),
+                    6 => F80::ZERO,
+                    _ => return Ok(0),
+                };
+                self.push(value)?;
+                [18, 19, 15, 19, 21, 20, 14, 0][i as usize]
+            }
+            (0xD9, 6 | 7) => self.function_instruction(reg, i)?,
+            (0xDB, 4) => match i {
+                0 => {
+                    self.control &= !INTERRUPT_ENABLE_MASK;
+                    5
+                }
+                1 => {
+                    self.control |= INTERRUPT_ENABLE_MASK;
+                    5
+                }
+                2 => {
+                    self.status &= !(0x00FF | BUSY);
+                    5
+                }
+                3 => {
+                    self.reset();
+                    5
+                }
+                _ => 0,
+            },
+            (0xDD, 0) | (0xDF, 0) => {
+                let physical = self.physical(i);
+                self.set_tag(physical, TAG_EMPTY);
+                if opcode == 0xDF {
+                    self.pop();
+                }
+                12
+            }
+            _ => 0,
+        };
+        Ok(clocks)
+    }
+
+    // D9 E0-E5: FCHS, FABS, FTST and FXAM
+    fn sign_instruction(&mut self, i: u8) -> Step<u32> {
+        let clocks = match i {
+            0 | 1 => {
+                let value = self.read(0)?;
+                let value = if i == 0 { value.negate() } else { value.abs() };
+                self.write(0, value);
+                15
+            }
+            4 => {
+                let st0 = self.read(0)?;
+                let order = self.compare(st0, F80::ZERO)?;
+                self.set_compare_condition(order);
+                42
+            }
+            5 => {
+                let value = self.st(0);
+                let class = if self.is_empty(0) {
+                    C3 | C0
+                } else {
+                    match value.class() {
+                        Class::Unnormal => 0,
+                        Class::NaN => C0,
+                        Class::Normal => C2,
+                        Class::Infinity => C2 | C0,
+                        Class::Zero => C3,
+                        Class::Denormal => C3 | C2,
+                    }
+                };
+                let sign = if value.sign { C1 } else { 0 };
+                self.set_condition(class | sign);
+                17
+            }
+            _ => 0,
+        };
+        Ok(clocks)
+    }
+
+    // D9 F0-FF
+    fn function_instruction(&mut self, reg: u8, i: u8) -> Step<u32> {
+        let clocks = match (reg, i) {
+            (6, 0) => {
+                let x = self.read(0)?;
+                let result = match self.check_operands(&[x])? {
+                    Some(nan) => nan,
+                    None => match x.class() {
+                        Class::Infinity if x.sign => F80::ONE.negate(),
+                        Class::Infinity => x,
+                        _ => self.deliver(transcendental::exp2m1(x.unpack()), Format::EXTENDED)?,
+                    },
+                };
+                self.write(0, result);
+                500
+            }
+            (6, 1) | (7, 1) => {
+                let (x, y) = (self.read(0)?, self.read(1)?);
+                let result = if reg == 6 {
+                    self.fyl2x(x, y)?
+                } else {
+                    self.fyl2xp1(x, y)?
+                };
+                self.write(1, result);
+                self.pop();
+                if reg == 6 {
+                    950
+                } else {
+                    850
+                }
+            }
+            (6, 2) => {
+                let x = self.read(0)?;
+                if let Some(nan) = self.check_operands(&[x])? {
+                    self.write(0, nan);
+                    self.push(nan)?;
+                    return Ok(450);
+                }
+                if x.is_infinity() {
+                    let value = self.invalid()?;
+                    self.write(0, value);
+                    self.push(value)?;
+                    return Ok(450);
+                }
+                match transcendental::tan(x.unpack()) {
+                    Some(tan) => {
+                        let tan = self.deliver(tan, Format::EXTENDED)?;
+                        self.write(0, tan);
+                        self.push(F80::ONE)?;
+                        self.set_condition(self.status & (C0 | C1 | C3));
+                    }
+                    None => self.status |= C2,
+                }
+                450
+            }
+            (6, 3) => {
+                let (x, y) = (self.read(0)?, self.read(1)?);
+                let result = self.fpatan(x, y)?;
+                self.write(1, result);
+                self.pop();
+                650
+            }
+            (6, 4) => {
+                let x = self.read(0)?;
+                let (exponent, significand) = match self.check_operands(&[x])? {
+                    Some(nan) => (nan, nan),
+                    None => match x.class() {
+                        Class::Infinity => (F80::infinity(false), x),
+                        _ if x.unpack().is_zero() => {
+                            self.signal(Exceptions::ZERO_DIVIDE)?;
+                            (F80::infinity(true), x)
+                        }
+                        _ => {
+                            let value = x.unpack();
+                            let exponent = integer(value.exp as i64);
+                            (exponent, Ext { exp: 0, ..value }.pack())
+                        }
+                    },
+                };
+                self.write(0, exponent);
+                self.push(significand)?;
+                50
+            }
+            (6, 6) => {
+                self.set_top(self.top().wrapping_sub(1));
+                9
+            }
+            (6, 7) => {
+                self.set_top(self.top() + 1);
+                9
+            }
+            (7, 0) => {
+                self.fprem()?;
+                125
+            }
+            (7, 2) => {
+                let x = self.read(0)?;
+                let result = match self.check_operands(&[x])? {
+                    Some(nan) => nan,
+                    None if x.unpack().is_zero() && !x.is_infinity() => x,
+                    None if x.sign => self.invalid()?,
+                    None if x.is_infinity() && !self.affine() => self.invalid()?,
+                    None if x.is_infinity() => x,
+                    None => self.deliver(x.unpack().sqrt(), self.arithmetic_format())?,
+                };
+                self.write(0, result);
+                183
+            }
+            (7, 4) => {
+                let x = self.read(0)?;
+                let result = match self.check_operands(&[x])? {
+                    Some(nan) => nan,
+                    None if x.is_infinity() => x,
+                    None => {
+                        let (integer, inexact) = x.unpack().round_to_int(self.rounding());
+                        if inexact {
+                            self.signal(Exceptions::PRECISION)?;
+                        }
+                        integer.pack()
+                    }
+                };
+                self.write(0, result);
+                45
+            }
+            (7, 5) => {
+                let (x, y) = (self.read(0)?, self.read(1)?);
+                let result = self.fscale(x, y)?;
+                self.write(0, result);
+                35
+            }
+            _ => 0,
+        };
+        Ok(clocks)
+    }
+
+    // ST(1) * log2(ST(0))
+    fn fyl2x(&mut self, x: F80, y: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[x, y])? {
+            return Ok(nan);
+        }
+        let x_zero = !x.is_infinity() && x.unpack().is_zero();
+        let y_zero = !y.is_infinity() && y.unpack().is_zero();
+        if x.sign && !x_zero {
+            return self.invalid();
+        }
+        if x_zero {
+            if y_zero {
+                return self.invalid();
+            }
+            self.signal(Exceptions::ZERO_DIVIDE)?;
+            return Ok(F80::infinity(!y.sign));
+        }
+        if x.is_infinity() {
+            return if y_zero {
+                self.invalid()
+            } else {
+                Ok(F80::infinity(y.sign))
+            };
+        }
+        let log = transcendental::log2(x.unpack());
+        if y.is_infinity() {
+            return if log.is_zero() {
+                self.invalid()
+            } else {
+                Ok(F80::infinity(y.sign != log.sign))
+            };
+        }
+        self.deliver(log.multiply(y.unpack()), Format::EXTENDED)
+    }
+
+    // ST(1) * log2(ST(0) + 1)
+    fn fyl2xp1(&mut self, x: F80, y: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[x, y])? {
+            return Ok(nan);
+        }
+        if x.is_infinity() || y.is_infinity() {
+            return self.invalid();
+        }
+        let x = x.unpack();
+        match x.compare(Ext::from_int(true, 1, 0)) {
+            Ordering::Less => return self.invalid(),
+            Ordering::Equal => {
+                self.signal(Exceptions::ZERO_DIVIDE)?;
+                return Ok(F80::infinity(!y.sign));
+            }
+            Ordering::Greater => {}
+        }
+        let log = transcendental::log2_1p(x);
+        self.deliver(log.multiply(y.unpack()), Format::EXTENDED)
+    }
+
+    // arctan(ST(1) / ST(0))
+    fn fpatan(&mut self, x: F80, y: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[x, y])? {
+            return Ok(nan);
+        }
+        let pi = transcendental::PI;
+        let angle = match (x.is_infinity(), y.is_infinity()) {
+            (true, true) => {
+                let quarter = pi.scale(-2);
+                if x.sign {
+                    quarter.multiply(Ext::from_int(false, 3, 0))
+                } else {
+                    quarter
+                }
+            }
+            (false, true) => pi.scale(-1),
+            (true, false) => {
+                if x.sign {
+                    pi
+                } else {
+                    Ext::from_int(false, 0, 0)
+                }
+            }
+            _ => transcendental::atan2(y.unpack(), x.unpack()),
+        };
+        let angle = Ext {
+            sign: y.sign,
+            ..angle
+        };
+        self.deliver(angle, Format::EXTENDED)
+    }
+
+    // ST(0) * 2^trunc(ST(1))
+    fn fscale(&mut self, x: F80, y: F80) -> Step<F80> {
+        if let Some(nan) = self.check_operands(&[x, y])? {
+            return Ok(nan);
+        }
+        let x_zero = !x.is_infinity() && x.unpack().is_zero();
+        if y.is_infinity() {
+            return match (y.sign, x_zero, x.is_infinity()) {
+                (false, true, _) | (true, _, true) => self.invalid(),
+                (false, _, _) => Ok(F80::infinity(x.sign)),
+                (true, _, _) => Ok(F80::zero(x.sign)),
+            };
+        }
+        if x.is_infinity() || x_zero {
+            return Ok(x);
+        }
+        let (n, _) = y.unpack().round_to_int(Rounding::Zero);
+        let n = n
+            .to_i64()
+            .unwrap_or(if y.sign { i64::MIN } else { i64::MAX });
+        let n = n.clamp(-100_000, 100_000) as i32;
+        self.deliver(x.unpack().scale(n), Format::EXTENDED)
+    }
+
+    // the partial remainder of ST(0) / ST(1) with a truncated quotient. At
+    // most 63 quotient bits are worked out at a time, leaving C2 set until
+    // the remainder is complete; C0, C3 and C1 then hold the low three bits
+    // of the quotient.
+    fn fprem(&mut self) -> Step<()> {
+        let (x, y) = (self.read(0)?, self.read(1)?);
+        if let Some(nan) = self.check_operands(&[x, y])? {
+            self.write(0, nan);
+            return Ok(());
+        }
+        let y_zero = !y.is_infinity() && y.unpack().is_zero();
+        if x.is_infinity() || y_zero {
+            let value = self.invalid()?;
+            self.write(0, value);
+            return Ok(());
+        }
+        if y.is_infinity() || x.unpack().is_zero() {
+            self.set_condition(0);
+            return Ok(());
+        }
+        let (a, b) = (x.unpack(), y.unpack());
+        let distance = a.exp - b.exp;
+        if distance < 0 {
+            self.set_condition(0);
+            return Ok(());
+        }
+        let steps = distance.min(63);
+        let divisor = b.sig >> 64;
+        let mut remainder = a.sig >> 64;
+        let mut quotient: u64 = 0;
+        if remainder >= divisor {
+            remainder -= divisor;
+            quotient = 1;
+        }
+        for _ in 0..steps {
+            remainder <<= 1;
+            quotient <<= 1;
+            if remainder >= divisor {
+                remainder -= divisor;
+                quotient |= 1;
+            }
+        }
+        let value = Ext::from_int(x.sign, remainder, b.exp - 63 + (distance - steps));
+        let value = self.deliver(value, Format::EXTENDED)?;
+        self.write(0, value);
+        if steps < distance {
+            self.set_condition(C2);
+        } else {
+            let bit = |n: u32, flag: u16| if quotient >> n & 1 != 0 { flag } else { 0 };
+            self.set_condition(bit(2, C0) | bit(1, C1) | bit(0, C3));
+        }
+        Ok(())
+    }
+
+    // runs one ESC instruction and returns how long the 8087 is busy with it
+    fn execute(&mut self, escape: Escape, mem: &mut MemoryBus) -> u32 {
+        let reg = (escape.modrm >> 3) & 7;
+        let control = match escape.operand {
+            EscapeOperand::Memory(_) => {
+                matches!((escape.opcode, reg), (0xD9, 4..=7) | (0xDD, 4 | 6 | 7))
+            }
+            EscapeOperand::Register(_) => escape.opcode == 0xDB && reg == 4,
+        };
+        if !control {
+            self.instruction_pointer = escape.instruction;
+            self.opcode = (escape.opcode as u16 & 7) << 8 | escape.modrm as u16;
+            if let EscapeOperand::Memory(address) = escape.operand {
+                self.operand_pointer = address;
+            }
+        }
+        let result = match escape.operand {
+            EscapeOperand::Memory(address) => {
+                self.memory_instruction(escape.opcode, reg, address, mem)
+            }
+            EscapeOperand::Register(i) => self.register_instruction(escape.opcode, reg, i),
+        };
+        // an aborted instruction still took its time, near enough
+        result.unwrap_or(50)
+    }
+}
+
+impl Coprocessor for Fpu {
+    fn escape(&mut self, escape: Escape, mem: &mut MemoryBus) {
+        self.busy = self.execute(escape, mem);
+        self.update_irq();
+    }
+
+    fn busy(&self) -> bool {
+        self.busy > 0
+    }
+
+    fn run(&mut self, cycles: u32) {
+        self.busy = self.busy.saturating_sub(cycles);
+    }
+
+    fn nmi_request(&self) -> bool {
+        self.pic.is_none() && self.int
+    }
+}
+
+const BCD_INDEFINITE: [u8; 10] = [0, 0, 0, 0, 0, 0, 0, 0xC0, 0xFF, 0xFF];
+
+fn integer(value: i64) -> F80 {
+    Ext::from_int(value < 0, value.unsigned_abs() as u128, 0).pack()
+}
+
+fn round_constant(constant: Ext) -> F80 {
+    constant.round(Format::EXTENDED, Rounding::Nearest).0
+}
+
+// 18 packed digits, least significant byte first, and the sign in bit 7 of
+// the last byte
+fn from_bcd(bytes: [u8; 10]) -> F80 {
+    let magnitude = bytes[..9].iter().rev().fold(0u64, |n, &byte| {
+        n * 100 + (byte >> 4) as u64 * 10 + (byte & 0xF) as u64
+    });
+    let value = Ext::from_int(bytes[9] & 0x80 != 0, magnitude as u128, 0);
+    value.pack()
+}
+
+fn to_bcd(value: i64) -> [u8; 10] {
+    let mut bytes = [0; 10];
+    let mut magnitude = value.unsigned_abs();
+    for byte in bytes[..9].iter_mut() {
+        let low = magnitude % 10;
+        let high = magnitude / 10 % 10;
+        *byte = (high << 4 | low) as u8;
+        magnitude /= 100;
+    }
+    if value < 0 {
+        bytes[9] = 0x80;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        cpu::{
+            interrupt::NMI,
+            physical_address,
+            tests::{cpu_with_code, DATA_SEGMENT},
+            Cpu,
+        },
+        io::IoDevice,
+    };
+
+    // runs `code` and a final WAIT with an 8087 attached and `data` at DS:0
+    fn run(code: &[u8], data: &[u8]) -> (Cpu, Rc<RefCell<Fpu>>) {
+        let code = [code, &[0x9B]].concat();
+        let mut cpu = cpu_with_code(&code);
+        cpu.mem.load(physical_address(DATA_SEGMENT, 0), data);
+        let fpu = Rc::new(RefCell::new(Fpu::new()));
+        cpu.set_coprocessor(Some(Box::new(fpu.clone())));
+        while (cpu.regs.ip as usize) < code.len() || cpu.is_repeating() {
+            cpu.step();
+        }
+        (cpu, fpu)
+    }
+
+    fn read_bytes<const SIZE: usize>(cpu: &mut Cpu, offset: u16) -> [u8; SIZE] {
+        Fpu::read_memory(&mut cpu.mem, physical_address(DATA_SEGMENT, offset))
+    }
+
+    fn read_f64(cpu: &mut Cpu, offset: u16) -> f64 {
+        f64::from_le_bytes(read_bytes(cpu, offset))
+    }
+
+    fn doubles(values: &[f64]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_arithmetic_and_store() {
+        let code = [
+            0xDD, 0x06, 0x00, 0x00, // fld qword [0]
+            0xDC, 0x0E, 0x08, 0x00, // fmul qword [8]
+            0xDD, 0x06, 0x00, 0x00, // fld qword [0]
+            0xDE, 0xE9, // fsubp st(1), st: 3.375 - 1.5
+            0xDD, 0x1E, 0x10, 0x00, // fstp qword [10h]
+        ];
+        let (mut cpu, fpu) = run(&code, &doubles(&[1.5, 2.25]));
+        assert_eq!(1.875, read_f64(&mut cpu, 0x10));
+        let fpu = fpu.borrow();
+        assert_eq!(0xFFFF, fpu.tag_word());
+        assert_eq!(0, fpu.status_word());
+    }
+
+    #[test]
+    fn test_wait_for_result() {
+        // fdiv st, st(0) ; wait
+        let mut cpu = cpu_with_code(&[0xD8, 0xF0, 0x9B]);
+        let fpu = Rc::new(RefCell::new(Fpu::new()));
+        cpu.set_coprocessor(Some(Box::new(fpu.clone())));
+        fpu.borrow_mut().push(F80::ONE).ok();
+        cpu.step();
+        assert!(fpu.borrow().status_word() & BUSY != 0);
+        let mut polls = 0;
+        while cpu.step() == 5 {
+            polls += 1;
+        }
+        assert!(polls > 30);
+        assert_eq!(3, cpu.regs.ip);
+        assert_eq!(F80::ONE, fpu.borrow().st(0));
+    }
+
+    #[test]
+    fn test_integers() {
+        let mut data = vec![0; 0x30];
+        data[..2].copy_from_slice(&(-1234i16).to_le_bytes());
+        data[8..16].copy_from_slice(&2.5f64.to_le_bytes());
+        let code = [
+            0xDF, 0x06, 0x00, 0x00, // fild word [0]
+            0xDF, 0x36, 0x10, 0x00, // fbstp [10h]
+            0xDF, 0x26, 0x10, 0x00, // fbld [10h]
+            0xDB, 0x1E, 0x20, 0x00, // fistp dword [20h]
+            0xDD, 0x06, 0x08, 0x00, // fld qword [8]
+            0xDF, 0x1E, 0x24, 0x00, // fistp word [24h]
+        ];
+        let (mut cpu, fpu) = run(&code, &data);
+        assert_eq!(
+            [0x34, 0x12, 0, 0, 0, 0, 0, 0, 0, 0x80],
+            read_bytes::<10>(&mut cpu, 0x10)
+        );
+        assert_eq!(-1234, i32::from_le_bytes(read_bytes(&mut cpu, 0x20)));
+        // to nearest even, which is inexact
+        assert_eq!(2, i16::from_le_bytes(read_bytes(&mut cpu, 0x24)));
+        assert_eq!(Exceptions::PRECISION.bits(), fpu.borrow().status_word());
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let code = [
+            0xDD, 0x06, 0x00, 0x00, // fld qword [0]
+            0xDF, 0x16, 0x10, 0x00, // fist word [10h]
+        ];
+        let (mut cpu, fpu) = run(&code, &doubles(&[40000.0]));
+        assert_eq!(0x8000, u16::from_le_bytes(read_bytes(&mut cpu, 0x10)));
+        assert_eq!(
+            Exceptions::INVALID.bits(),
+            fpu.borrow().status_word() & 0x3F
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let code = [
+            0xD9, 0xE8, // fld1
+            0xD9, 0xEE, // fldz
+            0xDE, 0xD9, // fcompp
+            0x9B, 0xDD, 0x3E, 0x10, 0x00, // fstsw [10h]
+            0xD9, 0xE8, // fld1
+            0xD9, 0xE4, // ftst
+            0x9B, 0xDD, 0x3E, 0x12, 0x00, // fstsw [12h]
+            0xD9, 0xE5, // fxam
+            0x9B, 0xDD, 0x3E, 0x14, 0x00, // fstsw [14h]
+        ];
+        let (mut cpu, _) = run(&code, &[]);
+        // 0 < 1, with the stack back where it was
+        assert_eq!(C0, u16::from_le_bytes(read_bytes(&mut cpu, 0x10)));
+        // 1 > 0, at TOP 7
+        assert_eq!(0x3800, u16::from_le_bytes(read_bytes(&mut cpu, 0x12)));
+        // a positive normal number
+        assert_eq!(0x3800 | C2, u16::from_le_bytes(read_bytes(&mut cpu, 0x14)));
+    }
+
+    #[test]
+    fn test_divide_by_zero() {
+        let code = [
+            0xD9, 0xE8, // fld1
+            0xD9, 0xEE, // fldz
+            0xDE, 0xF9, // fdivp st(1), st
+        ];
+        let (_, fpu) = run(&code, &[]);
+        let fpu = fpu.borrow();
+        assert_eq!(F80::infinity(false), fpu.st(0));
+        assert_eq!(
+            Exceptions::ZERO_DIVIDE.bits() | 7 << TOP_SHIFT,
+            fpu.status_word()
+        );
+        assert!(!fpu.interrupt_request());
+
+        // with zero divide unmasked nothing is stored or popped
+        let code = [
+            0xD9, 0x2E, 0x00, 0x00, // fldcw [0]
+            0xDB, 0xE0, // feni
+            0xD9, 0xE8, // fld1
+            0xD9, 0xEE, // fldz
+            0xDE, 0xF9, // fdivp st(1), st
+        ];
+        let (_, fpu) = run(&code, &0x03FBu16.to_le_bytes());
+        let fpu = fpu.borrow();
+        assert_eq!(F80::ZERO, fpu.st(0));
+        assert_eq!(F80::ONE, fpu.st(1));
+        assert_eq!(
+            INTERRUPT_REQUEST | Exceptions::ZERO_DIVIDE.bits() | 6 << TOP_SHIFT,
+            fpu.status_word()
+        );
+        assert!(fpu.interrupt_request());
+    }
+
+    #[test]
+    fn test_interrupt_signalling() {
+        let code = [
+            0xD9, 0x2E, 0x00, 0x00, // fldcw [0]
+            0xDB, 0xE0, // feni
+            0xD9, 0xE8, // fld1
+            0xD9, 0xEE, // fldz
+            0xDE, 0xF9, // fdivp st(1), st
+            0x9B, // wait
+            0xDB, 0xE2, // fnclex
+        ];
+        let mut cpu = cpu_with_code(&code);
+        cpu.mem
+            .load(physical_address(DATA_SEGMENT, 0), &0x03FBu16.to_le_bytes());
+        let fpu = Rc::new(RefCell::new(Fpu::new()));
+        cpu.set_coprocessor(Some(Box::new(fpu.clone())));
+        let pic = Rc::new(RefCell::new(Pic::new()));
+        // level triggered, so IRR follows the line
+        pic.borrow_mut().write_u8(0x20, 0x1B);
+        pic.borrow_mut().write_u8(0x21, 0x08);
+        pic.borrow_mut().write_u8(0x21, 0x01);
+        fpu.borrow_mut().set_pic(Some(pic.clone()), 5);
+        while cpu.regs.ip < 10 {
+            cpu.step();
+            assert_eq!(0, pic.borrow().irr());
+        }
+        cpu.step();
+        assert_eq!(0x20, pic.borrow().irr());
+        while cpu.regs.ip < 13 || cpu.is_repeating() {
+            cpu.step();
+        }
+        assert_eq!(0x20, pic.borrow().irr());
+        cpu.step();
+        assert_eq!(0, pic.borrow().irr());
+
+        // without the jumper the exception is an NMI
+        let mut cpu = cpu_with_code(&code[..12]);
+        cpu.mem
+            .load(physical_address(DATA_SEGMENT, 0), &0x03FBu16.to_le_bytes());
+        cpu.mem.write_u16(NMI as u32 * 4, 0x0100);
+        cpu.set_coprocessor(Some(Box::new(Fpu::new())));
+        while cpu.regs.ip < 12 {
+            cpu.step();
+        }
+        cpu.step();
+        assert_eq!((0, 0x0100), (cpu.regs.cs, cpu.regs.ip));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let (_, fpu) = run(&[0xD9, 0xE8].repeat(9), &[]);
+        let fpu = fpu.borrow();
+        assert_eq!(F80::INDEFINITE, fpu.st(0));
+        assert_eq!(F80::ONE, fpu.st(1));
+        assert_eq!(Exceptions::INVALID.bits(), fpu.status_word() & 0x3F);
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let code = [
+            0xD9, 0xEB, // fldpi
+            0x9B, 0xDD, 0x36, 0x00, 0x00, // fsave [0]
+        ];
+        let (mut cpu, fpu) = run(&code, &[]);
+        assert_eq!(0xFFFF, fpu.borrow().tag_word());
+        let environment: [u8; 14] = read_bytes(&mut cpu, 0);
+        // the pointers are to the FLDPI at 1000:0000
+        assert_eq!(
+            [0xFF, 0x03, 0x00, 0x38, 0xFF, 0x3F, 0, 0, 0xEB, 0x11, 0, 0, 0, 0],
+            environment
+        );
+        let pi = round_constant(transcendental::PI);
+        assert_eq!(pi.to_bytes(), read_bytes::<10>(&mut cpu, 14));
+        // frstor [0]
+        let code = [0xDD, 0x26, 0x00, 0x00];
+        let (_, fpu) = run(&code, &read_bytes::<94>(&mut cpu, 0));
+        let fpu = fpu.borrow();
+        assert_eq!(pi, fpu.st(0));
+        assert_eq!(0x3FFF, fpu.tag_word());
+        assert_eq!(7 << TOP_SHIFT, fpu.status_word());
+    }
+
+    #[test]
+    fn test_functions() {
+        let code = [
+            0xDD, 0x06, 0x00, 0x00, // fld qword [0]
+            0xD9, 0xFA, // fsqrt
+            0xDD, 0x1E, 0x20, 0x00, // fstp qword [20h]
+            0xDD, 0x06, 0x08, 0x00, // fld qword [8]
+            0xDD, 0x06, 0x10, 0x00, // fld qword [10h]
+            0xD9, 0xF8, // fprem
+            0x9B, 0xDD, 0x3E, 0x28, 0x00, // fstsw [28h]
+            0xDD, 0x1E, 0x30, 0x00, // fstp qword [30h]
+            0xD9, 0xE8, // fld1
+            0xD9, 0xE8, // fld1
+            0xD9, 0xF3, // fpatan
+            0xDD, 0x1E, 0x38, 0x00, // fstp qword [38h]
+        ];
+        let (mut cpu, fpu) = run(&code, &doubles(&[2.0, 2.0, 7.5]));
+        assert_eq!(2f64.sqrt(), read_f64(&mut cpu, 0x20));
+        // 7.5 = 3 * 2 + 1.5, after the inexact square root
+        let precision = Exceptions::PRECISION.bits();
+        assert_eq!(
+            precision | C3 | C1 | 6 << TOP_SHIFT,
+            u16::from_le_bytes(read_bytes(&mut cpu, 0x28))
+        );
+        assert_eq!(1.5, read_f64(&mut cpu, 0x30));
+        assert_eq!(std::f64::consts::FRAC_PI_4, read_f64(&mut cpu, 0x38));
+        let fpu = fpu.borrow();
+        assert_eq!(F80::from_f64_bits(2f64.to_bits()).0, fpu.st(0));
+    }
+}
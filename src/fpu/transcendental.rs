@@ -0,0 +1,267 @@
+// The transcendental instructions, worked out to about 125 bits before the
+// result is rounded so that they come out right to the last bit of the
+// extended format.
+
+use super::float80::{Ext, Rounding};
+
+// truncated to 128 bits
+pub const LN2: Ext = Ext::constant(0xB172_17F7_D1CF_79AB_C9E3_B398_03F2_F6AF, -1);
+pub const LOG2_E: Ext = Ext::constant(0xB8AA_3B29_5C17_F0BB_BE87_FED0_691D_3E88, 0);
+pub const LOG2_10: Ext = Ext::constant(0xD49A_784B_CD1B_8AFE_492B_F6FF_4DAF_DB4C, 1);
+pub const LOG10_2: Ext = Ext::constant(0x9A20_9A84_FBCF_F798_8F89_59AC_0B7C_9178, -2);
+pub const PI: Ext = Ext::constant(0xC90F_DAA2_2168_C234_C4C6_628B_80DC_1CD1, 1);
+const SQRT2: u128 = 0xB504_F333_F9DE_6484_597D_89B3_754A_BE9F;
+// tan(pi/8)
+const TAN_PI_8: u128 = 0xD413_CCCF_E779_9211_65F6_26CD_D52A_FA7C;
+
+const N: Rounding = Rounding::Nearest;
+
+fn int(value: u32) -> Ext {
+    Ext::from_int(false, value as u128, 0)
+}
+
+fn one() -> Ext {
+    int(1)
+}
+
+// adds terms until they no longer reach the last bits of the sum
+fn negligible(term: Ext, sum: Ext) -> bool {
+    term.is_zero() || (!sum.is_zero() && term.exp < sum.exp - 130)
+}
+
+// 2^x - 1
+pub fn exp2m1(x: Ext) -> Ext {
+    if x.is_zero() {
+        return x;
+    }
+    // 2^x = 2^n * 2^f with |f| <= 1/2
+    let (n, _) = x.round_to_int(N);
+    let f = x.sub(n, N);
+    let n = n.to_i64().unwrap_or(0).clamp(-20000, 20000) as i32;
+    let t = f.multiply(LN2);
+    let mut term = t;
+    let mut sum = t;
+    let mut k = 2;
+    while !negligible(term, sum) {
+        term = term.multiply(t).divide(int(k));
+        sum = sum.add(term, N);
+        k += 1;
+    }
+    if n == 0 {
+        sum
+    } else {
+        sum.add(one(), N).scale(n).sub(one(), N)
+    }
+}
+
+// 2 * atanh(t) = ln((1 + t) / (1 - t))
+fn atanh2(t: Ext) -> Ext {
+    let t2 = t.multiply(t);
+    let mut power = t;
+    let mut sum = t;
+    let mut k = 3;
+    loop {
+        power = power.multiply(t2);
+        let term = power.divide(int(k));
+        if negligible(term, sum) {
+            break;
+        }
+        sum = sum.add(term, N);
+        k += 2;
+    }
+    sum.scale(1)
+}
+
+// x > 0
+pub fn log2(x: Ext) -> Ext {
+    let mut exponent = x.exp;
+    let mut m = Ext { exp: 0, ..x };
+    // m in [sqrt(2)/2, sqrt(2)], where the series converges fastest
+    if m.sig > SQRT2 {
+        m.exp = -1;
+        exponent += 1;
+    }
+    let t = m.sub(one(), N).divide(m.add(one(), N));
+    let fraction = atanh2(t).multiply(LOG2_E);
+    Ext::from_int(exponent < 0, exponent.unsigned_abs() as u128, 0).add(fraction, N)
+}
+
+// log2(1 + x), x > -1
+pub fn log2_1p(x: Ext) -> Ext {
+    if x.abs().compare(Ext::constant(1 << 127, -1)).is_gt() {
+        return log2(x.add(one(), N));
+    }
+    let t = x.divide(x.add(int(2), N));
+    atanh2(t).multiply(LOG2_E)
+}
+
+// sine and cosine for |x| <= pi/4
+fn sin_cos(x: Ext) -> (Ext, Ext) {
+    let x2 = x.multiply(x).negate();
+    let mut term = x;
+    let mut sin = x;
+    let mut k = 2;
+    while !negligible(term, sin) {
+        term = term.multiply(x2).divide(int(k * (k + 1)));
+        sin = sin.add(term, N);
+        k += 2;
+    }
+    let mut term = one();
+    let mut cos = one();
+    let mut k = 1;
+    while !negligible(term, cos) {
+        term = term.multiply(x2).divide(int(k * (k + 1)));
+        cos = cos.add(term, N);
+        k += 2;
+    }
+    (sin, cos)
+}
+
+// None once |x| is too large to reduce by multiples of pi/2
+pub fn tan(x: Ext) -> Option<Ext> {
+    if x.is_zero() {
+        return Some(x);
+    }
+    if x.exp >= 63 {
+        return None;
+    }
+    let half_pi = PI.scale(-1);
+    let (quadrant, _) = x.divide(half_pi).round_to_int(N);
+    let r = x.sub(quadrant.multiply(half_pi), N);
+    let (sin, cos) = sin_cos(r);
+    let odd = quadrant.to_i64().unwrap_or(0) & 1 != 0;
+    Some(if odd {
+        cos.divide(sin).negate()
+    } else {
+        sin.divide(cos)
+    })
+}
+
+// 0 <= z <= 1
+fn atan(z: Ext) -> Ext {
+    // past tan(pi/8), atan(z) = pi/4 + atan((z - 1) / (z + 1))
+    let reduced = z.exp >= -1 || (z.exp == -2 && z.sig > TAN_PI_8);
+    let w = if reduced {
+        z.sub(one(), N).divide(z.add(one(), N))
+    } else {
+        z
+    };
+    let w2 = w.multiply(w).negate();
+    let mut power = w;
+    let mut sum = w;
+    let mut k = 3;
+    loop {
+        power = power.multiply(w2);
+        let term = power.divide(int(k));
+        if negligible(term, sum) {
+            break;
+        }
+        sum = sum.add(term, N);
+        k += 2;
+    }
+    if reduced {
+        PI.scale(-2).add(sum, N)
+    } else {
+        sum
+    }
+}
+
+// the angle of (x, y), in all four quadrants
+pub fn atan2(y: Ext, x: Ext) -> Ext {
+    let (a, b) = (y.abs(), x.abs());
+    let angle = if a.is_zero() {
+        if x.sign {
+            PI
+        } else {
+            a
+        }
+    } else if b.is_zero() {
+        PI.scale(-1)
+    } else {
+        let angle = if a.compare(b).is_le() {
+            atan(a.divide(b))
+        } else {
+            PI.scale(-1).sub(atan(b.divide(a)), N)
+        };
+        if x.sign {
+            PI.sub(angle, N)
+        } else {
+            angle
+        }
+    };
+    Ext {
+        sign: y.sign,
+        ..angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::float80::{Format, F80};
+    use super::*;
+
+    fn ext(value: f64) -> Ext {
+        F80::from_f64_bits(value.to_bits()).0.unpack()
+    }
+
+    fn extended(value: Ext) -> F80 {
+        value.round(Format::EXTENDED, N).0
+    }
+
+    fn f64_of(value: Ext) -> f64 {
+        f64::from_bits(value.round(Format::DOUBLE, N).0.to_f64_bits())
+    }
+
+    #[test]
+    fn test_constants() {
+        // the values FLDPI, FLDL2T, FLDL2E, FLDLG2 and FLDLN2 load
+        let cases = [
+            (PI, 0x4000, 0xC90F_DAA2_2168_C235),
+            (LOG2_10, 0x4000, 0xD49A_784B_CD1B_8AFE),
+            (LOG2_E, 0x3FFF, 0xB8AA_3B29_5C17_F0BC),
+            (LOG10_2, 0x3FFD, 0x9A20_9A84_FBCF_F799),
+            (LN2, 0x3FFE, 0xB172_17F7_D1CF_79AC),
+        ];
+        for (constant, exponent, significand) in cases {
+            let value = extended(constant);
+            assert_eq!((exponent, significand), (value.exponent, value.significand));
+        }
+    }
+
+    #[test]
+    fn test_exp2m1() {
+        assert_eq!(1.0, f64_of(exp2m1(ext(1.0))));
+        assert_eq!(-0.5, f64_of(exp2m1(ext(-1.0))));
+        // sqrt(2) - 1
+        let value = extended(exp2m1(ext(0.5)));
+        let expected = Ext::constant(SQRT2, 0).sub(one(), N);
+        assert_eq!(extended(expected), value);
+    }
+
+    #[test]
+    fn test_log2() {
+        assert_eq!(3.0, f64_of(log2(ext(8.0))));
+        assert_eq!(-2.0, f64_of(log2(ext(0.25))));
+        assert_eq!(extended(LOG2_10), extended(log2(ext(10.0))));
+        assert!(log2(ext(1.0)).is_zero());
+        assert_eq!(1.0, f64_of(log2_1p(ext(1.0))));
+        assert_eq!((1e-10f64).ln_1p() / 2f64.ln(), f64_of(log2_1p(ext(1e-10))));
+    }
+
+    #[test]
+    fn test_tan() {
+        let quarter_pi = PI.scale(-2);
+        assert_eq!(extended(one()), extended(tan(quarter_pi).unwrap()));
+        assert_eq!(0.5f64.tan(), f64_of(tan(ext(0.5)).unwrap()));
+        assert_eq!(2.0f64.tan(), f64_of(tan(ext(2.0)).unwrap()));
+        assert_eq!(None, tan(ext(1e19)));
+    }
+
+    #[test]
+    fn test_atan2() {
+        assert_eq!(extended(PI.scale(-2)), extended(atan2(ext(1.0), ext(1.0))));
+        assert_eq!(0.3f64.atan2(0.7), f64_of(atan2(ext(0.3), ext(0.7))));
+        assert_eq!((-2.0f64).atan2(-1.0), f64_of(atan2(ext(-2.0), ext(-1.0))));
+        assert_eq!(extended(PI), extended(atan2(ext(0.0), ext(-1.0))));
+    }
+}
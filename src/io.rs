@@ -0,0 +1,294 @@
+use std::{cell::RefCell, collections::BTreeMap, error::Error, fmt, rc::Rc};
+
+// value returned for ports nobody decodes
+pub const OPEN_BUS_VALUE: u8 = 0xFF;
+
+/// A handler for a range of I/O ports. Unlike memory devices, ports are passed
+/// as full port numbers, since most chips decode several registers.
+pub trait IoDevice {
+    fn read_u8(&mut self, port: u16) -> u8;
+    fn write_u8(&mut self, port: u16, value: u8);
+}
+
+impl<T: IoDevice + ?Sized> IoDevice for Rc<RefCell<T>> {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        self.borrow_mut().read_u8(port)
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        self.borrow_mut().write_u8(port, value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IoAccess {
+    Read,
+    Write(u8),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    Empty {
+        first: u16,
+        last: u16,
+    },
+    Overlap {
+        first: u16,
+        last: u16,
+        existing: u16,
+    },
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterError::Empty { first, last } => {
+                write!(f, "port range {:04X}h-{:04X}h is empty", first, last)
+            }
+            RegisterError::Overlap {
+                first,
+                last,
+                existing,
+            } => write!(
+                f,
+                "port range {:04X}h-{:04X}h overlaps the range registered at {:04X}h",
+                first, last, existing
+            ),
+        }
+    }
+}
+
+impl Error for RegisterError {}
+
+struct PortRange {
+    first: u16,
+    last: u16,
+    device: Box<dyn IoDevice>,
+}
+
+/// The 64K port address space used by IN and OUT.
+#[derive(Default)]
+pub struct IoBus {
+    // sorted by first port, never overlapping
+    ranges: Vec<PortRange>,
+    // accesses to ports no device claimed, with how often they happened
+    unhandled: BTreeMap<(u16, IoAccess), u64>,
+}
+
+impl IoBus {
+    pub fn new() -> IoBus {
+        IoBus::default()
+    }
+
+    // registers `device` for the ports first..=last
+    pub fn register(
+        &mut self,
+        first: u16,
+        last: u16,
+        device: Box<dyn IoDevice>,
+    ) -> Result<(), RegisterError> {
+        if last < first {
+            return Err(RegisterError::Empty { first, last });
+        }
+        let index = self.ranges.partition_point(|r| r.first < first);
+        let overlapping = [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.ranges.get(i))
+            .find(|r| r.first <= last && first <= r.last);
+        if let Some(range) = overlapping {
+            return Err(RegisterError::Overlap {
+                first,
+                last,
+                existing: range.first,
+            });
+        }
+        self.ranges.insert(
+            index,
+            PortRange {
+                first,
+                last,
+                device,
+            },
+        );
+        Ok(())
+    }
+
+    // returns the device that was registered starting at `first`
+    pub fn unregister(&mut self, first: u16) -> Option<Box<dyn IoDevice>> {
+        let index = self.ranges.iter().position(|r| r.first == first)?;
+        Some(self.ranges.remove(index).device)
+    }
+
+    pub fn is_handled(&self, port: u16) -> bool {
+        self.find(port).is_some()
+    }
+
+    fn find(&self, port: u16) -> Option<usize> {
+        let index = self.ranges.partition_point(|r| r.first <= port);
+        let index = index.checked_sub(1)?;
+        if port <= self.ranges[index].last {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn log_unhandled(&mut self, port: u16, access: IoAccess) {
+        *self.unhandled.entry((port, access)).or_insert(0) += 1;
+    }
+
+    pub fn unhandled(&self) -> &BTreeMap<(u16, IoAccess), u64> {
+        &self.unhandled
+    }
+
+    pub fn clear_unhandled(&mut self) {
+        self.unhandled.clear();
+    }
+
+    pub fn read_u8(&mut self, port: u16) -> u8 {
+        match self.find(port) {
+            Some(index) => self.ranges[index].device.read_u8(port),
+            None => {
+                self.log_unhandled(port, IoAccess::Read);
+                OPEN_BUS_VALUE
+            }
+        }
+    }
+
+    pub fn write_u8(&mut self, port: u16, value: u8) {
+        match self.find(port) {
+            Some(index) => self.ranges[index].device.write_u8(port, value),
+            None => self.log_unhandled(port, IoAccess::Write(value)),
+        }
+    }
+
+    // word accesses go out as two byte cycles, low port first
+    pub fn read_u16(&mut self, port: u16) -> u16 {
+        let low = self.read_u8(port);
+        let high = self.read_u8(port.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+
+    pub fn write_u16(&mut self, port: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_u8(port, low);
+        self.write_u8(port.wrapping_add(1), high);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Latches {
+        base: u16,
+        values: [u8; 4],
+        log: Vec<(u16, IoAccess)>,
+    }
+
+    impl IoDevice for Latches {
+        fn read_u8(&mut self, port: u16) -> u8 {
+            self.log.push((port, IoAccess::Read));
+            self.values[(port - self.base) as usize]
+        }
+
+        fn write_u8(&mut self, port: u16, value: u8) {
+            self.log.push((port, IoAccess::Write(value)));
+            self.values[(port - self.base) as usize] = value;
+        }
+    }
+
+    fn latches(base: u16) -> Rc<RefCell<Latches>> {
+        Rc::new(RefCell::new(Latches {
+            base,
+            values: [0; 4],
+            log: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn test_dispatch_by_range() {
+        let pic = latches(0x20);
+        let pit = latches(0x40);
+        let mut bus = IoBus::new();
+        bus.register(0x40, 0x43, Box::new(pit.clone())).unwrap();
+        bus.register(0x20, 0x21, Box::new(pic.clone())).unwrap();
+
+        bus.write_u8(0x21, 0xBC);
+        bus.write_u8(0x43, 0x36);
+        assert_eq!(0xBC, bus.read_u8(0x21));
+        assert_eq!(
+            vec![(0x21, IoAccess::Write(0xBC)), (0x21, IoAccess::Read)],
+            pic.borrow().log
+        );
+        assert_eq!(vec![(0x43, IoAccess::Write(0x36))], pit.borrow().log);
+        assert!(bus.unhandled().is_empty());
+    }
+
+    #[test]
+    fn test_word_access_is_two_byte_cycles() {
+        let dev = latches(0x40);
+        let mut bus = IoBus::new();
+        bus.register(0x40, 0x43, Box::new(dev.clone())).unwrap();
+
+        bus.write_u16(0x40, 0x1234);
+        assert_eq!(0x1234, bus.read_u16(0x40));
+        assert_eq!(
+            vec![
+                (0x40, IoAccess::Write(0x34)),
+                (0x41, IoAccess::Write(0x12)),
+                (0x40, IoAccess::Read),
+                (0x41, IoAccess::Read)
+            ],
+            dev.borrow().log
+        );
+    }
+
+    #[test]
+    fn test_unhandled_ports() {
+        let mut bus = IoBus::new();
+        bus.register(0x20, 0x21, Box::new(latches(0x20))).unwrap();
+
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0x201));
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0x201));
+        // high byte of a word straddling the end of a device's range
+        assert_eq!(0xFF00, bus.read_u16(0x21));
+        bus.write_u8(0x80, 0x01);
+
+        let expected: BTreeMap<(u16, IoAccess), u64> = [
+            ((0x22, IoAccess::Read), 1),
+            ((0x80, IoAccess::Write(0x01)), 1),
+            ((0x201, IoAccess::Read), 2),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(&expected, bus.unhandled());
+        bus.clear_unhandled();
+        assert!(bus.unhandled().is_empty());
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let mut bus = IoBus::new();
+        bus.register(0x3D4, 0x3DF, Box::new(latches(0x3D4)))
+            .unwrap();
+        assert_eq!(
+            Err(RegisterError::Overlap {
+                first: 0x3D0,
+                last: 0x3D4,
+                existing: 0x3D4
+            }),
+            bus.register(0x3D0, 0x3D4, Box::new(latches(0x3D0)))
+        );
+        assert_eq!(
+            Err(RegisterError::Empty {
+                first: 0x61,
+                last: 0x60
+            }),
+            bus.register(0x61, 0x60, Box::new(latches(0x60)))
+        );
+        assert!(bus.unregister(0x3D4).is_some());
+        assert!(!bus.is_handled(0x3D5));
+    }
+}
@@ -0,0 +1,532 @@
+// The XT keyboard and the system board interface that shifts its scan codes
+// in. A byte in the shift register shows on PPI port A and raises IRQ1; the
+// next one waits until the BIOS clears the register with a pulse on PB7.
+// Holding the clock line low through PB6 resets the keyboard, which answers
+// with AAh once the line is released.
+//
+// Keys come in as scan code set 1 make codes, from host key names or typed
+// text, and time follows the CPU clock through run_until.
+
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+    rc::Rc,
+};
+
+use crate::{
+    pic::Pic,
+    ppi::{KEYBOARD_CLEAR, KEYBOARD_CLOCK},
+};
+
+// CPU clocks in a millisecond at 4.77MHz
+const MS: u64 = 4773;
+// the clock line has to stay low this long for a reset
+const RESET_HOLD: u64 = 10 * MS;
+const SELF_TEST: u64 = 2 * MS;
+// a byte takes about a millisecond to clock over the cable
+const BYTE_TIME: u64 = MS;
+const TYPEMATIC_DELAY: u64 = 500 * MS;
+// about 10.9 characters a second
+const TYPEMATIC_INTERVAL: u64 = 92 * MS;
+// typed text goes in at 20 keys a second, slow enough for the BIOS buffer
+const KEYSTROKE_INTERVAL: u64 = 50 * MS;
+
+pub const SELF_TEST_PASSED: u8 = 0xAA;
+const BREAK: u8 = 0x80;
+const LEFT_SHIFT: u8 = 0x2A;
+
+// KeyboardEvent.code names of host keys, with their XT make codes. The
+// cursor and editing keys are the keypad ones on the XT.
+const HOST_KEYS: &[(&str, u8)] = &[
+    ("Escape", 0x01),
+    ("Digit1", 0x02),
+    ("Digit2", 0x03),
+    ("Digit3", 0x04),
+    ("Digit4", 0x05),
+    ("Digit5", 0x06),
+    ("Digit6", 0x07),
+    ("Digit7", 0x08),
+    ("Digit8", 0x09),
+    ("Digit9", 0x0A),
+    ("Digit0", 0x0B),
+    ("Minus", 0x0C),
+    ("Equal", 0x0D),
+    ("Backspace", 0x0E),
+    ("Tab", 0x0F),
+    ("KeyQ", 0x10),
+    ("KeyW", 0x11),
+    ("KeyE", 0x12),
+    ("KeyR", 0x13),
+    ("KeyT", 0x14),
+    ("KeyY", 0x15),
+    ("KeyU", 0x16),
+    ("KeyI", 0x17),
+    ("KeyO", 0x18),
+    ("KeyP", 0x19),
+    ("BracketLeft", 0x1A),
+    ("BracketRight", 0x1B),
+    ("Enter", 0x1C),
+    ("NumpadEnter", 0x1C),
+    ("ControlLeft", 0x1D),
+    ("ControlRight", 0x1D),
+    ("KeyA", 0x1E),
+    ("KeyS", 0x1F),
+    ("KeyD", 0x20),
+    ("KeyF", 0x21),
+    ("KeyG", 0x22),
+    ("KeyH", 0x23),
+    ("KeyJ", 0x24),
+    ("KeyK", 0x25),
+    ("KeyL", 0x26),
+    ("Semicolon", 0x27),
+    ("Quote", 0x28),
+    ("Backquote", 0x29),
+    ("ShiftLeft", 0x2A),
+    ("Backslash", 0x2B),
+    ("KeyZ", 0x2C),
+    ("KeyX", 0x2D),
+    ("KeyC", 0x2E),
+    ("KeyV", 0x2F),
+    ("KeyB", 0x30),
+    ("KeyN", 0x31),
+    ("KeyM", 0x32),
+    ("Comma", 0x33),
+    ("Period", 0x34),
+    ("Slash", 0x35),
+    ("NumpadDivide", 0x35),
+    ("ShiftRight", 0x36),
+    ("NumpadMultiply", 0x37),
+    ("PrintScreen", 0x37),
+    ("AltLeft", 0x38),
+    ("AltRight", 0x38),
+    ("Space", 0x39),
+    ("CapsLock", 0x3A),
+    ("F1", 0x3B),
+    ("F2", 0x3C),
+    ("F3", 0x3D),
+    ("F4", 0x3E),
+    ("F5", 0x3F),
+    ("F6", 0x40),
+    ("F7", 0x41),
+    ("F8", 0x42),
+    ("F9", 0x43),
+    ("F10", 0x44),
+    ("NumLock", 0x45),
+    ("ScrollLock", 0x46),
+    ("Numpad7", 0x47),
+    ("Home", 0x47),
+    ("Numpad8", 0x48),
+    ("ArrowUp", 0x48),
+    ("Numpad9", 0x49),
+    ("PageUp", 0x49),
+    ("NumpadSubtract", 0x4A),
+    ("Numpad4", 0x4B),
+    ("ArrowLeft", 0x4B),
+    ("Numpad5", 0x4C),
+    ("Numpad6", 0x4D),
+    ("ArrowRight", 0x4D),
+    ("NumpadAdd", 0x4E),
+    ("Numpad1", 0x4F),
+    ("End", 0x4F),
+    ("Numpad2", 0x50),
+    ("ArrowDown", 0x50),
+    ("Numpad3", 0x51),
+    ("PageDown", 0x51),
+    ("Numpad0", 0x52),
+    ("Insert", 0x52),
+    ("NumpadDecimal", 0x53),
+    ("Delete", 0x53),
+];
+
+// the US layout, each key with what it types unshifted and shifted
+const CHARACTERS: &[(u8, char, char)] = &[
+    (0x01, '\x1B', '\x1B'),
+    (0x02, '1', '!'),
+    (0x03, '2', '@'),
+    (0x04, '3', '#'),
+    (0x05, '4', '$'),
+    (0x06, '5', '%'),
+    (0x07, '6', '^'),
+    (0x08, '7', '&'),
+    (0x09, '8', '*'),
+    (0x0A, '9', '('),
+    (0x0B, '0', ')'),
+    (0x0C, '-', '_'),
+    (0x0D, '=', '+'),
+    (0x0E, '\x08', '\x08'),
+    (0x0F, '\t', '\t'),
+    (0x10, 'q', 'Q'),
+    (0x11, 'w', 'W'),
+    (0x12, 'e', 'E'),
+    (0x13, 'r', 'R'),
+    (0x14, 't', 'T'),
+    (0x15, 'y', 'Y'),
+    (0x16, 'u', 'U'),
+    (0x17, 'i', 'I'),
+    (0x18, 'o', 'O'),
+    (0x19, 'p', 'P'),
+    (0x1A, '[', '{'),
+    (0x1B, ']', '}'),
+    (0x1C, '\n', '\n'),
+    (0x1E, 'a', 'A'),
+    (0x1F, 's', 'S'),
+    (0x20, 'd', 'D'),
+    (0x21, 'f', 'F'),
+    (0x22, 'g', 'G'),
+    (0x23, 'h', 'H'),
+    (0x24, 'j', 'J'),
+    (0x25, 'k', 'K'),
+    (0x26, 'l', 'L'),
+    (0x27, ';', ':'),
+    (0x28, '\'', '"'),
+    (0x29, '`', '~'),
+    (0x2B, '\\', '|'),
+    (0x2C, 'z', 'Z'),
+    (0x2D, 'x', 'X'),
+    (0x2E, 'c', 'C'),
+    (0x2F, 'v', 'V'),
+    (0x30, 'b', 'B'),
+    (0x31, 'n', 'N'),
+    (0x32, 'm', 'M'),
+    (0x33, ',', '<'),
+    (0x34, '.', '>'),
+    (0x35, '/', '?'),
+    (0x39, ' ', ' '),
+];
+
+// the make code of a host key, by its KeyboardEvent.code name
+pub fn host_scan_code(code: &str) -> Option<u8> {
+    HOST_KEYS
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map(|&(_, make)| make)
+}
+
+// the make and break codes that type `c`, with shift around them if needed
+pub fn keystroke(c: char) -> Option<Vec<u8>> {
+    let c = if c == '\r' { '\n' } else { c };
+    let &(make, unshifted, _) = CHARACTERS
+        .iter()
+        .find(|&&(_, unshifted, shifted)| c == unshifted || c == shifted)?;
+    Some(if c == unshifted {
+        vec![make, make | BREAK]
+    } else {
+        vec![LEFT_SHIFT, make, make | BREAK, LEFT_SHIFT | BREAK]
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct Keyboard {
+    // scan codes on their way to the system board
+    queue: VecDeque<u8>,
+    // keystrokes of typed text not yet sent
+    typing: VecDeque<Vec<u8>>,
+    next_keystroke: u64,
+    // the interface's shift register and whether it holds a byte for IRQ1
+    data: u8,
+    full: bool,
+    // PB6 and PB7
+    clock: bool,
+    clear: bool,
+    // when the clock line went low
+    clock_low_since: Option<u64>,
+    // no byte goes out before then
+    next_send: u64,
+    held: HashSet<u8>,
+    // the key that repeats and when it next does
+    repeat: Option<(u8, u64)>,
+    // CPU clocks
+    now: u64,
+    pic: Option<Rc<RefCell<Pic>>>,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
+    }
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard {
+            queue: VecDeque::new(),
+            typing: VecDeque::new(),
+            next_keystroke: 0,
+            data: 0,
+            full: false,
+            clock: false,
+            clear: false,
+            clock_low_since: Some(0),
+            next_send: 0,
+            held: HashSet::new(),
+            repeat: None,
+            now: 0,
+            pic: None,
+        }
+    }
+
+    pub fn set_pic(&mut self, pic: Option<Rc<RefCell<Pic>>>) {
+        self.pic = pic;
+        self.update_irq();
+    }
+
+    // what port A reads
+    pub fn data(&self) -> u8 {
+        self.data
+    }
+
+    // nothing left to send or typed, and the last byte was taken
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.typing.is_empty() && !self.full
+    }
+
+    fn update_irq(&mut self) {
+        if let Some(pic) = &self.pic {
+            pic.borrow_mut().set_irq(1, self.full);
+        }
+    }
+
+    // follows PB6, the clock line, and PB7, which clears the shift register
+    // and keeps it clear while high
+    pub fn set_port_b(&mut self, value: u8) {
+        let clock = value & KEYBOARD_CLOCK != 0;
+        if clock && !self.clock {
+            if let Some(since) = self.clock_low_since.take() {
+                if self.now - since >= RESET_HOLD {
+                    self.reset();
+                }
+            }
+        } else if !clock && self.clock {
+            self.clock_low_since = Some(self.now);
+        }
+        self.clock = clock;
+        self.clear = value & KEYBOARD_CLEAR != 0;
+        if self.clear && self.full {
+            self.data = 0;
+            self.full = false;
+            self.update_irq();
+        }
+    }
+
+    // the keyboard forgets its keys and reports its self test
+    fn reset(&mut self) {
+        self.queue.clear();
+        self.held.clear();
+        self.repeat = None;
+        self.queue.push_back(SELF_TEST_PASSED);
+        self.next_send = self.now + SELF_TEST;
+    }
+
+    pub fn press(&mut self, make: u8) {
+        let make = make & !BREAK;
+        // the host's own auto-repeat is ignored, the keyboard has its own
+        if !self.held.insert(make) {
+            return;
+        }
+        self.queue.push_back(make);
+        self.repeat = Some((make, self.now + TYPEMATIC_DELAY));
+    }
+
+    pub fn release(&mut self, make: u8) {
+        let make = make & !BREAK;
+        if !self.held.remove(&make) {
+            return;
+        }
+        self.queue.push_back(make | BREAK);
+        if matches!(self.repeat, Some((key, _)) if key == make) {
+            self.repeat = None;
+        }
+    }
+
+    // a host key event, false for keys the XT does not have
+    pub fn host_key(&mut self, code: &str, pressed: bool) -> bool {
+        match host_scan_code(code) {
+            Some(make) if pressed => self.press(make),
+            Some(make) => self.release(make),
+            None => return false,
+        }
+        true
+    }
+
+    // queues the keystrokes that type `text`, or returns the first character
+    // no key types without queueing anything
+    pub fn type_text(&mut self, text: &str) -> Result<(), char> {
+        let keystrokes = text
+            .chars()
+            .map(|c| keystroke(c).ok_or(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.typing.extend(keystrokes);
+        Ok(())
+    }
+
+    // catches up with the CPU, to be called with its cycle count after
+    // every step
+    pub fn run_until(&mut self, cpu_cycles: u64) {
+        self.now = cpu_cycles;
+        if let Some((make, at)) = self.repeat {
+            if self.now >= at {
+                self.queue.push_back(make);
+                self.repeat = Some((make, at + TYPEMATIC_INTERVAL));
+            }
+        }
+        if self.queue.is_empty() && self.now >= self.next_keystroke {
+            if let Some(keystroke) = self.typing.pop_front() {
+                self.queue.extend(keystroke);
+                self.next_keystroke = self.now + KEYSTROKE_INTERVAL;
+            }
+        }
+        // the keyboard only sends with the clock released and the shift
+        // register empty
+        if self.clock && !self.clear && !self.full && self.now >= self.next_send {
+            if let Some(byte) = self.queue.pop_front() {
+                self.data = byte;
+                self.full = true;
+                self.next_send = self.now + BYTE_TIME;
+                self.update_irq();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::InterruptController,
+        io::IoDevice,
+        ppi::{Ppi, Switches},
+    };
+
+    fn pc_pic() -> Rc<RefCell<Pic>> {
+        let pic = Rc::new(RefCell::new(Pic::new()));
+        pic.borrow_mut().write_u8(0x20, 0x13);
+        pic.borrow_mut().write_u8(0x21, 0x08);
+        pic.borrow_mut().write_u8(0x21, 0x09);
+        pic
+    }
+
+    // a keyboard on a PPI set up by the BIOS, clock running
+    fn xt_keyboard() -> (Rc<RefCell<Keyboard>>, Ppi) {
+        let keyboard = Rc::new(RefCell::new(Keyboard::new()));
+        let mut ppi = Ppi::new(Switches::default());
+        ppi.set_keyboard(Some(keyboard.clone()));
+        ppi.write_u8(0x63, 0x99);
+        ppi.write_u8(0x61, KEYBOARD_CLOCK);
+        (keyboard, ppi)
+    }
+
+    // what the BIOS interrupt handler does: read port A and pulse PB7
+    fn acknowledge(ppi: &mut Ppi) -> u8 {
+        let value = ppi.read_u8(0x60);
+        let port_b = ppi.port_b();
+        ppi.write_u8(0x61, port_b | KEYBOARD_CLEAR);
+        ppi.write_u8(0x61, port_b);
+        value
+    }
+
+    // steps a millisecond at a time and collects every byte received
+    fn receive(keyboard: &Rc<RefCell<Keyboard>>, ppi: &mut Ppi, ms: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for _ in 0..ms {
+            let now = keyboard.borrow().now + MS;
+            keyboard.borrow_mut().run_until(now);
+            if keyboard.borrow().full {
+                bytes.push(acknowledge(ppi));
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_post_reset_handshake() {
+        let pic = pc_pic();
+        let (keyboard, mut ppi) = xt_keyboard();
+        keyboard.borrow_mut().set_pic(Some(pic.clone()));
+        keyboard.borrow_mut().press(0x1E);
+        // the BIOS holds the clock low for 20ms, then lets go
+        ppi.write_u8(0x61, 0x08);
+        keyboard.borrow_mut().run_until(20 * MS);
+        ppi.write_u8(0x61, 0xC8);
+        ppi.write_u8(0x61, 0x48);
+        keyboard.borrow_mut().run_until(21 * MS);
+        assert!(!pic.borrow().intr());
+        keyboard.borrow_mut().run_until(23 * MS);
+        assert_eq!(0x09, pic.borrow_mut().acknowledge());
+        // the keystroke from before the reset is gone
+        assert_eq!(vec![SELF_TEST_PASSED], receive(&keyboard, &mut ppi, 10));
+    }
+
+    #[test]
+    fn test_short_clock_pulse_does_not_reset() {
+        let (keyboard, mut ppi) = xt_keyboard();
+        keyboard.borrow_mut().run_until(100 * MS);
+        ppi.write_u8(0x61, 0x00);
+        keyboard.borrow_mut().run_until(101 * MS);
+        ppi.write_u8(0x61, KEYBOARD_CLOCK);
+        assert!(receive(&keyboard, &mut ppi, 10).is_empty());
+    }
+
+    #[test]
+    fn test_one_byte_at_a_time() {
+        let (keyboard, mut ppi) = xt_keyboard();
+        keyboard.borrow_mut().press(0x1E);
+        keyboard.borrow_mut().release(0x1E);
+        keyboard.borrow_mut().run_until(MS);
+        assert_eq!(0x1E, ppi.read_u8(0x60));
+        // not cleared, so the break code waits
+        keyboard.borrow_mut().run_until(10 * MS);
+        assert_eq!(0x1E, ppi.read_u8(0x60));
+        assert_eq!(0x1E, acknowledge(&mut ppi));
+        assert_eq!(0, ppi.read_u8(0x60));
+        assert_eq!(vec![0x9E], receive(&keyboard, &mut ppi, 5));
+        assert!(keyboard.borrow().is_idle());
+    }
+
+    #[test]
+    fn test_typematic_repeat() {
+        let (keyboard, mut ppi) = xt_keyboard();
+        keyboard.borrow_mut().press(0x39);
+        // a second press from the host's own repeat changes nothing
+        keyboard.borrow_mut().press(0x39);
+        let bytes = receive(&keyboard, &mut ppi, 700);
+        // the make code, then again after 500ms and every 92ms after that
+        assert_eq!(vec![0x39; 4], bytes);
+        keyboard.borrow_mut().release(0x39);
+        assert_eq!(vec![0xB9], receive(&keyboard, &mut ppi, 200));
+    }
+
+    #[test]
+    fn test_host_keys() {
+        let (keyboard, mut ppi) = xt_keyboard();
+        assert!(keyboard.borrow_mut().host_key("ShiftLeft", true));
+        assert!(keyboard.borrow_mut().host_key("ArrowUp", true));
+        assert!(keyboard.borrow_mut().host_key("ArrowUp", false));
+        assert!(keyboard.borrow_mut().host_key("ShiftLeft", false));
+        assert!(!keyboard.borrow_mut().host_key("MetaLeft", true));
+        // a release without a press is dropped
+        keyboard.borrow_mut().release(0x10);
+        assert_eq!(
+            vec![0x2A, 0x48, 0xC8, 0xAA],
+            receive(&keyboard, &mut ppi, 10)
+        );
+    }
+
+    #[test]
+    fn test_type_text() {
+        let (keyboard, mut ppi) = xt_keyboard();
+        assert_eq!(Err('é'), keyboard.borrow_mut().type_text("né"));
+        assert!(keyboard.borrow().is_idle());
+        keyboard.borrow_mut().type_text("Hi\n").unwrap();
+        let bytes = receive(&keyboard, &mut ppi, 200);
+        assert_eq!(vec![0x2A, 0x23, 0xA3, 0xAA, 0x17, 0x97, 0x1C, 0x9C], bytes);
+        assert!(keyboard.borrow().is_idle());
+    }
+
+    #[test]
+    fn test_keystrokes_are_paced() {
+        let (keyboard, mut ppi) = xt_keyboard();
+        keyboard.borrow_mut().type_text("ab").unwrap();
+        assert_eq!(vec![0x1E, 0x9E], receive(&keyboard, &mut ppi, 40));
+        assert_eq!(vec![0x30, 0xB0], receive(&keyboard, &mut ppi, 40));
+    }
+}
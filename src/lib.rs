@@ -0,0 +1,11 @@
+pub mod alu;
+pub mod cpu;
+pub mod dma;
+pub mod fpu;
+pub mod io;
+pub mod keyboard;
+pub mod memory;
+pub mod pic;
+pub mod pit;
+pub mod ppi;
+pub mod video;
@@ -1,5 +1,3 @@
-mod alu;
-
 fn main() {
     println!("Hello, world!");
 }
@@ -0,0 +1,586 @@
+use std::{cell::RefCell, error::Error, fmt, rc::Rc};
+
+// the 8088 has 20 address lines, anything above wraps around
+pub const ADDRESS_SPACE_SIZE: u32 = 0x10_0000;
+pub const ADDRESS_MASK: u32 = ADDRESS_SPACE_SIZE - 1;
+
+// value seen on the data bus when nothing drives it
+pub const OPEN_BUS_VALUE: u8 = 0xFF;
+
+/// A handler for a range of the physical address space. Offsets are relative to
+/// the start of the region the device is mapped at.
+pub trait MemoryDevice {
+    fn read_u8(&mut self, offset: u32) -> u8;
+    fn write_u8(&mut self, offset: u32, value: u8);
+
+    // whether the device answers at `offset` just now. Only overlays ask;
+    // the bus passes what they don't decode to the regions underneath.
+    fn decodes(&self, _offset: u32) -> bool {
+        true
+    }
+}
+
+// lets a device be mapped on the bus and still be reachable from elsewhere
+// (e.g. a video card that also sits on the I/O bus)
+impl<T: MemoryDevice + ?Sized> MemoryDevice for Rc<RefCell<T>> {
+    fn read_u8(&mut self, offset: u32) -> u8 {
+        self.borrow_mut().read_u8(offset)
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) {
+        self.borrow_mut().write_u8(offset, value)
+    }
+
+    fn decodes(&self, offset: u32) -> bool {
+        self.borrow().decodes(offset)
+    }
+}
+
+pub struct Ram {
+    data: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new(size: usize) -> Ram {
+        Ram {
+            data: vec![0; size],
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl MemoryDevice for Ram {
+    fn read_u8(&mut self, offset: u32) -> u8 {
+        // a region larger than the device mirrors it
+        self.data[offset as usize % self.data.len()]
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) {
+        let len = self.data.len();
+        self.data[offset as usize % len] = value;
+    }
+}
+
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Rom {
+        Rom { data }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl MemoryDevice for Rom {
+    fn read_u8(&mut self, offset: u32) -> u8 {
+        self.data[offset as usize % self.data.len()]
+    }
+
+    fn write_u8(&mut self, _offset: u32, _value: u8) {}
+}
+
+/// Gets told about every write into a `VideoRam`, so it can redraw lazily.
+pub trait VideoRenderer {
+    fn vram_written(&mut self, offset: u32, value: u8);
+}
+
+pub struct VideoRam {
+    ram: Ram,
+    renderer: Option<Rc<RefCell<dyn VideoRenderer>>>,
+}
+
+impl VideoRam {
+    pub fn new(size: usize) -> VideoRam {
+        VideoRam {
+            ram: Ram::new(size),
+            renderer: None,
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Rc<RefCell<dyn VideoRenderer>>) {
+        self.renderer = Some(renderer);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.ram.as_slice()
+    }
+}
+
+impl MemoryDevice for VideoRam {
+    fn read_u8(&mut self, offset: u32) -> u8 {
+        self.ram.read_u8(offset)
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) {
+        self.ram.write_u8(offset, value);
+        if let Some(renderer) = &self.renderer {
+            let len = self.ram.as_slice().len() as u32;
+            renderer.borrow_mut().vram_written(offset % len, value);
+        }
+    }
+}
+
+pub struct OpenBus;
+
+impl MemoryDevice for OpenBus {
+    fn read_u8(&mut self, _offset: u32) -> u8 {
+        OPEN_BUS_VALUE
+    }
+
+    fn write_u8(&mut self, _offset: u32, _value: u8) {}
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapError {
+    OutOfRange {
+        start: u32,
+        size: u32,
+    },
+    Overlap {
+        start: u32,
+        size: u32,
+        existing: u32,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::OutOfRange { start, size } => write!(
+                f,
+                "region {:05X}h-{:05X}h is outside the 1MB address space",
+                start,
+                start + size - 1
+            ),
+            MapError::Overlap {
+                start,
+                size,
+                existing,
+            } => write!(
+                f,
+                "region {:05X}h-{:05X}h overlaps the region mapped at {:05X}h",
+                start,
+                start + size - 1,
+                existing
+            ),
+        }
+    }
+}
+
+impl Error for MapError {}
+
+struct Region {
+    start: u32,
+    size: u32,
+    device: Box<dyn MemoryDevice>,
+    // clocks added to every bus cycle that reaches the region
+    wait_states: u32,
+}
+
+impl Region {
+    fn contains(&self, address: u32) -> bool {
+        address >= self.start && address - self.start < self.size
+    }
+}
+
+/// The 1MB physical address space. Addresses not claimed by any region behave
+/// like an open bus.
+pub struct MemoryBus {
+    // sorted by start address, never overlapping
+    regions: Vec<Region>,
+    // regions laid over the others, e.g. a card that only decodes part of
+    // its window until it is told to; never overlapping each other
+    overlays: Vec<Region>,
+    open_bus: OpenBus,
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        MemoryBus::new()
+    }
+}
+
+impl MemoryBus {
+    pub fn new() -> MemoryBus {
+        MemoryBus {
+            regions: Vec::new(),
+            overlays: Vec::new(),
+            open_bus: OpenBus,
+        }
+    }
+
+    pub fn map(
+        &mut self,
+        start: u32,
+        size: u32,
+        device: Box<dyn MemoryDevice>,
+    ) -> Result<(), MapError> {
+        if size == 0 || start >= ADDRESS_SPACE_SIZE || size > ADDRESS_SPACE_SIZE - start {
+            return Err(MapError::OutOfRange { start, size });
+        }
+        let index = self.regions.partition_point(|r| r.start < start);
+        let overlapping = [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.regions.get(i))
+            .find(|r| r.start < start + size && start < r.start + r.size);
+        if let Some(region) = overlapping {
+            return Err(MapError::Overlap {
+                start,
+                size,
+                existing: region.start,
+            });
+        }
+        self.regions.insert(
+            index,
+            Region {
+                start,
+                size,
+                device,
+                wait_states: 0,
+            },
+        );
+        Ok(())
+    }
+
+    // maps a device over whatever else is there; it takes the addresses it
+    // decodes and leaves the rest to the regions underneath
+    pub fn map_overlay(
+        &mut self,
+        start: u32,
+        size: u32,
+        device: Box<dyn MemoryDevice>,
+    ) -> Result<(), MapError> {
+        if size == 0 || start >= ADDRESS_SPACE_SIZE || size > ADDRESS_SPACE_SIZE - start {
+            return Err(MapError::OutOfRange { start, size });
+        }
+        let overlapping = self
+            .overlays
+            .iter()
+            .find(|r| r.start < start + size && start < r.start + r.size);
+        if let Some(region) = overlapping {
+            return Err(MapError::Overlap {
+                start,
+                size,
+                existing: region.start,
+            });
+        }
+        self.overlays.push(Region {
+            start,
+            size,
+            device,
+            wait_states: 0,
+        });
+        Ok(())
+    }
+
+    pub fn map_ram(&mut self, start: u32, size: u32) -> Result<(), MapError> {
+        self.map(start, size, Box::new(Ram::new(size as usize)))
+    }
+
+    pub fn map_rom(&mut self, start: u32, data: Vec<u8>) -> Result<(), MapError> {
+        let size = data.len() as u32;
+        self.map(start, size, Box::new(Rom::new(data)))
+    }
+
+    // returns the device that was mapped at exactly `start`, an overlay
+    // before a region
+    pub fn unmap(&mut self, start: u32) -> Option<Box<dyn MemoryDevice>> {
+        for regions in [&mut self.overlays, &mut self.regions] {
+            if let Some(index) = regions.iter().position(|r| r.start == start) {
+                return Some(regions.remove(index).device);
+            }
+        }
+        None
+    }
+
+    // slows down the region mapped at exactly `start`, e.g. video RAM shared
+    // with the display adapter. Returns false when nothing is mapped there.
+    pub fn set_wait_states(&mut self, start: u32, wait_states: u32) -> bool {
+        let mut regions = self.overlays.iter_mut().chain(self.regions.iter_mut());
+        match regions.find(|r| r.start == start) {
+            Some(region) => {
+                region.wait_states = wait_states;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn wait_states(&self, address: u32) -> u32 {
+        match self.find(address & ADDRESS_MASK) {
+            Some(region) => region.wait_states,
+            None => 0,
+        }
+    }
+
+    pub fn is_mapped(&self, address: u32) -> bool {
+        self.find(address & ADDRESS_MASK).is_some()
+    }
+
+    fn find_overlay(&self, address: u32) -> Option<usize> {
+        self.overlays
+            .iter()
+            .position(|r| r.contains(address) && r.device.decodes(address - r.start))
+    }
+
+    fn find_region(&self, address: u32) -> Option<usize> {
+        let index = self.regions.partition_point(|r| r.start <= address);
+        let index = index.checked_sub(1)?;
+        if self.regions[index].contains(address) {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn find(&self, address: u32) -> Option<&Region> {
+        match self.find_overlay(address) {
+            Some(index) => Some(&self.overlays[index]),
+            None => self.find_region(address).map(|index| &self.regions[index]),
+        }
+    }
+
+    fn device(&mut self, address: u32) -> (&mut dyn MemoryDevice, u32) {
+        let address = address & ADDRESS_MASK;
+        let region = match self.find_overlay(address) {
+            Some(index) => Some(&mut self.overlays[index]),
+            None => self
+                .find_region(address)
+                .map(|index| &mut self.regions[index]),
+        };
+        match region {
+            Some(region) => (region.device.as_mut(), address - region.start),
+            None => (&mut self.open_bus, address),
+        }
+    }
+
+    pub fn read_u8(&mut self, address: u32) -> u8 {
+        let (device, offset) = self.device(address);
+        device.read_u8(offset)
+    }
+
+    pub fn write_u8(&mut self, address: u32, value: u8) {
+        let (device, offset) = self.device(address);
+        device.write_u8(offset, value)
+    }
+
+    // the 8088 data bus is 8 bits wide, so words are always two byte cycles
+    pub fn read_u16(&mut self, address: u32) -> u16 {
+        let low = self.read_u8(address);
+        let high = self.read_u8(address + 1);
+        u16::from_le_bytes([low, high])
+    }
+
+    pub fn write_u16(&mut self, address: u32, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_u8(address, low);
+        self.write_u8(address + 1, high);
+    }
+
+    // stores through the normal write path, so ROM regions are left untouched
+    pub fn load(&mut self, address: u32, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_u8(address + i as u32, *byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct WriteLog {
+        writes: Vec<(u32, u8)>,
+    }
+
+    impl VideoRenderer for WriteLog {
+        fn vram_written(&mut self, offset: u32, value: u8) {
+            self.writes.push((offset, value));
+        }
+    }
+
+    fn xt_bus(ram_kb: u32) -> MemoryBus {
+        let mut bus = MemoryBus::new();
+        bus.map_ram(0, ram_kb * 1024).unwrap();
+        bus.map_rom(0xFE000, vec![0xEA; 0x2000]).unwrap();
+        bus
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut bus = xt_bus(640);
+        bus.write_u8(0x9FFFF, 0x12);
+        assert_eq!(0x12, bus.read_u8(0x9FFFF));
+        bus.write_u16(0x400, 0xBEEF);
+        assert_eq!(0xEF, bus.read_u8(0x400));
+        assert_eq!(0xBE, bus.read_u8(0x401));
+        assert_eq!(0xBEEF, bus.read_u16(0x400));
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = xt_bus(256);
+        bus.write_u8(0x40000, 0x12);
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0x40000));
+        assert_eq!(0xFFFF, bus.read_u16(0xA0000));
+        assert!(bus.is_mapped(0x3FFFF));
+        assert!(!bus.is_mapped(0x40000));
+    }
+
+    #[test]
+    fn test_rom_ignores_writes() {
+        let mut bus = xt_bus(640);
+        bus.write_u8(0xFFFF0, 0x90);
+        assert_eq!(0xEA, bus.read_u8(0xFFFF0));
+        bus.load(0xFE000, &[1, 2, 3]);
+        assert_eq!(0xEA, bus.read_u8(0xFE001));
+    }
+
+    #[test]
+    fn test_option_rom() {
+        let mut bus = xt_bus(640);
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0xC8000));
+        bus.map_rom(0xC8000, vec![0x55, 0xAA, 0x10]).unwrap();
+        assert_eq!(0xAA55, bus.read_u16(0xC8000));
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0xC8003));
+    }
+
+    #[test]
+    fn test_address_wraps_at_1mb() {
+        let mut bus = xt_bus(640);
+        bus.write_u8(0x100000, 0x34);
+        assert_eq!(0x34, bus.read_u8(0));
+        // word straddling the top of memory takes its high byte from 00000h
+        assert_eq!(0x34EA, bus.read_u16(0xFFFFF));
+    }
+
+    #[test]
+    fn test_mirrored_region() {
+        let mut bus = MemoryBus::new();
+        bus.map(0xB8000, 0x8000, Box::new(Ram::new(0x4000)))
+            .unwrap();
+        bus.write_u8(0xB8010, 0x41);
+        assert_eq!(0x41, bus.read_u8(0xBC010));
+    }
+
+    #[test]
+    fn test_video_ram_notifies_renderer() {
+        let log = Rc::new(RefCell::new(WriteLog { writes: Vec::new() }));
+        let mut vram = VideoRam::new(0x4000);
+        vram.set_renderer(log.clone());
+        let vram = Rc::new(RefCell::new(vram));
+
+        let mut bus = MemoryBus::new();
+        bus.map(0xB8000, 0x8000, Box::new(vram.clone())).unwrap();
+        bus.write_u16(0xB8000, 0x0741);
+        bus.write_u8(0xBC002, 0x42);
+
+        assert_eq!(vec![(0, 0x41), (1, 0x07), (2, 0x42)], log.borrow().writes);
+        assert_eq!(&[0x41, 0x07, 0x42], &vram.borrow().as_slice()[0..3]);
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let mut bus = xt_bus(640);
+        assert_eq!(
+            Err(MapError::Overlap {
+                start: 0x9F000,
+                size: 0x2000,
+                existing: 0
+            }),
+            bus.map_ram(0x9F000, 0x2000)
+        );
+        assert_eq!(
+            Err(MapError::Overlap {
+                start: 0xFD000,
+                size: 0x2000,
+                existing: 0xFE000
+            }),
+            bus.map_ram(0xFD000, 0x2000)
+        );
+        assert_eq!(
+            Err(MapError::OutOfRange {
+                start: 0xFF000,
+                size: 0x2000
+            }),
+            bus.map_ram(0xFF000, 0x2000)
+        );
+        assert!(bus.map_ram(0xA0000, 0x10000).is_ok());
+    }
+
+    struct LowHalf(Ram);
+
+    impl MemoryDevice for LowHalf {
+        fn read_u8(&mut self, offset: u32) -> u8 {
+            self.0.read_u8(offset)
+        }
+
+        fn write_u8(&mut self, offset: u32, value: u8) {
+            self.0.write_u8(offset, value)
+        }
+
+        fn decodes(&self, offset: u32) -> bool {
+            offset < 0x4000
+        }
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut bus = xt_bus(640);
+        bus.map_ram(0xB8000, 0x4000).unwrap();
+        bus.map_overlay(0xB4000, 0x8000, Box::new(LowHalf(Ram::new(0x8000))))
+            .unwrap();
+        bus.write_u8(0xB4000, 0x12);
+        bus.write_u8(0xB8000, 0x34);
+        assert_eq!(0x12, bus.read_u8(0xB4000));
+        assert_eq!(0x34, bus.read_u8(0xB8000));
+        // the region underneath takes what the overlay leaves
+        assert!(bus.unmap(0xB8000).is_some());
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0xB8000));
+        assert!(!bus.is_mapped(0xB8000));
+        assert!(bus.is_mapped(0xB7FFF));
+        assert_eq!(
+            Err(MapError::Overlap {
+                start: 0xB0000,
+                size: 0x8000,
+                existing: 0xB4000
+            }),
+            bus.map_overlay(0xB0000, 0x8000, Box::new(Ram::new(0x8000)))
+        );
+    }
+
+    #[test]
+    fn test_unmap() {
+        let mut bus = xt_bus(640);
+        assert!(bus.unmap(0xFE000).is_some());
+        assert_eq!(OPEN_BUS_VALUE, bus.read_u8(0xFFFF0));
+        assert!(bus.unmap(0xFE000).is_none());
+    }
+
+    #[test]
+    fn test_wait_states() {
+        let mut bus = xt_bus(640);
+        bus.map(0xB8000, 0x4000, Box::new(VideoRam::new(0x4000)))
+            .unwrap();
+        assert!(bus.set_wait_states(0xB8000, 4));
+        assert!(!bus.set_wait_states(0xB8001, 4));
+        assert_eq!(4, bus.wait_states(0xBBFFF));
+        assert_eq!(0, bus.wait_states(0x9FFFF));
+        assert_eq!(0, bus.wait_states(0xC0000));
+        bus.unmap(0xB8000);
+        bus.map_ram(0xB8000, 0x4000).unwrap();
+        assert_eq!(0, bus.wait_states(0xB8000));
+    }
+}
@@ -0,0 +1,550 @@
+// The 8259A programmable interrupt controller, decoded at 20h/21h on the PC
+// and XT. It takes the IRQ lines of the other chips, prioritises them and
+// drives the CPU's INTR line, then hands over the vector in the INTA cycles.
+//
+// Only the 8086 mode is modelled, the 8080 CALL sequence is never used on a
+// PC. A cascaded PIC programs and behaves as a single one; nothing on an XT
+// bus is wired to a slave.
+
+use crate::{cpu::InterruptController, io::IoDevice};
+
+// ICW1
+const ICW1: u8 = 0x10;
+const ICW4_NEEDED: u8 = 0x01;
+const SINGLE: u8 = 0x02;
+const LEVEL_TRIGGERED: u8 = 0x08;
+// ICW4
+const AUTO_EOI: u8 = 0x02;
+const SPECIAL_FULLY_NESTED: u8 = 0x10;
+// OCW2 and OCW3 share A0 = 0 with ICW1, OCW3 has this bit set
+const OCW3: u8 = 0x08;
+// OCW3
+const POLL: u8 = 0x04;
+const READ_REGISTER: u8 = 0x02;
+const READ_ISR: u8 = 0x01;
+const SET_SPECIAL_MASK: u8 = 0x40;
+const SPECIAL_MASK: u8 = 0x20;
+// the poll word when there is a request
+const POLL_REQUEST: u8 = 0x80;
+
+// where the next write to the odd port goes during initialisation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Init {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pic {
+    // the IRQ input levels
+    lines: u8,
+    // requests latched on a rising edge, in edge triggered mode
+    irr: u8,
+    isr: u8,
+    imr: u8,
+    init: Init,
+    icw1: u8,
+    // ICW2, the vector of IR0
+    vector_base: u8,
+    // ICW3, which lines have slaves or the slave's ID
+    cascade: u8,
+    icw4: u8,
+    // the IR with the lowest priority, the one after it is the highest
+    lowest: u8,
+    rotate_on_auto_eoi: bool,
+    special_mask: bool,
+    read_isr: bool,
+    // the next read is a poll
+    poll: bool,
+}
+
+impl Default for Pic {
+    fn default() -> Self {
+        Pic::new()
+    }
+}
+
+impl Pic {
+    // a PIC that has not been programmed yet. Until ICW1 nothing is masked
+    // and the vectors start at 0, the BIOS always initialises it first.
+    pub fn new() -> Pic {
+        Pic {
+            lines: 0,
+            irr: 0,
+            isr: 0,
+            imr: 0,
+            init: Init::Ready,
+            icw1: ICW1 | SINGLE,
+            vector_base: 0,
+            cascade: 0,
+            icw4: 0,
+            lowest: 7,
+            rotate_on_auto_eoi: false,
+            special_mask: false,
+            read_isr: false,
+            poll: false,
+        }
+    }
+
+    // drives IRQ line `irq`. In edge triggered mode a rising edge latches a
+    // request that stays until it is acknowledged; in level triggered mode
+    // the request lasts as long as the line is high.
+    pub fn set_irq(&mut self, irq: u8, level: bool) {
+        let bit = 1 << (irq & 7);
+        if level && self.lines & bit == 0 {
+            self.irr |= bit;
+        }
+        if level {
+            self.lines |= bit;
+        } else {
+            self.lines &= !bit;
+        }
+    }
+
+    // pulses an IRQ line, for devices that only signal an event
+    pub fn raise_irq(&mut self, irq: u8) {
+        self.set_irq(irq, true);
+        self.set_irq(irq, false);
+    }
+
+    pub fn irr(&self) -> u8 {
+        if self.icw1 & LEVEL_TRIGGERED != 0 {
+            self.lines
+        } else {
+            self.irr
+        }
+    }
+
+    pub fn isr(&self) -> u8 {
+        self.isr
+    }
+
+    pub fn imr(&self) -> u8 {
+        self.imr
+    }
+
+    // the slave lines of a master, or a slave's ID
+    pub fn cascade(&self) -> u8 {
+        self.cascade
+    }
+
+    // the vector of IR0
+    pub fn vector_base(&self) -> u8 {
+        self.vector_base
+    }
+
+    fn auto_eoi(&self) -> bool {
+        self.icw4 & AUTO_EOI != 0
+    }
+
+    // IR levels from the highest priority to the lowest
+    fn by_priority(&self) -> impl Iterator<Item = u8> {
+        let first = self.lowest + 1;
+        (0..8).map(move |i| (first + i) & 7)
+    }
+
+    // the request that wins, if it beats everything in service. In special
+    // mask mode the ISR holds nothing off, only the IMR does; in special
+    // fully nested mode a request at the level in service gets through too.
+    fn highest_request(&self) -> Option<u8> {
+        if self.init != Init::Ready {
+            return None;
+        }
+        let requests = self.irr() & !self.imr;
+        let nested = self.icw4 & SPECIAL_FULLY_NESTED != 0;
+        for ir in self.by_priority() {
+            let bit = 1 << ir;
+            let in_service = self.isr & bit != 0 && !self.special_mask;
+            if requests & bit != 0 && (!in_service || nested) {
+                return Some(ir);
+            }
+            if in_service {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn highest_in_service(&self) -> Option<u8> {
+        self.by_priority().find(|ir| self.isr & (1 << ir) != 0)
+    }
+
+    // the INTA cycles, or a poll read. The request moves from IRR to ISR,
+    // and straight out again with automatic EOI.
+    fn accept(&mut self) -> Option<u8> {
+        let ir = self.highest_request()?;
+        let bit = 1 << ir;
+        self.irr &= !bit;
+        if !self.auto_eoi() {
+            self.isr |= bit;
+        } else if self.rotate_on_auto_eoi {
+            self.lowest = ir;
+        }
+        Some(ir)
+    }
+
+    fn write_icw1(&mut self, value: u8) {
+        self.icw1 = value;
+        self.init = Init::Icw2;
+        // the edge sense latches are cleared, so a line already high has to
+        // go low and back up to request again
+        self.irr = 0;
+        self.isr = 0;
+        self.imr = 0;
+        self.lowest = 7;
+        self.special_mask = false;
+        self.rotate_on_auto_eoi = false;
+        self.read_isr = false;
+        self.poll = false;
+        self.icw4 = 0;
+    }
+
+    fn write_ocw2(&mut self, value: u8) {
+        let level = value & 7;
+        // R, SL and EOI
+        match value >> 5 {
+            // non-specific EOI, and with rotation
+            0b001 | 0b101 => {
+                if let Some(ir) = self.highest_in_service() {
+                    self.isr &= !(1 << ir);
+                    if value & 0x80 != 0 {
+                        self.lowest = ir;
+                    }
+                }
+            }
+            // specific EOI, and with rotation
+            0b011 | 0b111 => {
+                self.isr &= !(1 << level);
+                if value & 0x80 != 0 {
+                    self.lowest = level;
+                }
+            }
+            0b100 => self.rotate_on_auto_eoi = true,
+            0b000 => self.rotate_on_auto_eoi = false,
+            // set priority
+            0b110 => self.lowest = level,
+            _ => {}
+        }
+    }
+
+    fn write_ocw3(&mut self, value: u8) {
+        if value & SET_SPECIAL_MASK != 0 {
+            self.special_mask = value & SPECIAL_MASK != 0;
+        }
+        if value & READ_REGISTER != 0 {
+            self.read_isr = value & READ_ISR != 0;
+        }
+        self.poll = value & POLL != 0;
+    }
+}
+
+impl IoDevice for Pic {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        // a poll is answered on either port and acknowledges the request
+        if self.poll {
+            self.poll = false;
+            return match self.accept() {
+                Some(ir) => POLL_REQUEST | ir,
+                None => 0,
+            };
+        }
+        if port & 1 != 0 {
+            self.imr
+        } else if self.read_isr {
+            self.isr
+        } else {
+            self.irr()
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        if port & 1 == 0 {
+            if value & ICW1 != 0 {
+                self.write_icw1(value);
+            } else if value & OCW3 != 0 {
+                self.write_ocw3(value);
+            } else {
+                self.write_ocw2(value);
+            }
+            return;
+        }
+        self.init = match self.init {
+            Init::Ready => {
+                self.imr = value;
+                Init::Ready
+            }
+            Init::Icw2 => {
+                self.vector_base = value & 0xF8;
+                if self.icw1 & SINGLE == 0 {
+                    Init::Icw3
+                } else if self.icw1 & ICW4_NEEDED != 0 {
+                    Init::Icw4
+                } else {
+                    Init::Ready
+                }
+            }
+            Init::Icw3 => {
+                self.cascade = value;
+                if self.icw1 & ICW4_NEEDED != 0 {
+                    Init::Icw4
+                } else {
+                    Init::Ready
+                }
+            }
+            Init::Icw4 => {
+                self.icw4 = value;
+                Init::Ready
+            }
+        };
+    }
+}
+
+impl InterruptController for Pic {
+    fn intr(&self) -> bool {
+        self.highest_request().is_some()
+    }
+
+    // with the request gone by the time of the INTA cycles the 8259A answers
+    // with IR7 and leaves the ISR alone, the spurious interrupt
+    fn acknowledge(&mut self) -> u8 {
+        match self.accept() {
+            Some(ir) => self.vector_base | ir,
+            None => self.vector_base | 7,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::cpu::{
+        physical_address,
+        tests::{cpu_with_code, STACK_SEGMENT},
+    };
+
+    // what the IBM BIOS programs: edge triggered, single, vectors at 08h,
+    // buffered 8086 mode
+    fn pc_pic() -> Pic {
+        let mut pic = Pic::new();
+        pic.write_u8(0x20, 0x13);
+        pic.write_u8(0x21, 0x08);
+        pic.write_u8(0x21, 0x09);
+        pic
+    }
+
+    #[test]
+    fn test_initialisation() {
+        let mut pic = Pic::new();
+        pic.write_u8(0x21, 0xFF);
+        pic.set_irq(1, true);
+        pic.write_u8(0x20, 0x13);
+        // ICW1 clears the mask and the edge latches
+        assert_eq!(0, pic.imr());
+        assert_eq!(0, pic.irr());
+        pic.set_irq(1, false);
+        pic.set_irq(1, true);
+        // no requests until the last ICW
+        assert!(!pic.intr());
+        pic.write_u8(0x21, 0x08);
+        assert!(!pic.intr());
+        pic.write_u8(0x21, 0x09);
+        assert!(pic.intr());
+        assert_eq!(0x09, pic.acknowledge());
+        // the next write to 21h is OCW1
+        pic.write_u8(0x21, 0xBC);
+        assert_eq!(0xBC, pic.read_u8(0x21));
+        assert_eq!(0x08, pic.vector_base());
+    }
+
+    #[test]
+    fn test_cascade_takes_icw3() {
+        let mut pic = Pic::new();
+        // cascaded, ICW4 needed
+        pic.write_u8(0x20, 0x11);
+        pic.write_u8(0x21, 0x70);
+        pic.write_u8(0x21, 0x02);
+        pic.write_u8(0x21, 0x01);
+        assert_eq!(0x02, pic.cascade());
+        assert_eq!(0x01, pic.icw4);
+        pic.raise_irq(0);
+        assert_eq!(0x70, pic.acknowledge());
+    }
+
+    #[test]
+    fn test_fixed_priority_and_nesting() {
+        let mut pic = pc_pic();
+        pic.raise_irq(3);
+        pic.raise_irq(1);
+        assert_eq!(0x0A, pic.irr());
+        assert_eq!(0x09, pic.acknowledge());
+        assert_eq!(0x02, pic.isr());
+        // IRQ3 waits behind IRQ1 in service, IRQ0 interrupts it
+        assert!(!pic.intr());
+        pic.raise_irq(0);
+        assert_eq!(0x08, pic.acknowledge());
+        assert_eq!(0x03, pic.isr());
+        // a non-specific EOI ends the highest level in service
+        pic.write_u8(0x20, 0x20);
+        assert_eq!(0x02, pic.isr());
+        assert!(!pic.intr());
+        pic.write_u8(0x20, 0x20);
+        assert_eq!(0x0B, pic.acknowledge());
+    }
+
+    #[test]
+    fn test_specific_eoi() {
+        let mut pic = pc_pic();
+        pic.raise_irq(4);
+        pic.acknowledge();
+        pic.raise_irq(2);
+        pic.acknowledge();
+        assert_eq!(0x14, pic.isr());
+        pic.write_u8(0x20, 0x64);
+        assert_eq!(0x04, pic.isr());
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut pic = pc_pic();
+        pic.write_u8(0x21, 0x01);
+        pic.raise_irq(0);
+        assert!(!pic.intr());
+        // the request stays latched behind the mask
+        assert_eq!(0x01, pic.irr());
+        pic.write_u8(0x21, 0x00);
+        assert_eq!(0x08, pic.acknowledge());
+    }
+
+    #[test]
+    fn test_read_isr_and_irr() {
+        let mut pic = pc_pic();
+        pic.raise_irq(5);
+        pic.raise_irq(6);
+        pic.acknowledge();
+        assert_eq!(0x40, pic.read_u8(0x20));
+        pic.write_u8(0x20, 0x0B);
+        assert_eq!(0x20, pic.read_u8(0x20));
+        pic.write_u8(0x20, 0x0A);
+        assert_eq!(0x40, pic.read_u8(0x20));
+    }
+
+    #[test]
+    fn test_rotating_priority() {
+        let mut pic = pc_pic();
+        // rotate on non-specific EOI, the serviced level goes to the back
+        pic.raise_irq(0);
+        pic.raise_irq(1);
+        assert_eq!(0x08, pic.acknowledge());
+        pic.write_u8(0x20, 0xA0);
+        pic.raise_irq(0);
+        assert_eq!(0x09, pic.acknowledge());
+        pic.write_u8(0x20, 0xA0);
+        assert_eq!(0x08, pic.acknowledge());
+
+        // set priority makes IR4 the lowest, so IR5 is the highest
+        let mut pic = pc_pic();
+        pic.write_u8(0x20, 0xC4);
+        pic.raise_irq(0);
+        pic.raise_irq(6);
+        assert_eq!(0x0E, pic.acknowledge());
+    }
+
+    #[test]
+    fn test_auto_eoi_rotation() {
+        let mut pic = Pic::new();
+        pic.write_u8(0x20, 0x13);
+        pic.write_u8(0x21, 0x08);
+        pic.write_u8(0x21, 0x03);
+        pic.write_u8(0x20, 0x80);
+        pic.raise_irq(2);
+        pic.raise_irq(3);
+        assert_eq!(0x0A, pic.acknowledge());
+        assert_eq!(0, pic.isr());
+        pic.raise_irq(2);
+        assert_eq!(0x0B, pic.acknowledge());
+    }
+
+    #[test]
+    fn test_special_mask_mode() {
+        let mut pic = pc_pic();
+        pic.raise_irq(2);
+        pic.acknowledge();
+        pic.raise_irq(5);
+        assert!(!pic.intr());
+        // with IR2 masked lower levels get through while it is in service
+        pic.write_u8(0x21, 0x04);
+        pic.write_u8(0x20, 0x68);
+        assert_eq!(0x0D, pic.acknowledge());
+        pic.write_u8(0x20, 0x48);
+        pic.raise_irq(6);
+        assert!(!pic.intr());
+    }
+
+    #[test]
+    fn test_poll() {
+        let mut pic = pc_pic();
+        pic.write_u8(0x20, 0x0C);
+        assert_eq!(0x00, pic.read_u8(0x20));
+        pic.raise_irq(3);
+        pic.write_u8(0x20, 0x0C);
+        assert_eq!(0x83, pic.read_u8(0x20));
+        assert_eq!(0x08, pic.isr());
+        // one read per poll command
+        assert_eq!(0, pic.read_u8(0x20));
+    }
+
+    #[test]
+    fn test_level_triggered() {
+        let mut pic = Pic::new();
+        pic.write_u8(0x20, 0x1B);
+        pic.write_u8(0x21, 0x08);
+        pic.write_u8(0x21, 0x01);
+        // a line already high requests right away
+        pic.set_irq(1, true);
+        assert_eq!(0x09, pic.acknowledge());
+        pic.write_u8(0x20, 0x20);
+        // still high after the EOI, so it requests again
+        assert!(pic.intr());
+        pic.set_irq(1, false);
+        assert!(!pic.intr());
+    }
+
+    #[test]
+    fn test_spurious_interrupt() {
+        let mut pic = Pic::new();
+        pic.write_u8(0x20, 0x1B);
+        pic.write_u8(0x21, 0x08);
+        pic.write_u8(0x21, 0x01);
+        pic.set_irq(2, true);
+        assert!(pic.intr());
+        pic.set_irq(2, false);
+        assert_eq!(0x0F, pic.acknowledge());
+        assert_eq!(0, pic.isr());
+    }
+
+    #[test]
+    fn test_interrupts_cpu() {
+        // sti ; nop
+        let mut cpu = cpu_with_code(&[0xFB, 0x90]);
+        let pic = Rc::new(RefCell::new(pc_pic()));
+        cpu.io.register(0x20, 0x21, Box::new(pic.clone())).unwrap();
+        cpu.set_interrupt_controller(Box::new(pic.clone()));
+        cpu.mem.write_u16(0x09 * 4, 0x0100);
+        cpu.mem.write_u16(0x09 * 4 + 2, 0x5000);
+        cpu.step();
+        pic.borrow_mut().raise_irq(1);
+        cpu.step();
+        cpu.step();
+        assert_eq!((0x5000, 0x0100), (cpu.regs.cs, cpu.regs.ip));
+        assert_eq!(0x02, pic.borrow().isr());
+        // the handler's EOI goes through the I/O bus
+        cpu.io.write_u8(0x20, 0x20);
+        assert_eq!(0, pic.borrow().isr());
+        assert_eq!(0xFA, cpu.regs.sp);
+        assert_eq!(2, cpu.mem.read_u16(physical_address(STACK_SEGMENT, 0xFA)));
+    }
+}
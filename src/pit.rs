@@ -0,0 +1,598 @@
+// The 8253 programmable interval timer at 40h-43h. On the PC channel 0 is
+// the system timer on IRQ0, channel 1 paces DRAM refresh and channel 2 feeds
+// the speaker, with its gate and output on the PPI.
+//
+// The PIT only sees the CPU clock, through run_until, so the two can never
+// drift apart and a run is the same every time.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{cpu::DmaRefresh, io::IoDevice, pic::Pic};
+
+// the PC divides its 14.31818MHz crystal by 3 for the CPU and by 12 for the
+// PIT, which so counts at 1.193182MHz, one tick every 4 CPU clocks
+pub const CPU_CLOCKS_PER_TICK: u64 = 4;
+
+const CONTROL_PORT: u16 = 3;
+// control word
+const LATCH: u8 = 0x00;
+const BCD: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Low,
+    High,
+    // low byte then high byte
+    Word,
+}
+
+#[derive(Debug, Clone)]
+struct Counter {
+    mode: u8,
+    bcd: bool,
+    access: Access,
+    // the count register, what gets loaded into the counting element
+    reload: u16,
+    count: u16,
+    latched: Option<u16>,
+    // the low byte of a word being written
+    written_low: Option<u8>,
+    // the next read of a word is the high byte
+    read_high: bool,
+    out: bool,
+    gate: bool,
+    // a count has been written since the control word
+    written: bool,
+    // the count register goes into the counting element on the next clock
+    load_pending: bool,
+    running: bool,
+    // the strobe of modes 4 and 5 is still to come
+    armed: bool,
+    // an odd count in mode 3 holds OUT high one clock longer
+    extra_clock: bool,
+}
+
+impl Counter {
+    fn new() -> Counter {
+        Counter {
+            mode: 0,
+            bcd: false,
+            access: Access::Word,
+            reload: 0,
+            count: 0,
+            latched: None,
+            written_low: None,
+            read_high: false,
+            out: false,
+            gate: true,
+            written: false,
+            load_pending: false,
+            running: false,
+            armed: false,
+            extra_clock: false,
+        }
+    }
+
+    fn control(&mut self, value: u8) {
+        self.mode = (value >> 1) & 7;
+        // modes 6 and 7 are 2 and 3
+        if self.mode > 5 {
+            self.mode -= 4;
+        }
+        self.bcd = value & BCD != 0;
+        self.access = match (value >> 4) & 3 {
+            1 => Access::Low,
+            2 => Access::High,
+            _ => Access::Word,
+        };
+        self.out = self.mode != 0;
+        self.latched = None;
+        self.written_low = None;
+        self.read_high = false;
+        self.written = false;
+        self.load_pending = false;
+        self.running = false;
+        self.armed = false;
+        self.extra_clock = false;
+    }
+
+    fn latch(&mut self) {
+        if self.latched.is_none() {
+            self.latched = Some(self.count);
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let [low, high] = self.latched.unwrap_or(self.count).to_le_bytes();
+        match self.access {
+            Access::Low => {
+                self.latched = None;
+                low
+            }
+            Access::High => {
+                self.latched = None;
+                high
+            }
+            Access::Word if !self.read_high => {
+                self.read_high = true;
+                low
+            }
+            Access::Word => {
+                self.read_high = false;
+                self.latched = None;
+                high
+            }
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        // in mode 0 the first byte already stops the count
+        if self.mode == 0 {
+            self.out = false;
+        }
+        let reload = match (self.access, self.written_low) {
+            (Access::Low, _) => value as u16,
+            (Access::High, _) => (value as u16) << 8,
+            (Access::Word, None) => {
+                self.written_low = Some(value);
+                return;
+            }
+            (Access::Word, Some(low)) => {
+                self.written_low = None;
+                u16::from_le_bytes([low, value])
+            }
+        };
+        self.reload = reload;
+        self.written = true;
+        match self.mode {
+            0 | 4 => self.load_pending = true,
+            // a new count waits for the end of the current period
+            2 | 3 if !self.running => self.load_pending = true,
+            // 1 and 5 wait for a trigger on the gate
+            _ => {}
+        }
+    }
+
+    fn set_gate(&mut self, gate: bool) {
+        let rising = gate && !self.gate;
+        self.gate = gate;
+        match self.mode {
+            1 | 5 if rising && self.written => self.load_pending = true,
+            2 | 3 if !gate => self.out = true,
+            2 | 3 if rising && self.written => {
+                self.running = false;
+                self.load_pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn load(&mut self) {
+        self.count = if self.mode == 3 {
+            self.reload & !1
+        } else {
+            self.reload
+        };
+        self.running = true;
+        self.armed = true;
+        self.extra_clock = false;
+        if self.mode == 1 {
+            self.out = false;
+        }
+    }
+
+    fn decrement(&mut self, by: u16) {
+        self.count = if self.bcd {
+            let digits = [0, 4, 8, 12].map(|shift| (self.count >> shift) & 0xF);
+            let value = digits[0] + digits[1] * 10 + digits[2] * 100 + digits[3] * 1000;
+            let value = (value + 10000 - by) % 10000;
+            [1, 10, 100, 1000]
+                .iter()
+                .enumerate()
+                .map(|(i, unit)| (value / unit % 10) << (i * 4))
+                .sum()
+        } else {
+            self.count.wrapping_sub(by)
+        };
+    }
+
+    // a falling edge on CLK
+    fn tick(&mut self) {
+        if self.load_pending && (self.gate || !matches!(self.mode, 2 | 3)) {
+            self.load_pending = false;
+            self.load();
+            return;
+        }
+        if !self.running {
+            return;
+        }
+        match self.mode {
+            0 if self.gate && self.written_low.is_none() => {
+                self.decrement(1);
+                if self.count == 0 {
+                    self.out = true;
+                }
+            }
+            1 => {
+                self.decrement(1);
+                if self.count == 0 {
+                    self.out = true;
+                }
+            }
+            2 if self.gate => {
+                if self.count == 1 {
+                    self.count = self.reload;
+                    self.out = true;
+                } else {
+                    self.decrement(1);
+                    if self.count == 1 {
+                        self.out = false;
+                    }
+                }
+            }
+            3 if self.gate => {
+                if self.extra_clock {
+                    self.extra_clock = false;
+                    self.half_period();
+                } else {
+                    self.decrement(2);
+                    if self.count == 0 {
+                        if self.reload & 1 != 0 && self.out {
+                            self.extra_clock = true;
+                        } else {
+                            self.half_period();
+                        }
+                    }
+                }
+            }
+            4 | 5 => {
+                // the strobe lasts one clock
+                self.out = true;
+                if self.mode == 5 || self.gate {
+                    self.decrement(1);
+                    if self.count == 0 && self.armed {
+                        self.armed = false;
+                        self.out = false;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn half_period(&mut self) {
+        self.out = !self.out;
+        self.count = self.reload & !1;
+    }
+
+    // the count a period takes, 0 standing for the largest
+    fn period(&self) -> u32 {
+        match (self.reload, self.bcd) {
+            (0, false) => 0x10000,
+            (0, true) => 10000,
+            (reload, false) => reload as u32,
+            (reload, true) => (0..4).fold(0, |value, i| {
+                value * 10 + ((reload >> (12 - i * 4)) & 0xF) as u32
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pit {
+    counters: [Counter; 3],
+    // PIT clocks run so far
+    ticks: u64,
+    // channel 0's output goes to IRQ0
+    pic: Option<Rc<RefCell<Pic>>>,
+}
+
+impl Default for Pit {
+    fn default() -> Self {
+        Pit::new()
+    }
+}
+
+impl Pit {
+    pub fn new() -> Pit {
+        Pit {
+            counters: [Counter::new(), Counter::new(), Counter::new()],
+            ticks: 0,
+            pic: None,
+        }
+    }
+
+    pub fn set_pic(&mut self, pic: Option<Rc<RefCell<Pic>>>) {
+        if let Some(pic) = &pic {
+            pic.borrow_mut().set_irq(0, self.counters[0].out);
+        }
+        self.pic = pic;
+    }
+
+    // catches up with the CPU, to be called with its cycle count after
+    // every step
+    pub fn run_until(&mut self, cpu_cycles: u64) {
+        let target = cpu_cycles / CPU_CLOCKS_PER_TICK;
+        while self.ticks < target {
+            let irq0 = self.counters[0].out;
+            for counter in &mut self.counters {
+                counter.tick();
+            }
+            self.ticks += 1;
+            self.update_irq0(irq0);
+        }
+    }
+
+    fn update_irq0(&mut self, before: bool) {
+        let out = self.counters[0].out;
+        if out != before {
+            if let Some(pic) = &self.pic {
+                pic.borrow_mut().set_irq(0, out);
+            }
+        }
+    }
+
+    // gates 0 and 1 are tied high on the PC, gate 2 is bit 0 of PPI port B
+    pub fn set_gate(&mut self, channel: usize, gate: bool) {
+        let irq0 = self.counters[0].out;
+        self.counters[channel].set_gate(gate);
+        self.update_irq0(irq0);
+    }
+
+    pub fn gate(&self, channel: usize) -> bool {
+        self.counters[channel].gate
+    }
+
+    pub fn out(&self, channel: usize) -> bool {
+        self.counters[channel].out
+    }
+
+    // the refresh that channel 1 paces, while it runs as a rate generator or
+    // square wave; each of its periods is one DMA cycle
+    pub fn dma_refresh(&self) -> Option<DmaRefresh> {
+        let counter = &self.counters[1];
+        if !matches!(counter.mode, 2 | 3) || !counter.running || !counter.gate {
+            return None;
+        }
+        Some(DmaRefresh {
+            period: counter.period() * CPU_CLOCKS_PER_TICK as u32,
+            ..DmaRefresh::IBM_PC
+        })
+    }
+}
+
+impl IoDevice for Pit {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        match port & 3 {
+            // the 8253 has no read-back command, the control port floats
+            CONTROL_PORT => 0xFF,
+            channel => self.counters[channel as usize].read(),
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        let irq0 = self.counters[0].out;
+        match port & 3 {
+            CONTROL_PORT => {
+                let channel = (value >> 6) as usize;
+                // select counter 3 is illegal on the 8253
+                if channel == 3 {
+                    return;
+                }
+                let counter = &mut self.counters[channel];
+                if (value >> 4) & 3 == LATCH {
+                    counter.latch();
+                } else {
+                    counter.control(value);
+                }
+            }
+            channel => self.counters[channel as usize].write(value),
+        }
+        self.update_irq0(irq0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::InterruptController;
+
+    // runs `ticks` more PIT clocks and returns OUT after each of them
+    fn outputs(pit: &mut Pit, channel: usize, ticks: usize) -> Vec<bool> {
+        (0..ticks)
+            .map(|_| {
+                let next = (pit.ticks + 1) * CPU_CLOCKS_PER_TICK;
+                pit.run_until(next);
+                pit.out(channel)
+            })
+            .collect()
+    }
+
+    fn levels(pattern: &str) -> Vec<bool> {
+        pattern.chars().map(|c| c == '1').collect()
+    }
+
+    #[test]
+    fn test_mode0_interrupt_on_terminal_count() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x30);
+        assert!(!pit.out(0));
+        pit.write_u8(0x40, 3);
+        pit.write_u8(0x40, 0);
+        // a clock to load, three to count down, then high for good
+        assert_eq!(levels("000111"), outputs(&mut pit, 0, 6));
+        // the first byte of a new count stops it
+        pit.write_u8(0x40, 2);
+        assert!(!pit.out(0));
+        let count = pit.counters[0].count;
+        outputs(&mut pit, 0, 3);
+        assert_eq!(count, pit.counters[0].count);
+        pit.write_u8(0x40, 0);
+        assert_eq!(levels("001"), outputs(&mut pit, 0, 3));
+    }
+
+    #[test]
+    fn test_mode0_gate_pauses() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x90);
+        pit.write_u8(0x42, 2);
+        pit.set_gate(2, false);
+        assert_eq!(levels("0000"), outputs(&mut pit, 2, 4));
+        pit.set_gate(2, true);
+        assert_eq!(levels("01"), outputs(&mut pit, 2, 2));
+    }
+
+    #[test]
+    fn test_mode1_one_shot() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x92);
+        pit.set_gate(2, false);
+        pit.write_u8(0x42, 3);
+        assert_eq!(levels("111"), outputs(&mut pit, 2, 3));
+        pit.set_gate(2, true);
+        assert_eq!(levels("00011"), outputs(&mut pit, 2, 5));
+        // retriggering starts the count over
+        pit.set_gate(2, false);
+        pit.set_gate(2, true);
+        outputs(&mut pit, 2, 2);
+        pit.set_gate(2, false);
+        pit.set_gate(2, true);
+        assert_eq!(levels("00011"), outputs(&mut pit, 2, 5));
+    }
+
+    #[test]
+    fn test_mode2_rate_generator() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x14);
+        pit.write_u8(0x40, 4);
+        assert!(pit.out(0));
+        assert_eq!(levels("11101110"), outputs(&mut pit, 0, 8));
+        // a new count waits for the end of the period
+        pit.write_u8(0x40, 2);
+        assert_eq!(levels("10101"), outputs(&mut pit, 0, 5));
+        // gate low forces OUT high, gate high starts over
+        pit.set_gate(0, false);
+        assert!(pit.out(0));
+        pit.set_gate(0, true);
+        assert_eq!(levels("101"), outputs(&mut pit, 0, 3));
+    }
+
+    #[test]
+    fn test_mode3_square_wave() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x16);
+        pit.write_u8(0x40, 4);
+        assert_eq!(levels("110011001"), outputs(&mut pit, 0, 9));
+        // an odd count is high one clock longer than it is low
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x16);
+        pit.write_u8(0x40, 5);
+        assert_eq!(levels("11100111001"), outputs(&mut pit, 0, 11));
+    }
+
+    #[test]
+    fn test_mode4_software_strobe() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x18);
+        pit.write_u8(0x40, 2);
+        assert_eq!(levels("110111"), outputs(&mut pit, 0, 6));
+        // only one strobe per count
+        outputs(&mut pit, 0, 0x10000);
+        assert!(pit.out(0));
+    }
+
+    #[test]
+    fn test_mode5_hardware_strobe() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x9A);
+        pit.write_u8(0x42, 2);
+        assert_eq!(levels("111"), outputs(&mut pit, 2, 3));
+        pit.set_gate(2, false);
+        pit.set_gate(2, true);
+        assert_eq!(levels("11011"), outputs(&mut pit, 2, 5));
+    }
+
+    #[test]
+    fn test_latch_and_access_modes() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x34);
+        pit.write_u8(0x40, 0x34);
+        pit.write_u8(0x40, 0x12);
+        outputs(&mut pit, 0, 3);
+        pit.write_u8(0x43, 0x00);
+        outputs(&mut pit, 0, 5);
+        // the latch holds until both bytes are read, a second latch is
+        // ignored meanwhile
+        assert_eq!(0x32, pit.read_u8(0x40));
+        pit.write_u8(0x43, 0x00);
+        assert_eq!(0x12, pit.read_u8(0x40));
+        assert_eq!(0x2D, pit.read_u8(0x40));
+
+        // high byte only
+        pit.write_u8(0x43, 0x64);
+        pit.write_u8(0x41, 0x02);
+        outputs(&mut pit, 1, 2);
+        assert_eq!(0x01, pit.read_u8(0x41));
+        assert_eq!(0x0200, pit.counters[1].reload);
+        assert_eq!(0xFF, pit.read_u8(0x43));
+    }
+
+    #[test]
+    fn test_bcd() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x31);
+        pit.write_u8(0x40, 0x01);
+        pit.write_u8(0x40, 0x10);
+        outputs(&mut pit, 0, 3);
+        assert_eq!(0x0999, pit.counters[0].count);
+        // 0 is 10000 in BCD
+        pit.write_u8(0x43, 0x35);
+        pit.write_u8(0x40, 0x00);
+        pit.write_u8(0x40, 0x00);
+        outputs(&mut pit, 0, 2);
+        assert_eq!(0x9999, pit.counters[0].count);
+        assert_eq!(10000, pit.counters[0].period());
+    }
+
+    #[test]
+    fn test_runs_on_cpu_clock() {
+        let mut pit = Pit::new();
+        pit.write_u8(0x43, 0x14);
+        pit.write_u8(0x40, 10);
+        pit.run_until(4 * 6 + 3);
+        assert_eq!(6, pit.ticks);
+        assert_eq!(5, pit.counters[0].count);
+    }
+
+    #[test]
+    fn test_channel0_drives_irq0() {
+        let pic = Rc::new(RefCell::new(Pic::new()));
+        pic.borrow_mut().write_u8(0x20, 0x13);
+        pic.borrow_mut().write_u8(0x21, 0x08);
+        pic.borrow_mut().write_u8(0x21, 0x09);
+        let mut pit = Pit::new();
+        pit.set_pic(Some(pic.clone()));
+        // the BIOS setup: mode 3, count 65536
+        pit.write_u8(0x43, 0x36);
+        pit.write_u8(0x40, 0);
+        pit.write_u8(0x40, 0);
+        // setting mode 3 raised OUT already
+        assert_eq!(0x08, pic.borrow_mut().acknowledge());
+        pic.borrow_mut().write_u8(0x20, 0x20);
+        // low after half the count, high again after the whole
+        pit.run_until(0x10000 * CPU_CLOCKS_PER_TICK);
+        assert!(!pic.borrow().intr());
+        pit.run_until(0x10001 * CPU_CLOCKS_PER_TICK);
+        assert_eq!(0x08, pic.borrow_mut().acknowledge());
+    }
+
+    #[test]
+    fn test_channel1_paces_refresh() {
+        let mut pit = Pit::new();
+        assert_eq!(None, pit.dma_refresh());
+        pit.write_u8(0x43, 0x54);
+        pit.write_u8(0x41, 18);
+        pit.run_until(CPU_CLOCKS_PER_TICK);
+        assert_eq!(Some(DmaRefresh::IBM_PC), pit.dma_refresh());
+    }
+}
@@ -0,0 +1,320 @@
+// The 8255 programmable peripheral interface at 60h-63h, wired the way the
+// XT uses it. Port A brings in the keyboard scan code, port B drives the
+// speaker, the timer 2 gate and the keyboard and error check controls, and
+// port C reads back the configuration switches and the timer 2 output.
+//
+// Only mode 0, plain input and output, is modelled; the PC never strobes
+// the ports.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{io::IoDevice, keyboard::Keyboard, pit::Pit};
+
+const PORT_A: usize = 0;
+const PORT_B: usize = 1;
+const PORT_C: usize = 2;
+// mode word
+const MODE_SET: u8 = 0x80;
+const A_INPUT: u8 = 0x10;
+const C_UPPER_INPUT: u8 = 0x08;
+const B_INPUT: u8 = 0x02;
+const C_LOWER_INPUT: u8 = 0x01;
+// port B
+pub const TIMER2_GATE: u8 = 0x01;
+pub const SPEAKER_DATA: u8 = 0x02;
+pub const HIGH_SWITCHES: u8 = 0x08;
+// low holds the keyboard clock line low
+pub const KEYBOARD_CLOCK: u8 = 0x40;
+pub const KEYBOARD_CLEAR: u8 = 0x80;
+// port C
+const TIMER2_OUT: u8 = 0x20;
+
+// what switch block SW1 says is on the display adapter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Display {
+    // a card with its own BIOS, such as the EGA
+    Ega,
+    Cga40,
+    #[default]
+    Cga80,
+    Monochrome,
+}
+
+// the XT's SW1. A switch that is off reads as 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Switches {
+    // POST runs over and over, for burn-in
+    pub post_loop: bool,
+    pub coprocessor: bool,
+    // RAM on the system board, in 64K banks up to 256K
+    pub memory_kb: u32,
+    pub display: Display,
+    // 1 to 4
+    pub floppy_drives: u8,
+}
+
+impl Default for Switches {
+    fn default() -> Self {
+        Switches {
+            post_loop: false,
+            coprocessor: false,
+            memory_kb: 256,
+            display: Display::default(),
+            floppy_drives: 2,
+        }
+    }
+}
+
+impl Switches {
+    pub fn bits(&self) -> u8 {
+        let banks = (self.memory_kb / 64).clamp(1, 4) as u8 - 1;
+        let display = match self.display {
+            Display::Ega => 0,
+            Display::Cga40 => 1,
+            Display::Cga80 => 2,
+            Display::Monochrome => 3,
+        };
+        let drives = self.floppy_drives.clamp(1, 4) - 1;
+        !self.post_loop as u8
+            | (self.coprocessor as u8) << 1
+            | banks << 2
+            | display << 4
+            | drives << 6
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ppi {
+    control: u8,
+    // output latches of ports A, B and C
+    latches: [u8; 3],
+    switches: Switches,
+    // port A reads its shift register, port B drives its clock and clear
+    keyboard: Option<Rc<RefCell<Keyboard>>>,
+    // gate 2 comes from port B, OUT2 goes to port C
+    pit: Option<Rc<RefCell<Pit>>>,
+}
+
+impl Ppi {
+    pub fn new(switches: Switches) -> Ppi {
+        Ppi {
+            // every port is an input after reset
+            control: MODE_SET | A_INPUT | C_UPPER_INPUT | B_INPUT | C_LOWER_INPUT,
+            latches: [0; 3],
+            switches,
+            keyboard: None,
+            pit: None,
+        }
+    }
+
+    pub fn set_pit(&mut self, pit: Option<Rc<RefCell<Pit>>>) {
+        self.pit = pit;
+        self.update_gate();
+    }
+
+    pub fn switches(&self) -> Switches {
+        self.switches
+    }
+
+    pub fn set_switches(&mut self, switches: Switches) {
+        self.switches = switches;
+    }
+
+    pub fn set_keyboard(&mut self, keyboard: Option<Rc<RefCell<Keyboard>>>) {
+        self.keyboard = keyboard;
+        self.port_b_changed();
+    }
+
+    // what the CPU last wrote to port B, or 0 while it is an input
+    pub fn port_b(&self) -> u8 {
+        if self.control & B_INPUT != 0 {
+            0
+        } else {
+            self.latches[PORT_B]
+        }
+    }
+
+    // the speaker cone follows timer 2 ANDed with the data bit
+    pub fn speaker(&self) -> bool {
+        let out2 = match &self.pit {
+            Some(pit) => pit.borrow().out(2),
+            None => false,
+        };
+        self.port_b() & SPEAKER_DATA != 0 && out2
+    }
+
+    fn update_gate(&mut self) {
+        let gate = self.port_b() & TIMER2_GATE != 0;
+        if let Some(pit) = &self.pit {
+            pit.borrow_mut().set_gate(2, gate);
+        }
+    }
+
+    fn port_b_changed(&mut self) {
+        self.update_gate();
+        if let Some(keyboard) = &self.keyboard {
+            keyboard.borrow_mut().set_port_b(self.port_b());
+        }
+    }
+
+    fn port_c_input(&self) -> u8 {
+        let switches = self.switches.bits();
+        let nibble = if self.port_b() & HIGH_SWITCHES != 0 {
+            switches >> 4
+        } else {
+            switches & 0x0F
+        };
+        let out2 = match &self.pit {
+            Some(pit) if pit.borrow().out(2) => TIMER2_OUT,
+            _ => 0,
+        };
+        // no parity or I/O channel errors in bits 6 and 7
+        nibble | out2
+    }
+}
+
+impl IoDevice for Ppi {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        match port & 3 {
+            0 if self.control & A_INPUT != 0 => match &self.keyboard {
+                Some(keyboard) => keyboard.borrow().data(),
+                None => 0,
+            },
+            0 => self.latches[PORT_A],
+            // nothing drives port B on the XT
+            1 if self.control & B_INPUT != 0 => 0xFF,
+            1 => self.latches[PORT_B],
+            2 => {
+                let mut input_mask = 0;
+                if self.control & C_UPPER_INPUT != 0 {
+                    input_mask |= 0xF0;
+                }
+                if self.control & C_LOWER_INPUT != 0 {
+                    input_mask |= 0x0F;
+                }
+                (self.port_c_input() & input_mask) | (self.latches[PORT_C] & !input_mask)
+            }
+            // the control register cannot be read back
+            _ => 0xFF,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        match port & 3 {
+            3 if value & MODE_SET != 0 => {
+                // a mode change resets every output
+                self.control = value;
+                self.latches = [0; 3];
+                self.port_b_changed();
+            }
+            // port C bit set/reset
+            3 => {
+                let bit = 1 << ((value >> 1) & 7);
+                if value & 1 != 0 {
+                    self.latches[PORT_C] |= bit;
+                } else {
+                    self.latches[PORT_C] &= !bit;
+                }
+            }
+            port => {
+                self.latches[port as usize] = value;
+                if port as usize == PORT_B {
+                    self.port_b_changed();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the BIOS setup, A and C in, B out
+    fn xt_ppi(switches: Switches) -> Ppi {
+        let mut ppi = Ppi::new(switches);
+        ppi.write_u8(0x63, 0x99);
+        ppi
+    }
+
+    #[test]
+    fn test_switches() {
+        let switches = Switches {
+            post_loop: false,
+            coprocessor: true,
+            memory_kb: 640,
+            display: Display::Monochrome,
+            floppy_drives: 2,
+        };
+        assert_eq!(0b0111_1111, switches.bits());
+        assert_eq!(
+            0b0010_0001,
+            Switches {
+                memory_kb: 64,
+                floppy_drives: 1,
+                ..Switches::default()
+            }
+            .bits()
+        );
+
+        let mut ppi = xt_ppi(switches);
+        assert_eq!(0x0F, ppi.read_u8(0x62));
+        ppi.write_u8(0x61, HIGH_SWITCHES);
+        assert_eq!(0x07, ppi.read_u8(0x62));
+    }
+
+    #[test]
+    fn test_keyboard_data() {
+        let keyboard = Rc::new(RefCell::new(Keyboard::new()));
+        let mut ppi = xt_ppi(Switches::default());
+        ppi.set_keyboard(Some(keyboard.clone()));
+        ppi.write_u8(0x61, KEYBOARD_CLOCK);
+        keyboard.borrow_mut().press(0x1E);
+        keyboard.borrow_mut().run_until(0);
+        assert_eq!(0x1E, ppi.read_u8(0x60));
+        ppi.write_u8(0x61, 0xCC);
+        assert_eq!(0xCC, ppi.read_u8(0x61));
+        assert_eq!(0xCC, ppi.port_b());
+    }
+
+    #[test]
+    fn test_timer2_gate_and_speaker() {
+        let pit = Rc::new(RefCell::new(Pit::new()));
+        let mut ppi = xt_ppi(Switches::default());
+        ppi.set_pit(Some(pit.clone()));
+        assert!(!pit.borrow().gate(2));
+        // the BIOS beep: channel 2 as a square wave, then gate and data on
+        pit.borrow_mut().write_u8(0x43, 0xB6);
+        pit.borrow_mut().write_u8(0x42, 4);
+        pit.borrow_mut().write_u8(0x42, 0);
+        ppi.write_u8(0x61, TIMER2_GATE | SPEAKER_DATA);
+        assert!(pit.borrow().gate(2));
+        assert!(ppi.speaker());
+        assert_eq!(TIMER2_OUT, ppi.read_u8(0x62) & TIMER2_OUT);
+        pit.borrow_mut()
+            .run_until(3 * crate::pit::CPU_CLOCKS_PER_TICK);
+        assert!(!ppi.speaker());
+        assert_eq!(0, ppi.read_u8(0x62) & TIMER2_OUT);
+        // a mode word clears port B, which closes the gate
+        ppi.write_u8(0x63, 0x99);
+        assert!(!pit.borrow().gate(2));
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut ppi = Ppi::new(Switches::default());
+        // after reset port B is an input that nothing drives
+        ppi.write_u8(0x61, 0x55);
+        assert_eq!(0xFF, ppi.read_u8(0x61));
+        // all outputs, then port C bit set/reset
+        ppi.write_u8(0x63, 0x80);
+        ppi.write_u8(0x60, 0x12);
+        assert_eq!(0x12, ppi.read_u8(0x60));
+        ppi.write_u8(0x63, 0x0F);
+        ppi.write_u8(0x63, 0x03);
+        assert_eq!(0x82, ppi.read_u8(0x62));
+        ppi.write_u8(0x63, 0x0E);
+        assert_eq!(0x02, ppi.read_u8(0x62));
+        assert_eq!(0xFF, ppi.read_u8(0x63));
+    }
+}
@@ -0,0 +1,1098 @@
+// The IBM Color Graphics Adapter: a 6845 at 3D4h/3D5h, the mode control and
+// colour select registers at 3D8h and 3D9h, the status register at 3DAh and
+// 16K of video RAM at B8000h, decoded twice up to BFFFFh.
+//
+// The card runs off the 14.31818MHz crystal, three dots for every CPU clock,
+// so the beam position follows from the CPU cycle count handed to run_until.
+// render draws the whole displayed area from video RAM as it is now.
+//
+// In raster accurate mode the 6845 counters are clocked a character at a
+// time instead, and every character is drawn as the beam passes it, border
+// and sync included. Register changes then show from the character the beam
+// had reached, so splits, mid-line palette changes and vertical total tricks
+// come out as on the card. So does snow: in 80 column text a CPU write to
+// video RAM takes the bus from the CRTC, which displays the CPU's byte as
+// both character and attribute, for the one character clock the write
+// took. The beam only moves in run_until, so these land where it was at the
+// start of the instruction that made them; writes during sync or the border
+// leave no trace.
+//
+// Either way the card's output is kept as RGBI dots, which render shows as
+// an RGBI monitor would, or decoded as composite video once set_composite
+// has picked the card revision.
+
+use std::{cell::RefCell, rc::Rc};
+
+use super::{
+    composite::{self, Revision, Signal},
+    crtc::{Character, Crtc},
+    font_8x8, DisplayAdapter, Framebuffer,
+};
+use crate::{
+    io::{IoDevice, OPEN_BUS_VALUE},
+    memory::{VideoRam, VideoRenderer},
+};
+
+pub const VRAM_START: u32 = 0xB8000;
+pub const VRAM_SIZE: u32 = 0x4000;
+// the address window the card decodes, VRAM_SIZE twice over
+pub const VRAM_WINDOW: u32 = 0x8000;
+pub const FIRST_PORT: u16 = 0x3D0;
+pub const LAST_PORT: u16 = 0x3DF;
+pub const DOTS_PER_CPU_CLOCK: u64 = 3;
+pub const FONT_SIZE: usize = 256 * 8;
+// the raster accurate picture: a whole line of 912 dots and all 262 lines,
+// starting where horizontal and vertical sync end
+pub const RASTER_WIDTH: usize = 912;
+pub const RASTER_HEIGHT: usize = 262;
+
+// mode control register
+pub const HIGH_RES_TEXT: u8 = 0x01;
+pub const GRAPHICS: u8 = 0x02;
+// turns off the colour burst, and picks the third 320x200 palette
+pub const BLACK_AND_WHITE: u8 = 0x04;
+pub const VIDEO_ENABLE: u8 = 0x08;
+pub const HIGH_RES_GRAPHICS: u8 = 0x10;
+// attribute bit 7 blinks instead of giving intense backgrounds
+pub const BLINK: u8 = 0x20;
+// colour select register
+const BACKGROUND: u8 = 0x0F;
+pub const INTENSE_PALETTE: u8 = 0x10;
+pub const PALETTE_1: u8 = 0x20;
+// status register
+pub const DISPLAY_INACTIVE: u8 = 0x01;
+pub const LIGHT_PEN_TRIGGER: u8 = 0x02;
+pub const VERTICAL_RETRACE: u8 = 0x08;
+
+const VSYNC_LINES: u64 = super::crtc::VSYNC_LINES as u64;
+// the blink counter: the cursor flips every 8 frames, blinking text every 16
+const CURSOR_BLINK: u64 = 8;
+const TEXT_BLINK: u64 = 16;
+
+pub struct Cga {
+    crtc: Crtc,
+    mode: u8,
+    colour: u8,
+    vram: Rc<RefCell<VideoRam>>,
+    font: Box<[u8; FONT_SIZE]>,
+    light_pen: bool,
+    now: u64,
+    // the beam, in dots since the frame began
+    dot: u64,
+    frame: u64,
+    signal: Signal,
+    composite: Option<Revision>,
+    framebuffer: Framebuffer,
+    raster: Option<Box<Raster>>,
+    contention: Rc<RefCell<BusContention>>,
+}
+
+// the beam of raster accurate mode
+struct Raster {
+    // the frame being drawn; signal holds the last finished one
+    image: Signal,
+    x: usize,
+    y: usize,
+    hsync: bool,
+    vsync: bool,
+    // dots run that do not make up a whole character clock yet
+    dots: u64,
+    buffer: Vec<u8>,
+}
+
+// CPU writes into video RAM, each with the character clock it fell in
+#[derive(Default)]
+struct BusContention {
+    enabled: bool,
+    // character clocks the beam has run
+    clock: u64,
+    writes: Vec<(u64, u8)>,
+}
+
+impl BusContention {
+    // the byte that took the bus for this character clock, if any. The CRTC
+    // only misses one fetch a character, so the first write wins and the
+    // rest, like any from earlier clocks, are dropped.
+    fn take(&mut self) -> Option<u8> {
+        let snow = self
+            .writes
+            .iter()
+            .find(|&&(clock, _)| clock == self.clock)
+            .map(|&(_, byte)| byte);
+        self.writes.clear();
+        self.clock += 1;
+        snow
+    }
+}
+
+impl VideoRenderer for BusContention {
+    fn vram_written(&mut self, _offset: u32, value: u8) {
+        if self.enabled {
+            self.writes.push((self.clock, value));
+        }
+    }
+}
+
+impl Default for Cga {
+    fn default() -> Self {
+        Cga::new()
+    }
+}
+
+impl Cga {
+    pub fn new() -> Cga {
+        let contention = Rc::new(RefCell::new(BusContention::default()));
+        let mut vram = VideoRam::new(VRAM_SIZE as usize);
+        vram.set_renderer(contention.clone());
+        Cga {
+            crtc: Crtc::new(),
+            mode: 0,
+            colour: 0,
+            vram: Rc::new(RefCell::new(vram)),
+            font: Box::new(font_8x8()),
+            light_pen: false,
+            now: 0,
+            dot: 0,
+            frame: 0,
+            signal: Signal::default(),
+            composite: None,
+            framebuffer: Framebuffer::default(),
+            raster: None,
+            contention,
+        }
+    }
+
+    // draws a character clock at a time; render then returns the last whole
+    // frame the beam drew, RASTER_WIDTH by RASTER_HEIGHT
+    pub fn set_raster_accurate(&mut self, on: bool) {
+        if on == self.raster.is_some() {
+            return;
+        }
+        let mut contention = self.contention.borrow_mut();
+        contention.enabled = on;
+        contention.writes.clear();
+        self.raster = on.then(|| {
+            Box::new(Raster {
+                image: Signal::new(RASTER_WIDTH, RASTER_HEIGHT),
+                x: 0,
+                y: 0,
+                hsync: false,
+                vsync: false,
+                dots: 0,
+                buffer: Vec::with_capacity(16),
+            })
+        });
+        if on {
+            self.signal = Signal::new(RASTER_WIDTH, RASTER_HEIGHT);
+        }
+    }
+
+    pub fn is_raster_accurate(&self) -> bool {
+        self.raster.is_some()
+    }
+
+    // render decodes the picture as a composite monitor would, for the given
+    // revision of the card, or shows RGBI when None
+    pub fn set_composite(&mut self, revision: Option<Revision>) {
+        self.composite = revision;
+    }
+
+    pub fn composite(&self) -> Option<Revision> {
+        self.composite
+    }
+
+    // what the card puts out, the same picture render shows
+    pub fn signal(&mut self) -> &Signal {
+        if self.raster.is_none() {
+            self.draw();
+        }
+        &self.signal
+    }
+
+    // to be mapped at VRAM_START for VRAM_WINDOW bytes. The card keeps the
+    // VideoRam's renderer for itself.
+    pub fn vram(&self) -> Rc<RefCell<VideoRam>> {
+        self.vram.clone()
+    }
+
+    // the 8x8 glyphs of the character ROM, leftmost pixel in bit 7
+    pub fn set_font(&mut self, font: &[u8; FONT_SIZE]) {
+        *self.font = *font;
+    }
+
+    pub fn crtc(&self) -> &Crtc {
+        &self.crtc
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn colour(&self) -> u8 {
+        self.colour
+    }
+
+    // frames the beam has finished since power on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // dots in one character; 40 column text and graphics use the slow clock
+    fn char_width(&self) -> u64 {
+        if self.mode & HIGH_RES_TEXT != 0 {
+            8
+        } else {
+            16
+        }
+    }
+
+    fn line_dots(&self) -> u64 {
+        self.crtc.horizontal_total() as u64 * self.char_width()
+    }
+
+    // dots into the line and lines into the frame
+    fn beam(&self) -> (u64, u64) {
+        let line_dots = self.line_dots();
+        (self.dot % line_dots, self.dot / line_dots)
+    }
+
+    fn status(&self) -> u8 {
+        let (display, vsync) = if self.raster.is_some() {
+            (self.crtc.display_enabled(), self.crtc.in_vsync())
+        } else {
+            let (x, line) = self.beam();
+            let row_height = self.crtc.row_height() as u64;
+            let vsync = self.crtc.vsync_position() as u64 * row_height;
+            (
+                x < self.crtc.horizontal_displayed() as u64 * self.char_width()
+                    && line < self.crtc.vertical_displayed() as u64 * row_height,
+                (vsync..vsync + VSYNC_LINES).contains(&line),
+            )
+        };
+        let mut status = 0;
+        if !display {
+            status |= DISPLAY_INACTIVE;
+        }
+        if vsync {
+            status |= VERTICAL_RETRACE;
+        }
+        if self.light_pen {
+            status |= LIGHT_PEN_TRIGGER;
+        }
+        status
+    }
+
+    fn address_at_beam(&self) -> u16 {
+        if self.raster.is_some() {
+            return self.crtc.address();
+        }
+        let (x, line) = self.beam();
+        let row = line / self.crtc.row_height() as u64;
+        let column = x / self.char_width();
+        let offset = row * self.crtc.horizontal_displayed() as u64 + column;
+        self.crtc.start_address().wrapping_add(offset as u16)
+    }
+
+    fn graphics_colour(&self, pixel: u8) -> u8 {
+        if pixel == 0 {
+            return self.colour & BACKGROUND;
+        }
+        let palette = if self.mode & BLACK_AND_WHITE != 0 {
+            [3, 4, 7]
+        } else if self.colour & PALETTE_1 != 0 {
+            [3, 5, 7]
+        } else {
+            [2, 4, 6]
+        };
+        let intense = if self.colour & INTENSE_PALETTE != 0 {
+            8
+        } else {
+            0
+        };
+        palette[pixel as usize - 1] | intense
+    }
+
+    // the colours of one character's dots on one of its scan lines. Snow
+    // replaces what the CRTC fetched in text modes.
+    fn character(
+        &self,
+        vram: &[u8],
+        address: u16,
+        row_line: u32,
+        cursor: bool,
+        snow: Option<u8>,
+        dots: &mut Vec<u8>,
+    ) {
+        let width = self.char_width() as usize;
+        if self.mode & GRAPHICS != 0 {
+            // two bytes a character, odd scan lines in the second 8K
+            let offset = ((address as usize * 2) & 0x1FFF) | (row_line as usize & 1) << 13;
+            let word = u16::from_be_bytes([vram[offset], vram[offset + 1]]);
+            if self.mode & HIGH_RES_GRAPHICS != 0 {
+                for i in 0..width {
+                    let bit = word >> (15 - i * 16 / width) & 1;
+                    dots.push(if bit != 0 {
+                        self.colour & BACKGROUND
+                    } else {
+                        0
+                    });
+                }
+            } else {
+                for i in 0..width {
+                    let pixel = (word >> (14 - i * 8 / width * 2) & 3) as u8;
+                    dots.push(self.graphics_colour(pixel));
+                }
+            }
+            return;
+        }
+
+        let offset = (address as usize * 2) & (VRAM_SIZE as usize - 1);
+        let (code, attribute) = match snow {
+            Some(byte) => (byte, byte),
+            None => (vram[offset], vram[offset + 1]),
+        };
+        let mut foreground = attribute & 0x0F;
+        let mut background = attribute >> 4;
+        if self.mode & BLINK != 0 {
+            background &= 7;
+            if attribute & 0x80 != 0 && self.frame & TEXT_BLINK != 0 {
+                foreground = background;
+            }
+        }
+        // the character ROM only sees the low three row address lines
+        let mut glyph = self.font[code as usize * 8 + (row_line as usize & 7)];
+        if cursor && self.frame & CURSOR_BLINK == 0 {
+            glyph = 0xFF;
+        }
+        for i in 0..width {
+            let lit = glyph & (0x80 >> (i * 8 / width)) != 0;
+            dots.push(if lit { foreground } else { background });
+        }
+    }
+
+    fn clock_character(&mut self, raster: &mut Raster) {
+        let Character {
+            address,
+            row_line,
+            display,
+            cursor,
+            hsync,
+            vsync,
+        } = self.crtc.tick();
+        // the monitor starts a line when horizontal sync ends, and a frame
+        // when vertical sync does
+        if raster.hsync && !hsync {
+            raster.x = 0;
+            raster.y += 1;
+        }
+        if raster.vsync && !vsync {
+            raster.y = 0;
+            self.signal.clone_from(&raster.image);
+            raster.image.fill(0);
+            self.frame += 1;
+        }
+        // the burst follows sync, so the mode decides it as the line starts
+        if raster.hsync && !hsync {
+            let burst = self.mode & BLACK_AND_WHITE == 0;
+            raster.image.set_colour_burst(raster.y, burst);
+        }
+        raster.hsync = hsync;
+        raster.vsync = vsync;
+
+        let snow = self.contention.borrow_mut().take();
+        let width = self.char_width() as usize;
+        let dots = &mut raster.buffer;
+        dots.clear();
+        if hsync || vsync || self.mode & VIDEO_ENABLE == 0 {
+            dots.resize(width, 0);
+        } else if display {
+            let snow = snow.filter(|_| self.mode & (HIGH_RES_TEXT | GRAPHICS) == HIGH_RES_TEXT);
+            let vram = self.vram.borrow();
+            self.character(vram.as_slice(), address, row_line, cursor, snow, dots);
+        } else {
+            dots.resize(width, self.colour & BACKGROUND);
+        }
+        for &colour in dots.iter() {
+            raster.image.set_dot(raster.x, raster.y, colour);
+            raster.x += 1;
+        }
+    }
+
+    // the displayed area, one dot per pixel of the character and scan line
+    fn draw(&mut self) {
+        let columns = self.crtc.horizontal_displayed();
+        let row_height = self.crtc.row_height();
+        let width = columns as usize * self.char_width() as usize;
+        let height = (self.crtc.vertical_displayed() * row_height) as usize;
+        let mut signal = std::mem::take(&mut self.signal);
+        signal.resize(width, height);
+        for y in 0..height {
+            signal.set_colour_burst(y, self.mode & BLACK_AND_WHITE == 0);
+        }
+        if self.mode & VIDEO_ENABLE == 0 {
+            signal.fill(0);
+            self.signal = signal;
+            return;
+        }
+
+        let vram = self.vram.borrow();
+        let mut dots = Vec::with_capacity(width);
+        for y in 0..height {
+            let row = y as u32 / row_height;
+            let start = self
+                .crtc
+                .start_address()
+                .wrapping_add((row * columns) as u16);
+            dots.clear();
+            let row_line = y as u32 % row_height;
+            for column in 0..columns {
+                let address = start.wrapping_add(column as u16) & 0x3FFF;
+                let cursor = self.crtc.cursor_at(address, row_line);
+                self.character(vram.as_slice(), address, row_line, cursor, None, &mut dots);
+            }
+            for (x, &colour) in dots.iter().enumerate() {
+                signal.set_dot(x, y, colour);
+            }
+        }
+        drop(vram);
+        self.signal = signal;
+    }
+}
+
+impl DisplayAdapter for Cga {
+    fn run_until(&mut self, cpu_cycles: u64) {
+        if cpu_cycles <= self.now {
+            return;
+        }
+        let dots = (cpu_cycles - self.now) * DOTS_PER_CPU_CLOCK;
+        self.now = cpu_cycles;
+        if let Some(mut raster) = self.raster.take() {
+            raster.dots += dots;
+            while raster.dots >= self.char_width() {
+                raster.dots -= self.char_width();
+                self.clock_character(&mut raster);
+            }
+            self.raster = Some(raster);
+            return;
+        }
+        self.dot += dots;
+        let frame_dots = self.line_dots() * self.crtc.frame_lines() as u64;
+        self.frame += self.dot / frame_dots;
+        self.dot %= frame_dots;
+    }
+
+    fn render(&mut self) -> &Framebuffer {
+        if self.raster.is_none() {
+            self.draw();
+        }
+        match self.composite {
+            Some(revision) => self.framebuffer = composite::decode(&self.signal, revision),
+            None => self.signal.to_rgbi(&mut self.framebuffer),
+        }
+        &self.framebuffer
+    }
+}
+
+impl IoDevice for Cga {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        match port & 0x0F {
+            0..=7 if port & 1 != 0 => self.crtc.read(),
+            0x0A => self.status(),
+            // the index, mode and colour registers are write only
+            _ => OPEN_BUS_VALUE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        match port & 0x0F {
+            0..=7 if port & 1 == 0 => self.crtc.select(value),
+            0..=7 => self.crtc.write(value),
+            0x08 => self.mode = value & 0x3F,
+            0x09 => self.colour = value & 0x3F,
+            0x0B => self.light_pen = false,
+            0x0C if !self.light_pen => {
+                self.light_pen = true;
+                let address = self.address_at_beam();
+                self.crtc.latch_light_pen(address);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        memory::{MemoryBus, MemoryDevice},
+        video::RGBI_PALETTE,
+    };
+
+    const TEXT_40: [u8; 16] = [
+        0x38, 0x28, 0x2D, 0x0A, 0x1F, 0x06, 0x19, 0x1C, 0x02, 0x07, 0x06, 0x07, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    const TEXT_80: [u8; 16] = [
+        0x71, 0x50, 0x5A, 0x0A, 0x1F, 0x06, 0x19, 0x1C, 0x02, 0x07, 0x06, 0x07, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    const GRAPHICS_MODE: [u8; 16] = [
+        0x38, 0x28, 0x2D, 0x0A, 0x7F, 0x06, 0x64, 0x70, 0x02, 0x01, 0x06, 0x07, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    const LINE_CLOCKS: u64 = 304;
+
+    // what the BIOS does for a mode set
+    fn cga_in_mode(crtc: &[u8; 16], mode: u8, colour: u8) -> Cga {
+        let mut cga = Cga::new();
+        for (index, &value) in crtc.iter().enumerate() {
+            cga.write_u8(0x3D4, index as u8);
+            cga.write_u8(0x3D5, value);
+        }
+        cga.write_u8(0x3D8, mode);
+        cga.write_u8(0x3D9, colour);
+        cga
+    }
+
+    fn poke(cga: &Cga, offset: u32, bytes: &[u8]) {
+        let vram = cga.vram();
+        for (i, &byte) in bytes.iter().enumerate() {
+            vram.borrow_mut().write_u8(offset + i as u32, byte);
+        }
+    }
+
+    fn colour_at(framebuffer: &Framebuffer, x: usize, y: usize) -> usize {
+        let rgba = framebuffer.pixel(x, y);
+        RGBI_PALETTE.iter().position(|&c| c == rgba).unwrap()
+    }
+
+    fn row_colours(framebuffer: &Framebuffer, y: usize, width: usize) -> Vec<usize> {
+        (0..width).map(|x| colour_at(framebuffer, x, y)).collect()
+    }
+
+    // a hex digit of palette index per pixel, a line of text per scan line
+    fn snapshot(
+        framebuffer: &Framebuffer,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> String {
+        let mut text = String::new();
+        for y in y..y + height {
+            for x in x..x + width {
+                text.push(char::from_digit(colour_at(framebuffer, x, y) as u32, 16).unwrap());
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn test_status_timing() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0);
+        assert_eq!(0, cga.read_u8(0x3DA));
+        // 640 of the 912 dots in a line are displayed
+        cga.run_until(213);
+        assert_eq!(0, cga.read_u8(0x3DA));
+        cga.run_until(214);
+        assert_eq!(DISPLAY_INACTIVE, cga.read_u8(0x3DA));
+        cga.run_until(LINE_CLOCKS);
+        assert_eq!(0, cga.read_u8(0x3DA));
+
+        // 200 lines are displayed, then vertical sync from 224 to 239
+        cga.run_until(200 * LINE_CLOCKS);
+        assert_eq!(DISPLAY_INACTIVE, cga.read_u8(0x3DA));
+        cga.run_until(224 * LINE_CLOCKS);
+        assert_eq!(DISPLAY_INACTIVE | VERTICAL_RETRACE, cga.read_u8(0x3DA));
+        cga.run_until(240 * LINE_CLOCKS - 1);
+        assert_eq!(VERTICAL_RETRACE, cga.read_u8(0x3DA) & VERTICAL_RETRACE);
+        cga.run_until(240 * LINE_CLOCKS);
+        assert_eq!(DISPLAY_INACTIVE, cga.read_u8(0x3DA));
+
+        // 262 lines make a frame
+        assert_eq!(0, cga.frame());
+        cga.run_until(262 * LINE_CLOCKS);
+        assert_eq!(1, cga.frame());
+        assert_eq!(0, cga.read_u8(0x3DA));
+    }
+
+    #[test]
+    fn test_light_pen() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0);
+        // row 2, column 10
+        cga.run_until(16 * LINE_CLOCKS + 27);
+        cga.write_u8(0x3DC, 0);
+        assert_eq!(LIGHT_PEN_TRIGGER, cga.read_u8(0x3DA));
+        cga.write_u8(0x3D4, 16);
+        assert_eq!(0, cga.read_u8(0x3D5));
+        cga.write_u8(0x3D4, 17);
+        assert_eq!(170, cga.read_u8(0x3D5));
+        cga.write_u8(0x3DB, 0);
+        assert_eq!(0, cga.read_u8(0x3DA));
+        assert_eq!(0xFF, cga.read_u8(0x3D8));
+    }
+
+    #[test]
+    fn test_vram_window() {
+        let cga = Cga::new();
+        let mut mem = MemoryBus::new();
+        mem.map(VRAM_START, VRAM_WINDOW, Box::new(cga.vram()))
+            .unwrap();
+        mem.write_u8(0xBC001, 0x42);
+        assert_eq!(0x42, mem.read_u8(0xB8001));
+        assert_eq!(0x42, cga.vram().borrow().as_slice()[1]);
+    }
+
+    #[test]
+    fn test_80_column_text() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0);
+        // a bright white A on blue in the top left, a red B next to it
+        poke(&cga, 0, b"A\x1FB\x04");
+        let framebuffer = cga.render();
+        assert_eq!((640, 200), (framebuffer.width(), framebuffer.height()));
+        assert_eq!(
+            vec![1, 1, 15, 15, 1, 1, 1, 1, 4, 4, 4, 4, 4, 4, 0, 0],
+            row_colours(framebuffer, 0, 16)
+        );
+        // A's crossbar
+        assert_eq!(
+            vec![15, 15, 15, 15, 15, 15, 1, 1],
+            row_colours(framebuffer, 4, 8)
+        );
+        // the cursor is at address 0 on lines 6 and 7
+        assert_eq!(vec![15; 8], row_colours(framebuffer, 6, 8));
+        // the second row starts 80 characters in
+        poke(&cga, 160, b"_\x0E");
+        let framebuffer = cga.render();
+        assert_eq!(vec![14; 8], row_colours(framebuffer, 15, 8));
+    }
+
+    #[test]
+    fn test_40_column_text() {
+        let mut cga = cga_in_mode(&TEXT_40, 0x28, 0);
+        cga.write_u8(0x3D4, 10);
+        cga.write_u8(0x3D5, 0x20);
+        poke(&cga, 0, b"|\x07");
+        let framebuffer = cga.render();
+        assert_eq!((640, 200), (framebuffer.width(), framebuffer.height()));
+        // every dot of the glyph is doubled
+        let mut expected = vec![0; 16];
+        expected[6..10].fill(7);
+        assert_eq!(expected, row_colours(framebuffer, 0, 16));
+        // no cursor
+        assert_eq!(expected, row_colours(framebuffer, 6, 16));
+    }
+
+    #[test]
+    fn test_blink() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0);
+        cga.write_u8(0x3D4, 10);
+        cga.write_u8(0x3D5, 0x20);
+        // blinking yellow on blue, or yellow on light blue without blink
+        poke(&cga, 0, b"\xDB\x9E");
+        cga.set_font(&[0xFF; FONT_SIZE]);
+        assert_eq!(14, colour_at(cga.render(), 0, 0));
+        cga.run_until(16 * 262 * LINE_CLOCKS);
+        assert_eq!(1, colour_at(cga.render(), 0, 0));
+        cga.write_u8(0x3D8, 0x09);
+        assert_eq!(14, colour_at(cga.render(), 0, 0));
+        cga.set_font(&[0; FONT_SIZE]);
+        assert_eq!(9, colour_at(cga.render(), 0, 0));
+    }
+
+    #[test]
+    fn test_cursor_blinks() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0);
+        cga.write_u8(0x3D4, 15);
+        cga.write_u8(0x3D5, 1);
+        poke(&cga, 2, b" \x07");
+        assert_eq!(7, colour_at(cga.render(), 8, 7));
+        cga.run_until(8 * 262 * LINE_CLOCKS);
+        assert_eq!(0, colour_at(cga.render(), 8, 7));
+    }
+
+    #[test]
+    fn test_320x200() {
+        // palette 1, intense, blue background
+        let mut cga = cga_in_mode(&GRAPHICS_MODE, 0x2A, 0x31);
+        // pixels 0, 1, 2, 3 on line 0 and 3, 2, 1, 0 on line 1
+        poke(&cga, 0, &[0x1B]);
+        poke(&cga, 0x2000, &[0xE4]);
+        // line 2 starts 80 bytes in
+        poke(&cga, 80, &[0xC0]);
+        let framebuffer = cga.render();
+        assert_eq!((640, 200), (framebuffer.width(), framebuffer.height()));
+        assert_eq!(
+            vec![1, 1, 11, 11, 13, 13, 15, 15],
+            row_colours(framebuffer, 0, 8)
+        );
+        assert_eq!(
+            vec![15, 15, 13, 13, 11, 11, 1, 1],
+            row_colours(framebuffer, 1, 8)
+        );
+        assert_eq!(vec![15, 15, 1, 1], row_colours(framebuffer, 2, 4));
+
+        // palette 0 without intensity, then the black and white palette
+        cga.write_u8(0x3D9, 0x00);
+        assert_eq!(
+            vec![0, 0, 2, 2, 4, 4, 6, 6],
+            row_colours(cga.render(), 0, 8)
+        );
+        cga.write_u8(0x3D8, 0x2E);
+        assert_eq!(
+            vec![0, 0, 3, 3, 4, 4, 7, 7],
+            row_colours(cga.render(), 0, 8)
+        );
+    }
+
+    #[test]
+    fn test_640x200() {
+        // yellow on black
+        let mut cga = cga_in_mode(&GRAPHICS_MODE, 0x1E, 0x0E);
+        poke(&cga, 0, &[0xA5, 0x01]);
+        let framebuffer = cga.render();
+        assert_eq!(
+            vec![14, 0, 14, 0, 0, 14, 0, 14, 0, 0, 0, 0, 0, 0, 0, 14, 0],
+            row_colours(framebuffer, 0, 17)
+        );
+    }
+
+    #[test]
+    fn test_video_disabled() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x21, 0);
+        poke(&cga, 0, b"\xDB\x7F");
+        cga.set_font(&[0xFF; FONT_SIZE]);
+        let framebuffer = cga.render();
+        assert!(framebuffer
+            .as_rgba()
+            .chunks(4)
+            .all(|p| p == RGBI_PALETTE[0]));
+    }
+
+    // where the first displayed dot lands in the raster picture, with the
+    // BIOS's sync positions
+    const RASTER_TOP: usize = 22;
+    const TEXT_LEFT: usize = 112;
+    const GRAPHICS_LEFT: usize = 32;
+    const FRAME_CLOCKS: u64 = 262 * LINE_CLOCKS;
+
+    #[test]
+    fn test_raster_matches_render() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0x01);
+        cga.write_u8(0x3D4, 10);
+        cga.write_u8(0x3D5, 0x20);
+        for i in 0..2000u32 {
+            poke(&cga, i * 2, &[(i * 7) as u8 | 0x20, (i * 13) as u8 & 0x7F]);
+        }
+        let expected = cga.render().clone();
+        cga.set_raster_accurate(true);
+        cga.run_until(2 * FRAME_CLOCKS);
+        assert_eq!(2, cga.frame());
+        let framebuffer = cga.render();
+        assert_eq!(
+            (RASTER_WIDTH, RASTER_HEIGHT),
+            (framebuffer.width(), framebuffer.height())
+        );
+        assert_eq!(
+            snapshot(&expected, 0, 0, 640, 200),
+            snapshot(framebuffer, TEXT_LEFT, RASTER_TOP, 640, 200)
+        );
+        // the border on all four sides, and black during sync
+        assert_eq!(1, colour_at(framebuffer, TEXT_LEFT - 1, RASTER_TOP));
+        assert_eq!(1, colour_at(framebuffer, TEXT_LEFT + 640, RASTER_TOP));
+        assert_eq!(1, colour_at(framebuffer, TEXT_LEFT, RASTER_TOP - 1));
+        assert_eq!(1, colour_at(framebuffer, TEXT_LEFT, RASTER_TOP + 200));
+        assert_eq!(0, colour_at(framebuffer, RASTER_WIDTH - 1, RASTER_TOP));
+        assert_eq!(0, colour_at(framebuffer, TEXT_LEFT, RASTER_HEIGHT - 1));
+    }
+
+    #[test]
+    fn test_composite() {
+        // white on black 640x200, two dots on and two off
+        let mut cga = cga_in_mode(&GRAPHICS_MODE, 0x1A, 0x0F);
+        poke(&cga, 0, &[0x33; 80]);
+        assert_eq!(&[0, 0, 15, 15, 0, 0, 15, 15], &cga.signal().line(0)[..8]);
+        assert_eq!(RGBI_PALETTE[15], cga.render().pixel(2, 0));
+        cga.set_composite(Some(Revision::New));
+        let framebuffer = cga.render();
+        let artifact = framebuffer.pixel(8, 0);
+        assert_eq!([74, 223, 0, 0xFF], artifact);
+        assert_eq!(artifact, framebuffer.pixel(101, 0));
+
+        // the raster picture keeps the dots at the same carrier phase
+        cga.set_raster_accurate(true);
+        cga.run_until(2 * FRAME_CLOCKS);
+        let framebuffer = cga.render();
+        assert_eq!(artifact, framebuffer.pixel(GRAPHICS_LEFT + 8, RASTER_TOP));
+        // no colour burst in black and white mode
+        cga.write_u8(0x3D8, 0x1E);
+        cga.run_until(4 * FRAME_CLOCKS);
+        let [r, g, b, _] = cga.render().pixel(GRAPHICS_LEFT + 8, RASTER_TOP);
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn test_raster_status() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x29, 0);
+        cga.set_raster_accurate(true);
+        cga.run_until(250);
+        assert_eq!(DISPLAY_INACTIVE, cga.read_u8(0x3DA));
+        cga.run_until(LINE_CLOCKS + 3);
+        assert_eq!(0, cga.read_u8(0x3DA));
+        cga.run_until(230 * LINE_CLOCKS);
+        assert_eq!(DISPLAY_INACTIVE | VERTICAL_RETRACE, cga.read_u8(0x3DA));
+    }
+
+    #[test]
+    fn test_vertical_total_split() {
+        // two CRTC frames in each monitor frame: 13 rows from the first page
+        // with no vertical sync, then 12 rows from the second. Each part's
+        // counts are set once it has begun, along with the start address of
+        // the other, which is only looked at when a CRTC frame begins.
+        let first = [(4, 12), (5, 0), (6, 13), (7, 0x7F), (12, 0x08)];
+        let second = [(4, 18), (5, 6), (6, 12), (7, 14), (12, 0x00)];
+        let mut cga = cga_in_mode(&TEXT_80, 0x09, 0x01);
+        cga.set_font(&[0xFF; FONT_SIZE]);
+        cga.set_raster_accurate(true);
+        poke(&cga, 0, b"\xDB\x0E");
+        poke(&cga, 0x1000, b"\xDB\x0C");
+        let program = |cga: &mut Cga, registers: &[(u8, u8)]| {
+            for &(index, value) in registers {
+                cga.write_u8(0x3D4, index);
+                cga.write_u8(0x3D5, value);
+            }
+        };
+        for frame in 0..3 {
+            let start = frame * FRAME_CLOCKS;
+            cga.run_until(start + 10 * LINE_CLOCKS);
+            program(&mut cga, &first);
+            cga.run_until(start + 110 * LINE_CLOCKS);
+            program(&mut cga, &second);
+        }
+        cga.run_until(3 * FRAME_CLOCKS);
+        // sync comes 112 lines into the second part, 8 lines early
+        let top = RASTER_TOP + 8;
+        let framebuffer = cga.render();
+        assert_eq!(14, colour_at(framebuffer, TEXT_LEFT, top));
+        assert_eq!(0, colour_at(framebuffer, TEXT_LEFT + 8, top));
+        assert_eq!(0, colour_at(framebuffer, TEXT_LEFT, top + 8));
+        assert_eq!(12, colour_at(framebuffer, TEXT_LEFT, top + 104));
+        assert_eq!(0, colour_at(framebuffer, TEXT_LEFT, top + 112));
+        assert_eq!(1, colour_at(framebuffer, TEXT_LEFT, top + 200));
+    }
+
+    #[test]
+    fn test_160x100() {
+        // 80 column text with two lines a row, each cell a right half block
+        let crtc = [
+            0x71, 0x50, 0x5A, 0x0A, 0x7F, 0x06, 0x64, 0x70, 0x02, 0x01, 0x06, 0x07, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let mut cga = cga_in_mode(&crtc, 0x09, 0);
+        cga.write_u8(0x3D4, 10);
+        cga.write_u8(0x3D5, 0x20);
+        let mut font = font_8x8();
+        font[0xDE * 8..0xDF * 8].fill(0x0F);
+        cga.set_font(&font);
+        for i in 0..8000u32 {
+            let (row, column) = (i / 80, i % 80);
+            poke(&cga, i * 2, &[0xDE, (row * 5 + column * 3) as u8]);
+        }
+        cga.set_raster_accurate(true);
+        cga.run_until(2 * FRAME_CLOCKS);
+        let framebuffer = cga.render();
+        // every cell is four dots of its background then four of its
+        // foreground, two lines a row
+        let mut expected = String::new();
+        for y in 0..200 {
+            for x in 0..640 {
+                let attribute = (y / 2 * 5 + x / 8 * 3) as u8;
+                let colour = if x % 8 < 4 {
+                    attribute >> 4
+                } else {
+                    attribute & 0x0F
+                };
+                expected.push(char::from_digit(colour as u32, 16).unwrap());
+            }
+            expected.push('\n');
+        }
+        assert_eq!(
+            expected,
+            snapshot(framebuffer, TEXT_LEFT, RASTER_TOP, 640, 200)
+        );
+    }
+
+    #[test]
+    fn test_1024_colours() {
+        // 160x100 timing with the colour burst on. The glyphs programs use
+        // for it have top two lines of fine dot patterns, the IBM ROM's for
+        // 55h, 13h, B0h, B1h, DDh, DEh and DBh, so that each glyph and
+        // attribute decodes to a colour of its own.
+        let crtc = [
+            0x71, 0x50, 0x5A, 0x0A, 0x7F, 0x06, 0x64, 0x70, 0x02, 0x01, 0x06, 0x07, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let glyphs: [(u8, [u8; 2]); 7] = [
+            (0x55, [0xCC, 0xCC]),
+            (0x13, [0x66, 0x66]),
+            (0xB0, [0x22, 0x88]),
+            (0xB1, [0x55, 0xAA]),
+            (0xDD, [0xF0, 0xF0]),
+            (0xDE, [0x0F, 0x0F]),
+            (0xDB, [0xFF, 0xFF]),
+        ];
+        let mut cga = cga_in_mode(&crtc, 0x09, 0);
+        cga.write_u8(0x3D4, 10);
+        cga.write_u8(0x3D5, 0x20);
+        let mut font = font_8x8();
+        for (code, lines) in glyphs {
+            font[code as usize * 8..code as usize * 8 + 2].copy_from_slice(&lines);
+        }
+        cga.set_font(&font);
+        let cells = glyphs.len() * 256;
+        for i in 0..cells {
+            let (code, _) = glyphs[i / 256];
+            poke(&cga, i as u32 * 2, &[code, i as u8]);
+        }
+
+        let signal = cga.signal().clone();
+        assert_eq!(
+            &[15, 15, 0, 0, 15, 15, 0, 0],
+            &signal.line(1)[15 * 8..16 * 8]
+        );
+        // the next row of cells starts two lines down
+        assert_eq!(&[0, 0, 5, 5], &signal.line(2)[..4]);
+        cga.set_composite(Some(Revision::New));
+        let framebuffer = cga.render().clone();
+        assert_eq!(composite::decode(&signal, Revision::New), framebuffer);
+
+        // the middle of each cell is the colour of its pattern of dots
+        // repeated, worked out on its own
+        let mut colours = std::collections::HashSet::new();
+        for i in 0..cells {
+            let (_, lines) = glyphs[i / 256];
+            let attribute = i as u8;
+            let (foreground, background) = (attribute & 0x0F, attribute >> 4);
+            let (y, x) = (i / 80 * 2, i % 80 * 8);
+            for (line, glyph) in lines.into_iter().enumerate() {
+                let mut dots = Signal::new(16, 1);
+                for dot in 0..16 {
+                    let lit = glyph & (0x80 >> (dot % 8)) != 0;
+                    dots.set_dot(dot, 0, if lit { foreground } else { background });
+                }
+                let expected = composite::decode(&dots, Revision::New).pixel(12, 0);
+                assert_eq!(expected, framebuffer.pixel(x + 4, y + line));
+                colours.insert(expected);
+            }
+        }
+        // well over the 256 of 80 column text
+        assert!(colours.len() > 1024);
+    }
+
+    #[test]
+    fn test_mid_line_colour() {
+        // the background colour changed part way along eight lines
+        let mut cga = cga_in_mode(&GRAPHICS_MODE, 0x0A, 0);
+        cga.set_raster_accurate(true);
+        for i in 0..8 {
+            cga.run_until(FRAME_CLOCKS + (10 + i) * LINE_CLOCKS + 60 + i * 12);
+            cga.write_u8(0x3D9, i as u8 + 1);
+        }
+        cga.run_until(2 * FRAME_CLOCKS);
+        let framebuffer = cga.render();
+        assert_eq!(0, colour_at(framebuffer, GRAPHICS_LEFT, RASTER_TOP + 9));
+        // a write takes effect at the first character the beam has yet to
+        // reach, cycle * 3 / 16 characters into the CRTC's line. The raster
+        // line starts at the end of hsync, with the last two characters of
+        // the CRTC's previous line, and hsync itself is black.
+        let writes: Vec<_> = (0..8).map(|i| (10 + i, (60 + i * 12) * 3 / 16)).collect();
+        let mut expected = String::new();
+        for line in 8..20 {
+            for x in 0..RASTER_WIDTH {
+                let (line, character) = match x / 16 {
+                    c if c < 2 => (line - 1, c + 55),
+                    c => (line, c - 2),
+                };
+                let colour = if (45..55).contains(&character) {
+                    0
+                } else {
+                    writes.iter().filter(|&&w| w <= (line, character)).count()
+                };
+                expected.push(char::from_digit(colour as u32, 16).unwrap());
+            }
+            expected.push('\n');
+        }
+        assert_eq!(
+            expected,
+            snapshot(framebuffer, 0, RASTER_TOP + 8, RASTER_WIDTH, 12)
+        );
+    }
+
+    #[test]
+    fn test_snow() {
+        let mut cga = cga_in_mode(&TEXT_80, 0x09, 0);
+        cga.write_u8(0x3D4, 10);
+        cga.write_u8(0x3D5, 0x20);
+        cga.set_raster_accurate(true);
+        let vram = cga.vram();
+        // writes while the first three lines are displayed, and one in the
+        // border which leaves no trace
+        for (line, clock) in [(0, 0), (1, 40), (2, 100), (2, 250)] {
+            cga.run_until(FRAME_CLOCKS + line * LINE_CLOCKS + clock);
+            vram.borrow_mut().write_u8(0x3000, 0xB1);
+        }
+        cga.run_until(2 * FRAME_CLOCKS);
+        let framebuffer = cga.render();
+        // eight dots of colour 0Bh at the character after each write, cycle
+        // * 3 / 8 characters into the line, and nothing for the write that
+        // lands in hsync
+        let mut expected = String::new();
+        for (line, clock) in [(0, 0), (1, 40), (2, 100), (3, 0)] {
+            let character = clock * 3 / 8;
+            for x in 0..RASTER_WIDTH {
+                let snow = line < 3 && x / 8 == character + 14;
+                expected.push(if snow { 'b' } else { '0' });
+            }
+            expected.push('\n');
+        }
+        assert_eq!(
+            expected,
+            snapshot(framebuffer, 0, RASTER_TOP, RASTER_WIDTH, 4)
+        );
+
+        // a burst of writes in hsync and the border is dropped rather than
+        // held over to the characters displayed after it
+        let mut cga = cga_in_mode(&TEXT_80, 0x09, 0);
+        cga.set_raster_accurate(true);
+        cga.run_until(FRAME_CLOCKS + 250);
+        for _ in 0..40 {
+            cga.vram().borrow_mut().write_u8(0x3000, 0xB1);
+        }
+        cga.run_until(FRAME_CLOCKS + 2 * LINE_CLOCKS);
+        cga.vram().borrow_mut().write_u8(0x3000, 0xB1);
+        cga.vram().borrow_mut().write_u8(0x3001, 0x4F);
+        cga.run_until(2 * FRAME_CLOCKS);
+        let framebuffer = cga.render();
+        assert_eq!(
+            vec![0; RASTER_WIDTH],
+            row_colours(framebuffer, RASTER_TOP + 1, RASTER_WIDTH)
+        );
+        // and of several writes in one character clock only the first shows
+        let mut row = vec![0; RASTER_WIDTH];
+        row[TEXT_LEFT..TEXT_LEFT + 8].fill(0x0B);
+        assert_eq!(row, row_colours(framebuffer, RASTER_TOP + 2, RASTER_WIDTH));
+
+        // the same writes in 40 columns make no snow
+        let mut cga = cga_in_mode(&TEXT_40, 0x08, 0);
+        cga.set_raster_accurate(true);
+        cga.run_until(FRAME_CLOCKS + 40);
+        cga.vram().borrow_mut().write_u8(0x3000, 0xB1);
+        cga.run_until(2 * FRAME_CLOCKS);
+        assert!(cga
+            .render()
+            .as_rgba()
+            .chunks(4)
+            .all(|p| p == RGBI_PALETTE[0]));
+    }
+}
@@ -0,0 +1,318 @@
+// The CGA's composite output, as an NTSC colour monitor or TV decodes it.
+// The card makes the composite signal from the same RGBI it sends to the
+// digital connector: a 3.58MHz square wave whose phase sets the hue, plus a
+// level for intensity and, on the later revision of the card, a level made
+// from R, G and B too. The carrier comes off the 14.31818MHz dot clock, four
+// dots to a cycle, so fine patterns of dots in 640x200 graphics or 80 column
+// text decode to colours of their own, the artifact colours.
+//
+// This is a model of that description rather than of measured waveforms.
+// Each colour's carrier sits at the hue of its NTSC colour bar, rounded to
+// the half dot the card can shift it by. The decoder is the simplest that
+// works: luma is the average over one carrier cycle and chroma is
+// demodulated against the burst over the same four dots.
+
+use super::{Framebuffer, RGBI_PALETTE};
+
+// the composite circuit changed between revisions of the card
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Revision {
+    // colours 1 to 6 differ only in hue, so all are equally bright
+    #[default]
+    Old,
+    // R, G and B add to the luma as well, close to their NTSC weights
+    New,
+}
+
+// dots to one cycle of the colour carrier
+pub const CARRIER_DOTS: usize = 4;
+// where each of colours 1 to 6 turns its carrier on, in eighths of a cycle
+const CARRIER_PHASE: [usize; 6] = [0, 5, 6, 2, 1, 4];
+// levels added to the carrier, as a fraction of its swing
+const INTENSITY_LEVEL: f64 = 0.5;
+const RGB_LEVEL: f64 = 0.75;
+const RGB_WEIGHTS: [f64; 3] = [0.30, 0.59, 0.11];
+// where the monitor's tint control is set, in degrees
+const TINT: f64 = -30.0;
+
+// what a CGA drives onto its outputs: an RGBI colour for every dot, and
+// whether each line carried a colour burst
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signal {
+    width: usize,
+    height: usize,
+    dots: Vec<u8>,
+    colour_burst: Vec<bool>,
+}
+
+impl Signal {
+    // black, with a colour burst on every line
+    pub fn new(width: usize, height: usize) -> Signal {
+        Signal {
+            width,
+            height,
+            dots: vec![0; width * height],
+            colour_burst: vec![true; height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn dot(&self, x: usize, y: usize) -> u8 {
+        self.dots[y * self.width + x]
+    }
+
+    pub fn line(&self, y: usize) -> &[u8] {
+        &self.dots[y * self.width..(y + 1) * self.width]
+    }
+
+    // dots outside the picture are dropped
+    pub fn set_dot(&mut self, x: usize, y: usize, colour: u8) {
+        if x < self.width && y < self.height {
+            self.dots[y * self.width + x] = colour & 0x0F;
+        }
+    }
+
+    pub fn colour_burst(&self, y: usize) -> bool {
+        self.colour_burst[y]
+    }
+
+    pub fn set_colour_burst(&mut self, y: usize, on: bool) {
+        if y < self.height {
+            self.colour_burst[y] = on;
+        }
+    }
+
+    pub fn fill(&mut self, colour: u8) {
+        self.dots.fill(colour & 0x0F);
+    }
+
+    // keeps the contents only if the size stays the same
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width != self.width || height != self.height {
+            *self = Signal::new(width, height);
+        }
+    }
+
+    // the picture on an RGBI monitor
+    pub fn to_rgbi(&self, framebuffer: &mut Framebuffer) {
+        framebuffer.resize(self.width, self.height);
+        for y in 0..self.height {
+            for (x, &colour) in self.line(y).iter().enumerate() {
+                framebuffer.set_pixel(x, y, RGBI_PALETTE[colour as usize]);
+            }
+        }
+    }
+}
+
+// the carrier of a colour half a dot at a time
+fn carrier(colour: u8, half_dot: usize) -> f64 {
+    let on = match colour & 7 {
+        0 => false,
+        7 => true,
+        c => (half_dot + 8 - CARRIER_PHASE[c as usize - 1]) % 8 < 4,
+    };
+    if on {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+// the composite level of a colour at each dot of a carrier cycle, from
+// black at 0 to bright white at 1
+fn levels(revision: Revision) -> [[f64; CARRIER_DOTS]; 16] {
+    let mut levels = [[0.0; CARRIER_DOTS]; 16];
+    for (colour, levels) in levels.iter_mut().enumerate() {
+        let colour = colour as u8;
+        let mut base = 0.0;
+        let mut full = 1.0 + INTENSITY_LEVEL;
+        if colour & 8 != 0 {
+            base += INTENSITY_LEVEL;
+        }
+        if revision == Revision::New {
+            for (i, weight) in RGB_WEIGHTS.into_iter().enumerate() {
+                if colour & (4 >> i) != 0 {
+                    base += RGB_LEVEL * weight;
+                }
+            }
+            full += RGB_LEVEL;
+        }
+        for (dot, level) in levels.iter_mut().enumerate() {
+            let carrier = (carrier(colour, dot * 2) + carrier(colour, dot * 2 + 1)) / 2.0;
+            *level = (carrier + base) / full;
+        }
+    }
+    levels
+}
+
+fn to_u8(value: f64) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// what a composite monitor shows for the signal, a pixel per dot. Dot 0 of
+// every line has to be at the same carrier phase, as it is for any picture
+// taken from the card's lines.
+pub fn decode(signal: &Signal, revision: Revision) -> Framebuffer {
+    let levels = levels(revision);
+    // the burst's phase at each dot of a cycle
+    let reference: [(f64, f64); CARRIER_DOTS] =
+        std::array::from_fn(|dot| (dot as f64 * 90.0 + TINT).to_radians().sin_cos());
+    let mut framebuffer = Framebuffer::new(signal.width, signal.height);
+    let mut line = Vec::with_capacity(signal.width);
+    for y in 0..signal.height {
+        line.clear();
+        line.extend(
+            signal
+                .line(y)
+                .iter()
+                .enumerate()
+                .map(|(x, &colour)| levels[colour as usize][x % CARRIER_DOTS]),
+        );
+        for x in 0..signal.width {
+            let (mut luma, mut u, mut v) = (0.0, 0.0, 0.0);
+            // one cycle around the dot, blanking beyond the ends of the line
+            for i in (x + 1).saturating_sub(2)..(x + 3).min(signal.width) {
+                let (sin, cos) = reference[i % CARRIER_DOTS];
+                luma += line[i];
+                u += line[i] * cos;
+                v += line[i] * sin;
+            }
+            luma /= CARRIER_DOTS as f64;
+            // without a burst the monitor's colour killer leaves grey
+            if !signal.colour_burst(y) {
+                (u, v) = (0.0, 0.0);
+            }
+            let (u, v) = (u / 2.0, v / 2.0);
+            framebuffer.set_pixel(
+                x,
+                y,
+                [
+                    to_u8(luma + 1.140 * v),
+                    to_u8(luma - 0.395 * u - 0.581 * v),
+                    to_u8(luma + 2.032 * u),
+                    0xFF,
+                ],
+            );
+        }
+    }
+    framebuffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a line of one colour, or of a repeating pattern of dots
+    fn line_of(pattern: &[u8], width: usize) -> Signal {
+        let mut signal = Signal::new(width, 1);
+        for x in 0..width {
+            signal.set_dot(x, 0, pattern[x % pattern.len()]);
+        }
+        signal
+    }
+
+    fn rgb(framebuffer: &Framebuffer, x: usize) -> [u8; 3] {
+        framebuffer.pixel(x, 0)[..3].try_into().unwrap()
+    }
+
+    fn is_grey([r, g, b]: [u8; 3]) -> bool {
+        r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1
+    }
+
+    #[test]
+    fn test_solid_colours() {
+        for revision in [Revision::Old, Revision::New] {
+            let decoded: Vec<_> = (0..16)
+                .map(|colour| rgb(&decode(&line_of(&[colour], 16), revision), 8))
+                .collect();
+            assert_eq!([0, 0, 0], decoded[0]);
+            assert_eq!([255, 255, 255], decoded[15]);
+            assert!(is_grey(decoded[7]) && is_grey(decoded[8]));
+            assert!(decoded[8][0] < decoded[7][0]);
+
+            let [r, g, b] = decoded[1];
+            assert!(b > r && b > g);
+            let [r, g, b] = decoded[2];
+            assert!(g > r && g > b);
+            let [r, g, b] = decoded[4];
+            assert!(r > g && r > b);
+            let [r, _, b] = decoded[6];
+            assert!(r > b);
+        }
+    }
+
+    #[test]
+    fn test_revisions() {
+        let luma = |revision, colour| {
+            let [r, g, b] = rgb(&decode(&line_of(&[colour], 16), revision), 8);
+            0.30 * r as f64 + 0.59 * g as f64 + 0.11 * b as f64
+        };
+        // blue and brown carry the same level on the old card
+        let old = luma(Revision::Old, 6) - luma(Revision::Old, 1);
+        let new = luma(Revision::New, 6) - luma(Revision::New, 1);
+        assert!(new > old + 20.0);
+    }
+
+    #[test]
+    fn test_artifact_colours() {
+        for revision in [Revision::Old, Revision::New] {
+            // white dots on black, every pattern of four
+            let colours: Vec<_> = (0..16u8)
+                .map(|pattern| {
+                    let dots: Vec<_> = (0..4)
+                        .map(|i| if pattern & (8 >> i) != 0 { 15 } else { 0 })
+                        .collect();
+                    let framebuffer = decode(&line_of(&dots, 32), revision);
+                    // the same colour all along the line
+                    for x in 9..24 {
+                        assert_eq!(rgb(&framebuffer, 8), rgb(&framebuffer, x));
+                    }
+                    rgb(&framebuffer, 8)
+                })
+                .collect();
+            assert_eq!([0, 0, 0], colours[0b0000]);
+            assert_eq!([255, 255, 255], colours[0b1111]);
+            // dots alternating at twice the carrier frequency are filtered out
+            assert!(is_grey(colours[0b0101]) && is_grey(colours[0b1010]));
+            assert_eq!([74, 223, 0], colours[0b0011]);
+            assert_eq!([181, 32, 255], colours[0b1100]);
+            assert_eq!([0, 57, 255], colours[0b1000]);
+            assert_eq!([255, 45, 33], colours[0b0110]);
+            // patterns a dot apart are different colours
+            for pattern in [0b0001, 0b0011, 0b0111] {
+                let shifted = (pattern << 1 | pattern >> 3) & 0x0F;
+                assert_ne!(colours[pattern], colours[shifted]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_colour_burst() {
+        let mut signal = line_of(&[0, 0, 15, 15], 16);
+        let coloured = decode(&signal, Revision::New);
+        assert!(!is_grey(rgb(&coloured, 8)));
+        signal.set_colour_burst(0, false);
+        let grey = decode(&signal, Revision::New);
+        assert!(is_grey(rgb(&grey, 8)));
+        assert_eq!(rgb(&grey, 8), rgb(&grey, 9));
+    }
+
+    #[test]
+    fn test_rgbi() {
+        let mut signal = Signal::new(4, 2);
+        signal.set_dot(1, 1, 0x1E);
+        signal.set_dot(4, 0, 15);
+        let mut framebuffer = Framebuffer::default();
+        signal.to_rgbi(&mut framebuffer);
+        assert_eq!((4, 2), (framebuffer.width(), framebuffer.height()));
+        assert_eq!(RGBI_PALETTE[14], framebuffer.pixel(1, 1));
+        assert_eq!(RGBI_PALETTE[0], framebuffer.pixel(0, 0));
+    }
+}
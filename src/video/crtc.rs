@@ -0,0 +1,386 @@
+// The 6845 CRT controller used by the IBM display adapters. It counts
+// characters and scan lines and hands the adapter the memory address and row
+// of every character it displays. Adapters can either work the beam timing
+// out from the registers, or clock the counters a character at a time with
+// tick, which keeps the 6845's habit of comparing counters for equality only.
+// Lowering R9 below the current row line or R4 below the current row makes
+// the counter run on and wrap, the basis of most vertical total tricks.
+
+pub const REGISTER_COUNT: usize = 18;
+// the bits each register implements
+const MASKS: [u8; REGISTER_COUNT] = [
+    0xFF, 0xFF, 0xFF, 0x0F, 0x7F, 0x1F, 0x7F, 0x7F, 0x03, 0x1F, 0x7F, 0x1F, 0x3F, 0xFF, 0x3F, 0xFF,
+    0x3F, 0xFF,
+];
+// the cursor address and the light pen can be read back, nothing else
+const READABLE: std::ops::RangeInclusive<usize> = 14..=17;
+const LIGHT_PEN_HIGH: usize = 16;
+// cursor start register
+const CURSOR_MODE_SHIFT: u8 = 5;
+const CURSOR_OFF: u8 = 1;
+// vertical sync is 16 lines whatever R3 says
+pub const VSYNC_LINES: u8 = 16;
+
+// what the 6845 drives for one character clock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Character {
+    pub address: u16,
+    pub row_line: u32,
+    pub display: bool,
+    pub cursor: bool,
+    pub hsync: bool,
+    pub vsync: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Crtc {
+    index: u8,
+    registers: [u8; REGISTER_COUNT],
+    // character in the line, line in the row, row in the frame
+    h: u8,
+    row_line: u8,
+    row: u8,
+    // lines of vertical total adjust begun, while in it
+    adjust: Option<u8>,
+    address: u16,
+    row_start: u16,
+    // where the next row starts, taken at R1 on a row's last line
+    next_row_start: u16,
+    // past R1 on this line, or past R6 in this frame
+    h_ended: bool,
+    v_ended: bool,
+    hsync_left: u8,
+    vsync_left: u8,
+}
+
+impl Crtc {
+    pub fn new() -> Crtc {
+        Crtc::default()
+    }
+
+    pub fn select(&mut self, index: u8) {
+        self.index = index & 0x1F;
+    }
+
+    pub fn read(&self) -> u8 {
+        let index = self.index as usize;
+        if READABLE.contains(&index) {
+            self.registers[index]
+        } else {
+            0
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let index = self.index as usize;
+        if index < LIGHT_PEN_HIGH {
+            self.registers[index] = value & MASKS[index];
+        }
+    }
+
+    pub fn register(&self, index: usize) -> u8 {
+        self.registers[index]
+    }
+
+    // in characters
+    pub fn horizontal_total(&self) -> u32 {
+        self.registers[0] as u32 + 1
+    }
+
+    pub fn horizontal_displayed(&self) -> u32 {
+        self.registers[1] as u32
+    }
+
+    pub fn hsync_position(&self) -> u32 {
+        self.registers[2] as u32
+    }
+
+    pub fn hsync_width(&self) -> u32 {
+        self.registers[3] as u32
+    }
+
+    // in character rows
+    pub fn vertical_total(&self) -> u32 {
+        self.registers[4] as u32 + 1
+    }
+
+    // extra scan lines after the last row
+    pub fn vertical_adjust(&self) -> u32 {
+        self.registers[5] as u32
+    }
+
+    pub fn vertical_displayed(&self) -> u32 {
+        self.registers[6] as u32
+    }
+
+    pub fn vsync_position(&self) -> u32 {
+        self.registers[7] as u32
+    }
+
+    // scan lines in a character row
+    pub fn row_height(&self) -> u32 {
+        self.registers[9] as u32 + 1
+    }
+
+    pub fn frame_lines(&self) -> u32 {
+        self.vertical_total() * self.row_height() + self.vertical_adjust()
+    }
+
+    pub fn cursor_start(&self) -> u32 {
+        (self.registers[10] & 0x1F) as u32
+    }
+
+    pub fn cursor_end(&self) -> u32 {
+        self.registers[11] as u32
+    }
+
+    pub fn cursor_off(&self) -> bool {
+        (self.registers[10] >> CURSOR_MODE_SHIFT) & 3 == CURSOR_OFF
+    }
+
+    pub fn start_address(&self) -> u16 {
+        u16::from_be_bytes([self.registers[12], self.registers[13]])
+    }
+
+    pub fn cursor_address(&self) -> u16 {
+        u16::from_be_bytes([self.registers[14], self.registers[15]])
+    }
+
+    pub fn cursor_at(&self, address: u16, row_line: u32) -> bool {
+        address == self.cursor_address()
+            && !self.cursor_off()
+            && (self.cursor_start()..=self.cursor_end()).contains(&row_line)
+    }
+
+    // the memory address the counters are on
+    pub fn address(&self) -> u16 {
+        self.address & 0x3FFF
+    }
+
+    pub fn display_enabled(&self) -> bool {
+        !self.h_ended && !self.v_ended && self.h != self.registers[1]
+    }
+
+    pub fn in_vsync(&self) -> bool {
+        self.vsync_left > 0
+    }
+
+    // one character clock
+    pub fn tick(&mut self) -> Character {
+        let r = self.registers;
+        if self.h == r[1] {
+            self.h_ended = true;
+            if self.row_line == r[9] {
+                self.next_row_start = self.address;
+            }
+        }
+        if self.h == r[2] {
+            self.hsync_left = r[3];
+        }
+        let display = !self.h_ended && !self.v_ended;
+        let address = self.address();
+        let character = Character {
+            address,
+            row_line: self.row_line as u32,
+            display,
+            cursor: display && self.cursor_at(address, self.row_line as u32),
+            hsync: self.hsync_left > 0,
+            vsync: self.vsync_left > 0,
+        };
+        self.hsync_left = self.hsync_left.saturating_sub(1);
+        if self.h == r[0] {
+            self.h = 0;
+            self.h_ended = false;
+            self.end_line();
+        } else {
+            self.h = self.h.wrapping_add(1);
+            self.address = self.address.wrapping_add(1);
+        }
+        character
+    }
+
+    fn end_line(&mut self) {
+        let r = self.registers;
+        self.vsync_left = self.vsync_left.saturating_sub(1);
+        match self.adjust {
+            Some(lines) if lines == r[5] => return self.new_frame(),
+            Some(lines) => {
+                self.adjust = Some((lines + 1) & 0x1F);
+                self.row_line = (self.row_line + 1) & 0x1F;
+            }
+            None if self.row_line == r[9] => {
+                if self.row == r[4] && r[5] == 0 {
+                    return self.new_frame();
+                }
+                if self.row == r[4] {
+                    self.adjust = Some(1);
+                }
+                self.row = (self.row + 1) & 0x7F;
+                self.row_line = 0;
+                self.row_start = self.next_row_start;
+                self.start_row();
+            }
+            None => self.row_line = (self.row_line + 1) & 0x1F,
+        }
+        self.address = self.row_start;
+    }
+
+    fn new_frame(&mut self) {
+        self.row = 0;
+        self.row_line = 0;
+        self.adjust = None;
+        // the start address is only looked at here
+        self.row_start = self.start_address();
+        self.next_row_start = self.row_start;
+        self.address = self.row_start;
+        self.v_ended = false;
+        self.start_row();
+    }
+
+    fn start_row(&mut self) {
+        if self.row == self.registers[6] {
+            self.v_ended = true;
+        }
+        if self.row == self.registers[7] {
+            self.vsync_left = VSYNC_LINES;
+        }
+    }
+
+    // the light pen strobe captures the address being displayed
+    pub fn latch_light_pen(&mut self, address: u16) {
+        let [high, low] = (address & 0x3FFF).to_be_bytes();
+        self.registers[LIGHT_PEN_HIGH] = high;
+        self.registers[LIGHT_PEN_HIGH + 1] = low;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(crtc: &mut Crtc, values: &[u8]) {
+        for (index, &value) in values.iter().enumerate() {
+            crtc.select(index as u8);
+            crtc.write(value);
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut crtc = Crtc::new();
+        // the BIOS table for 80x25 text
+        program(
+            &mut crtc,
+            &[
+                0x71, 0x50, 0x5A, 0x0A, 0x1F, 0x06, 0x19, 0x1C, 0x02, 0x07, 0x06, 0x07, 0x00, 0x00,
+                0x01, 0x23,
+            ],
+        );
+        assert_eq!(114, crtc.horizontal_total());
+        assert_eq!(80, crtc.horizontal_displayed());
+        assert_eq!(262, crtc.frame_lines());
+        assert_eq!(8, crtc.row_height());
+        assert_eq!((6, 7), (crtc.cursor_start(), crtc.cursor_end()));
+        assert!(!crtc.cursor_off());
+        assert_eq!(0x0123, crtc.cursor_address());
+
+        // only the cursor and light pen read back
+        crtc.select(1);
+        assert_eq!(0, crtc.read());
+        crtc.select(15);
+        assert_eq!(0x23, crtc.read());
+
+        // unimplemented bits are dropped
+        crtc.select(4);
+        crtc.write(0xFF);
+        assert_eq!(0x7F, crtc.register(4));
+        crtc.select(10);
+        crtc.write(0x20);
+        assert!(crtc.cursor_off());
+    }
+
+    // where each line of a frame starts, by ticking the counters
+    fn line_starts(crtc: &mut Crtc, lines: usize) -> Vec<(u16, bool)> {
+        let mut starts = Vec::new();
+        let mut h = 0;
+        while starts.len() < lines {
+            let character = crtc.tick();
+            if h == 0 {
+                starts.push((character.address, character.display));
+            }
+            h = (h + 1) % crtc.horizontal_total();
+        }
+        starts
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut crtc = Crtc::new();
+        // 4 characters a line, 2 displayed, two lines a row, three rows and
+        // a line of adjust, two rows displayed
+        program(&mut crtc, &[3, 2, 2, 1, 2, 1, 2, 1, 0, 1]);
+        let mut line = crtc.clone();
+        let character = line.tick();
+        assert!(character.display && !character.hsync);
+        line.tick();
+        let character = line.tick();
+        assert!(!character.display && character.hsync);
+        assert!(!line.tick().hsync);
+        assert_eq!(
+            vec![
+                (0, true),
+                (0, true),
+                (2, true),
+                (2, true),
+                (4, false),
+                (4, false),
+                (6, false),
+                (0, true),
+            ],
+            line_starts(&mut crtc, 8)
+        );
+    }
+
+    #[test]
+    fn test_vertical_sync() {
+        let mut crtc = Crtc::new();
+        // 20 rows of one line, vsync from row 2
+        program(&mut crtc, &[1, 1, 0, 0, 19, 0, 1, 2, 0, 0]);
+        let mut vsync = Vec::new();
+        for _ in 0..40 {
+            vsync.push(crtc.tick().vsync);
+            crtc.tick();
+        }
+        let mut expected = vec![false; 40];
+        expected[2..18].fill(true);
+        expected[22..38].fill(true);
+        assert_eq!(expected, vsync);
+    }
+
+    #[test]
+    fn test_lowered_max_scan_line_wraps() {
+        let mut crtc = Crtc::new();
+        program(&mut crtc, &[0, 1, 0xFF, 0, 0x7F, 0, 0x7F, 0x7F, 0, 3]);
+        for _ in 0..3 {
+            crtc.tick();
+        }
+        // on line 3 of the row, so R9 = 1 only matches after wrapping at 31
+        crtc.select(9);
+        crtc.write(1);
+        let lines = (0..29).filter(|_| crtc.tick().row_line == 0).count();
+        assert_eq!(0, lines);
+        assert_eq!(0, crtc.tick().row_line);
+    }
+
+    #[test]
+    fn test_light_pen() {
+        let mut crtc = Crtc::new();
+        crtc.select(16);
+        crtc.write(0x12);
+        assert_eq!(0, crtc.read());
+        crtc.latch_light_pen(0x4567);
+        assert_eq!(0x05, crtc.read());
+        crtc.select(17);
+        assert_eq!(0x67, crtc.read());
+    }
+}
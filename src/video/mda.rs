@@ -0,0 +1,512 @@
+// The IBM Monochrome Display Adapter and the Hercules Graphics Card built on
+// it: a 6845 at 3B4h/3B5h, the mode control register at 3B8h, the status
+// register at 3BAh and video RAM at B0000h. The MDA has 4K, decoded over and
+// over up to B7FFFh, and draws 80x25 text in 9x14 cells.
+//
+// The Hercules card adds 64K, two 720x348 graphics pages of 32K each, and the
+// configuration register at 3BFh that has to allow graphics and the second
+// page before the mode register may switch to them. Until the second page
+// is allowed the card only decodes B0000h-B7FFFh, leaving B8000h-BFFFFh to a
+// colour adapter, so it goes on the bus as an overlay.
+//
+// Both cards run off a 16.257MHz crystal, which is no multiple of the CPU
+// clock, so the beam position is worked out from the cycle count in dots.
+
+use std::{cell::RefCell, rc::Rc};
+
+use super::{crtc::Crtc, font_8x14, DisplayAdapter, Framebuffer, MONOCHROME_PALETTE};
+use crate::{
+    io::{IoDevice, OPEN_BUS_VALUE},
+    memory::{MemoryDevice, VideoRam, OPEN_BUS_VALUE as OPEN_MEMORY},
+};
+
+pub const VRAM_START: u32 = 0xB0000;
+pub const MDA_VRAM_SIZE: u32 = 0x1000;
+pub const HERCULES_VRAM_SIZE: u32 = 0x10000;
+pub const PAGE_SIZE: u32 = 0x8000;
+pub const FIRST_PORT: u16 = 0x3B0;
+// 3BCh-3BEh belong to the printer port
+pub const LAST_PORT: u16 = 0x3BB;
+pub const CONFIG_PORT: u16 = 0x3BF;
+pub const FONT_SIZE: usize = 256 * 14;
+// the 14.31818MHz the CPU clock is divided from, and the card's crystal
+const SYSTEM_CRYSTAL: u128 = 14_318_180;
+const DOT_CLOCK: u128 = 16_257_000;
+
+// mode control register
+pub const HIGH_RES: u8 = 0x01;
+pub const GRAPHICS: u8 = 0x02;
+pub const VIDEO_ENABLE: u8 = 0x08;
+pub const BLINK: u8 = 0x20;
+pub const DISPLAY_PAGE_1: u8 = 0x80;
+// configuration register
+pub const ALLOW_GRAPHICS: u8 = 0x01;
+pub const ALLOW_PAGE_1: u8 = 0x02;
+// status register
+pub const HORIZONTAL_RETRACE: u8 = 0x01;
+// Hercules only, low while the beam flies back to the top
+pub const NOT_VERTICAL_RETRACE: u8 = 0x80;
+
+// the underline sits on this line of a character row
+const UNDERLINE_LINE: u32 = 12;
+// box drawing characters carry their eighth column into the ninth
+const LINE_GRAPHICS: std::ops::RangeInclusive<u8> = 0xC0..=0xDF;
+const VSYNC_LINES: u64 = 16;
+const CURSOR_BLINK: u64 = 8;
+const TEXT_BLINK: u64 = 16;
+// indexes into MONOCHROME_PALETTE
+const OFF: u8 = 0;
+const NORMAL: u8 = 1;
+const INTENSE: u8 = 2;
+
+pub struct Mda {
+    hercules: bool,
+    crtc: Crtc,
+    mode: u8,
+    config: u8,
+    vram: Rc<RefCell<VideoRam>>,
+    font: Box<[u8; FONT_SIZE]>,
+    now: u64,
+    dot: u64,
+    frame: u64,
+    framebuffer: Framebuffer,
+}
+
+impl Mda {
+    pub fn new() -> Mda {
+        Mda::with_vram(false, MDA_VRAM_SIZE)
+    }
+
+    pub fn hercules() -> Mda {
+        Mda::with_vram(true, HERCULES_VRAM_SIZE)
+    }
+
+    fn with_vram(hercules: bool, size: u32) -> Mda {
+        Mda {
+            hercules,
+            crtc: Crtc::new(),
+            mode: 0,
+            config: 0,
+            vram: Rc::new(RefCell::new(VideoRam::new(size as usize))),
+            font: Box::new(font_8x14()),
+            now: 0,
+            dot: 0,
+            frame: 0,
+            framebuffer: Framebuffer::default(),
+        }
+    }
+
+    pub fn is_hercules(&self) -> bool {
+        self.hercules
+    }
+
+    // the bytes to map at VRAM_START; the Mda itself is the memory device,
+    // since the Hercules configuration decides what the top half decodes
+    pub fn window_size(&self) -> u32 {
+        if self.hercules {
+            HERCULES_VRAM_SIZE
+        } else {
+            PAGE_SIZE
+        }
+    }
+
+    pub fn vram(&self) -> Rc<RefCell<VideoRam>> {
+        self.vram.clone()
+    }
+
+    // glyphs of 14 rows, leftmost pixel in bit 7, each glyph's rows one
+    // after another. The character ROM keeps rows 0-7 and 8-13 in separate
+    // 2K halves, so a dump of it has to be put in that order first.
+    pub fn set_font(&mut self, font: &[u8; FONT_SIZE]) {
+        *self.font = *font;
+    }
+
+    pub fn crtc(&self) -> &Crtc {
+        &self.crtc
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    fn dots_at(cpu_cycles: u64) -> u64 {
+        (cpu_cycles as u128 * 3 * DOT_CLOCK / SYSTEM_CRYSTAL) as u64
+    }
+
+    fn graphics(&self) -> bool {
+        self.hercules && self.mode & GRAPHICS != 0
+    }
+
+    // a text cell is 9 dots, a graphics character 16 pixels of one dot
+    fn char_width(&self) -> u64 {
+        if self.graphics() {
+            16
+        } else {
+            9
+        }
+    }
+
+    fn line_dots(&self) -> u64 {
+        self.crtc.horizontal_total() as u64 * self.char_width()
+    }
+
+    fn beam(&self) -> (u64, u64) {
+        let line_dots = self.line_dots();
+        (self.dot % line_dots, self.dot / line_dots)
+    }
+
+    fn status(&self) -> u8 {
+        let (x, line) = self.beam();
+        let char_width = self.char_width();
+        let mut status = 0;
+        let hsync = self.crtc.hsync_position() as u64 * char_width;
+        if (hsync..hsync + self.crtc.hsync_width() as u64 * char_width).contains(&x) {
+            status |= HORIZONTAL_RETRACE;
+        }
+        let vsync = self.crtc.vsync_position() as u64 * self.crtc.row_height() as u64;
+        if self.hercules && !(vsync..vsync + VSYNC_LINES).contains(&line) {
+            status |= NOT_VERTICAL_RETRACE;
+        }
+        status
+    }
+
+    fn vram_offset(&self, offset: u32) -> Option<u32> {
+        if !self.hercules {
+            Some(offset % MDA_VRAM_SIZE)
+        } else if offset < PAGE_SIZE || self.config & ALLOW_PAGE_1 != 0 {
+            Some(offset % HERCULES_VRAM_SIZE)
+        } else {
+            None
+        }
+    }
+
+    fn text_cell(&self, vram: &[u8], address: u16, row_line: u32, dots: &mut Vec<u8>) {
+        let offset = (address as usize * 2) & (MDA_VRAM_SIZE as usize - 1);
+        let (code, attribute) = (vram[offset], vram[offset + 1]);
+        let (mut foreground, background) = match attribute & 0x77 {
+            0x00 => (OFF, OFF),
+            0x70 => (OFF, NORMAL),
+            _ if attribute & 0x08 != 0 => (INTENSE, OFF),
+            _ => (NORMAL, OFF),
+        };
+        // a ninth column to the right of the glyph, dark unless filled in
+        let mut glyph = if row_line < 14 {
+            (self.font[code as usize * 14 + row_line as usize] as u16) << 1
+        } else {
+            0
+        };
+        if LINE_GRAPHICS.contains(&code) {
+            glyph |= glyph >> 1 & 1;
+        }
+        if attribute & 0x07 == 0x01 && row_line == UNDERLINE_LINE {
+            glyph = 0x1FF;
+        }
+        if self.mode & BLINK != 0 && attribute & 0x80 != 0 && self.frame & TEXT_BLINK != 0 {
+            foreground = background;
+        }
+        if self.crtc.cursor_at(address, row_line) && self.frame & CURSOR_BLINK == 0 {
+            glyph = 0x1FF;
+        }
+        for i in 0..9 {
+            let lit = glyph & (0x100 >> i) != 0;
+            dots.push(if lit { foreground } else { background });
+        }
+    }
+
+    fn graphics_cell(&self, vram: &[u8], address: u16, row_line: u32, dots: &mut Vec<u8>) {
+        let page = if self.mode & DISPLAY_PAGE_1 != 0 {
+            PAGE_SIZE as usize
+        } else {
+            0
+        };
+        // four interleaved banks of 8K, one for each line of a row
+        let offset = page + (row_line as usize & 3) * 0x2000 + ((address as usize * 2) & 0x1FFF);
+        let word = u16::from_be_bytes([vram[offset], vram[offset + 1]]);
+        for i in 0..16 {
+            dots.push(if word & (0x8000 >> i) != 0 {
+                NORMAL
+            } else {
+                OFF
+            });
+        }
+    }
+}
+
+impl Default for Mda {
+    fn default() -> Self {
+        Mda::new()
+    }
+}
+
+impl DisplayAdapter for Mda {
+    fn run_until(&mut self, cpu_cycles: u64) {
+        if cpu_cycles <= self.now {
+            return;
+        }
+        self.dot += Mda::dots_at(cpu_cycles) - Mda::dots_at(self.now);
+        self.now = cpu_cycles;
+        let frame_dots = self.line_dots() * self.crtc.frame_lines() as u64;
+        self.frame += self.dot / frame_dots;
+        self.dot %= frame_dots;
+    }
+
+    fn render(&mut self) -> &Framebuffer {
+        let columns = self.crtc.horizontal_displayed();
+        let row_height = self.crtc.row_height();
+        let width = columns as usize * self.char_width() as usize;
+        let height = (self.crtc.vertical_displayed() * row_height) as usize;
+        let mut framebuffer = std::mem::take(&mut self.framebuffer);
+        framebuffer.resize(width, height);
+        if self.mode & VIDEO_ENABLE == 0 {
+            framebuffer.fill(MONOCHROME_PALETTE[OFF as usize]);
+            self.framebuffer = framebuffer;
+            return &self.framebuffer;
+        }
+
+        let vram = self.vram.borrow();
+        let mut dots = Vec::with_capacity(width);
+        for y in 0..height {
+            let row = y as u32 / row_height;
+            let row_line = y as u32 % row_height;
+            let start = self
+                .crtc
+                .start_address()
+                .wrapping_add((row * columns) as u16);
+            dots.clear();
+            for column in 0..columns {
+                let address = start.wrapping_add(column as u16);
+                if self.graphics() {
+                    self.graphics_cell(vram.as_slice(), address, row_line, &mut dots);
+                } else {
+                    self.text_cell(vram.as_slice(), address, row_line, &mut dots);
+                }
+            }
+            for (x, &shade) in dots.iter().enumerate() {
+                framebuffer.set_pixel(x, y, MONOCHROME_PALETTE[shade as usize]);
+            }
+        }
+        drop(vram);
+        self.framebuffer = framebuffer;
+        &self.framebuffer
+    }
+}
+
+impl IoDevice for Mda {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        match port & 0x0F {
+            0..=7 if port & 1 != 0 => self.crtc.read(),
+            0x0A => self.status(),
+            _ => OPEN_BUS_VALUE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        match port & 0x0F {
+            0..=7 if port & 1 == 0 => self.crtc.select(value),
+            0..=7 => self.crtc.write(value),
+            0x08 if self.hercules => {
+                let mut allowed = HIGH_RES | VIDEO_ENABLE | BLINK;
+                if self.config & ALLOW_GRAPHICS != 0 {
+                    allowed |= GRAPHICS;
+                }
+                if self.config & ALLOW_PAGE_1 != 0 {
+                    allowed |= DISPLAY_PAGE_1;
+                }
+                self.mode = value & allowed;
+            }
+            0x08 => self.mode = value & (HIGH_RES | VIDEO_ENABLE | BLINK),
+            0x0F if self.hercules => self.config = value & (ALLOW_GRAPHICS | ALLOW_PAGE_1),
+            _ => {}
+        }
+    }
+}
+
+impl MemoryDevice for Mda {
+    fn read_u8(&mut self, offset: u32) -> u8 {
+        match self.vram_offset(offset) {
+            Some(offset) => self.vram.borrow_mut().read_u8(offset),
+            None => OPEN_MEMORY,
+        }
+    }
+
+    fn write_u8(&mut self, offset: u32, value: u8) {
+        if let Some(offset) = self.vram_offset(offset) {
+            self.vram.borrow_mut().write_u8(offset, value);
+        }
+    }
+
+    fn decodes(&self, offset: u32) -> bool {
+        self.vram_offset(offset).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryBus;
+
+    const TEXT: [u8; 16] = [
+        0x61, 0x50, 0x52, 0x0F, 0x19, 0x06, 0x19, 0x19, 0x02, 0x0D, 0x0B, 0x0C, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    const HERCULES_GRAPHICS: [u8; 16] = [
+        0x35, 0x2D, 0x2E, 0x07, 0x5B, 0x02, 0x57, 0x57, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    fn program(mda: &mut Mda, crtc: &[u8; 16], mode: u8) {
+        for (index, &value) in crtc.iter().enumerate() {
+            IoDevice::write_u8(mda, 0x3B4, index as u8);
+            IoDevice::write_u8(mda, 0x3B5, value);
+        }
+        IoDevice::write_u8(mda, 0x3B8, mode);
+    }
+
+    fn poke(mda: &mut Mda, offset: u32, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            MemoryDevice::write_u8(mda, offset + i as u32, byte);
+        }
+    }
+
+    fn shades(framebuffer: &Framebuffer, y: usize, x: usize, width: usize) -> Vec<usize> {
+        (x..x + width)
+            .map(|x| {
+                let rgba = framebuffer.pixel(x, y);
+                MONOCHROME_PALETTE.iter().position(|&c| c == rgba).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_text() {
+        let mut mda = Mda::new();
+        program(&mut mda, &TEXT, 0x29);
+        // normal, intense, underlined, reverse and hidden
+        poke(&mut mda, 0, b"\xDB\x07\xDB\x0F_\x01\xDB\x70A\x00");
+        mda.set_font(&[0xFF; FONT_SIZE]);
+        let framebuffer = mda.render();
+        assert_eq!((720, 350), (framebuffer.width(), framebuffer.height()));
+        // the ninth column is dark except for line graphics
+        let mut expected = vec![1; 9];
+        expected.extend([2; 9]);
+        expected.extend([1; 8]);
+        expected.push(0);
+        expected.extend([0; 18]);
+        assert_eq!(expected, shades(framebuffer, 0, 0, 45));
+
+        mda.set_font(&[0; FONT_SIZE]);
+        let framebuffer = mda.render();
+        assert_eq!(vec![0; 9], shades(framebuffer, 11, 18, 9));
+        assert_eq!(vec![1; 9], shades(framebuffer, 12, 18, 9));
+        assert_eq!(vec![1; 9], shades(framebuffer, 0, 27, 9));
+    }
+
+    #[test]
+    fn test_cursor_and_blink() {
+        let mut mda = Mda::new();
+        program(&mut mda, &TEXT, 0x29);
+        let mut font = [0xFF; FONT_SIZE];
+        font[..14].fill(0);
+        mda.set_font(&font);
+        poke(&mut mda, 0, b"\x00\x07\xDB\x87");
+        // the cursor on lines 11 and 12 of the first cell
+        assert_eq!(vec![0; 9], shades(mda.render(), 10, 0, 9));
+        assert_eq!(vec![1; 9], shades(mda.render(), 11, 0, 9));
+        assert_eq!(vec![1; 8], shades(mda.render(), 0, 9, 8));
+
+        let frame_cycles = 95809;
+        // the cursor is off from frame 8, blinking text from frame 16
+        mda.run_until(24 * frame_cycles);
+        assert_eq!(24, mda.frame());
+        assert_eq!(vec![0; 9], shades(mda.render(), 11, 0, 9));
+        assert_eq!(vec![0; 8], shades(mda.render(), 0, 9, 8));
+        IoDevice::write_u8(&mut mda, 0x3B8, 0x09);
+        assert_eq!(vec![1; 8], shades(mda.render(), 0, 9, 8));
+    }
+
+    #[test]
+    fn test_status() {
+        let mut mda = Mda::new();
+        program(&mut mda, &TEXT, 0x29);
+        // horizontal sync starts 738 dots into the line
+        mda.run_until(216);
+        assert_eq!(0, IoDevice::read_u8(&mut mda, 0x3BA));
+        mda.run_until(217);
+        assert_eq!(HORIZONTAL_RETRACE, IoDevice::read_u8(&mut mda, 0x3BA));
+
+        let mut hercules = Mda::hercules();
+        program(&mut hercules, &TEXT, 0x29);
+        assert_eq!(
+            NOT_VERTICAL_RETRACE,
+            IoDevice::read_u8(&mut hercules, 0x3BA)
+        );
+        // 350 lines of 882 dots
+        hercules.run_until(350 * 882 * 14_318_180 / 48_771_000 + 1);
+        assert_eq!(
+            0,
+            IoDevice::read_u8(&mut hercules, 0x3BA) & NOT_VERTICAL_RETRACE
+        );
+    }
+
+    #[test]
+    fn test_mda_vram_mirrors() {
+        let mda = Rc::new(RefCell::new(Mda::new()));
+        let mut mem = MemoryBus::new();
+        let size = mda.borrow().window_size();
+        mem.map(VRAM_START, size, Box::new(mda.clone())).unwrap();
+        mem.write_u8(0xB7001, 0x42);
+        assert_eq!(0x42, mem.read_u8(0xB0001));
+        assert_eq!(0x42, mda.borrow().vram().borrow().as_slice()[1]);
+    }
+
+    #[test]
+    fn test_hercules_config() {
+        let mut hercules = Mda::hercules();
+        // graphics and the second page stay off until allowed
+        IoDevice::write_u8(&mut hercules, 0x3B8, 0x8A);
+        assert_eq!(VIDEO_ENABLE, hercules.mode());
+        MemoryDevice::write_u8(&mut hercules, 0x8000, 0x42);
+        assert_eq!(0xFF, MemoryDevice::read_u8(&mut hercules, 0x8000));
+        MemoryDevice::write_u8(&mut hercules, 0x7000, 0x42);
+        assert_eq!(0x42, MemoryDevice::read_u8(&mut hercules, 0x7000));
+
+        IoDevice::write_u8(&mut hercules, 0x3BF, ALLOW_GRAPHICS | ALLOW_PAGE_1);
+        IoDevice::write_u8(&mut hercules, 0x3B8, 0x8A);
+        assert_eq!(GRAPHICS | VIDEO_ENABLE | DISPLAY_PAGE_1, hercules.mode());
+        MemoryDevice::write_u8(&mut hercules, 0x8000, 0x42);
+        assert_eq!(0x42, MemoryDevice::read_u8(&mut hercules, 0x8000));
+
+        // the plain MDA ignores both
+        let mut mda = Mda::new();
+        IoDevice::write_u8(&mut mda, 0x3BF, ALLOW_GRAPHICS);
+        IoDevice::write_u8(&mut mda, 0x3B8, 0x0A);
+        assert_eq!(VIDEO_ENABLE, mda.mode());
+    }
+
+    #[test]
+    fn test_hercules_graphics() {
+        let mut hercules = Mda::hercules();
+        IoDevice::write_u8(&mut hercules, 0x3BF, ALLOW_GRAPHICS | ALLOW_PAGE_1);
+        program(&mut hercules, &HERCULES_GRAPHICS, 0x0A);
+        // line 0, line 1 and line 4 of page 0, line 0 of page 1
+        poke(&mut hercules, 0, &[0x80, 0x01]);
+        poke(&mut hercules, 0x2000, &[0x40]);
+        poke(&mut hercules, 90, &[0x20]);
+        poke(&mut hercules, 0x8000, &[0xFF]);
+        let framebuffer = hercules.render();
+        assert_eq!((720, 348), (framebuffer.width(), framebuffer.height()));
+        let mut expected = vec![0; 16];
+        expected[0] = 1;
+        expected[15] = 1;
+        assert_eq!(expected, shades(framebuffer, 0, 0, 16));
+        assert_eq!(vec![0, 1, 0], shades(framebuffer, 1, 0, 3));
+        assert_eq!(vec![0, 0, 1], shades(framebuffer, 4, 0, 3));
+
+        IoDevice::write_u8(&mut hercules, 0x3B8, 0x8A);
+        assert_eq!(vec![1; 8], shades(hercules.render(), 0, 0, 8));
+    }
+}
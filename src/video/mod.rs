@@ -0,0 +1,231 @@
+// Display adapters. Each one keeps its own video RAM and CRTC and draws what
+// it would send to the monitor into an RGBA framebuffer, which a frontend can
+// put on screen and tests can compare against. The CGA's picture can also go
+// through the composite module, for what a composite monitor would make of it.
+
+use std::{cell::RefCell, error::Error, rc::Rc};
+
+use crate::{
+    io::{IoBus, IoDevice},
+    memory::MemoryBus,
+    ppi::Display,
+};
+
+pub mod cga;
+pub mod composite;
+pub mod crtc;
+mod font;
+pub mod mda;
+
+pub use cga::Cga;
+pub use font::{font_8x14, font_8x8};
+pub use mda::Mda;
+
+/// What the machine needs from a display adapter besides its registers:
+/// keeping it in step with the CPU, and a picture of what it shows.
+pub trait DisplayAdapter: IoDevice {
+    fn run_until(&mut self, cpu_cycles: u64);
+    fn render(&mut self) -> &Framebuffer;
+}
+
+// the adapters a machine can be configured with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Adapter {
+    #[default]
+    Cga,
+    Mda,
+    Hercules,
+}
+
+impl Adapter {
+    // how SW1 has to be set for the BIOS to use it
+    pub fn display_switch(self) -> Display {
+        match self {
+            Adapter::Cga => Display::Cga80,
+            Adapter::Mda | Adapter::Hercules => Display::Monochrome,
+        }
+    }
+
+    // builds the card and puts its registers and video RAM on the buses
+    pub fn install(
+        self,
+        mem: &mut MemoryBus,
+        io: &mut IoBus,
+    ) -> Result<Rc<RefCell<dyn DisplayAdapter>>, Box<dyn Error>> {
+        if self == Adapter::Cga {
+            let cga = Rc::new(RefCell::new(Cga::new()));
+            let vram = cga.borrow().vram();
+            mem.map(cga::VRAM_START, cga::VRAM_WINDOW, Box::new(vram))?;
+            io.register(cga::FIRST_PORT, cga::LAST_PORT, Box::new(cga.clone()))?;
+            return Ok(cga);
+        }
+
+        let mda = Rc::new(RefCell::new(if self == Adapter::Hercules {
+            Mda::hercules()
+        } else {
+            Mda::new()
+        }));
+        let size = mda.borrow().window_size();
+        if self == Adapter::Hercules {
+            mem.map_overlay(mda::VRAM_START, size, Box::new(mda.clone()))?;
+        } else {
+            mem.map(mda::VRAM_START, size, Box::new(mda.clone()))?;
+        }
+        io.register(mda::FIRST_PORT, mda::LAST_PORT, Box::new(mda.clone()))?;
+        if self == Adapter::Hercules {
+            io.register(mda::CONFIG_PORT, mda::CONFIG_PORT, Box::new(mda.clone()))?;
+        }
+        Ok(mda)
+    }
+}
+
+// the 16 colours of an RGBI monitor, with dark yellow turned brown
+pub const RGBI_PALETTE: [[u8; 4]; 16] = [
+    [0x00, 0x00, 0x00, 0xFF],
+    [0x00, 0x00, 0xAA, 0xFF],
+    [0x00, 0xAA, 0x00, 0xFF],
+    [0x00, 0xAA, 0xAA, 0xFF],
+    [0xAA, 0x00, 0x00, 0xFF],
+    [0xAA, 0x00, 0xAA, 0xFF],
+    [0xAA, 0x55, 0x00, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x55, 0x55, 0xFF, 0xFF],
+    [0x55, 0xFF, 0x55, 0xFF],
+    [0x55, 0xFF, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55, 0xFF],
+    [0xFF, 0x55, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x55, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+];
+
+// off, normal and intense on a green monochrome monitor
+pub const MONOCHROME_PALETTE: [[u8; 4]; 3] = [
+    [0x00, 0x00, 0x00, 0xFF],
+    [0x00, 0xAA, 0x00, 0xFF],
+    [0x55, 0xFF, 0x55, 0xFF],
+];
+
+// an image four bytes a pixel, red first, rows top to bottom
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn as_rgba(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        self.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    // pixels outside the image are dropped
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        if x < self.width && y < self.height {
+            let i = (y * self.width + x) * 4;
+            self.pixels[i..i + 4].copy_from_slice(&rgba);
+        }
+    }
+
+    pub fn fill(&mut self, rgba: [u8; 4]) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+
+    // keeps the contents only if the size stays the same
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width != self.width || height != self.height {
+            *self = Framebuffer::new(width, height);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install() {
+        let cga_80x25 = [
+            0x71, 0x50, 0x5A, 0x0A, 0x1F, 0x06, 0x19, 0x1C, 0x02, 0x07, 0x06, 0x07,
+        ];
+        let mda_80x25 = [
+            0x61, 0x50, 0x52, 0x0F, 0x19, 0x06, 0x19, 0x19, 0x02, 0x0D, 0x0B, 0x0C,
+        ];
+        for (adapter, crtc, vram, size) in [
+            (Adapter::Cga, 0x3D4, 0xB8000, (640, 200)),
+            (Adapter::Mda, 0x3B4, 0xB0000, (720, 350)),
+            (Adapter::Hercules, 0x3B4, 0xB0000, (720, 350)),
+        ] {
+            let mut mem = MemoryBus::new();
+            let mut io = IoBus::new();
+            let card = adapter.install(&mut mem, &mut io).unwrap();
+            // the Hercules card leaves the CGA's window alone until its
+            // second page is allowed
+            assert_eq!(adapter == Adapter::Cga, mem.is_mapped(0xB8000));
+            assert_eq!(adapter == Adapter::Hercules, io.is_handled(0x3BF));
+
+            let table = if adapter == Adapter::Cga {
+                cga_80x25
+            } else {
+                mda_80x25
+            };
+            for (index, value) in table.into_iter().enumerate() {
+                io.write_u8(crtc, index as u8);
+                io.write_u8(crtc + 1, value);
+            }
+            io.write_u8(crtc + 4, 0x29);
+            // a full block, which both fonts leave blank
+            mem.write_u16(vram, 0x7000);
+            card.borrow_mut().run_until(1000);
+            let mut card = card.borrow_mut();
+            let framebuffer = card.render();
+            assert_eq!(size, (framebuffer.width(), framebuffer.height()));
+            assert_ne!([0, 0, 0, 0xFF], framebuffer.pixel(0, 0));
+            assert_eq!([0, 0, 0, 0xFF], framebuffer.pixel(9, 0));
+        }
+        assert_eq!(Display::Monochrome, Adapter::Hercules.display_switch());
+        assert_eq!(Display::Cga80, Adapter::default().display_switch());
+    }
+
+    #[test]
+    fn test_hercules_beside_cga() {
+        let mut mem = MemoryBus::new();
+        let mut io = IoBus::new();
+        Adapter::Cga.install(&mut mem, &mut io).unwrap();
+        Adapter::Hercules.install(&mut mem, &mut io).unwrap();
+        mem.write_u8(0xB0000, 0x12);
+        mem.write_u8(0xB8000, 0x34);
+        assert_eq!(0x12, mem.read_u8(0xB0000));
+        assert_eq!(0x34, mem.read_u8(0xB8000));
+
+        // with the second page allowed the Hercules card takes B8000h over
+        io.write_u8(0x3BF, mda::ALLOW_PAGE_1);
+        assert_eq!(0, mem.read_u8(0xB8000));
+        mem.write_u8(0xB8000, 0x56);
+        io.write_u8(0x3BF, 0);
+        assert_eq!(0x34, mem.read_u8(0xB8000));
+    }
+}